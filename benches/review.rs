@@ -0,0 +1,120 @@
+// Benchmarks for the parts of a review that scale with the size of the diff.
+// The crate is a binary, so the modules are compiled in directly
+#![allow(dead_code)]
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use std::hint::black_box;
+
+#[path = "../src/diff.rs"]
+mod diff;
+#[path = "../src/findings.rs"]
+mod findings;
+#[path = "../src/heuristics.rs"]
+mod heuristics;
+#[path = "../src/output.rs"]
+mod output;
+#[path = "../src/state.rs"]
+mod state;
+#[path = "../src/terminal.rs"]
+mod terminal;
+
+// The renderer only keeps findings around, their contents don't matter here
+mod history {
+    pub struct ReviewFinding;
+}
+
+// Only asked whether a large report may go to a temp file
+mod encryption {
+    pub fn enabled() -> bool {
+        false
+    }
+}
+
+use output::{DiffAnnotations, OutputBuffer};
+use terminal::Theme;
+
+// Hunks of Rust-looking code: mostly context, some replaced lines, an
+// occasional formatting-only hunk and a block moved from the top of the file
+fn synthetic_patch(hunks: usize) -> String {
+    let mut patch = String::new();
+    for hunk in 0..hunks {
+        let start = hunk * 40 + 1;
+        patch.push_str(&format!("@@ -{},12 +{},12 @@ fn section_{}() {{\n", start, start, hunk));
+        if hunk % 10 == 9 {
+            for i in 0..6 {
+                patch.push_str(&format!("-    let value_{} = compute({});\n", i, i));
+                patch.push_str(&format!("+    let value_{}  =  compute({});\n", i, i));
+            }
+            continue;
+        }
+        for i in 0..4 {
+            patch.push_str(&format!("     let context_{} = state.get({}).cloned();\n", i, i));
+        }
+        patch.push_str(&format!("-    let result = client.fetch({}).unwrap();\n", hunk));
+        patch.push_str(&format!("+    let result = client.fetch({}).await?;\n", hunk));
+        patch.push_str("+    // TODO: retry on timeouts\n");
+        patch.push_str("+    let mut items = Vec::new();\n");
+        for i in 0..3 {
+            patch.push_str(&format!("+    items.push(result.field_{}.to_string());\n", i));
+        }
+        for i in 0..3 {
+            patch.push_str(&format!("     log::debug!(\"processed {{}}\", context_{});\n", i));
+        }
+    }
+    patch
+}
+
+const SIZES: [usize; 3] = [100, 1_000, 10_000];
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("diff");
+    for hunks in SIZES {
+        let patch = synthetic_patch(hunks);
+        group.throughput(Throughput::Bytes(patch.len() as u64));
+        group.bench_with_input(BenchmarkId::new("parse_hunks", hunks), &patch, |b, patch| {
+            b.iter(|| diff::parse_hunks(black_box(patch)).len())
+        });
+        group.bench_with_input(BenchmarkId::new("without_formatting_hunks", hunks), &patch, |b, patch| {
+            b.iter(|| diff::without_formatting_hunks(black_box(patch)))
+        });
+        group.bench_with_input(BenchmarkId::new("detect_moves", hunks), &patch, |b, patch| {
+            let files = [("src/a.rs", patch.as_str()), ("src/b.rs", patch.as_str())];
+            b.iter(|| diff::detect_moves(black_box(&files)).len())
+        });
+    }
+    group.finish();
+}
+
+fn heuristics(c: &mut Criterion) {
+    let mut group = c.benchmark_group("heuristics");
+    for hunks in SIZES {
+        let patch = synthetic_patch(hunks);
+        group.throughput(Throughput::Bytes(patch.len() as u64));
+        group.bench_with_input(BenchmarkId::new("check", hunks), &patch, |b, patch| {
+            b.iter(|| heuristics::check(black_box(patch)).len())
+        });
+    }
+    group.finish();
+}
+
+fn render(c: &mut Criterion) {
+    let mut group = c.benchmark_group("render");
+    for hunks in SIZES {
+        let patch = synthetic_patch(hunks);
+        group.throughput(Throughput::Bytes(patch.len() as u64));
+        for (name, theme) in [("ascii", Theme::ascii()), ("plain", Theme::plain())] {
+            group.bench_with_input(BenchmarkId::new(name, hunks), &patch, |b, patch| {
+                b.iter(|| {
+                    let mut output = OutputBuffer::new(theme);
+                    output.add_diff_header("src/lib.rs");
+                    output.add_diff_content(black_box(patch), &DiffAnnotations::default());
+                    output.content.len()
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse, heuristics, render);
+criterion_main!(benches);
@@ -0,0 +1,538 @@
+use crate::aws;
+use crate::http;
+use crate::{ClaudeMessage, MessageContent};
+use chrono::Utc;
+use clap::ValueEnum;
+use log::warn;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::env;
+use std::error::Error;
+
+// Who answers the prompts
+#[derive(Deserialize, ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    #[default]
+    Anthropic,
+    Openai,
+    Ollama,
+    // Anthropic's models through AWS
+    Bedrock,
+}
+
+impl Provider {
+    pub fn default_model(self) -> &'static str {
+        match self {
+            Provider::Anthropic => "claude-3-5-sonnet-20241022",
+            Provider::Openai => "gpt-4o",
+            Provider::Ollama => "codellama",
+            Provider::Bedrock => "anthropic.claude-3-5-sonnet-20241022-v2:0",
+        }
+    }
+
+    // Usable as the model without declaring them in [models]
+    pub fn aliases(self) -> [(&'static str, &'static str); 2] {
+        match self {
+            Provider::Anthropic => [("fast", "claude-3-5-haiku-20241022"), ("smart", "claude-3-5-sonnet-20241022")],
+            Provider::Openai => [("fast", "gpt-4o-mini"), ("smart", "gpt-4o")],
+            Provider::Ollama => [("fast", "codellama:7b"), ("smart", "codellama:34b")],
+            Provider::Bedrock => [
+                ("fast", "anthropic.claude-3-5-haiku-20241022-v1:0"),
+                ("smart", "anthropic.claude-3-5-sonnet-20241022-v2:0"),
+            ],
+        }
+    }
+
+    // A local Ollama server takes requests from anyone who can reach it, and
+    // Bedrock signs them with AWS credentials
+    pub fn needs_key(self) -> bool {
+        matches!(self, Provider::Anthropic | Provider::Openai)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Provider::Anthropic => "Anthropic",
+            Provider::Openai => "OpenAI",
+            Provider::Ollama => "Ollama",
+            Provider::Bedrock => "Bedrock",
+        }
+    }
+
+    // Catches a model meant for another provider before any request is made.
+    // Anthropic and Bedrock IDs are recognizable, OpenAI-compatible APIs and
+    // Ollama name their models freely
+    pub fn check_model(self, model: &str) -> Result<(), String> {
+        let claude = model.starts_with("claude-");
+        let bedrock = model.starts_with("arn:aws:bedrock:") || model.split('.').any(|part| part == "anthropic");
+        let fits = match self {
+            Provider::Anthropic => claude,
+            Provider::Bedrock => bedrock,
+            Provider::Openai | Provider::Ollama => !claude && !bedrock,
+        };
+        if fits {
+            return Ok(());
+        }
+        let hint = if claude {
+            "it needs --ai-provider anthropic".to_string()
+        } else if bedrock {
+            "it needs --ai-provider bedrock".to_string()
+        } else {
+            format!("{} models look like {}", self.name(), self.default_model())
+        };
+        Err(format!("{} is not a model {} serves, {}", model, self.name(), hint))
+    }
+
+    pub fn check_temperature(self, temperature: f64) -> Result<(), String> {
+        let max = match self {
+            Provider::Anthropic | Provider::Bedrock => 1.0,
+            Provider::Openai | Provider::Ollama => 2.0,
+        };
+        if (0.0..=max).contains(&temperature) {
+            Ok(())
+        } else {
+            Err(format!("The temperature must be between 0 and {} with {}, not {}", max, self.name(), temperature))
+        }
+    }
+
+    pub fn client(self, api_key: &str) -> Result<Box<dyn AiProvider>, Box<dyn Error>> {
+        Ok(match self {
+            Provider::Anthropic => Box::new(Anthropic {
+                api_key: api_key.to_string(),
+            }),
+            Provider::Openai => Box::new(OpenAi {
+                api_key: api_key.to_string(),
+                base_url: env::var("OPENAI_BASE_URL")
+                    .ok()
+                    .map(|url| url.trim_end_matches('/').to_string())
+                    .filter(|url| !url.is_empty())
+                    .unwrap_or_else(|| "https://api.openai.com/v1".to_string()),
+            }),
+            Provider::Ollama => Box::new(Ollama {
+                base_url: ollama_url(),
+                max_context: ollama_max_context()?,
+            }),
+            Provider::Bedrock => {
+                let region = aws::region().ok_or("No AWS region, set AWS_REGION or region in ~/.aws/config")?;
+                Box::new(Bedrock {
+                    endpoint: bedrock_url(&region),
+                    credentials: aws::credentials()?,
+                    region,
+                })
+            }
+        })
+    }
+}
+
+// OLLAMA_HOST as Ollama itself reads it, a bare host:port included
+pub fn ollama_url() -> String {
+    let host = env::var("OLLAMA_HOST").unwrap_or_default();
+    let host = host.trim().trim_end_matches('/');
+    match host {
+        "" => "http://localhost:11434".to_string(),
+        host if host.contains("://") => host.to_string(),
+        host => format!("http://{}", host),
+    }
+}
+
+// The largest context window asked of Ollama, from OLLAMA_NUM_CTX. Memory use
+// grows with it, and a model can't go past what it was trained on
+fn ollama_max_context() -> Result<u32, Box<dyn Error>> {
+    match env::var("OLLAMA_NUM_CTX") {
+        Ok(value) => Ok(value
+            .trim()
+            .parse()
+            .map_err(|_| format!("OLLAMA_NUM_CTX should be a number of tokens, got {}", value))?),
+        Err(_) => Ok(OLLAMA_MAX_CONTEXT),
+    }
+}
+
+// The bedrock-runtime endpoint of the region, unless AWS_ENDPOINT_URL_BEDROCK_RUNTIME
+// points somewhere else (a VPC endpoint, say)
+pub fn bedrock_url(region: &str) -> String {
+    env::var("AWS_ENDPOINT_URL_BEDROCK_RUNTIME")
+        .ok()
+        .map(|url| url.trim_end_matches('/').to_string())
+        .filter(|url| !url.is_empty())
+        .unwrap_or_else(|| format!("https://bedrock-runtime.{}.amazonaws.com", region))
+}
+
+// One answer of the model
+pub struct Reply {
+    pub text: String,
+    // Cut at max_tokens, worth asking for the rest
+    pub truncated: bool,
+    // The model that answered, which may be more specific than the one asked
+    pub model: Option<String>,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
+// An AI API: builds the request for a conversation and reads the answer out
+// of the response. Sending, fallbacks and continuations are shared
+pub trait AiProvider {
+    fn request(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ClaudeMessage],
+        max_tokens: u32,
+        temperature: Option<f64>,
+    ) -> reqwest::RequestBuilder;
+
+    // Reads the body of the response
+    fn reply(&self, body: &str) -> Result<Reply, Box<dyn Error>>;
+
+    // Text of one line of a response as it streams in, for showing the
+    // answer before it's complete. Only streaming APIs have any
+    fn delta(&self, _line: &str) -> Option<String> {
+        None
+    }
+
+    // Turns that get the model to go on with a reply cut at max_tokens: the
+    // reply so far as the start of its answer
+    fn continuation(&self, prefix: String) -> Vec<ClaudeMessage> {
+        vec![ClaudeMessage {
+            role: "assistant".to_string(),
+            content: MessageContent::Text(prefix),
+        }]
+    }
+}
+
+const ANTHROPIC_MESSAGES_URL: &str = "https://api.anthropic.com/v1/messages";
+
+pub struct Anthropic {
+    api_key: String,
+}
+
+impl AiProvider for Anthropic {
+    fn request(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ClaudeMessage],
+        max_tokens: u32,
+        temperature: Option<f64>,
+    ) -> reqwest::RequestBuilder {
+        let mut body = json!({ "model": model, "messages": messages, "max_tokens": max_tokens, "stream": true });
+        if let Some(system) = system {
+            body["system"] = json!(system);
+        }
+        if let Some(temperature) = temperature {
+            body["temperature"] = json!(temperature);
+        }
+        http::client()
+            .post(ANTHROPIC_MESSAGES_URL)
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", "2023-06-01")
+            .json(&body)
+    }
+
+    // Server-sent events: the model and input tokens come first, then the
+    // text in pieces, then why it stopped and the output tokens
+    fn reply(&self, body: &str) -> Result<Reply, Box<dyn Error>> {
+        let mut reply = Reply {
+            text: String::new(),
+            truncated: false,
+            model: None,
+            input_tokens: 0,
+            output_tokens: 0,
+        };
+        let mut stopped = false;
+        for event in body.lines().filter_map(event_data) {
+            match event["type"].as_str().unwrap_or_default() {
+                "message_start" => {
+                    reply.model = event["message"]["model"].as_str().map(str::to_string);
+                    reply.input_tokens = event["message"]["usage"]["input_tokens"].as_u64().unwrap_or(0);
+                }
+                "content_block_delta" => reply.text.push_str(event["delta"]["text"].as_str().unwrap_or_default()),
+                "message_delta" => {
+                    reply.truncated = event["delta"]["stop_reason"] == "max_tokens";
+                    reply.output_tokens = event["usage"]["output_tokens"].as_u64().unwrap_or(0);
+                }
+                "message_stop" => stopped = true,
+                "error" => {
+                    let message = event["error"]["message"].as_str().unwrap_or("unknown error");
+                    // Failures of the API itself come in the stream once it has
+                    // started, as the status they would have had before
+                    let status = match event["error"]["type"].as_str().unwrap_or_default() {
+                        "overloaded_error" => 529,
+                        "api_error" => 500,
+                        "rate_limit_error" => 429,
+                        _ => return Err(format!("Anthropic: {}", message).into()),
+                    };
+                    return Err(Box::new(http::StatusError {
+                        url: ANTHROPIC_MESSAGES_URL.to_string(),
+                        status,
+                        message: Some(message.to_string()),
+                        rate_limit: None,
+                    }));
+                }
+                _ => {}
+            }
+        }
+        if !stopped {
+            return Err("The Anthropic response ended before the answer was done".into());
+        }
+        Ok(reply)
+    }
+
+    fn delta(&self, line: &str) -> Option<String> {
+        let event = event_data(line)?;
+        (event["type"] == "content_block_delta")
+            .then(|| event["delta"]["text"].as_str().map(str::to_string))
+            .flatten()
+    }
+}
+
+// The JSON of a server-sent event's data line
+fn event_data(line: &str) -> Option<Value> {
+    serde_json::from_str(line.strip_prefix("data:")?.trim()).ok()
+}
+
+// The Messages API answer in one piece, as Bedrock's InvokeModel passes it on
+fn messages_reply(body: &str) -> Result<Reply, Box<dyn Error>> {
+    let response: Value = serde_json::from_str(body)?;
+    Ok(Reply {
+        text: response["content"][0]["text"].as_str().ok_or("Failed to get response text")?.to_string(),
+        truncated: response["stop_reason"] == "max_tokens",
+        model: response["model"].as_str().map(str::to_string),
+        input_tokens: response["usage"]["input_tokens"].as_u64().unwrap_or(0),
+        output_tokens: response["usage"]["output_tokens"].as_u64().unwrap_or(0),
+    })
+}
+
+// Claude through Bedrock's InvokeModel, signed with SigV4. The body is
+// Anthropic's, with the model in the URL instead
+pub struct Bedrock {
+    endpoint: String,
+    region: String,
+    credentials: aws::Credentials,
+}
+
+impl AiProvider for Bedrock {
+    fn request(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ClaudeMessage],
+        max_tokens: u32,
+        temperature: Option<f64>,
+    ) -> reqwest::RequestBuilder {
+        let mut body = json!({ "anthropic_version": "bedrock-2023-05-31", "messages": messages, "max_tokens": max_tokens });
+        if let Some(system) = system {
+            body["system"] = json!(system);
+        }
+        if let Some(temperature) = temperature {
+            body["temperature"] = json!(temperature);
+        }
+        let body = body.to_string();
+        // Model IDs have a colon, encoded in the URL and encoded again in the signature
+        let path = format!("/model/{}/invoke", aws::uri_encode(model));
+        let host = self.endpoint.split_once("://").map_or(self.endpoint.as_str(), |(_, host)| host);
+        let signed = aws::sign(&self.credentials, &self.region, "bedrock", host, &path, &body, Utc::now());
+        signed.into_iter().fold(
+            http::client()
+                .post(format!("{}{}", self.endpoint, path))
+                .header("content-type", "application/json")
+                .body(body),
+            |request, (name, value)| request.header(name, value),
+        )
+    }
+
+    fn reply(&self, body: &str) -> Result<Reply, Box<dyn Error>> {
+        messages_reply(body)
+    }
+}
+
+// Chat APIs answer a trailing assistant turn with a new reply rather than
+// finishing it, so the model is asked to
+fn ask_to_continue(prefix: String) -> Vec<ClaudeMessage> {
+    vec![
+        ClaudeMessage {
+            role: "assistant".to_string(),
+            content: MessageContent::Text(prefix),
+        },
+        ClaudeMessage {
+            role: "user".to_string(),
+            content: MessageContent::Text(
+                "Continue exactly where your answer stopped, without repeating any of it.".to_string(),
+            ),
+        },
+    ]
+}
+
+// OpenAI's chat completions, or any API compatible with them at
+// OPENAI_BASE_URL
+pub struct OpenAi {
+    api_key: String,
+    base_url: String,
+}
+
+impl OpenAi {
+    // Images go as data URLs instead of Anthropic's base64 sources
+    fn content(content: &MessageContent) -> Value {
+        match content {
+            MessageContent::Text(text) => json!(text),
+            MessageContent::Blocks(blocks) => Value::Array(
+                blocks
+                    .iter()
+                    .map(|block| match block["type"].as_str() {
+                        Some("image") => json!({
+                            "type": "image_url",
+                            "image_url": {
+                                "url": format!(
+                                    "data:{};base64,{}",
+                                    block["source"]["media_type"].as_str().unwrap_or_default(),
+                                    block["source"]["data"].as_str().unwrap_or_default()
+                                )
+                            }
+                        }),
+                        _ => block.clone(),
+                    })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl AiProvider for OpenAi {
+    fn request(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ClaudeMessage],
+        max_tokens: u32,
+        temperature: Option<f64>,
+    ) -> reqwest::RequestBuilder {
+        let mut turns: Vec<Value> = system.map(|system| json!({ "role": "system", "content": system })).into_iter().collect();
+        turns.extend(messages.iter().map(|m| json!({ "role": m.role, "content": Self::content(&m.content) })));
+        let mut body = json!({ "model": model, "messages": turns, "max_completion_tokens": max_tokens });
+        if let Some(temperature) = temperature {
+            body["temperature"] = json!(temperature);
+        }
+        http::client()
+            .post(format!("{}/chat/completions", self.base_url))
+            .bearer_auth(&self.api_key)
+            .json(&body)
+    }
+
+    fn reply(&self, body: &str) -> Result<Reply, Box<dyn Error>> {
+        let response: Value = serde_json::from_str(body)?;
+        let choice = &response["choices"][0];
+        Ok(Reply {
+            text: choice["message"]["content"].as_str().ok_or("Failed to get response text")?.to_string(),
+            truncated: choice["finish_reason"] == "length",
+            model: response["model"].as_str().map(str::to_string),
+            input_tokens: response["usage"]["prompt_tokens"].as_u64().unwrap_or(0),
+            output_tokens: response["usage"]["completion_tokens"].as_u64().unwrap_or(0),
+        })
+    }
+
+    fn continuation(&self, prefix: String) -> Vec<ClaudeMessage> {
+        ask_to_continue(prefix)
+    }
+}
+
+// A local Ollama server, so the code never leaves the machine. Its chat API
+// streams the answer as one JSON object per line
+// Ollama reads prompts with a 2048 token window unless told otherwise, and
+// silently drops the start of anything longer
+const OLLAMA_MIN_CONTEXT: u32 = 2048;
+const OLLAMA_MAX_CONTEXT: u32 = 32768;
+// Rough size of a token of code, for sizing the window before sending
+const CHARS_PER_TOKEN: usize = 3;
+
+pub struct Ollama {
+    base_url: String,
+    max_context: u32,
+}
+
+impl Ollama {
+    // Text and images go in separate fields of the message
+    fn message(message: &ClaudeMessage) -> Value {
+        match &message.content {
+            MessageContent::Text(text) => json!({ "role": message.role, "content": text }),
+            MessageContent::Blocks(blocks) => {
+                let text: Vec<&str> = blocks.iter().filter_map(|block| block["text"].as_str()).collect();
+                let images: Vec<&str> = blocks
+                    .iter()
+                    .filter(|block| block["type"] == "image")
+                    .filter_map(|block| block["source"]["data"].as_str())
+                    .collect();
+                json!({ "role": message.role, "content": text.join("\n\n"), "images": images })
+            }
+        }
+    }
+}
+
+impl AiProvider for Ollama {
+    fn request(
+        &self,
+        model: &str,
+        system: Option<&str>,
+        messages: &[ClaudeMessage],
+        max_tokens: u32,
+        temperature: Option<f64>,
+    ) -> reqwest::RequestBuilder {
+        let mut turns: Vec<Value> = system.map(|system| json!({ "role": "system", "content": system })).into_iter().collect();
+        turns.extend(messages.iter().map(Self::message));
+        // A window for the prompt and the answer, in steps of the default one
+        let chars: usize = turns.iter().map(|turn| turn["content"].as_str().unwrap_or_default().len()).sum();
+        let needed = u32::try_from(chars / CHARS_PER_TOKEN).unwrap_or(u32::MAX).saturating_add(max_tokens);
+        let num_ctx = needed.div_ceil(OLLAMA_MIN_CONTEXT).saturating_mul(OLLAMA_MIN_CONTEXT).min(self.max_context);
+        if needed > self.max_context {
+            warn!(
+                "The prompt and answer need about {} tokens, more than the {} of OLLAMA_NUM_CTX: \
+                Ollama drops the start of the prompt",
+                needed, self.max_context
+            );
+        }
+        let mut options = json!({ "num_predict": max_tokens, "num_ctx": num_ctx.max(OLLAMA_MIN_CONTEXT) });
+        if let Some(temperature) = temperature {
+            options["temperature"] = json!(temperature);
+        }
+        http::client().post(format!("{}/api/chat", self.base_url)).json(&json!({
+            "model": model,
+            "messages": turns,
+            "stream": true,
+            "options": options
+        }))
+    }
+
+    // The pieces of the answer are put back together, the last line carries
+    // why it stopped and the token counts
+    fn reply(&self, body: &str) -> Result<Reply, Box<dyn Error>> {
+        let mut text = String::new();
+        let mut done = Value::Null;
+        for line in body.lines().filter(|line| !line.trim().is_empty()) {
+            let chunk: Value = serde_json::from_str(line)?;
+            if let Some(error) = chunk["error"].as_str() {
+                return Err(format!("Ollama: {}", error).into());
+            }
+            text.push_str(chunk["message"]["content"].as_str().unwrap_or_default());
+            if chunk["done"] == true {
+                done = chunk;
+            }
+        }
+        if done.is_null() {
+            return Err("The Ollama response ended before the answer was done".into());
+        }
+        Ok(Reply {
+            text,
+            truncated: done["done_reason"] == "length",
+            model: done["model"].as_str().map(str::to_string),
+            input_tokens: done["prompt_eval_count"].as_u64().unwrap_or(0),
+            output_tokens: done["eval_count"].as_u64().unwrap_or(0),
+        })
+    }
+
+    fn delta(&self, line: &str) -> Option<String> {
+        let chunk: Value = serde_json::from_str(line).ok()?;
+        chunk["message"]["content"].as_str().filter(|text| !text.is_empty()).map(str::to_string)
+    }
+
+    fn continuation(&self, prefix: String) -> Vec<ClaudeMessage> {
+        ask_to_continue(prefix)
+    }
+}
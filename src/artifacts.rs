@@ -0,0 +1,272 @@
+use crate::config::{Artifact, Metric};
+use crate::findings::{Finding, Severity};
+use crate::http;
+use crate::state;
+use crate::terminal::Color;
+use crate::{OutputBuffer, PullRequestDetail, history};
+use log::{info, warn};
+use serde::Deserialize;
+use std::error::Error;
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+// Metrics come from reports and summaries, not build outputs
+const MAX_ARCHIVE_BYTES: usize = 100 * 1024 * 1024;
+// The file read out of the archive, decompressed. A few KB of zip can
+// inflate to gigabytes
+const MAX_FILE_BYTES: u64 = 20 * 1024 * 1024;
+
+#[derive(Deserialize, Debug)]
+struct Upload {
+    id: u64,
+    expired: bool,
+    workflow_run: Option<WorkflowRun>,
+}
+
+#[derive(Deserialize, Debug)]
+struct WorkflowRun {
+    head_sha: String,
+    #[serde(default)]
+    head_branch: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Number(f64),
+    Text(String),
+}
+
+impl Value {
+    fn parse(text: &str) -> Self {
+        let text = text.trim();
+        match text.parse() {
+            Ok(number) => Value::Number(number),
+            Err(_) => Value::Text(text.to_string()),
+        }
+    }
+
+    fn show(&self) -> String {
+        match self {
+            Value::Number(n) if n.fract() == 0.0 => format!("{}", *n as i64),
+            Value::Number(n) => format!("{:.2}", n),
+            Value::Text(text) => text.clone(),
+        }
+    }
+}
+
+// The artifact of the PR head, and the one to compare it with: from the base
+// commit, or the newest from the base branch as CI doesn't always run on the
+// exact merge base
+pub async fn find(
+    owner: &str,
+    repo: &str,
+    name: &str,
+    details: &PullRequestDetail,
+    github_token: Option<&str>,
+) -> Result<(Option<u64>, Option<u64>), Box<dyn Error>> {
+    let mut url = reqwest::Url::parse(&format!("{}/repos/{}/{}/actions/artifacts", http::github_api(), owner, repo))?;
+    // Names may hold anything, & and # included
+    url.query_pairs_mut().append_pair("name", name);
+    let listing = http::GithubClient::new(github_token).get_all(url.as_str()).await?;
+    let listing: Vec<Upload> = serde_json::from_str(&listing)?;
+    // Newest first, and expired ones can't be downloaded anymore
+    let uploads: Vec<(&Upload, &WorkflowRun)> = listing
+        .iter()
+        .filter(|a| !a.expired)
+        .filter_map(|a| a.workflow_run.as_ref().map(|run| (a, run)))
+        .collect();
+
+    let head = uploads.iter().find(|(_, run)| run.head_sha == details.head.sha);
+    let base = uploads
+        .iter()
+        .find(|(_, run)| run.head_sha == details.base.sha)
+        .or_else(|| uploads.iter().find(|(_, run)| run.head_branch == details.base.name));
+    Ok((head.map(|(a, _)| a.id), base.map(|(a, _)| a.id)))
+}
+
+// Artifacts are served as zip archives, kept in a temporary file while the
+// file is read out of them
+pub async fn download(
+    owner: &str,
+    repo: &str,
+    id: u64,
+    file: Option<&str>,
+    github_token: Option<&str>,
+) -> Result<String, Box<dyn Error>> {
+    let url = format!("{}/repos/{}/{}/actions/artifacts/{}/zip", http::github_api(), owner, repo, id);
+    let (path, mut archive) = state::create_private(&env::temp_dir(), "artifact", "zip")?;
+    let downloaded = http::GithubClient::new(github_token).download(&url, MAX_ARCHIVE_BYTES, &mut archive).await;
+    // Closed first, Windows doesn't remove open files
+    drop(archive);
+    let content = downloaded.and_then(|_| read_archive(&path, file));
+    if let Err(e) = fs::remove_file(&path) {
+        warn!("Could not remove {}: {}", path.display(), e);
+    }
+    content
+}
+
+fn read_archive(path: &Path, file: Option<&str>) -> Result<String, Box<dyn Error>> {
+    let mut archive = zip::ZipArchive::new(BufReader::new(File::open(path)?))?;
+    let entry = match file {
+        Some(file) => archive.by_name(file)?,
+        None if archive.len() == 1 => archive.by_index(0)?,
+        None => return Err("the artifact has several files, set which one to read with `file`".into()),
+    };
+    let mut content = Vec::new();
+    // One byte past the cap tells a file at the cap from a larger one
+    entry.take(MAX_FILE_BYTES + 1).read_to_end(&mut content)?;
+    if content.len() as u64 > MAX_FILE_BYTES {
+        return Err(format!("the file is larger than {} bytes once decompressed", MAX_FILE_BYTES).into());
+    }
+    Ok(String::from_utf8(content)?)
+}
+
+// Reads a metric out of a file. Without a pointer the file is the value
+fn read(content: &str, pointer: Option<&str>) -> Result<Value, Box<dyn Error>> {
+    let Some(pointer) = pointer else {
+        return Ok(Value::parse(content));
+    };
+    let json: serde_json::Value = serde_json::from_str(content)?;
+    match json.pointer(pointer) {
+        Some(serde_json::Value::Number(n)) => Ok(Value::Number(n.as_f64().unwrap_or_default())),
+        Some(serde_json::Value::String(text)) => Ok(Value::parse(text)),
+        Some(other) => Ok(Value::Text(other.to_string())),
+        None => Err(format!("{} is not in the file", pointer).into()),
+    }
+}
+
+// Growth of a number over the base, in percent
+fn increase(base: &Value, head: &Value) -> Option<f64> {
+    match (base, head) {
+        (Value::Number(base), Value::Number(head)) if *base != 0.0 => Some((head - base) / base * 100.0),
+        _ => None,
+    }
+}
+
+// The metrics of one artifact as report lines, recording a finding for each
+// one that grew past its threshold
+fn compare(
+    artifact: &Artifact,
+    metrics: &[Metric],
+    base: Option<&str>,
+    head: &str,
+    output: &mut OutputBuffer,
+) -> Vec<String> {
+    let theme = output.theme;
+    let mut lines = Vec::new();
+    for metric in metrics {
+        let head = match read(head, metric.pointer.as_deref()) {
+            Ok(value) => value,
+            Err(e) => {
+                lines.push(format!("{}: {}", metric.name, e));
+                continue;
+            }
+        };
+        let Some(base) = base.and_then(|base| read(base, metric.pointer.as_deref()).ok()) else {
+            lines.push(format!("{}: {} (nothing to compare with)", metric.name, head.show()));
+            continue;
+        };
+        if base == head {
+            lines.push(format!("{}: {} (unchanged)", metric.name, head.show()));
+            continue;
+        }
+
+        let Some(change) = increase(&base, &head) else {
+            lines.push(format!("{}: {} -> {}", metric.name, base.show(), head.show()));
+            continue;
+        };
+        let over = metric.max_increase.is_some_and(|max| change > max);
+        let color = match change {
+            _ if over => Color::Red,
+            c if c < 0.0 => Color::Green,
+            _ => Color::Yellow,
+        };
+        lines.push(format!(
+            "{}: {} -> {} {}",
+            metric.name,
+            base.show(),
+            head.show(),
+            theme.paint(&format!("({:+.1}%)", change), color)
+        ));
+        if over {
+            let finding = Finding::new(
+                Severity::Medium,
+                format!(
+                    "{} of {} grew {:.1}%, more than the {}% allowed",
+                    metric.name,
+                    artifact.name,
+                    change,
+                    metric.max_increase.unwrap_or_default()
+                ),
+            );
+            let line = format!("{}:{}", artifact.name, metric.name);
+            output.findings.push(history::ReviewFinding::new("artifact", "", &line, &finding.message));
+            lines.push(format!("  {}", output.format_finding(&finding)));
+        }
+    }
+    lines
+}
+
+// Adds the Artifacts section with the configured values of the PR's CI
+// artifacts, next to the ones of its base
+pub async fn review(
+    owner: &str,
+    repo: &str,
+    details: &PullRequestDetail,
+    artifacts: &[Artifact],
+    github_token: Option<&str>,
+    output: &mut OutputBuffer,
+) {
+    if artifacts.is_empty() {
+        return;
+    }
+
+    output.add_header("Artifacts");
+    for artifact in artifacts {
+        output.add_section(&artifact.name);
+        info!("Fetching the {} artifact...", artifact.name);
+        let (head, base) = match find(owner, repo, &artifact.name, details, github_token).await {
+            Ok(found) => found,
+            Err(e) => {
+                warn!("Could not list the {} artifacts: {}", artifact.name, e);
+                output.add_box_content("Could not be listed, see the log");
+                continue;
+            }
+        };
+        let Some(head) = head else {
+            output.add_box_content("Not uploaded for the PR head yet");
+            continue;
+        };
+
+        let file = artifact.file.as_deref();
+        let head = match download(owner, repo, head, file, github_token).await {
+            Ok(content) => content,
+            Err(e) => {
+                warn!("Could not read the {} artifact: {}", artifact.name, e);
+                output.add_box_content("Could not be read, see the log");
+                continue;
+            }
+        };
+        let base = match base {
+            Some(id) => match download(owner, repo, id, file, github_token).await {
+                Ok(content) => Some(content),
+                Err(e) => {
+                    warn!("Could not read the base {} artifact: {}", artifact.name, e);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let whole = [Metric {
+            name: artifact.name.clone(),
+            ..Default::default()
+        }];
+        let metrics = if artifact.metrics.is_empty() { &whole[..] } else { &artifact.metrics };
+        let lines = compare(artifact, metrics, base.as_deref(), &head, output);
+        output.add_box_content(&lines.join("\n"));
+    }
+    output.add_diff_separator();
+}
@@ -0,0 +1,201 @@
+use crate::diff;
+use crate::github;
+use crate::terminal::Color;
+use crate::{OutputBuffer, PullRequestDetail, ReviewOptions, get_pr_details, send_prompt};
+use log::warn;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+#[derive(Debug)]
+struct Citation {
+    text: String,
+    file: String,
+    start: u32,
+    end: u32,
+}
+
+// Prompts stop taking patches past this, the files left out are named
+pub const MAX_DIFF_CHARS: usize = 60_000;
+
+// Renders every patch with new-file line numbers so the model can cite them,
+// and records which lines exist for verifying the citations afterwards.
+// Files that would take the diff past MAX_DIFF_CHARS are left out and listed
+pub fn numbered_diff(details: &PullRequestDetail) -> (String, HashMap<String, HashSet<u32>>, Vec<&str>) {
+    let mut rendered = String::new();
+    let mut lines_by_file: HashMap<String, HashSet<u32>> = HashMap::new();
+    let mut left_out = Vec::new();
+
+    for file in &details.files {
+        let Some(patch) = file.ai_patch() else {
+            continue;
+        };
+        let mut section = format!("### {}\n", file.filename);
+        let mut known = HashSet::new();
+        for hunk in diff::parse_hunks(&patch) {
+            let mut line_number = hunk.new_start;
+            for line in &hunk.lines {
+                if line.starts_with('-') || line.starts_with('\\') {
+                    section.push_str(&format!("{:>6} {}\n", "", line));
+                } else {
+                    known.insert(line_number);
+                    section.push_str(&format!("{:>6} {}\n", line_number, line));
+                    line_number += 1;
+                }
+            }
+        }
+        section.push('\n');
+        if rendered.len() + section.len() > MAX_DIFF_CHARS {
+            left_out.push(file.filename.as_str());
+            continue;
+        }
+        rendered.push_str(&section);
+        lines_by_file.insert(file.filename.clone(), known);
+    }
+    if !left_out.is_empty() {
+        rendered.push_str(&format!(
+            "The patches of {} were left out for size, say so when they matter.\n",
+            left_out.join(", ")
+        ));
+    }
+    (rendered, lines_by_file, left_out)
+}
+
+// Finds "path:12" and "path:12-20" references, the path being anything that
+// looks like a file (has a dot or a slash)
+fn citations(answer: &str) -> Vec<Citation> {
+    let mut found = Vec::new();
+    for word in answer.split(|c: char| c.is_whitespace() || "`()[],;\"'".contains(c)) {
+        let word = word.trim_end_matches(['.', ':']);
+        let Some((file, lines)) = word.rsplit_once(':') else {
+            continue;
+        };
+        if !(file.contains('.') || file.contains('/')) || file.contains("://") {
+            continue;
+        }
+        let (start, end) = match lines.split_once('-') {
+            Some((start, end)) => (start.parse::<u32>(), end.parse()),
+            None => (lines.parse(), lines.parse()),
+        };
+        if let (Ok(start), Ok(end)) = (start, end) {
+            // "a.rs:20-12" means the same lines as "a.rs:12-20"
+            found.push(Citation {
+                text: word.to_string(),
+                file: file.to_string(),
+                start: start.min(end),
+                end: start.max(end),
+            });
+        }
+    }
+    found
+}
+
+// Whether every line a citation names is in the diff. A range longer than
+// the lines known can't be, however large it is
+fn cited_lines_exist(citation: &Citation, known: &HashSet<u32>) -> bool {
+    ((citation.end - citation.start) as usize) < known.len()
+        && (citation.start..=citation.end).all(|l| known.contains(&l))
+}
+
+// The text with the mark after every occurrence of the citation that stands
+// on its own, not "a.rs:1" inside "a.rs:12" or "src/a.rs:1"
+fn mark_citation(text: &str, citation: &str, mark: &str) -> String {
+    let part_of_path = |c: char| c.is_alphanumeric() || "/._-".contains(c);
+    let mut marked = String::with_capacity(text.len());
+    let mut copied = 0;
+    for (at, _) in text.match_indices(citation) {
+        let end = at + citation.len();
+        let before = text[..at].chars().next_back();
+        let after = text[end..].chars().next();
+        if before.is_some_and(part_of_path) || after.is_some_and(|c| c.is_alphanumeric() || c == '-') {
+            continue;
+        }
+        marked.push_str(&text[copied..end]);
+        marked.push(' ');
+        marked.push_str(mark);
+        copied = end;
+    }
+    marked.push_str(&text[copied..]);
+    marked
+}
+
+pub async fn ask(
+    reference: &str,
+    question: &str,
+    github_token: Option<&str>,
+    options: &ReviewOptions,
+    output: &mut OutputBuffer,
+) -> Result<(), Box<dyn Error>> {
+    let (owner, repo, pr_number) = github::parse_pr_ref(reference)?;
+    let (details, _) = get_pr_details(pr_number, &owner, &repo, github_token).await?;
+    let (diff, known_lines, left_out) = numbered_diff(&details);
+    if known_lines.is_empty() {
+        return Err(format!("PR #{} has no diff to answer from", pr_number).into());
+    }
+    if !left_out.is_empty() {
+        warn!("Left {} out of the question for size", left_out.join(", "));
+    }
+
+    let prompt = format!(
+        "Answer the question about pull request \"{}\" using only the diff below. Do not rely on \
+        anything outside of it. If the diff doesn't contain enough information, say so.\n\
+        Back every claim with a citation in the form path/to/file.rs:LINE or path/to/file.rs:START-END, \
+        using the line numbers shown in the left column (lines of the file after the change). \
+        Removed lines have no number and can't be cited.\n\n\
+        Question: {}\n\n{}",
+        details.title, question, diff
+    );
+    let answer = send_prompt(prompt, options).await?;
+
+    let theme = output.theme;
+    let mut verified = Vec::new();
+    let mut unverified = Vec::new();
+    for citation in citations(&answer) {
+        let exists = known_lines
+            .get(&citation.file)
+            .is_some_and(|known| cited_lines_exist(&citation, known));
+        if exists {
+            verified.push(citation);
+        } else {
+            unverified.push(citation);
+        }
+    }
+
+    // Made-up references are flagged in place rather than silently trusted
+    let mut shown = answer.trim().to_string();
+    let mut flagged = HashSet::new();
+    let mark = theme.paint("[not in diff]", Color::Red);
+    for citation in unverified.iter().filter(|c| flagged.insert(c.text.clone())) {
+        shown = mark_citation(&shown, &citation.text, &mark);
+    }
+
+    output.add_header(&format!("{}/{}#{}: {}", owner, repo, pr_number, details.title));
+    output.add_section("Question");
+    output.add_box_content(question);
+    output.add_section("Answer");
+    output.add_box_content(&shown);
+    output.add_section("Citations");
+    if verified.is_empty() && unverified.is_empty() {
+        output.add_box_content("The answer doesn't cite any lines, treat it with care.");
+    } else {
+        let mut lines: Vec<String> = verified
+            .iter()
+            .map(|c| {
+                format!(
+                    "{} {}\n  {}",
+                    theme.paint("verified", Color::Green),
+                    c.text,
+                    github::diff_link(&details.html_url, &c.file, c.start, c.end)
+                )
+            })
+            .collect();
+        lines.extend(
+            unverified
+                .iter()
+                .map(|c| format!("{} {} (not in the diff)", theme.paint("unverified", Color::Red), c.text)),
+        );
+        lines.dedup();
+        output.add_box_content(&lines.join("\n"));
+    }
+    output.add_diff_separator();
+    Ok(())
+}
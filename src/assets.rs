@@ -0,0 +1,139 @@
+use crate::http;
+use crate::{ClaudeMessage, FileChange, MessageContent, OutputBuffer, PullRequestDetail, ReviewOptions, send_messages};
+use log::{info, warn};
+use serde_json::{Value, json};
+use std::error::Error;
+
+// Formats the AI can look at, by extension
+const IMAGE_TYPES: [(&str, &str); 5] = [
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+];
+
+fn media_type(path: &str) -> Option<&'static str> {
+    let extension = path.rsplit_once('.')?.1.to_lowercase();
+    IMAGE_TYPES.iter().find(|(ext, _)| *ext == extension).map(|(_, media)| *media)
+}
+
+// Base64 contents of an image at a commit. The contents API only encodes
+// files up to 1 MB, larger ones come back empty and are skipped
+async fn image_at(
+    owner: &str,
+    repo: &str,
+    path: &str,
+    sha: &str,
+    github_token: Option<&str>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let url = format!(
+        "{}/repos/{}/{}/contents/{}?ref={}",
+        http::github_api(), owner, repo, path, sha
+    );
+    let file: Value = serde_json::from_str(&http::GithubClient::new(github_token).get(&url).await?)?;
+    if file["encoding"] != "base64" {
+        return Ok(None);
+    }
+    let content: String = file["content"]
+        .as_str()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    Ok(Some(content).filter(|c| !c.is_empty()))
+}
+
+fn image_block(media_type: &str, data: String) -> Value {
+    json!({
+        "type": "image",
+        "source": { "type": "base64", "media_type": media_type, "data": data },
+    })
+}
+
+async fn describe(
+    owner: &str,
+    repo: &str,
+    details: &PullRequestDetail,
+    file: &FileChange,
+    media_type: &str,
+    github_token: Option<&str>,
+    options: &ReviewOptions,
+) -> Result<String, Box<dyn Error>> {
+    let before_path = file.previous_filename.as_deref().unwrap_or(&file.filename);
+    let before = match file.status.as_str() {
+        "added" => None,
+        _ => image_at(owner, repo, before_path, &details.base.sha, github_token).await?,
+    };
+    let (head_owner, head_repo) = details.head_repo(owner, repo);
+    let Some(after) = image_at(head_owner, head_repo, &file.filename, &details.head.sha, github_token).await? else {
+        return Ok("Too large to look at (over 1 MB)".to_string());
+    };
+
+    let mut blocks = Vec::new();
+    let prompt = match before {
+        Some(before) => {
+            blocks.push(image_block(media_type, before));
+            blocks.push(image_block(media_type, after));
+            "The first image is the version before this pull request, the second one the version after it. \
+            Describe the visual differences in two or three sentences, and point out anything that looks broken \
+            (cut off text, misalignment, wrong colors, artifacts)."
+        }
+        None => {
+            blocks.push(image_block(media_type, after));
+            "This image is added by a pull request. Describe what it shows in one or two sentences, and point out \
+            anything that looks broken (cut off text, misalignment, artifacts)."
+        }
+    };
+    let messages = vec![ClaudeMessage {
+        role: "user".to_string(),
+        content: MessageContent::Blocks(blocks),
+    }];
+    // Sent as a second user message, which the API merges with the images
+    Ok(send_messages(messages, prompt.to_string(), options, &mut |_| {}).await?.trim().to_string())
+}
+
+// Adds the Asset Changes section for the images a PR adds or modifies
+pub async fn review(
+    owner: &str,
+    repo: &str,
+    details: &PullRequestDetail,
+    github_token: Option<&str>,
+    options: &ReviewOptions,
+    output: &mut OutputBuffer,
+) {
+    let images: Vec<(&FileChange, &str)> = details
+        .files
+        .iter()
+        .filter_map(|f| media_type(&f.filename).map(|media| (f, media)))
+        .collect();
+    if images.is_empty() {
+        return;
+    }
+
+    output.add_header("Asset Changes");
+    for (file, media_type) in images {
+        output.add_section(&format!("{} ({})", file.filename, file.status));
+        if file.status == "removed" {
+            output.add_box_content("Removed");
+            continue;
+        }
+        if options.no_ai().is_some() {
+            output.add_box_content("Not described without the AI");
+            continue;
+        }
+        if file.withheld {
+            output.add_box_content("Kept from the AI by llm.deny_paths");
+            continue;
+        }
+        info!("Describing {}...", file.filename);
+        match describe(owner, repo, details, file, media_type, github_token, options).await {
+            Ok(description) => output.add_box_content(&description),
+            Err(e) => {
+                warn!("Could not describe {}: {}", file.filename, e);
+                output.add_box_content("Could not be described, see the log");
+            }
+        }
+    }
+    output.add_diff_separator();
+}
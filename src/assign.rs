@@ -0,0 +1,169 @@
+use crate::config::Config;
+use crate::http;
+use crate::state;
+use crate::OutputBuffer;
+use log::info;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+
+const ROTATION_FILE: &str = "rotation.json";
+
+#[derive(Deserialize, Debug)]
+struct Login {
+    login: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct PullRequest {
+    user: Login,
+    #[serde(default)]
+    requested_reviewers: Vec<Login>,
+    state: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchResult {
+    total_count: u64,
+}
+
+// Last reviewer picked per repository, so ties rotate instead of always
+// landing on the first name in the list
+fn load_rotation() -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let Some(path) = state::data_dir().map(|dir| dir.join(ROTATION_FILE)) else {
+        return Ok(HashMap::new());
+    };
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+fn save_rotation(rotation: &HashMap<String, String>) -> Result<(), Box<dyn Error>> {
+    let path = state::ensure_data_dir()?.join(ROTATION_FILE);
+    fs::write(path, serde_json::to_string_pretty(rotation)?)?;
+    Ok(())
+}
+
+async fn open_review_count(
+    login: &str,
+    owner: &str,
+    repo: &str,
+    github: &http::GithubClient,
+) -> Result<u64, Box<dyn Error>> {
+    let url = format!(
+        "{}/search/issues?q=is:pr+is:open+repo:{}/{}+review-requested:{}&per_page=1",
+        http::github_api(), owner, repo, login
+    );
+    let result: SearchResult = serde_json::from_str(&github.get(&url).await?)?;
+    Ok(result.total_count)
+}
+
+// Picks the reviewer with the fewest open review requests. Ties go to
+// whoever comes first in the rotation after the last reviewer picked
+fn pick<'a>(candidates: &[(&'a str, u64)], rotation: &[String], last: Option<&str>) -> Option<&'a str> {
+    let start = last
+        .and_then(|last| rotation.iter().position(|r| r == last))
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    let order = |login: &str| {
+        let index = rotation.iter().position(|r| r == login).unwrap_or(0);
+        (index + rotation.len() - start % rotation.len()) % rotation.len()
+    };
+
+    candidates
+        .iter()
+        .min_by_key(|(login, count)| (*count, order(login)))
+        .map(|(login, _)| *login)
+}
+
+pub async fn assign(
+    owner: &str,
+    repo: &str,
+    pr_number: u32,
+    github_token: Option<&str>,
+    config: &Config,
+    dry_run: bool,
+    output: &mut OutputBuffer,
+) -> Result<(), Box<dyn Error>> {
+    let key = format!("{}/{}", owner, repo);
+    let rotation = config
+        .reviewers
+        .get(&key)
+        .filter(|r| !r.is_empty())
+        .ok_or_else(|| format!("No reviewer rotation configured for {} ([reviewers] in the config)", key))?;
+    if github_token.is_none() && !dry_run {
+        return Err("GITHUB_TOKEN is required to request reviewers".into());
+    }
+
+    let url = format!("{}/repos/{}/{}/pulls/{}", http::github_api(), owner, repo, pr_number);
+    let github = http::GithubClient::new(github_token);
+    let pr: PullRequest = serde_json::from_str(&github.get(&url).await?)?;
+
+    output.add_header(&format!("Reviewer assignment for {}#{}", key, pr_number));
+
+    if pr.state != "open" {
+        output.add_box_content(&format!("PR #{} is {}, nothing to assign.", pr_number, pr.state));
+        output.add_diff_separator();
+        return Ok(());
+    }
+    if let Some(existing) = pr
+        .requested_reviewers
+        .iter()
+        .find(|r| rotation.contains(&r.login))
+    {
+        output.add_box_content(&format!(
+            "{} from the rotation is already requested, leaving the PR as is.",
+            existing.login
+        ));
+        output.add_diff_separator();
+        return Ok(());
+    }
+
+    info!("Counting open review requests...");
+    let mut candidates = Vec::new();
+    let mut lines = Vec::new();
+    for login in rotation {
+        // Nobody reviews their own PR
+        if login.eq_ignore_ascii_case(&pr.user.login) {
+            lines.push(format!("{:<20} author, skipped", login));
+            continue;
+        }
+        let count = open_review_count(login, owner, repo, &github).await?;
+        lines.push(format!("{:<20} {} open review request(s)", login, count));
+        candidates.push((login.as_str(), count));
+    }
+
+    let _lock = state::lock()?;
+    let mut last_picked = load_rotation()?;
+    let Some(reviewer) = pick(&candidates, rotation, last_picked.get(&key).map(String::as_str)) else {
+        lines.push(String::new());
+        lines.push("No eligible reviewer in the rotation.".to_string());
+        output.add_box_content(&lines.join("\n"));
+        output.add_diff_separator();
+        return Ok(());
+    };
+
+    lines.push(String::new());
+    if dry_run {
+        lines.push(format!("Would request a review from {} (dry run)", reviewer));
+    } else {
+        github
+            .post(&format!("{}/requested_reviewers", url), &serde_json::json!({ "reviewers": [reviewer] }))
+            .await?;
+        // Nothing was requested, the rotation stays where it was
+        if http::read_only() {
+            lines.push(format!("Read-only: a review from {} was not requested", reviewer));
+            output.add_box_content(&lines.join("\n"));
+            output.add_diff_separator();
+            return Ok(());
+        }
+        last_picked.insert(key, reviewer.to_string());
+        save_rotation(&last_picked)?;
+        lines.push(format!("Requested a review from {}", reviewer));
+    }
+    output.add_box_content(&lines.join("\n"));
+    output.add_diff_separator();
+    Ok(())
+}
@@ -0,0 +1,135 @@
+use crate::OutputBuffer;
+use crate::http;
+use crate::state;
+use crate::terminal::Color;
+use crate::usage;
+use chrono::{DateTime, Duration, Local};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+const AUDIT_FILE: &str = "audit.jsonl";
+
+// Decisions and writes rubber made on someone's behalf, appended and never rewritten
+#[derive(Serialize, Deserialize, Debug)]
+struct AuditRecord {
+    timestamp: u64,
+    action: String,
+    target: String,
+    details: Value,
+}
+
+pub fn record(action: &str, target: &str, details: &Value) -> Result<(), Box<dyn Error>> {
+    let record = AuditRecord {
+        timestamp: usage::now(),
+        action: action.to_string(),
+        target: target.to_string(),
+        details: details.clone(),
+    };
+
+    let _lock = state::lock()?;
+    let path = state::ensure_data_dir()?.join(AUDIT_FILE);
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(&record)?)?;
+    Ok(())
+}
+
+// A request that changed something: posted, deleted or mutated. The payload
+// is kept as a digest, enough to match it with a --debug-http capture without
+// copying comment bodies into the log
+pub fn record_write(
+    method: &str,
+    target: &str,
+    payload: Option<&str>,
+    result: &Result<String, Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let mut details = json!({ "method": method });
+    if http::read_only() {
+        details["read_only"] = json!(true);
+    }
+    if let Some(payload) = payload {
+        details["payload_sha256"] = json!(format!("{:x}", Sha256::digest(payload.as_bytes())));
+    }
+    match result {
+        Ok(response) => {
+            // Most GitHub writes answer with the object they created
+            let response: Value = serde_json::from_str(response).unwrap_or_default();
+            details["ok"] = json!(true);
+            if let Some(url) = response["html_url"].as_str() {
+                details["url"] = json!(url);
+            }
+        }
+        Err(e) => {
+            details["ok"] = json!(false);
+            details["error"] = json!(e.to_string());
+        }
+    }
+    record("write", target, &details)
+}
+
+fn summary(record: &AuditRecord) -> String {
+    let details = &record.details;
+    let mut parts = Vec::new();
+    for key in ["method", "url", "error", "decision", "result"] {
+        if let Some(value) = details[key].as_str() {
+            parts.push(value.to_string());
+        }
+    }
+    if let Some(digest) = details["payload_sha256"].as_str() {
+        parts.push(format!("payload {}", &digest[..digest.len().min(12)]));
+    }
+    if details["read_only"] == true {
+        parts.push("(read-only, not sent)".to_string());
+    }
+    parts.join(" ")
+}
+
+// Lists the audit log, newest last. `action` and `target` keep the records
+// matching them
+pub fn show(
+    since: Option<Duration>,
+    action: Option<&str>,
+    target: Option<&str>,
+    output: &mut OutputBuffer,
+) -> Result<(), Box<dyn Error>> {
+    let content = {
+        let _lock = state::lock_shared()?;
+        match state::data_dir().map(|dir| dir.join(AUDIT_FILE)) {
+            Some(path) if path.exists() => fs::read_to_string(path)?,
+            _ => String::new(),
+        }
+    };
+    let cutoff = since.map(|since| usage::now().saturating_sub(since.num_seconds() as u64));
+    let records: Vec<AuditRecord> = content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .filter(|r: &AuditRecord| cutoff.is_none_or(|cutoff| r.timestamp >= cutoff))
+        .filter(|r| action.is_none_or(|action| r.action == action))
+        .filter(|r| target.is_none_or(|target| r.target.contains(target)))
+        .collect();
+
+    let theme = output.theme;
+    output.add_header("Audit Log");
+    if records.is_empty() {
+        output.add_box_content("No matching records");
+        output.add_diff_separator();
+        return Ok(());
+    }
+    let lines: Vec<String> = records
+        .iter()
+        .map(|r| {
+            let time = DateTime::from_timestamp(r.timestamp as i64, 0)
+                .map(|t| t.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_default();
+            let color = if r.details["ok"] == false { Color::Red } else { Color::Cyan };
+            let target = r.target.trim_start_matches(http::github_api()).trim_start_matches('/');
+            format!("{}  {}  {}  {}", time, theme.paint(&r.action, color), target, summary(r))
+        })
+        .collect();
+    output.add_box_content(&lines.join("\n"));
+    output.add_diff_separator();
+    Ok(())
+}
@@ -0,0 +1,249 @@
+use crate::audit;
+use crate::config::Config;
+use crate::http;
+use crate::risk;
+use crate::slash;
+use crate::terminal::Color;
+use crate::{FileChange, OutputBuffer};
+use log::info;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::error::Error;
+
+// Pinned to the head the gates were checked on: GitHub refuses it when a push
+// came in meanwhile, instead of merging a commit nobody looked at
+const ENABLE_AUTO_MERGE: &str = "mutation($id: ID!, $method: PullRequestMergeMethod!, $sha: GitObjectID!) {
+  enablePullRequestAutoMerge(input: { pullRequestId: $id, mergeMethod: $method, expectedHeadOid: $sha }) {
+    pullRequest { autoMergeRequest { enabledAt } }
+  }
+}";
+
+struct Gate {
+    name: &'static str,
+    passed: bool,
+    detail: String,
+}
+
+async fn ci_gate(base: &str, sha: &str, github: &http::GithubClient) -> Result<Gate, Box<dyn Error>> {
+    let runs: Vec<Value> =
+        serde_json::from_str(&github.get_all(&format!("{}/commits/{}/check-runs", base, sha)).await?)?;
+    let statuses: Value = serde_json::from_str(&github.get(&format!("{}/commits/{}/status", base, sha)).await?)?;
+
+    let mut problems = Vec::new();
+    for run in &runs {
+        let name = run["name"].as_str().unwrap_or("check");
+        match (run["status"].as_str(), run["conclusion"].as_str()) {
+            (Some("completed"), Some("success" | "neutral" | "skipped")) => {}
+            (Some("completed"), conclusion) => {
+                problems.push(format!("{} {}", name, conclusion.unwrap_or("failed")))
+            }
+            _ => problems.push(format!("{} still running", name)),
+        }
+    }
+
+    let status_count = statuses["total_count"].as_u64().unwrap_or(0);
+    if status_count > 0 && statuses["state"] != "success" {
+        problems.push(format!(
+            "commit status is {}",
+            statuses["state"].as_str().unwrap_or("unknown")
+        ));
+    }
+
+    let total = runs.len() as u64 + status_count;
+    Ok(if total == 0 {
+        // Green by absence is not green
+        Gate {
+            name: "CI",
+            passed: false,
+            detail: "no checks reported for the head commit".to_string(),
+        }
+    } else if problems.is_empty() {
+        Gate {
+            name: "CI",
+            passed: true,
+            detail: format!("{} check(s) passed", total),
+        }
+    } else {
+        Gate {
+            name: "CI",
+            passed: false,
+            detail: problems.join(", "),
+        }
+    })
+}
+
+async fn approvals_gate(
+    base: &str,
+    pr_number: u32,
+    sha: &str,
+    required: usize,
+    github: &http::GithubClient,
+) -> Result<Gate, Box<dyn Error>> {
+    let reviews: Vec<Value> =
+        serde_json::from_str(&github.get_all(&format!("{}/pulls/{}/reviews", base, pr_number)).await?)?;
+
+    // Only each reviewer's latest verdict counts, comments don't change it.
+    // Drive-by reviews don't count either way, as for GitHub's required reviews
+    let mut latest: HashMap<&str, &Value> = HashMap::new();
+    for review in &reviews {
+        let state = review["state"].as_str().unwrap_or_default();
+        let association = review["author_association"].as_str().unwrap_or_default();
+        if matches!(state, "APPROVED" | "CHANGES_REQUESTED" | "DISMISSED")
+            && slash::TRUSTED_ASSOCIATIONS.contains(&association)
+            && let Some(login) = review["user"]["login"].as_str()
+        {
+            latest.insert(login, review);
+        }
+    }
+
+    let blocking: Vec<&str> = latest
+        .iter()
+        .filter(|(_, r)| r["state"] == "CHANGES_REQUESTED")
+        .map(|(login, _)| *login)
+        .collect();
+    // Approvals given before the last push don't cover the code being merged
+    let approvals = latest
+        .values()
+        .filter(|r| r["state"] == "APPROVED" && r["commit_id"] == sha)
+        .count();
+
+    Ok(if !blocking.is_empty() {
+        Gate {
+            name: "Approvals",
+            passed: false,
+            detail: format!("changes requested by {}", blocking.join(", ")),
+        }
+    } else {
+        Gate {
+            name: "Approvals",
+            passed: approvals >= required,
+            detail: format!("{}/{} approval(s) on the head commit", approvals, required),
+        }
+    })
+}
+
+pub async fn run(
+    owner: &str,
+    repo: &str,
+    pr_number: u32,
+    github_token: Option<&str>,
+    config: &Config,
+    dry_run: bool,
+    output: &mut OutputBuffer,
+) -> Result<(), Box<dyn Error>> {
+    let key = format!("{}/{}", owner, repo);
+    let policy = &config.automerge;
+    if !policy.repos.contains(&key) {
+        return Err(format!("Auto-merge is not enabled for {} ([automerge] repos in the config)", key).into());
+    }
+    if github_token.is_none() {
+        return Err("GITHUB_TOKEN is required to check auto-merge eligibility".into());
+    }
+    let method = policy.merge_method.to_uppercase();
+    if !matches!(method.as_str(), "MERGE" | "SQUASH" | "REBASE") {
+        return Err(format!("Invalid merge_method {}, expected MERGE, SQUASH or REBASE", policy.merge_method).into());
+    }
+
+    info!("Checking auto-merge eligibility for PR #{}...", pr_number);
+    let base = format!("{}/repos/{}/{}", http::github_api(), owner, repo);
+    let github = http::GithubClient::new(github_token);
+    let pr: Value = serde_json::from_str(&github.get(&format!("{}/pulls/{}", base, pr_number)).await?)?;
+    let files: Vec<FileChange> =
+        serde_json::from_str(&github.get_all(&format!("{}/pulls/{}/files", base, pr_number)).await?)?;
+    let sha = pr["head"]["sha"].as_str().ok_or("PR has no head commit")?;
+
+    let risk = risk::score(&files, &config.policy);
+    let mut gates = vec![
+        Gate {
+            name: "State",
+            passed: pr["state"] == "open" && pr["draft"] != true,
+            detail: if pr["draft"] == true {
+                "draft".to_string()
+            } else {
+                pr["state"].as_str().unwrap_or("unknown").to_string()
+            },
+        },
+        // GitHub works mergeability out in the background, null until it has
+        Gate {
+            name: "Conflicts",
+            passed: pr["mergeable"] == true,
+            detail: if pr["mergeable"].is_null() {
+                "not computed yet, try again shortly".to_string()
+            } else {
+                format!("mergeable state: {}", pr["mergeable_state"].as_str().unwrap_or("unknown"))
+            },
+        },
+        ci_gate(&base, sha, &github).await?,
+        approvals_gate(&base, pr_number, sha, policy.required_approvals, &github).await?,
+        Gate {
+            name: "Risk",
+            passed: risk.score <= policy.max_risk,
+            detail: format!("score {} (max {})", risk.score, policy.max_risk),
+        },
+    ];
+    if pr["auto_merge"].is_object() {
+        gates.push(Gate {
+            name: "Auto-merge",
+            passed: false,
+            detail: "already enabled".to_string(),
+        });
+    }
+    let eligible = gates.iter().all(|g| g.passed);
+
+    output.add_header(&format!("Auto-merge for {}#{}", key, pr_number));
+    output.add_line(output.theme.glyphs().vertical);
+    for gate in &gates {
+        let (label, color) = if gate.passed {
+            ("pass", Color::Green)
+        } else {
+            ("fail", Color::Red)
+        };
+        output.add_row(&format!(
+            "{} {:<10} {}",
+            output.theme.paint(label, color),
+            gate.name,
+            gate.detail
+        ));
+    }
+    for reason in &risk.reasons {
+        output.add_row(&format!("     {:<10} {}", "", reason));
+    }
+    output.add_line(output.theme.glyphs().vertical);
+
+    // A failed mutation is recorded too before it's returned
+    let (decision, failure) = if !eligible {
+        ("not eligible".to_string(), None)
+    } else if dry_run {
+        ("eligible (dry run)".to_string(), None)
+    } else {
+        let variables = json!({ "id": pr["node_id"], "method": method, "sha": sha });
+        let enabled = github.graphql(ENABLE_AUTO_MERGE, variables, &key).await;
+        match enabled {
+            Ok(_) if http::read_only() => ("eligible (read-only, not enabled)".to_string(), None),
+            Ok(_) => ("auto-merge enabled".to_string(), None),
+            Err(e) => (format!("failed: {}", e), Some(e)),
+        }
+    };
+    output.add_row(&format!("Result: {}", decision));
+    output.add_diff_separator();
+
+    let gate_results: Vec<Value> = gates
+        .iter()
+        .map(|g| json!({ "gate": g.name, "passed": g.passed, "detail": g.detail }))
+        .collect();
+    audit::record(
+        "automerge",
+        &format!("{}#{}", key, pr_number),
+        &json!({
+            "head": sha,
+            "decision": decision,
+            "merge_method": method,
+            "risk": risk.score,
+            "gates": gate_results,
+        }),
+    )?;
+    match failure {
+        Some(e) => Err(format!("Could not enable auto-merge: {}", e).into()),
+        None => Ok(()),
+    }
+}
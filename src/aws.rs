@@ -0,0 +1,203 @@
+use crate::http;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+#[derive(Clone)]
+pub struct Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    // Only temporary credentials (SSO, assumed roles) have one
+    pub session_token: Option<String>,
+}
+
+// What a credential_process prints
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ProcessCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+fn profile() -> String {
+    env::var("AWS_PROFILE").ok().filter(|p| !p.is_empty()).unwrap_or_else(|| "default".to_string())
+}
+
+fn aws_file(variable: &str, name: &str) -> Option<PathBuf> {
+    env::var(variable)
+        .ok()
+        .map(PathBuf::from)
+        .or_else(|| env::var("HOME").ok().map(|home| PathBuf::from(home).join(".aws").join(name)))
+}
+
+// The keys of one [section] of an AWS ini file, empty when there's no such file or section
+fn section(path: Option<PathBuf>, name: &str) -> HashMap<String, String> {
+    let Some(text) = path.and_then(|path| fs::read_to_string(path).ok()) else {
+        return HashMap::new();
+    };
+    let mut keys = HashMap::new();
+    let mut inside = false;
+    for line in text.lines().map(str::trim) {
+        if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            inside = header.trim() == name;
+        } else if inside
+            && !line.starts_with('#')
+            && !line.starts_with(';')
+            && let Some((key, value)) = line.split_once('=')
+        {
+            keys.insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+    keys
+}
+
+// ~/.aws/config names every profile but the default one "profile <name>"
+fn config_section() -> HashMap<String, String> {
+    let profile = profile();
+    let name = if profile == "default" { profile } else { format!("profile {}", profile) };
+    section(aws_file("AWS_CONFIG_FILE", "config"), &name)
+}
+
+fn from_keys(keys: &HashMap<String, String>) -> Option<Credentials> {
+    Some(Credentials {
+        access_key_id: keys.get("aws_access_key_id")?.clone(),
+        secret_access_key: keys.get("aws_secret_access_key")?.clone(),
+        session_token: keys.get("aws_session_token").cloned(),
+    })
+}
+
+fn from_process(command: &str) -> Result<Credentials, Box<dyn Error>> {
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+    let output = Command::new(shell).args([flag, command]).stderr(Stdio::inherit()).output()?;
+    if !output.status.success() {
+        return Err(format!("credential_process failed ({})", output.status).into());
+    }
+    let printed: ProcessCredentials = serde_json::from_slice(&output.stdout)?;
+    Ok(Credentials {
+        access_key_id: printed.access_key_id,
+        secret_access_key: printed.secret_access_key,
+        session_token: printed.session_token,
+    })
+}
+
+// The part of the standard chain that doesn't call AWS: the environment, then
+// the profile's keys in ~/.aws/credentials, then its credential_process or
+// keys in ~/.aws/config. Web identity, container and instance credentials and
+// sso_session profiles aren't read, they work through a credential_process
+// of `aws configure export-credentials --format process`
+pub fn credentials() -> Result<Credentials, Box<dyn Error>> {
+    let credentials = if let (Ok(access_key_id), Ok(secret_access_key)) =
+        (env::var("AWS_ACCESS_KEY_ID"), env::var("AWS_SECRET_ACCESS_KEY"))
+    {
+        Credentials {
+            access_key_id,
+            secret_access_key,
+            session_token: env::var("AWS_SESSION_TOKEN").ok().filter(|t| !t.is_empty()),
+        }
+    } else if let Some(credentials) = from_keys(&section(aws_file("AWS_SHARED_CREDENTIALS_FILE", "credentials"), &profile())) {
+        credentials
+    } else {
+        let config = config_section();
+        match config.get("credential_process") {
+            Some(command) => from_process(command)?,
+            None => from_keys(&config).ok_or_else(|| {
+                format!(
+                    "No AWS credentials for profile {}: only AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY, the \
+                    profile's keys and its credential_process are read. For SSO, web identity, ECS or EC2 \
+                    instance roles, set credential_process = aws configure export-credentials --profile {} \
+                    --format process",
+                    profile(),
+                    profile()
+                )
+            })?,
+        }
+    };
+    http::register_secret(credentials.secret_access_key.clone());
+    if let Some(token) = &credentials.session_token {
+        http::register_secret(token.clone());
+    }
+    Ok(credentials)
+}
+
+pub fn region() -> Option<String> {
+    env::var("AWS_REGION")
+        .or_else(|_| env::var("AWS_DEFAULT_REGION"))
+        .ok()
+        .filter(|r| !r.is_empty())
+        .or_else(|| config_section().remove("region"))
+}
+
+// Percent-encodes everything but the characters SigV4 leaves alone
+pub fn uri_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+// Signature Version 4 headers for a POST of a JSON body to host + path, the
+// path already percent-encoded as it's sent
+pub fn sign(
+    credentials: &Credentials,
+    region: &str,
+    service: &str,
+    host: &str,
+    path: &str,
+    body: &str,
+    now: DateTime<Utc>,
+) -> Vec<(&'static str, String)> {
+    let timestamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = &timestamp[..8];
+    let payload_hash = format!("{:x}", Sha256::digest(body.as_bytes()));
+
+    // Sorted by name, as the canonical request wants them
+    let mut headers = vec![("content-type", "application/json".to_string()), ("host", host.to_string())];
+    headers.push(("x-amz-date", timestamp.clone()));
+    if let Some(token) = &credentials.session_token {
+        headers.push(("x-amz-security-token", token.clone()));
+    }
+    let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(";");
+    let canonical_headers: String = headers.iter().map(|(name, value)| format!("{}:{}\n", name, value.trim())).collect();
+    // Services other than S3 encode the path a second time
+    let canonical_path = path.split('/').map(uri_encode).collect::<Vec<_>>().join("/");
+    let canonical_request =
+        format!("POST\n{}\n\n{}\n{}\n{}", canonical_path, canonical_headers, signed_headers, payload_hash);
+
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{:x}",
+        timestamp,
+        scope,
+        Sha256::digest(canonical_request.as_bytes())
+    );
+    let key = [date, region, service, "aws4_request"]
+        .iter()
+        .fold(format!("AWS4{}", credentials.secret_access_key).into_bytes(), |key, part| hmac(&key, part));
+    let signature: String = hmac(&key, &string_to_sign).iter().map(|b| format!("{:02x}", b)).collect();
+
+    headers.retain(|(name, _)| *name != "host" && *name != "content-type");
+    headers.push((
+        "authorization",
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            credentials.access_key_id, scope, signed_headers, signature
+        ),
+    ));
+    headers
+}
@@ -0,0 +1,120 @@
+use crate::findings::{Finding, Severity};
+use serde::Deserialize;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+// Regressions at least this large (as a fraction) are reported as high severity
+const HIGH_REGRESSION: f64 = 0.2;
+
+#[derive(Deserialize, Debug)]
+struct Estimate {
+    estimate: f64,
+    unit: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChangeDetails {
+    mean: Estimate,
+    change: String,
+}
+
+// One `benchmark-complete` message from `cargo criterion --message-format=json`
+#[derive(Deserialize, Debug)]
+struct BenchmarkMessage {
+    reason: String,
+    id: String,
+    mean: Option<Estimate>,
+    change: Option<ChangeDetails>,
+}
+
+#[derive(Debug)]
+pub struct BenchChange {
+    pub id: String,
+    // Relative change of the mean against the baseline, e.g. 0.05 for +5%
+    pub change: f64,
+    pub regressed: bool,
+    pub mean: Option<String>,
+}
+
+impl BenchChange {
+    pub fn to_finding(&self) -> Finding {
+        let mean = self
+            .mean
+            .as_ref()
+            .map(|m| format!(" (mean {})", m))
+            .unwrap_or_default();
+
+        if self.regressed {
+            let severity = if self.change >= HIGH_REGRESSION {
+                Severity::High
+            } else {
+                Severity::Medium
+            };
+            Finding::new(
+                severity,
+                format!("{} regressed by {:+.1}%{}", self.id, self.change * 100.0, mean),
+            )
+        } else {
+            Finding::new(
+                Severity::Info,
+                format!("{} improved by {:+.1}%{}", self.id, self.change * 100.0, mean),
+            )
+        }
+    }
+}
+
+fn parse_message(value: serde_json::Value, changes: &mut Vec<BenchChange>) {
+    let Ok(message) = serde_json::from_value::<BenchmarkMessage>(value) else {
+        return;
+    };
+    if message.reason != "benchmark-complete" {
+        return;
+    }
+    let Some(change) = message.change else {
+        return;
+    };
+
+    // Criterion already applies its noise threshold, only keep significant changes
+    let regressed = match change.change.as_str() {
+        "Regressed" => true,
+        "Improved" => false,
+        _ => return,
+    };
+
+    changes.push(BenchChange {
+        id: message.id,
+        change: change.mean.estimate,
+        regressed,
+        mean: message
+            .mean
+            .map(|m| format!("{:.2} {}", m.estimate, m.unit)),
+    });
+}
+
+// Accepts the JSON lines emitted by cargo-criterion as well as a JSON array of
+// the same messages.
+pub fn load(path: &Path) -> Result<Vec<BenchChange>, Box<dyn Error>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let mut changes = Vec::new();
+
+    if content.trim_start().starts_with('[') {
+        let messages: Vec<serde_json::Value> = serde_json::from_str(&content)?;
+        for message in messages {
+            parse_message(message, &mut changes);
+        }
+    } else {
+        for line in content.lines().filter(|l| !l.trim().is_empty()) {
+            parse_message(serde_json::from_str(line)?, &mut changes);
+        }
+    }
+
+    // Worst regressions first
+    changes.sort_by(|a, b| {
+        b.regressed
+            .cmp(&a.regressed)
+            .then(b.change.abs().total_cmp(&a.change.abs()))
+    });
+    Ok(changes)
+}
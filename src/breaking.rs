@@ -0,0 +1,362 @@
+use crate::findings::{Finding, Severity};
+use crate::{OutputBuffer, PullRequestDetail, ReviewOptions, history, send_prompt};
+use log::{info, warn};
+use std::collections::HashMap;
+
+// Patches beyond this are left out of the prompt, their candidates are
+// judged by name only
+const MAX_PATCH_CHARS: usize = 60_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Surface {
+    CliFlag,
+    EnvVar,
+    ConfigKey,
+    Endpoint,
+    Database,
+}
+
+impl Surface {
+    fn label(self) -> &'static str {
+        match self {
+            Surface::CliFlag => "CLI flag",
+            Surface::EnvVar => "environment variable",
+            Surface::ConfigKey => "config key",
+            Surface::Endpoint => "HTTP endpoint",
+            Surface::Database => "database schema",
+        }
+    }
+}
+
+// Something users may depend on that the diff removes or changes
+#[derive(Debug)]
+struct Candidate {
+    surface: Surface,
+    file: String,
+    name: String,
+}
+
+impl Candidate {
+    fn shown(&self) -> String {
+        match self.surface {
+            Surface::CliFlag if self.name.len() > 1 => format!("--{}", self.name),
+            Surface::CliFlag => format!("-{}", self.name),
+            _ => self.name.clone(),
+        }
+    }
+}
+
+// Calls that define a flag, read a variable or declare a route, followed by
+// its name in quotes
+const FLAG_CALLS: [&str; 8] = [
+    "add_argument(",
+    ".option(",
+    ".requiredOption(",
+    "flag.String(",
+    "flag.Bool(",
+    "flag.Int(",
+    "flag.Duration(",
+    "long = ",
+];
+const ENV_CALLS: [&str; 9] = [
+    "env::var(",
+    "env::var_os(",
+    "os.getenv(",
+    "os.environ.get(",
+    "os.environ[",
+    "os.Getenv(",
+    "os.LookupEnv(",
+    "ENV.fetch(",
+    "ENV[",
+];
+const ROUTE_CALLS: [&str; 13] = [
+    ".route(",
+    "#[get(",
+    "#[post(",
+    "#[put(",
+    "#[patch(",
+    "#[delete(",
+    "@app.route(",
+    "@app.get(",
+    "@app.post(",
+    "@router.get(",
+    "@router.post(",
+    "HandleFunc(",
+    "Handle(",
+];
+// Express style routers: app.get('/users', ...)
+const ROUTE_OBJECTS: [&str; 2] = ["app.", "router."];
+const ROUTE_VERBS: [&str; 5] = ["get(", "post(", "put(", "patch(", "delete("];
+// Statements of a migration that break existing readers or writers
+const DESTRUCTIVE_SQL: [&str; 5] = ["DROP TABLE", "DROP COLUMN", "RENAME COLUMN", "RENAME TO", "SET NOT NULL"];
+
+// Files changelogs and migration notes usually live in
+const NOTE_FILES: [&str; 5] = ["changelog", "changes", "migration", "upgrading", "release_notes"];
+
+// The first quoted string after the needle
+fn quoted_after<'a>(line: &'a str, needle: &str) -> Option<&'a str> {
+    let rest = &line[line.find(needle)? + needle.len()..];
+    let rest = rest.trim_start();
+    let quote = rest.chars().next().filter(|c| matches!(c, '"' | '\'' | '`'))?;
+    let value = &rest[1..];
+    value.find(quote).map(|end| &value[..end])
+}
+
+fn is_config_file(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path).to_lowercase();
+    let config_like = ["config", "settings", "default"].iter().any(|word| name.contains(word))
+        || name.ends_with(".example")
+        || name.ends_with(".sample");
+    let format = [".toml", ".yaml", ".yml", ".json", ".ini", ".env", ".example", ".sample"]
+        .iter()
+        .any(|extension| name.contains(extension));
+    config_like && format
+}
+
+fn is_migration(path: &str) -> bool {
+    path.ends_with(".sql") || path.to_lowercase().contains("migration")
+}
+
+// The key a line of a TOML, YAML, JSON, INI or .env file sets
+fn config_key(line: &str) -> Option<&str> {
+    let line = line.trim();
+    if line.starts_with(['#', ';', '[', '{', '}', '-']) || line.starts_with("//") {
+        return None;
+    }
+    let end = line.find(['=', ':'])?;
+    let key = line[..end].trim().trim_matches('"');
+    let valid = !key.is_empty() && key.chars().all(|c| c.is_alphanumeric() || "_-.".contains(c));
+    valid.then_some(key)
+}
+
+// Names defined by a clap field: `#[arg(long)]` over `dry_run: bool` is --dry-run
+fn clap_flag(attribute: &str, field: Option<&str>) -> Option<String> {
+    let attribute = attribute.trim();
+    if !(attribute.starts_with("#[arg(") || attribute.starts_with("#[clap(")) || !attribute.contains("long") {
+        return None;
+    }
+    if let Some(name) = quoted_after(attribute, "long = ") {
+        return Some(name.to_string());
+    }
+    let field = field?.trim().trim_start_matches("pub ");
+    let (name, _) = field.split_once(':')?;
+    Some(name.trim().replace('_', "-"))
+}
+
+fn candidates_in(file: &str, patch: &str) -> Vec<(Surface, String)> {
+    let removed: Vec<&str> = patch
+        .lines()
+        .filter(|l| l.starts_with('-') && !l.starts_with("---"))
+        .map(|l| &l[1..])
+        .collect();
+    let mut found = Vec::new();
+
+    for (i, line) in removed.iter().enumerate() {
+        if let Some(flag) = clap_flag(line, removed.get(i + 1).copied()) {
+            found.push((Surface::CliFlag, flag));
+        } else if let Some(flag) = FLAG_CALLS.iter().find_map(|call| quoted_after(line, call)) {
+            found.push((Surface::CliFlag, flag.trim_start_matches('-').to_string()));
+        }
+        if let Some(variable) = ENV_CALLS.iter().find_map(|call| quoted_after(line, call)) {
+            found.push((Surface::EnvVar, variable.to_string()));
+        } else if let Some(rest) = line.split("process.env.").nth(1) {
+            let variable: String = rest.chars().take_while(|c| c.is_alphanumeric() || *c == '_').collect();
+            if !variable.is_empty() {
+                found.push((Surface::EnvVar, variable));
+            }
+        }
+        let route = ROUTE_CALLS.iter().find_map(|call| quoted_after(line, call)).or_else(|| {
+            ROUTE_OBJECTS.iter().find_map(|object| {
+                ROUTE_VERBS.iter().find_map(|verb| quoted_after(line, &format!("{}{}", object, verb)))
+            })
+        });
+        if let Some(route) = route.filter(|r| r.starts_with('/')) {
+            found.push((Surface::Endpoint, route.to_string()));
+        }
+        if let Some(key) = quoted_after(line, "#[serde(rename = ") {
+            found.push((Surface::ConfigKey, key.to_string()));
+        } else if is_config_file(file)
+            && let Some(key) = config_key(line)
+        {
+            found.push((Surface::ConfigKey, key.to_string()));
+        }
+    }
+
+    if is_migration(file) {
+        for line in patch.lines().filter(|l| l.starts_with('+') && !l.starts_with("+++")) {
+            let statement = line[1..].trim();
+            let upper = statement.to_uppercase();
+            if DESTRUCTIVE_SQL.iter().any(|keyword| upper.contains(keyword)) {
+                found.push((Surface::Database, statement.chars().take(100).collect()));
+            }
+        }
+    }
+    found
+}
+
+// What the diff removes that users may rely on. A name still present in the
+// added lines was moved or reworded, not removed
+fn detect(details: &PullRequestDetail) -> Vec<Candidate> {
+    let added: String = details
+        .files
+        .iter()
+        .filter_map(|f| f.patch.as_deref())
+        .flat_map(|patch| patch.lines().filter(|l| l.starts_with('+')))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut candidates: Vec<Candidate> = Vec::new();
+    for file in &details.files {
+        let Some(patch) = &file.patch else {
+            continue;
+        };
+        for (surface, name) in candidates_in(&file.filename, patch) {
+            let still_there = match surface {
+                Surface::Database => false,
+                // --dry-run is declared as dry_run in clap
+                Surface::CliFlag => added.contains(&name) || added.contains(&name.replace('-', "_")),
+                _ => added.contains(&name),
+            };
+            let seen = candidates.iter().any(|c| c.surface == surface && c.name == name);
+            if !still_there && !seen {
+                candidates.push(Candidate {
+                    surface,
+                    file: file.filename.clone(),
+                    name,
+                });
+            }
+        }
+    }
+    candidates
+}
+
+// The model sees the patches of the files involved and answers for each
+// candidate, by its index: the note users would need when it's breaking,
+// None when it's fine. Candidates it didn't answer for are left out. None
+// when it couldn't be asked
+async fn confirm(
+    details: &PullRequestDetail,
+    candidates: &[&Candidate],
+    options: &ReviewOptions,
+) -> Option<HashMap<usize, Option<String>>> {
+    let listed: Vec<String> = candidates
+        .iter()
+        .enumerate()
+        .map(|(i, c)| format!("{}. {} `{}` in {}", i + 1, c.surface.label(), c.shown(), c.file))
+        .collect();
+    let mut patches = String::new();
+    let mut left_out = Vec::new();
+    for file in details.files.iter().filter(|f| candidates.iter().any(|c| c.file == f.filename)) {
+        if let Some(patch) = file.ai_patch() {
+            let section = format!("### {}\n```\n{}\n```\n\n", file.filename, patch);
+            if patches.len() + section.len() > MAX_PATCH_CHARS {
+                left_out.push(file.filename.as_str());
+                continue;
+            }
+            patches.push_str(&section);
+        }
+    }
+    if !left_out.is_empty() {
+        patches.push_str(&format!(
+            "The patches of {} were left out for size, judge their items by name only.\n",
+            left_out.join(", ")
+        ));
+    }
+    let prompt = format!(
+        "These changes in a pull request may break users: scripts passing CLI flags, deployments setting \
+        environment variables, existing config files, API clients, or data already in the database.\n\n{}\n\n\
+        For each numbered item, decide from the patches below whether it really breaks existing users \
+        (a deprecated alias or a fallback that keeps the old name working is not breaking). Answer with \
+        one line per item and nothing else: the number, a colon, then BREAKING followed by the one-line \
+        changelog or migration note users need, or FINE followed by the reason.\n\n{}",
+        listed.join("\n"),
+        patches
+    );
+    let reply = match send_prompt(prompt, options).await {
+        Ok(reply) => reply,
+        Err(e) => {
+            warn!("Could not confirm the breaking changes: {}", e);
+            return None;
+        }
+    };
+
+    let mut confirmed = HashMap::new();
+    for line in reply.lines() {
+        let Some((number, verdict)) = line.split_once(':') else {
+            continue;
+        };
+        let Ok(number) = number.trim().trim_start_matches('-').trim().parse::<usize>() else {
+            continue;
+        };
+        let verdict = verdict.trim();
+        if let Some(note) = verdict.strip_prefix("BREAKING") {
+            confirmed.insert(number.saturating_sub(1), Some(note.trim_start_matches([' ', '-', ':']).to_string()));
+        } else if verdict.starts_with("FINE") {
+            confirmed.insert(number.saturating_sub(1), None);
+        }
+    }
+    Some(confirmed)
+}
+
+// Adds the Breaking Changes section: flags, environment variables, config
+// keys, routes and columns the PR removes, confirmed by the model
+pub async fn review(details: &PullRequestDetail, options: &ReviewOptions, output: &mut OutputBuffer) {
+    let candidates = detect(details);
+    if candidates.is_empty() {
+        return;
+    }
+    // Names found in files kept from the AI aren't sent to it either
+    let asked: Vec<&Candidate> = candidates
+        .iter()
+        .filter(|c| !details.files.iter().any(|f| f.filename == c.file && f.withheld))
+        .collect();
+    let confirmed = match options.no_ai() {
+        Some(_) => None,
+        None if asked.is_empty() => None,
+        None => {
+            info!("Confirming {} possible breaking change(s)...", asked.len());
+            confirm(details, &asked, options).await
+        }
+    };
+
+    output.add_header("Breaking Changes");
+    let mut lines = Vec::new();
+    for candidate in &candidates {
+        let subject = format!("{} `{}` ({})", candidate.surface.label(), candidate.shown(), candidate.file);
+        let position = asked.iter().position(|c| std::ptr::eq(*c, candidate));
+        let finding = match (&confirmed, position) {
+            (Some(confirmed), Some(i)) => match confirmed.get(&i) {
+                Some(Some(note)) if note.is_empty() => Finding::new(Severity::High, format!("Breaking: {}", subject)),
+                Some(Some(note)) => Finding::new(Severity::High, format!("Breaking: {} - {}", subject, note)),
+                Some(None) => continue,
+                None => Finding::new(
+                    Severity::Medium,
+                    format!("Could not determine whether this breaks users: {}", subject),
+                ),
+            },
+            // Unconfirmed, the detectors alone can't tell a removal from a rename
+            _ => Finding::new(Severity::Medium, format!("Possibly breaking: {}", subject)),
+        };
+        output.findings.push(history::ReviewFinding::new("breaking", &candidate.file, &candidate.name, &finding.message));
+        lines.push(output.format_finding(&finding));
+    }
+    if lines.is_empty() {
+        output.add_box_content("Removed names were checked, none break existing users");
+        output.add_diff_separator();
+        return;
+    }
+
+    let documented = details.files.iter().any(|f| {
+        let name = f.filename.rsplit('/').next().unwrap_or(&f.filename).to_lowercase();
+        NOTE_FILES.iter().any(|note| name.starts_with(note))
+    });
+    if !documented {
+        let finding = Finding::new(
+            Severity::Medium,
+            "No changelog or migration notes changed, users won't hear about these changes",
+        );
+        output.findings.push(history::ReviewFinding::new("breaking", "", "changelog", &finding.message));
+        lines.push(output.format_finding(&finding));
+    }
+    output.add_box_content(&lines.join("\n"));
+    output.add_diff_separator();
+}
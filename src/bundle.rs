@@ -0,0 +1,165 @@
+use crate::github;
+use crate::provider;
+use crate::repo_config;
+use crate::{PrResponses, ReviewOptions, fetch_pr, parse_pr, review_details};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// Bumped when the layout changes, older rubbers refuse newer bundles
+const FORMAT_VERSION: u32 = 1;
+
+// Layout of a bundle (tar, zstd compressed):
+//   manifest.json
+//   github/pr.json, github/files.json, github/comments.json  API responses
+//   github/review_comments.json                              missing in older bundles
+//   rubber/<name>                                           .rubber/ at the base commit
+//   context/<path>                                          changed files at the head commit
+#[derive(Serialize, Deserialize, Debug)]
+struct Manifest {
+    version: u32,
+    owner: String,
+    repo: String,
+    pr_number: u32,
+    base_sha: String,
+    head_sha: String,
+    exported_at: DateTime<Utc>,
+}
+
+fn append(
+    builder: &mut tar::Builder<impl std::io::Write>,
+    path: &str,
+    content: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(Utc::now().timestamp() as u64);
+    header.set_cksum();
+    builder.append_data(&mut header, path, content.as_bytes())?;
+    Ok(())
+}
+
+// Captures everything a review needs, so it can run without GitHub access
+pub async fn export(reference: &str, bundle: &Path, github_token: Option<&str>) -> Result<String, Box<dyn Error>> {
+    let (owner, repo, pr_number) = github::parse_pr_ref(reference)?;
+    let responses = fetch_pr(pr_number, &owner, &repo, github_token).await?;
+    let (details, comments) = parse_pr(&owner, &repo, &responses)?;
+    // .rubber/ and the changed files are read through GitHub's contents API
+    let github = provider::current().is_github();
+    let rubber_files = if github {
+        repo_config::fetch_remote(&owner, &repo, &details.base.sha, github_token).await?
+    } else {
+        HashMap::new()
+    };
+
+    let manifest = Manifest {
+        version: FORMAT_VERSION,
+        owner: owner.clone(),
+        repo: repo.clone(),
+        pr_number,
+        base_sha: details.base.sha.clone(),
+        head_sha: details.head.sha.clone(),
+        exported_at: Utc::now(),
+    };
+
+    let encoder = zstd::Encoder::new(File::create(bundle)?, 0)?.auto_finish();
+    let mut builder = tar::Builder::new(encoder);
+    append(&mut builder, "manifest.json", &serde_json::to_string_pretty(&manifest)?)?;
+    append(&mut builder, "github/pr.json", &responses.pr)?;
+    append(&mut builder, "github/files.json", &responses.files)?;
+    append(&mut builder, "github/comments.json", &responses.comments)?;
+    append(&mut builder, "github/review_comments.json", &responses.review_comments)?;
+    for (name, content) in &rubber_files {
+        append(&mut builder, &format!("rubber/{}", name), content)?;
+    }
+
+    let mut context_files = 0;
+    let (head_owner, head_repo) = details.head_repo(&owner, &repo);
+    for file in details.files.iter().filter(|f| github && f.status != "removed") {
+        info!("Fetching {} for context...", file.filename);
+        match github::file_at(head_owner, head_repo, &file.filename, &details.head.sha, github_token).await {
+            Ok(Some(content)) => {
+                append(&mut builder, &format!("context/{}", file.filename), &content)?;
+                context_files += 1;
+            }
+            Ok(None) => {}
+            // Binary files and the like, the diff is still there
+            Err(e) => warn!("Could not fetch {}: {}", file.filename, e),
+        }
+    }
+    builder.into_inner()?;
+
+    Ok(format!(
+        "Exported {}/{}#{} to {}: {} changed files, {} comments, {} context files, {} .rubber files\n",
+        owner,
+        repo,
+        pr_number,
+        bundle.display(),
+        details.files.len(),
+        comments.len(),
+        context_files,
+        rubber_files.len()
+    ))
+}
+
+// Reviews a PR from a bundle instead of the GitHub API
+pub async fn import(bundle: &Path, options: &ReviewOptions) -> Result<String, Box<dyn Error>> {
+    let decoder = zstd::Decoder::new(File::open(bundle)?)?;
+    let mut archive = tar::Archive::new(decoder);
+
+    let mut entries: HashMap<String, String> = HashMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().into_owned();
+        let mut content = String::new();
+        entry.read_to_string(&mut content)?;
+        entries.insert(path, content);
+    }
+    let mut take = |path: &str| {
+        entries
+            .remove(path)
+            .ok_or_else(|| format!("{} is not a rubber bundle: {} is missing", bundle.display(), path))
+    };
+
+    let manifest: Manifest = serde_json::from_str(&take("manifest.json")?)?;
+    if manifest.version > FORMAT_VERSION {
+        return Err(format!(
+            "{} was exported by a newer rubber (format {}), upgrade to import it",
+            bundle.display(),
+            manifest.version
+        )
+        .into());
+    }
+    let responses = PrResponses {
+        pr: take("github/pr.json")?,
+        files: take("github/files.json")?,
+        comments: take("github/comments.json")?,
+        review_comments: take("github/review_comments.json").unwrap_or_else(|_| "[]".into()),
+    };
+    let (mut details, comments) = parse_pr(&manifest.owner, &manifest.repo, &responses)?;
+    details.head_files = entries
+        .iter()
+        .filter_map(|(path, content)| Some((path.strip_prefix("context/")?.to_string(), content.clone())))
+        .collect();
+
+    let rubber_files: HashMap<String, String> = entries
+        .iter()
+        .filter_map(|(path, content)| Some((path.strip_prefix("rubber/")?.to_string(), content.clone())))
+        .collect();
+    let repo_config = repo_config::load_files(
+        format!("{} ({}/{}@{})", bundle.display(), manifest.owner, manifest.repo, manifest.base_sha),
+        &rubber_files,
+    )?;
+
+    info!(
+        "Reviewing {}/{}#{} as exported at {}",
+        manifest.owner, manifest.repo, manifest.pr_number, manifest.exported_at
+    );
+    review_details(&manifest.owner, &manifest.repo, details, &comments, &repo_config, None, options).await
+}
@@ -0,0 +1,176 @@
+use crate::OutputBuffer;
+use crate::encryption;
+use crate::state;
+use chrono::{Duration, Utc};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+// Stores in the data directory that only speed things up or can be rebuilt,
+// unlike usage.jsonl and audit.jsonl which are records and never touched here
+const STORES: [(&str, &str); 2] = [
+    ("history", "review history"),
+    ("checks", "CI check outcomes"),
+];
+
+#[derive(Default)]
+struct Stats {
+    files: usize,
+    bytes: u64,
+    oldest: Option<SystemTime>,
+}
+
+// Files under a directory with their modification time, recursively
+pub fn walk(dir: &Path, found: &mut Vec<(std::path::PathBuf, fs::Metadata)>) -> Result<(), Box<dyn Error>> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            walk(&entry.path(), found)?;
+        } else {
+            found.push((entry.path(), metadata));
+        }
+    }
+    Ok(())
+}
+
+fn stats(dir: &Path) -> Result<Stats, Box<dyn Error>> {
+    let mut files = Vec::new();
+    walk(dir, &mut files)?;
+    let mut stats = Stats::default();
+    for (_, metadata) in files {
+        stats.files += 1;
+        stats.bytes += metadata.len();
+        let modified = metadata.modified()?;
+        stats.oldest = Some(stats.oldest.map_or(modified, |oldest| oldest.min(modified)));
+    }
+    Ok(stats)
+}
+
+pub fn human_size(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{} B", bytes),
+        1024..1_048_576 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+// "30d", "12h", "2w" or "90m"
+pub fn parse_age(age: &str) -> Result<Duration, String> {
+    let invalid = || format!("Invalid age {}, expected e.g. 30d, 12h or 2w", age);
+    let split = age.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+    let (number, unit) = age.split_at(split);
+    let number: i64 = number.parse().map_err(|_| invalid())?;
+    let unit_seconds = match unit {
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    // Ages are taken off the current time, which has to stay representable
+    number
+        .checked_mul(unit_seconds)
+        .and_then(Duration::try_seconds)
+        .filter(|age| Utc::now().checked_sub_signed(*age).is_some_and(|cutoff| cutoff.timestamp() >= 0))
+        .ok_or_else(|| format!("Age {} is too long, it reaches back before 1970", age))
+}
+
+pub fn show_stats(output: &mut OutputBuffer) -> Result<(), Box<dyn Error>> {
+    let data_dir = state::data_dir().ok_or("Could not determine the data directory")?;
+    let _lock = state::lock_shared()?;
+
+    output.add_header("Cache");
+    let mut lines = Vec::new();
+    let mut total = 0;
+    for (name, description) in STORES {
+        let stats = stats(&data_dir.join(name))?;
+        total += stats.bytes;
+        let oldest = stats
+            .oldest
+            .and_then(|oldest| SystemTime::now().duration_since(oldest).ok())
+            .map(|age| format!(", oldest {} days old", age.as_secs() / 86400))
+            .unwrap_or_default();
+        lines.push(format!(
+            "{:<8} {:>9}  {} files{}  ({})",
+            name,
+            human_size(stats.bytes),
+            stats.files,
+            oldest,
+            description
+        ));
+    }
+    lines.push(format!("{:<8} {:>9}", "total", human_size(total)));
+    lines.push(String::new());
+    lines.push(format!("Stored in {}", data_dir.display()));
+    output.add_box_content(&lines.join("\n"));
+    output.add_diff_separator();
+    Ok(())
+}
+
+// Removes every file of the stores, or only those not modified within max_age
+pub fn remove(max_age: Option<Duration>, output: &mut OutputBuffer) -> Result<(), Box<dyn Error>> {
+    let data_dir = state::data_dir().ok_or("Could not determine the data directory")?;
+    let _lock = state::lock()?;
+    let cutoff = max_age
+        .map(|age| age.to_std().map(|age| SystemTime::now() - age))
+        .transpose()?;
+
+    let mut removed = 0;
+    let mut freed = 0;
+    for (name, _) in STORES {
+        let mut files = Vec::new();
+        walk(&data_dir.join(name), &mut files)?;
+        for (path, metadata) in files {
+            if cutoff.is_some_and(|cutoff| metadata.modified().is_ok_and(|modified| modified >= cutoff)) {
+                continue;
+            }
+            fs::remove_file(path)?;
+            removed += 1;
+            freed += metadata.len();
+        }
+    }
+
+    output.add_line(format!("Removed {} files, freed {}", removed, human_size(freed)));
+    Ok(())
+}
+
+// Everything encrypt_data covers in the data directory: the stores above and
+// the pending reviews, comment drafts, remembered feedback and digest state
+const ENCRYPTED: [&str; 6] = ["history", "checks", "pending", "drafts", "memory", "digest.json"];
+
+// Rewrites the files stored before encrypt_data was turned on, keeping their
+// modification times so pruning still goes by when they were last used
+pub fn encrypt(output: &mut OutputBuffer) -> Result<(), Box<dyn Error>> {
+    if !encryption::enabled() {
+        return Err("Set encrypt_data = true in the config first".into());
+    }
+    let data_dir = state::data_dir().ok_or("Could not determine the data directory")?;
+    let _lock = state::lock()?;
+    encryption::check()?;
+
+    let mut encrypted = 0;
+    for name in ENCRYPTED {
+        let store = data_dir.join(name);
+        let mut files = Vec::new();
+        if store.is_file() {
+            files.push((store.clone(), fs::metadata(&store)?));
+        } else {
+            walk(&store, &mut files)?;
+        }
+        for (path, metadata) in files {
+            if encryption::is_encrypted(&path)? {
+                continue;
+            }
+            encryption::write(&path, fs::read(&path)?)?;
+            fs::File::options().write(true).open(&path)?.set_modified(metadata.modified()?)?;
+            encrypted += 1;
+        }
+    }
+    output.add_line(format!("Encrypted {} files", encrypted));
+    Ok(())
+}
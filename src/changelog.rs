@@ -0,0 +1,126 @@
+use crate::config::Policy;
+use crate::findings::{Finding, Severity};
+use crate::github;
+use crate::provider;
+use crate::{Comment, OutputBuffer, PullRequestDetail, ReviewOptions, history, send_prompt};
+use log::{info, warn};
+
+// Labels saying the PR deliberately has no entry
+const SKIP_LABELS: [&str; 2] = ["skip-changelog", "no-changelog"];
+// Patches beyond this are left out of the prompt, the file list still names
+// every changed file
+const MAX_DIFF_CHARS: usize = 40_000;
+// Starts the comment asking for an entry, so it's only posted once
+const COMMENT_HEADING: &str = "**Changelog entry missing**";
+
+// Whether users would notice the change, and why, as judged by the model
+async fn user_facing(details: &PullRequestDetail, options: &ReviewOptions) -> Result<(bool, String), Box<dyn std::error::Error>> {
+    let mut diff = String::new();
+    for file in &details.files {
+        if let Some(patch) = file.ai_patch()
+            && diff.len() + patch.len() <= MAX_DIFF_CHARS
+        {
+            diff.push_str(&format!("### {}\n```\n{}\n```\n\n", file.filename, patch));
+        }
+    }
+    let files: Vec<&str> = details.files.iter().map(|f| f.filename.as_str()).collect();
+    let prompt = format!(
+        "Decide whether this pull request makes a user-facing change that belongs in a changelog: new or \
+        changed behavior, features, options, output or defaults, fixed bugs users could hit, removals, \
+        or performance changes users would notice. Refactors, tests, CI, internal tooling and docs-only \
+        typo fixes are not user-facing.\n\
+        Answer with a single line: USER-FACING or INTERNAL, then a colon and the reason in a few words.\n\n\
+        Title: {}\n\nDescription:\n{}\n\nChanged files:\n{}\n\n{}",
+        details.title,
+        details.body.as_deref().unwrap_or_default(),
+        files.join("\n"),
+        diff
+    );
+    let reply = send_prompt(prompt, options).await?;
+    let line = reply.lines().map(str::trim).find(|l| !l.is_empty()).unwrap_or_default();
+    let (verdict, reason) = line.split_once(':').unwrap_or((line, ""));
+    let verdict = verdict.trim().trim_matches('*').to_uppercase();
+    if verdict != "USER-FACING" && verdict != "INTERNAL" {
+        return Err(format!("Unexpected answer: {}", line).into());
+    }
+    Ok((verdict == "USER-FACING", reason.trim().to_string()))
+}
+
+// Adds the Changelog section when the policy requires entries: PRs with
+// user-facing changes must touch one of the changelog paths
+#[allow(clippy::too_many_arguments)]
+pub async fn review(
+    owner: &str,
+    repo: &str,
+    details: &PullRequestDetail,
+    comments: &[Comment],
+    policy: &Policy,
+    github_token: Option<&str>,
+    options: &ReviewOptions,
+    output: &mut OutputBuffer,
+) {
+    if !policy.require_changelog {
+        return;
+    }
+    output.add_header("Changelog");
+    if let Some(entry) = details.files.iter().find(|f| policy.is_changelog(&f.filename)) {
+        output.add_box_content(&format!("Entry added in {}", entry.filename));
+        output.add_diff_separator();
+        return;
+    }
+    if let Some(label) = details.labels.iter().find(|l| SKIP_LABELS.contains(&l.name.as_str())) {
+        output.add_box_content(&format!("Not required, the PR is labeled {}", label.name));
+        output.add_diff_separator();
+        return;
+    }
+
+    if options.no_ai().is_some() {
+        output.add_box_content("No entry found, whether one is needed takes the AI to judge");
+        output.add_diff_separator();
+        return;
+    }
+    info!("Checking whether the change is user-facing...");
+    let reason = match user_facing(details, options).await {
+        Ok((false, reason)) => {
+            output.add_box_content(&format!("Not required, the change is internal: {}", reason));
+            output.add_diff_separator();
+            return;
+        }
+        Ok((true, reason)) => reason,
+        Err(e) => {
+            warn!("Could not tell whether the change is user-facing: {}", e);
+            output.add_box_content("Could not be checked, see the log");
+            output.add_diff_separator();
+            return;
+        }
+    };
+
+    let finding = Finding::new(
+        Severity::Medium,
+        format!(
+            "User-facing change without a changelog entry ({}): add one to {}, or label the PR {}",
+            reason,
+            policy.changelog_paths.join(" or "),
+            SKIP_LABELS[0]
+        ),
+    );
+    output.findings.push(history::ReviewFinding::new("changelog", "", "", &finding.message));
+    output.add_box_content(&output.format_finding(&finding));
+
+    let asked = comments.iter().any(|c| c.body.contains(github::MARKER) && c.body.starts_with(COMMENT_HEADING));
+    if policy.changelog_comment && !asked && provider::current().is_github() {
+        let body = format!(
+            "{}\n\nThis PR looks user-facing ({}), please add an entry to {}. If it doesn't need one, \
+            label it `{}`.",
+            COMMENT_HEADING,
+            reason,
+            policy.changelog_paths.iter().map(|p| format!("`{}`", p)).collect::<Vec<_>>().join(" or "),
+            SKIP_LABELS[0]
+        );
+        match github::post_issue_comment(owner, repo, details.number, &body, github_token).await {
+            Ok(_) => output.add_box_content("Asked for an entry in a comment on the PR"),
+            Err(e) => warn!("Could not comment on the PR: {}", e),
+        }
+    }
+    output.add_diff_separator();
+}
@@ -0,0 +1,201 @@
+use crate::findings::{Finding, Severity};
+use crate::risk;
+use crate::{OutputBuffer, PullRequestDetail, ReviewOptions, history, send_prompt};
+use log::{info, warn};
+
+// Kinds of claims the diff alone settles
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Claim {
+    Tests,
+    Docs,
+    Changelog,
+    Migration,
+}
+
+impl Claim {
+    // By whole words, "latest" and "docker" claim nothing
+    fn of(item: &str) -> Option<Self> {
+        let item = item.to_lowercase();
+        let words: Vec<&str> = item.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()).collect();
+        let has = |candidates: &[&str]| words.iter().any(|word| candidates.contains(word));
+        let release_notes = words.windows(2).any(|pair| pair[0] == "release" && pair[1].starts_with("note"));
+        // Checked first: "documented in the changelog" is a changelog claim
+        if has(&["changelog", "changelogs"]) || release_notes {
+            Some(Claim::Changelog)
+        } else if has(&["migration", "migrations"]) {
+            Some(Claim::Migration)
+        } else if has(&["test", "tests", "tested", "testing"]) {
+            Some(Claim::Tests)
+        } else if has(&["doc", "docs", "documentation", "documented", "document", "readme"]) {
+            Some(Claim::Docs)
+        } else {
+            None
+        }
+    }
+
+    // Whether the diff backs the claim up
+    fn holds(self, details: &PullRequestDetail) -> bool {
+        details.files.iter().any(|file| {
+            let path = file.filename.to_lowercase();
+            let name = path.rsplit('/').next().unwrap_or(&path);
+            match self {
+                Claim::Tests => {
+                    risk::is_test(&file.filename)
+                        || name.starts_with("test_")
+                        || [".test.", ".spec.", "_test."].iter().any(|marker| name.contains(marker))
+                        || file.patch.as_deref().is_some_and(|patch| {
+                            patch
+                                .lines()
+                                .filter(|l| l.starts_with('+'))
+                                .any(|l| TEST_MARKERS.iter().any(|marker| l.contains(marker)))
+                        })
+                }
+                Claim::Docs => path.ends_with(".md") || path.split('/').any(|segment| segment == "docs"),
+                Claim::Changelog => name.starts_with("changelog") || path.starts_with("changelog/"),
+                Claim::Migration => {
+                    path.ends_with(".sql")
+                        || path.split('/').any(|segment| matches!(segment, "migration" | "migrations" | "migrate"))
+                }
+            }
+        })
+    }
+
+    fn missing(self) -> &'static str {
+        match self {
+            Claim::Tests => "no test changed or added",
+            Claim::Docs => "no documentation changed",
+            Claim::Changelog => "no changelog entry changed",
+            Claim::Migration => "no migration changed",
+        }
+    }
+}
+
+// Added lines that define a test inside a source file
+const TEST_MARKERS: [&str; 5] = ["#[test]", "#[tokio::test]", "def test_", "func Test", "it(\""];
+
+struct Item {
+    checked: bool,
+    text: String,
+}
+
+// The `- [ ]` and `- [x]` items of a description filled from a template
+fn items(body: &str) -> Vec<Item> {
+    body.lines()
+        .filter_map(|line| {
+            let line = line.trim_start().strip_prefix(['-', '*'])?.trim_start();
+            let (checked, text) = if let Some(text) = line.strip_prefix("[ ]") {
+                (false, text)
+            } else {
+                (true, line.strip_prefix("[x]").or_else(|| line.strip_prefix("[X]"))?)
+            };
+            let text = text.trim();
+            (!text.is_empty()).then(|| Item {
+                checked,
+                text: text.to_string(),
+            })
+        })
+        .collect()
+}
+
+// Patches beyond this are left out of the prompt
+const MAX_PATCH_CHARS: usize = 60_000;
+
+// The model judges checked items the diff can't settle by file names. None
+// when it couldn't be asked
+async fn contradictions(
+    details: &PullRequestDetail,
+    claims: &[&str],
+    options: &ReviewOptions,
+) -> Option<Vec<(usize, String)>> {
+    let listed: Vec<String> = claims.iter().enumerate().map(|(i, c)| format!("{}. {}", i + 1, c)).collect();
+    let mut patches = String::new();
+    let mut left_out = Vec::new();
+    for file in &details.files {
+        if let Some(patch) = file.ai_patch() {
+            let section = format!("### {}\n```\n{}\n```\n\n", file.filename, patch);
+            if patches.len() + section.len() > MAX_PATCH_CHARS {
+                left_out.push(file.filename.as_str());
+                continue;
+            }
+            patches.push_str(&section);
+        }
+    }
+    if !left_out.is_empty() {
+        patches.push_str(&format!(
+            "The patches of {} were left out for size, don't call an item contradicted for what they may hold.\n",
+            left_out.join(", ")
+        ));
+    }
+    let prompt = format!(
+        "The author of this pull request checked these boxes of the PR template:\n\n{}\n\n\
+        For each numbered item, decide from the patches below whether the diff contradicts it. Only \
+        flag what the diff clearly shows to be false, claims about things outside the diff (manual \
+        testing, reviews, deployments) are fine. Answer with one line per item and nothing else: the \
+        number, a colon, then CONTRADICTED followed by what the diff shows, or OK.\n\n{}",
+        listed.join("\n"),
+        patches
+    );
+    let reply = match send_prompt(prompt, options).await {
+        Ok(reply) => reply,
+        Err(e) => {
+            warn!("Could not check the template checklist: {}", e);
+            return None;
+        }
+    };
+
+    let mut contradicted = Vec::new();
+    for line in reply.lines() {
+        let Some((number, verdict)) = line.split_once(':') else {
+            continue;
+        };
+        let Ok(number) = number.trim().trim_start_matches('-').trim().parse::<usize>() else {
+            continue;
+        };
+        if let Some(reason) = verdict.trim().strip_prefix("CONTRADICTED") {
+            contradicted.push((number.saturating_sub(1), reason.trim_start_matches([' ', '-', ':']).to_string()));
+        }
+    }
+    Some(contradicted)
+}
+
+// Adds the Checklist section when the description has template checkboxes:
+// how many are checked, and the checked ones the diff contradicts
+pub async fn review(details: &PullRequestDetail, options: &ReviewOptions, output: &mut OutputBuffer) {
+    let items = items(details.body.as_deref().unwrap_or_default());
+    if items.is_empty() {
+        return;
+    }
+    output.add_section("Checklist");
+    let checked = items.iter().filter(|item| item.checked).count();
+    let mut lines = vec![format!("{} of {} items checked", checked, items.len())];
+
+    let mut mismatches = Vec::new();
+    let mut unsettled = Vec::new();
+    for item in items.iter().filter(|item| item.checked) {
+        match Claim::of(&item.text) {
+            Some(claim) if !claim.holds(details) => mismatches.push((item.text.clone(), claim.missing().to_string())),
+            Some(_) => {}
+            None => unsettled.push(item.text.as_str()),
+        }
+    }
+    if !unsettled.is_empty() && options.no_ai().is_none() {
+        info!("Checking {} checklist item(s) against the diff...", unsettled.len());
+        if let Some(contradicted) = contradictions(details, &unsettled, options).await {
+            for (i, reason) in contradicted {
+                if let Some(item) = unsettled.get(i) {
+                    mismatches.push((item.to_string(), reason));
+                }
+            }
+        }
+    }
+
+    for (item, reason) in mismatches {
+        let finding = Finding::new(Severity::Medium, format!("Checked \"{}\" but {}", item, reason));
+        output.findings.push(history::ReviewFinding::new("checklist", "", &item, &finding.message));
+        lines.push(output.format_finding(&finding));
+    }
+    for item in items.iter().filter(|item| !item.checked) {
+        lines.push(output.format_finding(&Finding::new(Severity::Info, format!("Unchecked: {}", item.text))));
+    }
+    output.add_box_content(&lines.join("\n"));
+}
@@ -0,0 +1,68 @@
+use crate::PullRequestDetail;
+use std::error::Error;
+use std::path::Path;
+use std::process::Command;
+
+fn git(root: &Path, args: &[&str]) -> Result<String, Box<dyn Error>> {
+    let output = Command::new("git").arg("-C").arg(root).args(args).output()?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string().into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// A value as one shell word. Branch names and clone URLs come from the fork's
+// owner and may hold anything the shell would act on
+fn shell_quote(value: &str) -> String {
+    let plain = !value.is_empty()
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:@%+=,".contains(c));
+    if plain {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', "'\\''"))
+    }
+}
+
+// What's wrong with a --repo-path checkout that isn't at the PR's head
+// commit, which duplicate detection, impact analysis and size builds take it
+// to be, with the commands that get it there. A PR from a fork has its head
+// commit in the fork, which the checkout's remotes usually don't fetch
+pub fn verify(root: &Path, owner: &str, repo: &str, details: &PullRequestDetail) -> Option<String> {
+    let sha = &details.head.sha;
+    let checked_out = match git(root, &["rev-parse", "HEAD"]) {
+        Ok(checked_out) => checked_out,
+        Err(e) => return Some(format!("Could not read the commit checked out in {}: {}", root.display(), e)),
+    };
+    if checked_out.eq_ignore_ascii_case(sha) {
+        return None;
+    }
+    let short = |sha: &str| sha[..sha.len().min(7)].to_string();
+    let mut problem = format!(
+        "{} is at {}, not at the PR's head {}: duplicates, impact and sizes describe the wrong commit",
+        root.display(),
+        short(&checked_out),
+        short(sha)
+    );
+    let known = git(root, &["cat-file", "-e", &format!("{}^{{commit}}", sha)]).is_ok();
+    if known {
+        problem.push_str(&format!("\nTo check it out: git checkout {}", short(sha)));
+    } else if details.is_fork(owner, repo)
+        && let Some(fork) = details.head.repo.as_ref().filter(|fork| !fork.clone_url.is_empty())
+    {
+        problem.push_str(&format!(
+            "\nThe PR comes from the fork {}, to check it out:\n  git fetch {} {} && git checkout {}",
+            fork.full_name,
+            shell_quote(&fork.clone_url),
+            // The full ref, as a branch named like an option would be taken for one
+            shell_quote(&format!("refs/heads/{}", details.head.name)),
+            short(sha)
+        ));
+    } else {
+        problem.push_str(&format!(
+            "\nTo check it out: git fetch origin pull/{}/head && git checkout {}",
+            details.number,
+            short(sha)
+        ));
+    }
+    Some(problem)
+}
@@ -0,0 +1,185 @@
+use crate::encryption;
+use crate::findings::{Finding, Severity};
+use crate::http;
+use crate::state;
+use crate::terminal::Color;
+use crate::{OutputBuffer, PullRequestDetail, history};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+const CHECKS_DIR: &str = "checks";
+// Commits remembered per check
+const MAX_COMMITS: usize = 200;
+// A check is called flaky once this share of the commits it failed on later
+// passed on a rerun, with enough failures to judge
+const FLAKY_RATE: f64 = 0.2;
+const MIN_FAILURES: usize = 3;
+
+// What a check did on one commit, across all its attempts
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Outcome {
+    sha: String,
+    failed: bool,
+    passed: bool,
+}
+
+// Outcomes by check name, oldest first
+type Record = BTreeMap<String, Vec<Outcome>>;
+
+struct Run {
+    id: u64,
+    name: String,
+    // None while the check is still running
+    conclusion: Option<String>,
+}
+
+impl Run {
+    fn passed(&self) -> bool {
+        matches!(self.conclusion.as_deref(), Some("success" | "neutral" | "skipped"))
+    }
+
+    fn failed(&self) -> bool {
+        matches!(
+            self.conclusion.as_deref(),
+            Some("failure" | "timed_out" | "cancelled" | "action_required")
+        )
+    }
+}
+
+// Every attempt of every check run on the commit, reruns included
+async fn fetch(owner: &str, repo: &str, sha: &str, github_token: Option<&str>) -> Result<Vec<Run>, Box<dyn Error>> {
+    let url = format!("{}/repos/{}/{}/commits/{}/check-runs?filter=all", http::github_api(), owner, repo, sha);
+    let runs: Vec<Value> = serde_json::from_str(&http::GithubClient::new(github_token).get_all(&url).await?)?;
+    Ok(runs
+        .iter()
+        .map(|run| Run {
+            id: run["id"].as_u64().unwrap_or_default(),
+            name: run["name"].as_str().unwrap_or("check").to_string(),
+            conclusion: run["conclusion"].as_str().map(str::to_string),
+        })
+        .collect())
+}
+
+fn record_path(owner: &str, repo: &str) -> Option<PathBuf> {
+    state::data_dir().map(|dir| dir.join(CHECKS_DIR).join(format!("{}.json", state::file_key(owner, repo))))
+}
+
+// Adds the commit's outcomes to the repository's record and returns it
+fn update(owner: &str, repo: &str, sha: &str, runs: &[Run]) -> Result<Record, Box<dyn Error>> {
+    let _lock = state::lock()?;
+    fs::create_dir_all(state::ensure_data_dir()?.join(CHECKS_DIR))?;
+    let path = record_path(owner, repo).ok_or("Could not determine the data directory")?;
+    let mut record: Record = if path.exists() {
+        serde_json::from_str(&encryption::read_to_string(&path)?)?
+    } else {
+        Record::new()
+    };
+
+    for run in runs.iter().filter(|r| r.conclusion.is_some()) {
+        let outcomes = record.entry(run.name.clone()).or_default();
+        let index = match outcomes.iter().position(|o| o.sha == sha) {
+            Some(index) => index,
+            None => {
+                outcomes.push(Outcome {
+                    sha: sha.to_string(),
+                    failed: false,
+                    passed: false,
+                });
+                outcomes.len() - 1
+            }
+        };
+        outcomes[index].failed |= run.failed();
+        outcomes[index].passed |= run.passed();
+        let excess = outcomes.len().saturating_sub(MAX_COMMITS);
+        outcomes.drain(..excess);
+    }
+
+    encryption::write(&path, serde_json::to_string_pretty(&record)?)?;
+    Ok(record)
+}
+
+// (commits where a failure passed on rerun, commits with a failure) when
+// that makes the check flaky
+fn flakiness(outcomes: &[Outcome]) -> Option<(usize, usize)> {
+    let failures = outcomes.iter().filter(|o| o.failed).count();
+    let recovered = outcomes.iter().filter(|o| o.failed && o.passed).count();
+    (failures >= MIN_FAILURES && recovered as f64 / failures as f64 >= FLAKY_RATE).then_some((recovered, failures))
+}
+
+// Adds the Checks section: the latest attempt of each check on the head
+// commit, with failing checks that are known to be flaky called out
+pub async fn review(
+    owner: &str,
+    repo: &str,
+    details: &PullRequestDetail,
+    github_token: Option<&str>,
+    output: &mut OutputBuffer,
+) {
+    info!("Fetching check runs...");
+    let runs = match fetch(owner, repo, &details.head.sha, github_token).await {
+        Ok(runs) => runs,
+        Err(e) => {
+            warn!("Could not fetch the check runs: {}", e);
+            return;
+        }
+    };
+    if runs.is_empty() {
+        return;
+    }
+    let record = match update(owner, repo, &details.head.sha, &runs) {
+        Ok(record) => record,
+        Err(e) => {
+            warn!("Could not store the check outcomes: {}", e);
+            Record::new()
+        }
+    };
+
+    let mut latest: BTreeMap<&str, &Run> = BTreeMap::new();
+    for run in &runs {
+        let newest = latest.get(run.name.as_str()).is_none_or(|other| run.id > other.id);
+        if newest {
+            latest.insert(&run.name, run);
+        }
+    }
+
+    let theme = output.theme;
+    let mut lines = Vec::new();
+    for (name, run) in latest {
+        let (state, color) = match run.conclusion.as_deref() {
+            None => ("running", Color::Yellow),
+            Some(_) if run.passed() => ("passed", Color::Green),
+            Some(conclusion) => (conclusion, Color::Red),
+        };
+        let rerun = runs.iter().any(|r| r.name == name && r.failed()) && run.passed();
+        lines.push(format!(
+            "{} {}{}",
+            theme.paint(state, color),
+            name,
+            if rerun { " (after a failed attempt)" } else { "" }
+        ));
+
+        if !run.failed() {
+            continue;
+        }
+        if let Some((recovered, failures)) = record.get(name).and_then(|o| flakiness(o)) {
+            let finding = Finding::new(
+                Severity::Info,
+                format!(
+                    "{} is known to be flaky: it passed on a rerun after {} of its last {} failures",
+                    name, recovered, failures
+                ),
+            );
+            output.findings.push(history::ReviewFinding::new("flaky-check", "", name, &finding.message));
+            lines.push(format!("  {}", output.format_finding(&finding)));
+        }
+    }
+
+    output.add_header("Checks");
+    output.add_box_content(&lines.join("\n"));
+    output.add_diff_separator();
+}
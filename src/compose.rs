@@ -0,0 +1,240 @@
+use crate::diff;
+use crate::encryption;
+use crate::github::{self, Anchor};
+use crate::http;
+use crate::pending;
+use crate::state;
+use crate::terminal::Theme;
+use crate::{OutputBuffer, get_pr_details};
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const DRAFTS_DIR: &str = "drafts";
+// Lines of the editor file starting with this are instructions, dropped when reading it back
+const HELP_PREFIX: &str = "<!-- rubber:";
+
+// Typed at the start of a line, expanded when the editor is closed
+const SHORTCUTS: [(&str, &str); 2] = [("/nit ", "**Nit:** "), ("/q ", "**Question:** ")];
+
+// The text of a line of the file's diff, None when the line isn't part of it
+// and GitHub wouldn't accept a comment there
+fn diff_line(patch: &str, line: u32) -> Option<String> {
+    for hunk in diff::parse_hunks(patch) {
+        let mut number = hunk.new_start;
+        for text in &hunk.lines {
+            if text.starts_with('-') || text.starts_with('\\') {
+                continue;
+            }
+            if number == line {
+                return Some(text.get(1..).unwrap_or_default().to_string());
+            }
+            number += 1;
+        }
+    }
+    None
+}
+
+// One draft per PR, and per line for anchored comments
+fn draft_path(owner: &str, repo: &str, number: u32, anchor: Option<&Anchor>) -> Result<PathBuf, Box<dyn Error>> {
+    let dir = state::ensure_data_dir()?.join(DRAFTS_DIR);
+    fs::create_dir_all(&dir)?;
+    let name = match anchor {
+        Some(anchor) => {
+            let hash = Sha256::digest(format!("{}:{}", anchor.path, anchor.line).as_bytes());
+            format!("{}__{}__{}.md", state::file_key(owner, repo), number, &format!("{:x}", hash)[..12])
+        }
+        None => format!("{}__{}.md", state::file_key(owner, repo), number),
+    };
+    Ok(dir.join(name))
+}
+
+fn help(target: &str, quoted: Option<&str>) -> String {
+    let mut lines = vec![
+        format!("{} commenting on {} -->", HELP_PREFIX, target),
+        format!("{} /nit and /q at the start of a line mark a nit or a question -->", HELP_PREFIX),
+    ];
+    if let Some(quoted) = quoted {
+        lines.push(format!("{} on: {} -->", HELP_PREFIX, quoted.trim()));
+        lines.push(format!("{} /suggest on its own line proposes a replacement for it -->", HELP_PREFIX));
+    }
+    lines.join("\n")
+}
+
+// Drops the instructions and expands the shortcuts
+fn expand(text: &str, quoted: Option<&str>) -> String {
+    let mut lines = Vec::new();
+    for line in text.lines().filter(|l| !l.starts_with(HELP_PREFIX)) {
+        if line.trim() == "/suggest"
+            && let Some(quoted) = quoted
+        {
+            lines.push(format!("```suggestion\n{}\n```", quoted));
+            continue;
+        }
+        let expanded = SHORTCUTS
+            .iter()
+            .find_map(|(shortcut, replacement)| {
+                line.strip_prefix(shortcut).map(|rest| format!("{}{}", replacement, rest))
+            })
+            .unwrap_or_else(|| line.to_string());
+        lines.push(expanded);
+    }
+    lines.join("\n").trim().to_string()
+}
+
+// Text as changed in the editor. The file it's edited in sits in the data
+// directory, readable only by the user, and is removed whatever happens
+fn edit_text(text: &str) -> Result<String, Box<dyn Error>> {
+    let (path, mut file) = state::create_private(&state::ensure_data_dir()?, "comment", "md")?;
+    let written = file.write_all(text.as_bytes());
+    // Closed first, Windows editors can't always open it otherwise
+    drop(file);
+    let edited = written
+        .map_err(Box::<dyn Error>::from)
+        .and_then(|_| edit(&path))
+        .and_then(|_| Ok(fs::read_to_string(&path)?));
+    if let Err(e) = fs::remove_file(&path) {
+        warn!("Could not remove {}: {}", path.display(), e);
+    }
+    edited
+}
+
+// $VISUAL or $EDITOR, which may carry arguments such as `code --wait`
+fn edit(path: &Path) -> Result<(), Box<dyn Error>> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| if cfg!(windows) { "notepad" } else { "vi" }.to_string());
+    let status = if cfg!(windows) {
+        Command::new("cmd").arg("/C").arg(&editor).arg(path).status()?
+    } else {
+        Command::new("sh").arg("-c").arg(format!("{} \"$1\"", editor)).arg("sh").arg(path).status()?
+    };
+    if !status.success() {
+        return Err(format!("{} exited with {}", editor, status).into());
+    }
+    Ok(())
+}
+
+// None once stdin is closed
+fn ask(question: &str) -> Result<Option<String>, Box<dyn Error>> {
+    print!("{}", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(answer.trim().to_lowercase()))
+}
+
+// Writes a comment in the user's editor, previews it and posts it, general or
+// anchored to a line of the diff given as path:line. Unposted comments are
+// kept as drafts and come back the next time. Pending comments go to the PR's
+// pending review instead of being posted
+pub async fn compose(
+    reference: &str,
+    line: Option<&str>,
+    pending: bool,
+    github_token: Option<&str>,
+    theme: Theme,
+    output: &mut OutputBuffer,
+) -> Result<(), Box<dyn Error>> {
+    let (owner, repo, number) = github::parse_pr_ref(reference)?;
+    let (anchor, quoted) = match line {
+        Some(line) => {
+            let (path, line) = line
+                .rsplit_once(':')
+                .and_then(|(path, line)| Some((path, line.parse::<u32>().ok()?)))
+                .ok_or_else(|| format!("Expected path:line, got {}", line))?;
+            let (details, _) = get_pr_details(number, &owner, &repo, github_token).await?;
+            let patch = details
+                .files
+                .iter()
+                .find(|f| f.filename == path)
+                .and_then(|f| f.patch.as_deref())
+                .ok_or_else(|| format!("{} has no diff in {}", path, reference))?;
+            let quoted = diff_line(patch, line)
+                .ok_or_else(|| format!("Line {} of {} is not part of the diff", line, path))?;
+            let anchor = Anchor {
+                commit_id: details.head.sha.clone(),
+                path: path.to_string(),
+                line,
+            };
+            (Some(anchor), Some(quoted))
+        }
+        None => (None, None),
+    };
+    let target = match &anchor {
+        Some(anchor) => format!("{} at {}:{}", reference, anchor.path, anchor.line),
+        None => reference.to_string(),
+    };
+
+    let draft = draft_path(&owner, &repo, number, anchor.as_ref())?;
+    let mut body = if draft.exists() { encryption::read_to_string(&draft)? } else { String::new() };
+    if !body.is_empty() {
+        info!("Continuing the draft in {}", draft.display());
+    }
+    loop {
+        let edited = edit_text(&format!("{}\n{}\n", help(&target, quoted.as_deref()), body))?;
+        body = expand(&edited, quoted.as_deref());
+        if body.is_empty() {
+            if draft.exists() {
+                fs::remove_file(&draft)?;
+            }
+            output.add_line("Empty comment, nothing posted.");
+            return Ok(());
+        }
+        // Kept until posted, so nothing is lost if the session ends here
+        encryption::write(&draft, &body)?;
+
+        let mut preview = OutputBuffer::streaming(theme);
+        preview.add_header(&format!("Preview: {}", target));
+        preview.add_box_content(&body);
+        preview.add_diff_separator();
+        let question = if pending {
+            "[a]dd to the pending review, [e]dit, [s]ave draft, [d]iscard: "
+        } else {
+            "[p]ost, [e]dit, [s]ave draft, [d]iscard: "
+        };
+        match ask(question)?.as_deref() {
+            Some("p" | "post") if !pending => break,
+            Some("a" | "add") if pending => break,
+            Some("s" | "save") | None => {
+                output.add_line(format!("Draft saved to {}", draft.display()));
+                return Ok(());
+            }
+            Some("d" | "discard") => {
+                fs::remove_file(&draft)?;
+                output.add_line("Draft discarded.");
+                return Ok(());
+            }
+            _ => continue,
+        }
+    }
+
+    // Nothing gets posted, so the draft stays
+    if http::read_only() && !pending {
+        github::post_user_comment(&owner, &repo, number, &body, anchor.as_ref(), github_token).await?;
+        output.add_line(format!("Read-only: the draft was kept in {}", draft.display()));
+        return Ok(());
+    }
+    if pending {
+        let count = pending::add(&owner, &repo, number, &body, anchor.as_ref())?;
+        fs::remove_file(&draft)?;
+        output.add_line(format!(
+            "Added to the pending review of {}, which now has {} comment(s). Submit it with \
+            rubber pending submit {} --verdict <approve|request-changes|comment>",
+            reference, count, reference
+        ));
+        return Ok(());
+    }
+
+    let posted = github::post_user_comment(&owner, &repo, number, &body, anchor.as_ref(), github_token).await?;
+    fs::remove_file(&draft)?;
+    output.add_line(format!("Posted {}", posted["html_url"].as_str().unwrap_or_default()));
+    Ok(())
+}
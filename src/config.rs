@@ -0,0 +1,634 @@
+use crate::ai;
+use crate::http;
+use clap::ValueEnum;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use log::warn;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+// Narrower boxes can't fit their titles
+const MIN_WIDTH: usize = 40;
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub policy: Policy,
+    // Reviewer rotation per "owner/repo", used by `rubber assign`
+    pub reviewers: HashMap<String, Vec<String>>,
+    pub automerge: AutoMerge,
+    pub digest: Digest,
+    // Monorepo subtrees with their own standards, declared as [[team]]
+    #[serde(rename = "team")]
+    pub teams: Vec<Team>,
+    // Where AI requests go, Anthropic by default
+    pub ai_provider: Option<ai::Provider>,
+    // Model used for reviews, an ID or an alias
+    pub model: Option<String>,
+    // Aliases of model IDs, added to and replacing the built-in fast and smart
+    pub models: HashMap<String, String>,
+    // Used when the model is overloaded or unavailable, an ID or an alias
+    pub fallback_model: Option<String>,
+    // Small model deciding which files need a review by the model, see --prefilter
+    pub prefilter_model: Option<String>,
+    // Language of the AI output, as a code (es, ja, de, ...) or a name
+    pub language: Option<String>,
+    pub tone: Option<Tone>,
+    pub detail: Option<Detail>,
+    // Output budget of each AI request, by default it depends on the detail
+    pub max_tokens: Option<u32>,
+    // Sampling temperature of the AI, the provider's default when unset
+    pub temperature: Option<f64>,
+    // Tries per GitHub or AI request answered with 429 or 5xx, 4 by default
+    pub max_attempts: Option<u32>,
+    // System prompt and example reviews, ~/.config/rubber/templates by default
+    pub templates: Option<PathBuf>,
+    // Named bundles of credentials and settings, picked with --profile
+    pub profile: HashMap<String, Profile>,
+    // CI artifacts read for each PR, declared as [[artifact]]
+    #[serde(rename = "artifact")]
+    pub artifacts: Vec<Artifact>,
+    pub sizes: Option<Sizes>,
+    // Appended to rubber's User-Agent, e.g. to tell an organization's traffic
+    // apart in GitHub's audit log
+    pub user_agent: Option<String>,
+    // "owner/repo" used when a command is given no repository
+    pub repository: Option<String>,
+    // GitHub REST API root, e.g. https://ghe.example.com/api/v3 for GitHub
+    // Enterprise Server
+    pub api_url: Option<String>,
+    // Prints the Anthropic API key, e.g. "op read op://dev/anthropic/key" or
+    // "vault kv get -field=key secret/anthropic", run when the key is first needed
+    pub anthropic_api_key_command: Option<String>,
+    // GitHub tokens for some organizations or hosts, declared as [[token]]
+    #[serde(rename = "token")]
+    pub tokens: Vec<TokenRoute>,
+    // Columns of the report's boxes, 80 by default
+    pub width: Option<usize>,
+    // false turns colors off, keeping the box drawing characters
+    pub color: Option<bool>,
+    // Encrypts the review history, CI outcomes and --debug-http captures with
+    // a key kept in the OS keyring
+    pub encrypt_data: bool,
+    // How long stored files are kept, and whether diffs are stored at all
+    pub retention: Retention,
+    // Repositories and paths whose code may never be sent to the AI
+    pub llm: Llm,
+    #[serde(skip)]
+    pub active_profile: Option<String>,
+    #[serde(skip)]
+    github_token: Option<String>,
+    #[serde(skip)]
+    anthropic_api_key: Option<String>,
+    // What anthropic_api_key_command printed, None when it failed
+    #[serde(skip)]
+    command_api_key: OnceLock<Option<String>>,
+}
+
+// How blunt the AI feedback is
+#[derive(Deserialize, ValueEnum, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Tone {
+    Strict,
+    Friendly,
+    Terse,
+}
+
+// How long and thorough the AI feedback is
+#[derive(Deserialize, ValueEnum, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Detail {
+    Brief,
+    #[default]
+    Normal,
+    Deep,
+}
+
+// Anything set in a profile replaces the top level setting while it is active
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    // Repositories ("owner/repo", globs allowed) that select this profile
+    // automatically, exact names also feed the digest
+    pub repos: Vec<String>,
+    pub github_token: Option<String>,
+    // Environment variables to read the credentials from instead of
+    // GITHUB_TOKEN and ANTHROPIC_API_KEY
+    pub github_token_env: Option<String>,
+    pub api_url: Option<String>,
+    pub anthropic_api_key_env: Option<String>,
+    pub anthropic_api_key_command: Option<String>,
+    pub ai_provider: Option<ai::Provider>,
+    pub model: Option<String>,
+    pub fallback_model: Option<String>,
+    pub max_tokens: Option<u32>,
+    pub temperature: Option<f64>,
+    pub repository: Option<String>,
+    pub language: Option<String>,
+    pub tone: Option<Tone>,
+    pub detail: Option<Detail>,
+    pub policy: Option<Policy>,
+    pub reviewers: HashMap<String, Vec<String>>,
+    pub automerge: Option<AutoMerge>,
+    pub digest: Option<Digest>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Digest {
+    // "owner/repo" entries summarized by `rubber digest`
+    pub repos: Vec<String>,
+    // Incoming webhook the digest is also posted to
+    pub slack_webhook: Option<String>,
+}
+
+// Checked when a PR is loaded, before any prompt is built from it
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Llm {
+    // "owner/repo" globs, e.g. "acme/oss-*". When set, no other repository is sent
+    pub allow_repos: Vec<String>,
+    // "owner/repo" globs never sent, even when allowed
+    pub deny_repos: Vec<String>,
+    // Globs of files whose changes are never sent, e.g. "**/secrets/**"
+    pub deny_paths: Vec<String>,
+    #[serde(skip)]
+    allow_set: GlobSet,
+    #[serde(skip)]
+    deny_set: GlobSet,
+    #[serde(skip)]
+    path_set: GlobSet,
+}
+
+impl Llm {
+    // GitHub ignores the case of repository names, so must the globs
+    fn compile(&mut self) -> Result<(), Box<dyn Error>> {
+        let lowercase = |patterns: &[String]| patterns.iter().map(|p| p.to_lowercase()).collect::<Vec<_>>();
+        self.allow_set = build_glob_set(&lowercase(&self.allow_repos))?;
+        self.deny_set = build_glob_set(&lowercase(&self.deny_repos))?;
+        self.path_set = build_glob_set(&self.deny_paths)?;
+        Ok(())
+    }
+
+    pub fn allows_repo(&self, repository: &str) -> bool {
+        let repository = repository.to_lowercase();
+        (self.allow_repos.is_empty() || self.allow_set.is_match(&repository)) && !self.deny_set.is_match(&repository)
+    }
+
+    pub fn allows_path(&self, path: &str) -> bool {
+        !self.path_set.is_match(path)
+    }
+}
+
+// Ages such as "30d" after which the files of a store in the data directory
+// are deleted, counted from when they were last written. Unset keeps them
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Retention {
+    pub history: Option<String>,
+    pub checks: Option<String>,
+    pub pending: Option<String>,
+    pub drafts: Option<String>,
+    pub memory: Option<String>,
+    // false leaves the PR's diffs out of --debug-http captures
+    pub store_patches: bool,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            history: None,
+            checks: None,
+            pending: None,
+            drafts: None,
+            memory: None,
+            store_patches: true,
+        }
+    }
+}
+
+// A GitHub token used for the repositories or API hosts it matches, instead
+// of the default one
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct TokenRoute {
+    // "owner/repo" globs, e.g. "acme/*"
+    pub repos: Vec<String>,
+    // API hosts, e.g. "ghe.acme.com"
+    pub hosts: Vec<String>,
+    pub token: Option<String>,
+    // Environment variable to read the token from instead
+    pub token_env: Option<String>,
+    #[serde(skip)]
+    repo_set: GlobSet,
+}
+
+impl TokenRoute {
+    // Every list given has to match: a route with hosts and repos is for those
+    // repositories on those hosts. A route with neither matches nothing
+    pub fn matches(&self, host: &str, repo: Option<&str>) -> bool {
+        if self.hosts.is_empty() && self.repos.is_empty() {
+            return false;
+        }
+        let host_matches = self.hosts.is_empty() || self.hosts.iter().any(|h| h.eq_ignore_ascii_case(host));
+        let repo_matches = self.repos.is_empty() || repo.is_some_and(|r| self.repo_set.is_match(r));
+        host_matches && repo_matches
+    }
+
+    pub fn resolve(&self) -> Option<String> {
+        self.token.clone().or_else(|| self.token_env.as_deref().and_then(|v| env::var(v).ok()))
+    }
+}
+
+// A part of a monorepo owned by one team, reviewed to that team's standards
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Team {
+    pub name: String,
+    // Globs of the paths the team owns, a file belongs to the first team matching it
+    pub paths: Vec<String>,
+    // Added to the AI prompt of the team's files
+    pub prompt: Option<String>,
+    // Replaces the top level policy for the team's files
+    pub policy: Option<Policy>,
+    // Incoming webhook told about PRs touching the team's paths (--notify-teams)
+    pub slack_webhook: Option<String>,
+    #[serde(skip)]
+    path_set: GlobSet,
+}
+
+impl Team {
+    pub fn owns(&self, path: &str) -> bool {
+        self.path_set.is_match(path)
+    }
+}
+
+// An artifact uploaded by a GitHub Actions workflow, with the values to
+// compare between the PR and its base
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Artifact {
+    // Name the workflow uploads it under
+    pub name: String,
+    // File inside the artifact, the only file when not set
+    pub file: Option<String>,
+    // Values read from the file, the whole file is one value when none are declared
+    #[serde(rename = "metric")]
+    pub metrics: Vec<Metric>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Metric {
+    pub name: String,
+    // JSON pointer to the value, e.g. /assets/main.js/size
+    pub pointer: Option<String>,
+    // Growth over the base, in percent, that is reported as a finding
+    pub max_increase: Option<f64>,
+}
+
+// Sizes of build outputs (binaries, wasm, JS bundles) compared between the
+// PR and its base
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sizes {
+    // Artifact with a JSON object of output names to sizes in bytes
+    pub artifact: Option<String>,
+    // File inside the artifact, the only file when not set
+    pub file: Option<String>,
+    // Without an artifact, the outputs are built in the --repo-path checkout
+    // with this command, and in a worktree of the base commit
+    pub build: Option<String>,
+    // Globs of the outputs, relative to the checkout. The files matching one
+    // are added up, so hashed bundle names still compare
+    pub outputs: Vec<String>,
+    // Growth over the base, in percent, that is reported as a finding
+    pub max_increase: f64,
+}
+
+impl Default for Sizes {
+    fn default() -> Self {
+        Self {
+            artifact: None,
+            file: None,
+            build: None,
+            outputs: Vec::new(),
+            max_increase: 5.0,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutoMerge {
+    // Only these "owner/repo" entries are ever auto-merged
+    pub repos: Vec<String>,
+    // Highest risk score (0-100) still eligible
+    pub max_risk: u32,
+    pub required_approvals: usize,
+    // MERGE, SQUASH or REBASE
+    pub merge_method: String,
+}
+
+impl Default for AutoMerge {
+    fn default() -> Self {
+        Self {
+            repos: Vec::new(),
+            max_risk: 20,
+            required_approvals: 1,
+            merge_method: "SQUASH".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    // Globs for paths where performance regressions matter
+    pub performance_paths: Vec<String>,
+    // Command reviewers are reminded to run when those paths change
+    pub bench_command: String,
+    // User-facing changes, as judged by the AI, must add a changelog entry
+    pub require_changelog: bool,
+    // Globs of the files an entry goes in: the changelog or a fragment directory
+    pub changelog_paths: Vec<String>,
+    // Also ask for the entry in a comment on the PR, once
+    pub changelog_comment: bool,
+    #[serde(skip)]
+    performance_set: GlobSet,
+    #[serde(skip)]
+    changelog_set: GlobSet,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            performance_paths: Vec::new(),
+            bench_command: "cargo bench".to_string(),
+            require_changelog: false,
+            changelog_paths: vec!["CHANGELOG.md".to_string(), "changelog/**".to_string()],
+            changelog_comment: false,
+            performance_set: GlobSet::empty(),
+            changelog_set: GlobSet::empty(),
+        }
+    }
+}
+
+impl Policy {
+    // Builds the matchers skipped by serde
+    pub fn compile(&mut self) -> Result<(), Box<dyn Error>> {
+        self.performance_set = build_glob_set(&self.performance_paths)?;
+        self.changelog_set = build_glob_set(&self.changelog_paths)?;
+        Ok(())
+    }
+
+    pub fn is_performance_sensitive(&self, path: &str) -> bool {
+        self.performance_set.is_match(path)
+    }
+
+    pub fn is_changelog(&self, path: &str) -> bool {
+        self.changelog_set.is_match(path)
+    }
+}
+
+// Runs a secrets manager's CLI and takes what it prints. Its prompts (a
+// password, a touch of the security key) go to the terminal
+fn secret_from_command(command: &str) -> Result<String, Box<dyn Error>> {
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+    // Password managers may ask for a passphrase or a touch first
+    let output = Command::new(shell)
+        .args([flag, command])
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(format!("it failed ({})", output.status).into());
+    }
+    let secret = String::from_utf8(output.stdout)?.trim().to_string();
+    if secret.is_empty() {
+        return Err("it printed nothing".into());
+    }
+    Ok(secret)
+}
+
+fn build_glob_set(patterns: &[String]) -> Result<GlobSet, Box<dyn Error>> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    Ok(builder.build()?)
+}
+
+pub fn config_dir() -> Option<PathBuf> {
+    if let Ok(dir) = env::var("XDG_CONFIG_HOME") {
+        return Some(PathBuf::from(dir).join("rubber"));
+    }
+    if cfg!(windows) {
+        return env::var("APPDATA").ok().map(|dir| PathBuf::from(dir).join("rubber"));
+    }
+    env::var("HOME")
+        .ok()
+        .map(|home| PathBuf::from(home).join(".config").join("rubber"))
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config.toml"))
+}
+
+impl Config {
+    // An explicit path must exist, the default location is optional
+    pub fn load(path: Option<&Path>) -> Result<Self, Box<dyn Error>> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+
+        let content = fs::read_to_string(&path)
+            .map_err(|e| format!("Could not read config {}: {}", path.display(), e))?;
+        Self::parse(&content).map_err(|e| format!("Invalid config {}: {}", path.display(), e).into())
+    }
+
+    pub fn parse(content: &str) -> Result<Self, Box<dyn Error>> {
+        let mut config: Config = toml::from_str(content)?;
+        if config.width.is_some_and(|width| width < MIN_WIDTH) {
+            return Err(format!("width must be at least {}", MIN_WIDTH).into());
+        }
+        if config.max_attempts == Some(0) {
+            return Err("max_attempts must be at least 1".into());
+        }
+        config.policy.compile()?;
+        config.llm.compile()?;
+        for route in &mut config.tokens {
+            if route.token.is_none() && route.token_env.is_none() {
+                return Err("Every [[token]] needs a token or a token_env".into());
+            }
+            route.repo_set = build_glob_set(&route.repos)?;
+        }
+        for team in &mut config.teams {
+            team.path_set = build_glob_set(&team.paths)?;
+            if let Some(policy) = &mut team.policy {
+                policy.compile()?;
+            }
+        }
+        Ok(config)
+    }
+
+    // An explicit name must exist, otherwise the first profile whose repos
+    // match the repository being reviewed is used
+    pub fn select_profile(&mut self, name: Option<&str>, repo: Option<&str>) -> Result<(), Box<dyn Error>> {
+        let name = match name {
+            Some(name) if self.profile.contains_key(name) => name.to_string(),
+            Some(name) => return Err(format!("Unknown profile {}", name).into()),
+            None => {
+                let Some(repo) = repo else {
+                    return Ok(());
+                };
+                let mut names: Vec<&String> = self.profile.keys().collect();
+                names.sort();
+                let matched = names.into_iter().find(|name| {
+                    self.profile[*name]
+                        .repos
+                        .iter()
+                        .any(|pattern| Glob::new(pattern).is_ok_and(|g| g.compile_matcher().is_match(repo)))
+                });
+                match matched {
+                    Some(name) => name.clone(),
+                    None => return Ok(()),
+                }
+            }
+        };
+
+        let profile = self.profile.remove(&name).unwrap_or_default();
+        let from_env = |variable: &str, what: &str| {
+            env::var(variable).map_err(|_| {
+                format!("Profile {} reads the {} from {}, which is not set", name, what, variable)
+            })
+        };
+        if let Some(variable) = &profile.github_token_env {
+            self.github_token = Some(from_env(variable, "GitHub token")?);
+        }
+        if let Some(token) = profile.github_token {
+            self.github_token = Some(token);
+        }
+        if profile.api_url.is_some() {
+            self.api_url = profile.api_url;
+        }
+        if let Some(variable) = &profile.anthropic_api_key_env {
+            self.anthropic_api_key = Some(from_env(variable, "Anthropic API key")?);
+        }
+        if profile.anthropic_api_key_command.is_some() {
+            self.anthropic_api_key_command = profile.anthropic_api_key_command;
+        }
+        if profile.ai_provider.is_some() {
+            self.ai_provider = profile.ai_provider;
+        }
+        if profile.model.is_some() {
+            self.model = profile.model;
+        }
+        if profile.fallback_model.is_some() {
+            self.fallback_model = profile.fallback_model;
+        }
+        if profile.max_tokens.is_some() {
+            self.max_tokens = profile.max_tokens;
+        }
+        if profile.temperature.is_some() {
+            self.temperature = profile.temperature;
+        }
+        if profile.repository.is_some() {
+            self.repository = profile.repository;
+        }
+        if profile.language.is_some() {
+            self.language = profile.language;
+        }
+        if profile.tone.is_some() {
+            self.tone = profile.tone;
+        }
+        if profile.detail.is_some() {
+            self.detail = profile.detail;
+        }
+        if let Some(mut policy) = profile.policy {
+            policy.compile()?;
+            self.policy = policy;
+        }
+        self.reviewers.extend(profile.reviewers);
+        if let Some(automerge) = profile.automerge {
+            self.automerge = automerge;
+        }
+        match profile.digest {
+            Some(digest) => self.digest = digest,
+            None if !profile.repos.is_empty() => {
+                self.digest.repos = profile.repos.into_iter().filter(|r| !r.contains('*')).collect();
+            }
+            None => {}
+        }
+        self.active_profile = Some(name);
+        Ok(())
+    }
+
+    // The model ID an alias stands for, anything else is taken to be an ID
+    pub fn model_id(&self, name: &str) -> String {
+        if let Some(id) = self.models.get(name) {
+            return id.clone();
+        }
+        self.ai_provider
+            .unwrap_or_default()
+            .aliases()
+            .iter()
+            .find(|(alias, _)| *alias == name)
+            .map_or(name, |(_, id)| *id)
+            .to_string()
+    }
+
+    pub fn team_for(&self, path: &str) -> Option<&Team> {
+        self.teams.iter().find(|team| team.owns(path))
+    }
+
+    // A token given on the command line wins over the profile and the environment
+    pub fn set_github_token(&mut self, token: &str) {
+        self.github_token = Some(token.to_string());
+    }
+
+    // Profile credentials win over the environment
+    pub fn github_token(&self) -> Option<String> {
+        self.github_token.clone().or_else(|| env::var("GITHUB_TOKEN").ok())
+    }
+
+    // The key of the provider AI requests go to
+    pub fn ai_api_key(&self) -> Option<String> {
+        match self.ai_provider.unwrap_or_default() {
+            ai::Provider::Anthropic => self.anthropic_api_key(),
+            ai::Provider::Openai => env::var("OPENAI_API_KEY").ok().filter(|key| !key.is_empty()),
+            ai::Provider::Ollama | ai::Provider::Bedrock => None,
+        }
+    }
+
+    // Profile credentials first, then the key command, then the environment
+    pub fn anthropic_api_key(&self) -> Option<String> {
+        if let Some(key) = &self.anthropic_api_key {
+            return Some(key.clone());
+        }
+        let Some(command) = &self.anthropic_api_key_command else {
+            return env::var("ANTHROPIC_API_KEY").ok();
+        };
+        self.command_api_key
+            .get_or_init(|| match secret_from_command(command) {
+                Ok(key) => {
+                    http::register_secret(key.clone());
+                    Some(key)
+                }
+                Err(e) => {
+                    warn!("Could not get the Anthropic API key from {}: {}", command, e);
+                    None
+                }
+            })
+            .clone()
+    }
+}
@@ -0,0 +1,175 @@
+use crate::diff::{self, LineKind};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Default)]
+pub struct Coverage {
+    // Hit counts per instrumented line, keyed by the path as written in the report
+    files: HashMap<String, HashMap<u32, u64>>,
+}
+
+#[derive(Debug, Default)]
+pub struct PatchCoverage {
+    pub covered: usize,
+    pub instrumented: usize,
+    pub uncovered: HashMap<String, HashSet<u32>>,
+}
+
+impl PatchCoverage {
+    pub fn percentage(&self) -> Option<f64> {
+        (self.instrumented > 0).then(|| self.covered as f64 * 100.0 / self.instrumented as f64)
+    }
+}
+
+fn record(files: &mut HashMap<String, HashMap<u32, u64>>, file: &str, line: u32, hits: u64) {
+    let entry = files.entry(file.to_string()).or_default().entry(line).or_default();
+    *entry = (*entry).max(hits);
+}
+
+fn parse_lcov(content: &str) -> Coverage {
+    let mut coverage = Coverage::default();
+    let mut current: Option<String> = None;
+
+    for line in content.lines() {
+        let line = line.trim();
+        if let Some(path) = line.strip_prefix("SF:") {
+            current = Some(path.replace('\\', "/"));
+        } else if let Some(data) = line.strip_prefix("DA:") {
+            // DA:<line>,<hits>[,<checksum>]
+            let mut parts = data.split(',');
+            let number = parts.next().and_then(|n| n.parse().ok());
+            let hits = parts.next().and_then(|h| h.parse().ok());
+            if let (Some(file), Some(number), Some(hits)) = (&current, number, hits) {
+                record(&mut coverage.files, file, number, hits);
+            }
+        } else if line == "end_of_record" {
+            current = None;
+        }
+    }
+
+    coverage
+}
+
+fn attribute(element: &BytesStart, name: &str) -> Option<String> {
+    element
+        .try_get_attribute(name)
+        .ok()
+        .flatten()
+        .and_then(|a| a.unescape_value().ok())
+        .map(|v| v.into_owned())
+}
+
+fn parse_cobertura(content: &str) -> Result<Coverage, Box<dyn Error>> {
+    let mut coverage = Coverage::default();
+    let mut reader = Reader::from_str(content);
+    let mut current: Option<String> = None;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) => match e.local_name().as_ref() {
+                b"class" => current = attribute(&e, "filename").map(|f| f.replace('\\', "/")),
+                b"line" => {
+                    let number = attribute(&e, "number").and_then(|n| n.parse().ok());
+                    let hits = attribute(&e, "hits").and_then(|h| h.parse().ok());
+                    if let (Some(file), Some(number), Some(hits)) = (&current, number, hits) {
+                        record(&mut coverage.files, file, number, hits);
+                    }
+                }
+                _ => {}
+            },
+            Event::End(e) if e.local_name().as_ref() == b"class" => current = None,
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(coverage)
+}
+
+// Both lcov tracefiles and Cobertura XML reports are supported
+pub fn load(path: &Path) -> Result<Coverage, Box<dyn Error>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+
+    if content.trim_start().starts_with('<') {
+        parse_cobertura(&content)
+    } else {
+        Ok(parse_lcov(&content))
+    }
+}
+
+impl Coverage {
+    // Reports usually hold absolute CI paths or paths relative to a source
+    // root, so match on whole trailing path components.
+    fn lines_for(&self, filename: &str) -> Option<&HashMap<u32, u64>> {
+        self.files.get(filename).or_else(|| {
+            self.files
+                .iter()
+                .filter(|(path, _)| {
+                    path.ends_with(&format!("/{}", filename))
+                        || filename.ends_with(&format!("/{}", path))
+                })
+                .max_by_key(|(path, _)| path.len())
+                .map(|(_, lines)| lines)
+        })
+    }
+
+    pub fn patch_coverage<'a>(&self, patches: impl Iterator<Item = (&'a str, &'a str)>) -> PatchCoverage {
+        let mut result = PatchCoverage::default();
+
+        for (filename, patch) in patches {
+            let Some(lines) = self.lines_for(filename) else {
+                continue;
+            };
+
+            for hunk in diff::parse_hunks(patch) {
+                for line in hunk.numbered_lines() {
+                    if line.kind != LineKind::Added {
+                        continue;
+                    }
+                    // Lines without an entry (comments, blank lines) aren't executable
+                    let Some(&hits) = lines.get(&line.number) else {
+                        continue;
+                    };
+                    result.instrumented += 1;
+                    if hits > 0 {
+                        result.covered += 1;
+                    } else {
+                        result
+                            .uncovered
+                            .entry(filename.to_string())
+                            .or_default()
+                            .insert(line.number);
+                    }
+                }
+            }
+        }
+
+        result
+    }
+}
+
+// Collapses sorted line numbers into "3-5, 9" style ranges
+pub fn format_ranges(lines: &HashSet<u32>) -> String {
+    let mut sorted: Vec<u32> = lines.iter().copied().collect();
+    sorted.sort_unstable();
+
+    let mut ranges: Vec<String> = Vec::new();
+    let mut iter = sorted.into_iter().peekable();
+    while let Some(start) = iter.next() {
+        let mut end = start;
+        while iter.peek() == Some(&(end + 1)) {
+            end = iter.next().unwrap_or(end);
+        }
+        if start == end {
+            ranges.push(start.to_string());
+        } else {
+            ranges.push(format!("{}-{}", start, end));
+        }
+    }
+    ranges.join(", ")
+}
@@ -0,0 +1,96 @@
+use chrono::{DateTime, Datelike, Duration, Local, Timelike};
+use std::error::Error;
+
+// A standard five field cron expression: minute hour day-of-month month day-of-week
+#[derive(Debug)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // Cron matches either day field when both are restricted
+    any_day: bool,
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> Result<(u64, bool), Box<dyn Error>> {
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>()?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(format!("Invalid step in {}", part).into());
+        }
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (start.parse()?, end.parse()?)
+        } else {
+            let value = range.parse()?;
+            // "5/15" means from 5 to the end in steps of 15
+            (value, if step > 1 { max } else { value })
+        };
+        if start < min || end > max || start > end {
+            return Err(format!("{} is out of range {}-{}", part, min, max).into());
+        }
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok((mask, field != "*"))
+}
+
+impl Cron {
+    pub fn parse(expression: &str) -> Result<Self, Box<dyn Error>> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!("Expected 5 fields in cron expression \"{}\"", expression).into());
+        };
+
+        let (minutes, _) = parse_field(minute, 0, 59)?;
+        let (hours, _) = parse_field(hour, 0, 23)?;
+        let (days, days_restricted) = parse_field(day, 1, 31)?;
+        let (months, _) = parse_field(month, 1, 12)?;
+        let (mut weekdays, weekdays_restricted) = parse_field(weekday, 0, 7)?;
+        // Both 0 and 7 are Sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+
+        Ok(Self {
+            minutes,
+            hours,
+            days,
+            months,
+            weekdays,
+            any_day: days_restricted && weekdays_restricted,
+        })
+    }
+
+    fn matches(&self, time: &DateTime<Local>) -> bool {
+        let bit = |mask: u64, value: u32| mask & (1 << value) != 0;
+        let day = bit(self.days, time.day());
+        let weekday = bit(self.weekdays, time.weekday().num_days_from_sunday());
+        let day_matches = if self.any_day { day || weekday } else { day && weekday };
+
+        bit(self.minutes, time.minute())
+            && bit(self.hours, time.hour())
+            && bit(self.months, time.month())
+            && day_matches
+    }
+
+    pub fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        let mut time = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        // Every valid expression fires at least once in four years (Feb 29)
+        let limit = after + Duration::days(4 * 366);
+        while time < limit {
+            if self.matches(&time) {
+                return Some(time);
+            }
+            time += Duration::minutes(1);
+        }
+        None
+    }
+}
@@ -0,0 +1,328 @@
+use crate::findings::{Finding, Severity};
+use crate::{FileChange, OutputBuffer, PullRequestDetail, github, history, http};
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+
+// Files listing dependency versions, with the OSV ecosystem of their packages
+const MANIFESTS: [(&str, &str); 5] = [
+    ("Cargo.lock", "crates.io"),
+    ("go.mod", "Go"),
+    ("package-lock.json", "npm"),
+    ("pnpm-lock.yaml", "npm"),
+    ("poetry.lock", "PyPI"),
+];
+
+// Changes listed per file before the rest is only counted
+const MAX_LISTED: usize = 50;
+
+// Queries OSV accepts in one querybatch request
+const OSV_BATCH: usize = 1000;
+
+// Versions of each package, lockfiles can hold several of the same one
+type Versions = BTreeMap<String, BTreeSet<String>>;
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+fn ecosystem(path: &str) -> Option<&'static str> {
+    let name = file_name(path);
+    MANIFESTS.iter().find(|(file, _)| *file == name).map(|(_, ecosystem)| *ecosystem)
+}
+
+// Generated files not worth reading line by line, go.mod is written by hand
+pub fn is_lockfile(path: &str) -> bool {
+    ecosystem(path).is_some() && file_name(path) != "go.mod"
+}
+
+// Cargo.lock and poetry.lock: [[package]] tables with a name and a version
+fn parse_package_tables(content: &str) -> Result<Versions, Box<dyn Error>> {
+    #[derive(Deserialize)]
+    struct Package {
+        name: String,
+        version: String,
+    }
+    #[derive(Deserialize)]
+    struct Lockfile {
+        #[serde(default)]
+        package: Vec<Package>,
+    }
+
+    let lockfile: Lockfile = toml::from_str(content)?;
+    let mut versions = Versions::new();
+    for package in lockfile.package {
+        versions.entry(package.name).or_default().insert(package.version);
+    }
+    Ok(versions)
+}
+
+// require lines, one at a time or in a require ( ... ) block
+fn parse_go_mod(content: &str) -> Versions {
+    let mut versions = Versions::new();
+    let mut in_block = false;
+    for line in content.lines() {
+        let line = line.split("//").next().unwrap_or_default().trim();
+        if line.starts_with("require") && line.ends_with('(') {
+            in_block = true;
+            continue;
+        }
+        if in_block && line == ")" {
+            in_block = false;
+            continue;
+        }
+        let entry = match line.strip_prefix("require ") {
+            Some(entry) => entry,
+            None if in_block => line,
+            None => continue,
+        };
+        let mut parts = entry.split_whitespace();
+        if let (Some(name), Some(version)) = (parts.next(), parts.next()) {
+            versions.entry(name.to_string()).or_default().insert(version.to_string());
+        }
+    }
+    versions
+}
+
+// lockfileVersion 1 nests dependencies inside each other
+fn collect_npm_v1(dependencies: &Value, versions: &mut Versions) {
+    for (name, package) in dependencies.as_object().into_iter().flatten() {
+        if let Some(version) = package["version"].as_str() {
+            versions.entry(name.clone()).or_default().insert(version.to_string());
+        }
+        collect_npm_v1(&package["dependencies"], versions);
+    }
+}
+
+// package-lock.json: "packages" keyed by install path from lockfileVersion 2
+fn parse_package_lock(content: &str) -> Result<Versions, Box<dyn Error>> {
+    let lock: Value = serde_json::from_str(content)?;
+    let mut versions = Versions::new();
+    match lock["packages"].as_object() {
+        Some(packages) => {
+            for (path, package) in packages {
+                // The root project has an empty path
+                let Some((_, name)) = path.rsplit_once("node_modules/") else {
+                    continue;
+                };
+                if let Some(version) = package["version"].as_str() {
+                    versions.entry(name.to_string()).or_default().insert(version.to_string());
+                }
+            }
+        }
+        None => collect_npm_v1(&lock["dependencies"], &mut versions),
+    }
+    Ok(versions)
+}
+
+// pnpm-lock.yaml keys its packages as "/name@1.0.0" (v6), "name@1.0.0" (v9)
+// or "/name/1.0.0" (v5), possibly followed by the peer dependencies
+fn parse_pnpm_lock(content: &str) -> Versions {
+    let legacy = content
+        .lines()
+        .find_map(|l| l.strip_prefix("lockfileVersion:"))
+        .is_some_and(|v| v.trim().trim_matches(['\'', '"']).starts_with('5'));
+    let mut versions = Versions::new();
+    let mut in_packages = false;
+    for line in content.lines().filter(|l| !l.trim().is_empty()) {
+        if !line.starts_with(' ') {
+            in_packages = line.trim_end() == "packages:";
+            continue;
+        }
+        let Some(key) = line.strip_prefix("  ").filter(|k| in_packages && !k.starts_with(' ')) else {
+            continue;
+        };
+        let key = key.trim_end().trim_end_matches(':').trim_matches(['\'', '"']).trim_start_matches('/');
+        let key = key.split('(').next().unwrap_or(key);
+        let entry = if legacy {
+            key.rsplit_once('/')
+                .map(|(name, version)| (name, version.split('_').next().unwrap_or(version)))
+        } else {
+            // Scoped names start with an @ of their own
+            key.get(1..)
+                .and_then(|rest| rest.rfind('@'))
+                .map(|at| (&key[..at + 1], &key[at + 2..]))
+        };
+        if let Some((name, version)) = entry {
+            versions.entry(name.to_string()).or_default().insert(version.to_string());
+        }
+    }
+    versions
+}
+
+fn parse(path: &str, content: &str) -> Result<Versions, Box<dyn Error>> {
+    Ok(match file_name(path) {
+        "go.mod" => parse_go_mod(content),
+        "package-lock.json" => parse_package_lock(content)?,
+        "pnpm-lock.yaml" => parse_pnpm_lock(content),
+        _ => parse_package_tables(content)?,
+    })
+}
+
+struct Change {
+    name: String,
+    before: Vec<String>,
+    after: Vec<String>,
+}
+
+impl Change {
+    fn describe(&self) -> String {
+        match (self.before.is_empty(), self.after.is_empty()) {
+            (true, _) => format!("+ {} {}", self.name, self.after.join(", ")),
+            (_, true) => format!("- {} {}", self.name, self.before.join(", ")),
+            _ => format!("~ {} {} -> {}", self.name, self.before.join(", "), self.after.join(", ")),
+        }
+    }
+
+    // Versions the PR brings in, the ones worth checking for advisories
+    fn introduced(&self) -> impl Iterator<Item = &String> {
+        self.after.iter().filter(|v| !self.before.contains(v))
+    }
+}
+
+fn changes(before: &Versions, after: &Versions) -> Vec<Change> {
+    let names: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let old = before.get(name).cloned().unwrap_or_default();
+            let new = after.get(name).cloned().unwrap_or_default();
+            (old != new).then(|| Change {
+                name: name.clone(),
+                before: old.difference(&new).cloned().collect(),
+                after: new.difference(&old).cloned().collect(),
+            })
+        })
+        .collect()
+}
+
+async fn file_changes(
+    owner: &str,
+    repo: &str,
+    details: &PullRequestDetail,
+    file: &FileChange,
+    github_token: Option<&str>,
+) -> Result<Vec<Change>, Box<dyn Error>> {
+    let before_path = file.previous_filename.as_deref().unwrap_or(&file.filename);
+    let before = match file.status.as_str() {
+        "added" => None,
+        _ => github::file_at(owner, repo, before_path, &details.base.sha, github_token).await?,
+    };
+    let after = match file.status.as_str() {
+        "removed" => None,
+        _ => details.head_file(owner, repo, &file.filename, github_token).await?,
+    };
+    let before = before.map(|c| parse(before_path, &c)).transpose()?.unwrap_or_default();
+    let after = after.map(|c| parse(&file.filename, &c)).transpose()?.unwrap_or_default();
+    Ok(changes(&before, &after))
+}
+
+// Known advisory IDs of each package version, from the OSV database. A batch
+// takes at most OSV_BATCH queries, a lockfile's worth goes in several
+async fn advisories(ecosystem: &str, packages: &[(&str, &str)]) -> Result<Vec<Vec<String>>, Box<dyn Error>> {
+    let mut found = Vec::with_capacity(packages.len());
+    for batch in packages.chunks(OSV_BATCH) {
+        let queries: Vec<Value> = batch
+            .iter()
+            .map(|(name, version)| json!({ "package": { "name": name, "ecosystem": ecosystem }, "version": version }))
+            .collect();
+        let response: Value = serde_json::from_str(
+            &http::post_query("https://api.osv.dev/v1/querybatch", &json!({ "queries": queries })).await?,
+        )?;
+        let results = response["results"]
+            .as_array()
+            .filter(|results| results.len() == batch.len())
+            .ok_or("Unexpected response from OSV")?;
+        found.extend(results.iter().map(|result| {
+            result["vulns"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|v| v["id"].as_str().map(str::to_string))
+                .collect()
+        }));
+    }
+    Ok(found)
+}
+
+// Adds the Dependency Changes section for the lockfiles and manifests a PR touches
+pub async fn review(
+    owner: &str,
+    repo: &str,
+    details: &PullRequestDetail,
+    github_token: Option<&str>,
+    output: &mut OutputBuffer,
+) {
+    let manifests: Vec<(&FileChange, &str)> = details
+        .files
+        .iter()
+        .filter_map(|f| ecosystem(&f.filename).map(|ecosystem| (f, ecosystem)))
+        .collect();
+    if manifests.is_empty() {
+        return;
+    }
+
+    output.add_header("Dependency Changes");
+    for (file, ecosystem) in manifests {
+        output.add_section(&file.filename);
+        info!("Comparing dependencies in {}...", file.filename);
+        let changes = match file_changes(owner, repo, details, file, github_token).await {
+            Ok(changes) => changes,
+            Err(e) => {
+                warn!("Could not compare the dependencies in {}: {}", file.filename, e);
+                output.add_box_content("Could not be read, see the log");
+                continue;
+            }
+        };
+        if changes.is_empty() {
+            output.add_box_content("No dependency versions changed");
+            continue;
+        }
+
+        let mut lines: Vec<String> = changes.iter().take(MAX_LISTED).map(Change::describe).collect();
+        if changes.len() > MAX_LISTED {
+            lines.push(format!("... and {} more", changes.len() - MAX_LISTED));
+        }
+
+        let introduced: Vec<(&str, &str)> = changes
+            .iter()
+            .flat_map(|c| c.introduced().map(|v| (c.name.as_str(), v.as_str())))
+            .collect();
+        if !introduced.is_empty() {
+            lines.push(String::new());
+            match advisories(ecosystem, &introduced).await {
+                Ok(found) => {
+                    let mut clean = true;
+                    for ((name, version), ids) in introduced.iter().zip(found) {
+                        if ids.is_empty() {
+                            continue;
+                        }
+                        clean = false;
+                        let finding = Finding::new(
+                            Severity::High,
+                            format!("{} {} has known advisories: {}", name, version, ids.join(", ")),
+                        );
+                        output.findings.push(history::ReviewFinding::new(
+                            "advisory",
+                            &file.filename,
+                            &finding.message,
+                            &finding.message,
+                        ));
+                        lines.push(output.format_finding(&finding));
+                    }
+                    if clean {
+                        lines.push("No known advisories for the new versions".to_string());
+                    }
+                }
+                Err(e) => {
+                    warn!("Could not look up advisories: {}", e);
+                    lines.push("Advisory lookup failed, see the log".to_string());
+                }
+            }
+        }
+        output.add_box_content(&lines.join("\n"));
+    }
+    output.add_diff_separator();
+}
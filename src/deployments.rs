@@ -0,0 +1,108 @@
+use crate::terminal::Color;
+use crate::{OutputBuffer, PullRequestDetail, http};
+use log::{info, warn};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+#[derive(Deserialize, Debug)]
+struct Deployment {
+    id: u64,
+    environment: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct DeploymentStatus {
+    state: String,
+    #[serde(default)]
+    environment_url: String,
+    #[serde(default)]
+    log_url: String,
+}
+
+struct Environment {
+    name: String,
+    // None while the deployment hasn't reported anything yet
+    status: Option<DeploymentStatus>,
+}
+
+// The newest deployment of the commit to each environment, with its latest status
+async fn fetch(
+    owner: &str,
+    repo: &str,
+    sha: &str,
+    github_token: Option<&str>,
+) -> Result<Vec<Environment>, Box<dyn Error>> {
+    let base = format!("{}/repos/{}/{}/deployments", http::github_api(), owner, repo);
+    let url = format!("{}?sha={}", base, sha);
+    let github = http::GithubClient::new(github_token);
+    let deployments: Vec<Deployment> = serde_json::from_str(&github.get_all(&url).await?)?;
+
+    // Deployments come newest first
+    let mut newest: BTreeMap<String, u64> = BTreeMap::new();
+    for deployment in deployments {
+        newest.entry(deployment.environment).or_insert(deployment.id);
+    }
+
+    let mut environments = Vec::new();
+    for (name, id) in newest {
+        let statuses: Vec<DeploymentStatus> =
+            serde_json::from_str(&github.get(&format!("{}/{}/statuses?per_page=1", base, id)).await?)?;
+        environments.push(Environment {
+            name,
+            status: statuses.into_iter().next(),
+        });
+    }
+    Ok(environments)
+}
+
+// Adds the Deployments section, so reviewers can open a running preview
+// straight from the report
+pub async fn review(
+    owner: &str,
+    repo: &str,
+    details: &PullRequestDetail,
+    github_token: Option<&str>,
+    output: &mut OutputBuffer,
+) {
+    info!("Fetching deployments...");
+    let environments = match fetch(owner, repo, &details.head.sha, github_token).await {
+        Ok(environments) => environments,
+        Err(e) => {
+            warn!("Could not fetch the deployments: {}", e);
+            return;
+        }
+    };
+    if environments.is_empty() {
+        return;
+    }
+
+    let theme = output.theme;
+    let mut lines = Vec::new();
+    for environment in environments {
+        let Some(status) = environment.status else {
+            lines.push(format!("{} {}", theme.paint("pending", Color::Yellow), environment.name));
+            continue;
+        };
+        let color = match status.state.as_str() {
+            "success" => Color::Green,
+            "error" | "failure" => Color::Red,
+            "inactive" => Color::Dim,
+            _ => Color::Yellow,
+        };
+        lines.push(format!("{} {}", theme.paint(&status.state, color), environment.name));
+        // A successful deployment links to the running environment, anything
+        // else to its logs
+        let url = match status.state.as_str() {
+            "success" if !status.environment_url.is_empty() => &status.environment_url,
+            _ => &status.log_url,
+        };
+        if !url.is_empty() {
+            lines.push(format!("  {}", url));
+        }
+    }
+
+    output.add_header("Deployments");
+    output.add_box_content(&lines.join("\n"));
+    output.add_diff_separator();
+}
@@ -0,0 +1,540 @@
+use std::collections::HashMap;
+
+// Blocks shorter than this are too generic ("}", "Ok(())") to call a move
+const MIN_MOVED_LINES: usize = 3;
+const MIN_MOVED_CHARS: usize = 30;
+
+#[derive(Debug)]
+pub struct Hunk<'a> {
+    pub header: &'a str,
+    pub old_start: u32,
+    pub new_start: u32,
+    pub lines: Vec<&'a str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineKind {
+    Added,
+    Removed,
+    Context,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DiffLine<'a> {
+    pub kind: LineKind,
+    // Line number in the old file for removed/context lines, in the new file for added lines
+    pub number: u32,
+    // Line number in the new file, None for removed lines
+    pub new_number: Option<u32>,
+    pub text: &'a str,
+}
+
+// Code as tokens: words, string literals whole with the spaces inside them,
+// and every other character on its own. Whitespace only separates tokens
+fn tokens<'a>(lines: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut tokens = Vec::new();
+    for line in lines {
+        let mut rest = line;
+        while let Some(c) = rest.chars().next() {
+            let len = if c.is_whitespace() {
+                rest = &rest[c.len_utf8()..];
+                continue;
+            } else if c.is_alphanumeric() || c == '_' {
+                rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len())
+            } else if matches!(c, '"' | '\'' | '`') {
+                // Up to the closing quote, or the end of the line for one
+                // that isn't closed on it (an apostrophe in a comment)
+                let mut escaped = false;
+                rest[1..]
+                    .find(|next: char| {
+                        let closes = next == c && !escaped;
+                        escaped = next == '\\' && !escaped;
+                        closes
+                    })
+                    .map_or(rest.len(), |end| end + 2)
+            } else {
+                c.len_utf8()
+            };
+            tokens.push(&rest[..len]);
+            rest = &rest[len..];
+        }
+    }
+    tokens
+}
+
+impl Hunk<'_> {
+    fn changed_lines(&self, marker: char) -> impl Iterator<Item = &str> {
+        self.lines
+            .iter()
+            .filter(move |l| l.starts_with(marker))
+            .map(|l| &l[1..])
+    }
+
+    pub fn to_patch(&self) -> String {
+        let mut out = String::new();
+        push_hunk(&mut out, self);
+        out
+    }
+
+    // Lines the hunk spans in the new version of the file
+    pub fn new_len(&self) -> u32 {
+        self.lines.iter().filter(|l| !l.starts_with(['-', '\\'])).count() as u32
+    }
+
+    pub fn change_count(&self) -> usize {
+        self.lines
+            .iter()
+            .filter(|l| l.starts_with('+') || l.starts_with('-'))
+            .count()
+    }
+
+    // A hunk is formatting-only when its removed and added lines are the same
+    // tokens, whatever the whitespace between them. This covers re-indentation,
+    // trailing whitespace, blank line churn, spacing around operators and
+    // re-wrapping of long expressions, but not `a b` becoming `ab`
+    pub fn is_formatting_only(&self) -> bool {
+        if self.change_count() == 0 {
+            return false;
+        }
+        tokens(self.changed_lines('-')) == tokens(self.changed_lines('+'))
+    }
+
+    pub fn numbered_lines(&self) -> Vec<DiffLine<'_>> {
+        let mut old_line = self.old_start;
+        let mut new_line = self.new_start;
+        let mut numbered = Vec::with_capacity(self.lines.len());
+
+        for line in &self.lines {
+            let (kind, number, new_number) = match line.chars().next() {
+                Some('+') => {
+                    new_line += 1;
+                    (LineKind::Added, new_line - 1, Some(new_line - 1))
+                }
+                Some('-') => {
+                    old_line += 1;
+                    (LineKind::Removed, old_line - 1, None)
+                }
+                // "\ No newline at end of file" doesn't take up a line
+                Some('\\') => (LineKind::Context, old_line, None),
+                _ => {
+                    old_line += 1;
+                    new_line += 1;
+                    (LineKind::Context, old_line - 1, Some(new_line - 1))
+                }
+            };
+            numbered.push(DiffLine {
+                kind,
+                number,
+                new_number,
+                text: line,
+            });
+        }
+
+        numbered
+    }
+}
+
+fn parse_range_start(range: &str) -> u32 {
+    range[1..]
+        .split(',')
+        .next()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(1)
+}
+
+pub fn parse_hunks(patch: &str) -> Vec<Hunk<'_>> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+
+    for line in patch.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.starts_with("@@") {
+            // @@ -old_start,old_len +new_start,new_len @@ optional context
+            let mut ranges = line.split_whitespace().skip(1);
+            let old_start = ranges.next().map(parse_range_start).unwrap_or(1);
+            let new_start = ranges.next().map(parse_range_start).unwrap_or(1);
+            hunks.push(Hunk {
+                header: line,
+                old_start,
+                new_start,
+                lines: Vec::new(),
+            });
+        } else if let Some(hunk) = hunks.last_mut() {
+            hunk.lines.push(line);
+        } else {
+            // Patches without a hunk header are treated as a single hunk
+            hunks.push(Hunk {
+                header: "",
+                old_start: 1,
+                new_start: 1,
+                lines: vec![line],
+            });
+        }
+    }
+
+    hunks
+}
+
+fn push_hunk(out: &mut String, hunk: &Hunk) {
+    if !hunk.header.is_empty() {
+        out.push_str(hunk.header);
+        out.push('\n');
+    }
+    for line in &hunk.lines {
+        out.push_str(line);
+        out.push('\n');
+    }
+}
+
+// Rebuilds the patch keeping only the hunks that carry real changes, so
+// reformatting noise doesn't reach the reviewers.
+pub fn without_formatting_hunks(patch: &str) -> (String, usize) {
+    let mut kept = String::new();
+    let mut skipped = 0;
+
+    for hunk in parse_hunks(patch) {
+        if hunk.is_formatting_only() {
+            skipped += 1;
+            continue;
+        }
+        push_hunk(&mut kept, &hunk);
+    }
+
+    (kept, skipped)
+}
+
+#[derive(Debug, Clone)]
+pub struct MovedRange {
+    pub start: u32,
+    pub len: usize,
+    pub other_file: String,
+    pub other_start: u32,
+}
+
+impl MovedRange {
+    fn contains(&self, line: u32) -> bool {
+        line >= self.start && ((line - self.start) as usize) < self.len
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct FileMoves {
+    // Added ranges are in new-file line numbers, removed ranges in old-file ones
+    pub added: Vec<MovedRange>,
+    pub removed: Vec<MovedRange>,
+}
+
+impl FileMoves {
+    pub fn find(&self, line: &DiffLine) -> Option<&MovedRange> {
+        let ranges = match line.kind {
+            LineKind::Added => &self.added,
+            LineKind::Removed => &self.removed,
+            LineKind::Context => return None,
+        };
+        ranges.iter().find(|r| r.contains(line.number))
+    }
+}
+
+struct Block {
+    file: usize,
+    lines: Vec<(u32, String)>,
+}
+
+fn collect_blocks(patch: &str, file: usize, kind: LineKind, blocks: &mut Vec<Block>) {
+    for hunk in parse_hunks(patch) {
+        let mut current: Vec<(u32, String)> = Vec::new();
+        for line in hunk.numbered_lines() {
+            if line.kind == kind {
+                current.push((line.number, line.text[1..].trim().to_string()));
+            } else if !current.is_empty() {
+                blocks.push(Block {
+                    file,
+                    lines: std::mem::take(&mut current),
+                });
+            }
+        }
+        if !current.is_empty() {
+            blocks.push(Block {
+                file,
+                lines: current,
+            });
+        }
+    }
+}
+
+// Finds runs of removed lines that reappear (ignoring indentation) as added
+// lines anywhere in the PR, so relocated code isn't mistaken for new code.
+pub fn detect_moves(files: &[(&str, &str)]) -> HashMap<String, FileMoves> {
+    let mut removed_blocks = Vec::new();
+    let mut added_blocks = Vec::new();
+    for (index, (_, patch)) in files.iter().enumerate() {
+        collect_blocks(patch, index, LineKind::Removed, &mut removed_blocks);
+        collect_blocks(patch, index, LineKind::Added, &mut added_blocks);
+    }
+
+    // Index every non-blank removed line by its normalized content
+    let mut index: HashMap<&str, Vec<(usize, usize)>> = HashMap::new();
+    for (block_idx, block) in removed_blocks.iter().enumerate() {
+        for (line_idx, (_, text)) in block.lines.iter().enumerate() {
+            if !text.is_empty() {
+                index.entry(text).or_default().push((block_idx, line_idx));
+            }
+        }
+    }
+
+    let mut moves: HashMap<String, FileMoves> = HashMap::new();
+    // A removed line moved to one place only, duplicating a block doesn't
+    // make both copies moves
+    let mut used: Vec<Vec<bool>> = removed_blocks.iter().map(|block| vec![false; block.lines.len()]).collect();
+
+    for added in &added_blocks {
+        let mut i = 0;
+        while i < added.lines.len() {
+            let mut best: Option<(usize, usize, usize)> = None;
+
+            for &(block_idx, start) in index.get(added.lines[i].1.as_str()).into_iter().flatten() {
+                let removed = &removed_blocks[block_idx];
+                let len = added.lines[i..]
+                    .iter()
+                    .zip(&removed.lines[start..])
+                    .zip(&used[block_idx][start..])
+                    .take_while(|((a, r), used)| a.1 == r.1 && !**used)
+                    .count();
+                if best.is_none_or(|(_, _, best_len)| len > best_len) {
+                    best = Some((block_idx, start, len));
+                }
+            }
+
+            match best {
+                Some((block_idx, start, len))
+                    if len >= MIN_MOVED_LINES
+                        && added.lines[i..i + len]
+                            .iter()
+                            .map(|(_, text)| text.len())
+                            .sum::<usize>()
+                            >= MIN_MOVED_CHARS =>
+                {
+                    let removed = &removed_blocks[block_idx];
+                    let (to_file, to_start) = (files[added.file].0, added.lines[i].0);
+                    let (from_file, from_start) = (files[removed.file].0, removed.lines[start].0);
+
+                    moves.entry(to_file.to_string()).or_default().added.push(MovedRange {
+                        start: to_start,
+                        len,
+                        other_file: from_file.to_string(),
+                        other_start: from_start,
+                    });
+                    moves.entry(from_file.to_string()).or_default().removed.push(MovedRange {
+                        start: from_start,
+                        len,
+                        other_file: to_file.to_string(),
+                        other_start: to_start,
+                    });
+                    used[block_idx][start..start + len].fill(true);
+                    i += len;
+                }
+                _ => i += 1,
+            }
+        }
+    }
+
+    moves
+}
+
+// Replaces moved blocks with a one-line marker so reviewers only see what
+// actually changed.
+pub fn without_moved_lines(patch: &str, moves: &FileMoves) -> String {
+    let mut kept = String::new();
+
+    for hunk in parse_hunks(patch) {
+        if !hunk.header.is_empty() {
+            kept.push_str(hunk.header);
+            kept.push('\n');
+        }
+        for line in hunk.numbered_lines() {
+            match moves.find(&line) {
+                Some(range) if range.start == line.number => {
+                    let direction = if line.kind == LineKind::Added { "from" } else { "to" };
+                    kept.push_str(&format!(
+                        " [{} unchanged lines moved {} {}:{}]\n",
+                        range.len, direction, range.other_file, range.other_start
+                    ));
+                }
+                Some(_) => {}
+                None => {
+                    kept.push_str(line.text);
+                    kept.push('\n');
+                }
+            }
+        }
+    }
+
+    kept
+}
+
+pub const DISABLE_MARKER: &str = "rubber:off";
+pub const ENABLE_MARKER: &str = "rubber:on";
+
+// Whether a line of the version being merged turns review off or back on
+fn marker(line: &str) -> Option<bool> {
+    if line.starts_with('-') {
+        None
+    } else if line.contains(DISABLE_MARKER) {
+        Some(true)
+    } else if line.contains(ENABLE_MARKER) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+fn excluded_note(hidden: usize) -> String {
+    format!("\\ [{} changed lines excluded by {}]\n", hidden, DISABLE_MARKER)
+}
+
+// Drops everything between `rubber:off` and `rubber:on` comments in the new
+// version of the file, returning the patch and the number of changed lines
+// excluded. The lines between hunks aren't in the patch, so whether a hunk
+// starts inside a region comes from its first marker, a `rubber:on` closing
+// one opened above it. A hunk without markers carries over the state of the
+// one before. Excluded lines split the hunk, the rest of it resuming under a
+// header of its own so the line numbers stay right
+pub fn without_disabled_regions(patch: &str) -> (String, usize) {
+    let mut kept = String::new();
+    let mut disabled = false;
+    let mut excluded = 0;
+
+    for hunk in parse_hunks(patch) {
+        if let Some(off) = hunk.lines.iter().find_map(|line| marker(line)) {
+            disabled = !off;
+        }
+        let mut pieces = String::new();
+        let mut piece: Vec<&str> = Vec::new();
+        let (mut old_line, mut new_line) = (hunk.old_start, hunk.new_start);
+        let (mut piece_old, mut piece_new) = (old_line, new_line);
+        let mut hidden = 0;
+        let mut split = false;
+        for line in &hunk.lines {
+            if let Some(off) = marker(line) {
+                disabled = off;
+            }
+            if disabled && (line.starts_with('+') || line.starts_with('-')) {
+                if !piece.is_empty() {
+                    pieces.push_str(&hunk_piece(piece_old, piece_new, &piece));
+                    piece.clear();
+                }
+                hidden += 1;
+                split = true;
+            } else {
+                if hidden > 0 {
+                    pieces.push_str(&excluded_note(hidden));
+                    excluded += hidden;
+                    hidden = 0;
+                }
+                if piece.is_empty() {
+                    (piece_old, piece_new) = (old_line, new_line);
+                }
+                piece.push(line);
+            }
+            match line.chars().next() {
+                Some('+') => new_line += 1,
+                Some('-') => old_line += 1,
+                Some('\\') => {}
+                _ => {
+                    old_line += 1;
+                    new_line += 1;
+                }
+            }
+        }
+        if !piece.is_empty() {
+            pieces.push_str(&hunk_piece(piece_old, piece_new, &piece));
+        }
+        if hidden > 0 {
+            pieces.push_str(&excluded_note(hidden));
+            excluded += hidden;
+        }
+
+        if split {
+            kept.push_str(&pieces);
+        } else {
+            push_hunk(&mut kept, &hunk);
+        }
+    }
+
+    (kept, excluded)
+}
+
+// Splits a patch into pieces of about max_bytes at most, packing whole hunks
+// together. A hunk too large on its own is cut at line boundaries, every cut
+// starting with a header saying where it is in the file
+pub fn chunk(patch: &str, max_bytes: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    for hunk in parse_hunks(patch) {
+        for piece in split_hunk(&hunk, max_bytes) {
+            if !current.is_empty() && current.len() + piece.len() > max_bytes {
+                chunks.push(std::mem::take(&mut current));
+            }
+            current.push_str(&piece);
+        }
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+fn split_hunk(hunk: &Hunk, max_bytes: usize) -> Vec<String> {
+    let whole = hunk.to_patch();
+    if whole.len() <= max_bytes || hunk.header.is_empty() {
+        return vec![whole];
+    }
+    let mut pieces = Vec::new();
+    let (mut old_start, mut new_start) = (hunk.old_start, hunk.new_start);
+    let mut lines: Vec<&str> = Vec::new();
+    let mut size = 0;
+    for line in &hunk.lines {
+        if !lines.is_empty() && size + line.len() + 1 > max_bytes {
+            pieces.push(hunk_piece(old_start, new_start, &lines));
+            old_start += lines.iter().filter(|l| !l.starts_with(['+', '\\'])).count() as u32;
+            new_start += lines.iter().filter(|l| !l.starts_with(['-', '\\'])).count() as u32;
+            lines.clear();
+            size = 0;
+        }
+        lines.push(line);
+        size += line.len() + 1;
+    }
+    if !lines.is_empty() {
+        pieces.push(hunk_piece(old_start, new_start, &lines));
+    }
+    pieces
+}
+
+fn hunk_piece(old_start: u32, new_start: u32, lines: &[&str]) -> String {
+    // "\ No newline at end of file" doesn't take up a line
+    let old_len = lines.iter().filter(|l| !l.starts_with(['+', '\\'])).count();
+    let new_len = lines.iter().filter(|l| !l.starts_with(['-', '\\'])).count();
+    let mut piece = format!("@@ -{},{} +{},{} @@\n", old_start, old_len, new_start, new_len);
+    for line in lines {
+        piece.push_str(line);
+        piece.push('\n');
+    }
+    piece
+}
+
+// Cuts a patch down to at most max_bytes, at a line boundary. When the first
+// line after the hunk header is already too long, like minified code, it is
+// cut mid-line instead so something of it is left. Returns false when it
+// already fit
+pub fn truncate(patch: &mut String, max_bytes: usize) -> bool {
+    if patch.len() <= max_bytes {
+        return false;
+    }
+    let header_end = patch.find('\n').unwrap_or(patch.len());
+    let end = match patch.as_bytes()[..max_bytes].iter().rposition(|b| *b == b'\n') {
+        Some(end) if end > header_end => end,
+        _ => patch.floor_char_boundary(max_bytes),
+    };
+    patch.truncate(end);
+    patch.shrink_to_fit();
+    true
+}
@@ -0,0 +1,236 @@
+use crate::OutputBuffer;
+use crate::config::Config;
+use crate::cron::Cron;
+use crate::encryption;
+use crate::http;
+use crate::state;
+use crate::terminal::Theme;
+use chrono::{DateTime, Duration, Local, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+const DIGEST_FILE: &str = "digest.json";
+
+#[derive(Deserialize, Debug)]
+struct User {
+    login: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct PullRequest {
+    number: u32,
+    title: String,
+    user: User,
+    html_url: String,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    closed_at: Option<DateTime<Utc>>,
+    merged_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    draft: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct DigestState {
+    last_run: Option<DateTime<Utc>>,
+}
+
+#[derive(Default)]
+struct RepoActivity {
+    opened: Vec<PullRequest>,
+    merged: Vec<PullRequest>,
+    closed: Vec<PullRequest>,
+    updated: Vec<PullRequest>,
+}
+
+impl RepoActivity {
+    fn is_empty(&self) -> bool {
+        self.opened.is_empty() && self.merged.is_empty() && self.closed.is_empty() && self.updated.is_empty()
+    }
+}
+
+fn load_state() -> Result<DigestState, Box<dyn Error>> {
+    match state::data_dir().map(|dir| dir.join(DIGEST_FILE)) {
+        Some(path) if path.exists() => Ok(serde_json::from_str(&encryption::read_to_string(&path)?)?),
+        _ => Ok(DigestState::default()),
+    }
+}
+
+fn save_state(digest: &DigestState) -> Result<(), Box<dyn Error>> {
+    let _lock = state::lock()?;
+    let path = state::ensure_data_dir()?.join(DIGEST_FILE);
+    encryption::write(&path, serde_json::to_string_pretty(digest)?)?;
+    Ok(())
+}
+
+async fn repo_activity(
+    repo: &str,
+    since: DateTime<Utc>,
+    github_token: Option<&str>,
+) -> Result<RepoActivity, Box<dyn Error>> {
+    let github = http::GithubClient::new(github_token);
+    let mut activity = RepoActivity::default();
+
+    // Sorted by last update, so stop at the first page that reaches past `since`
+    for page in 1..=10 {
+        let url = format!(
+            "{}/repos/{}/pulls?state=all&sort=updated&direction=desc&per_page=100&page={}",
+            http::github_api(), repo, page
+        );
+        let prs: Vec<PullRequest> = serde_json::from_str(&github.get(&url).await?)?;
+        let last_page = prs.len() < 100;
+
+        for pr in prs {
+            if pr.updated_at < since {
+                return Ok(activity);
+            }
+            if pr.merged_at.is_some_and(|t| t >= since) {
+                activity.merged.push(pr);
+            } else if pr.closed_at.is_some_and(|t| t >= since) {
+                activity.closed.push(pr);
+            } else if pr.created_at >= since {
+                activity.opened.push(pr);
+            } else {
+                activity.updated.push(pr);
+            }
+        }
+        if last_page {
+            break;
+        }
+    }
+    Ok(activity)
+}
+
+fn pr_line(pr: &PullRequest) -> String {
+    format!(
+        "#{:<5} {}{} by {}",
+        pr.number,
+        pr.title,
+        if pr.draft { " [draft]" } else { "" },
+        pr.user.login
+    )
+}
+
+// Slack mrkdwn version of the report, links instead of box drawing
+fn slack_text(since: DateTime<Utc>, activity: &[(String, RepoActivity)]) -> String {
+    let mut text = format!(
+        "*PR digest since {}*\n",
+        since.with_timezone(&Local).format("%a %b %e %H:%M")
+    );
+    for (repo, activity) in activity {
+        text.push_str(&format!("\n*{}*\n", repo));
+        if activity.is_empty() {
+            text.push_str("_No activity_\n");
+            continue;
+        }
+        for (label, prs) in [
+            ("Opened", &activity.opened),
+            ("Merged", &activity.merged),
+            ("Closed", &activity.closed),
+            ("Updated", &activity.updated),
+        ] {
+            for pr in prs {
+                text.push_str(&format!(
+                    "• {}: <{}|#{} {}> by {}\n",
+                    label, pr.html_url, pr.number, pr.title, pr.user.login
+                ));
+            }
+        }
+    }
+    text
+}
+
+fn render(since: DateTime<Utc>, activity: &[(String, RepoActivity)], output: &mut OutputBuffer) {
+    output.add_header(&format!(
+        "PR Digest since {}",
+        since.with_timezone(&Local).format("%Y-%m-%d %H:%M")
+    ));
+    for (repo, activity) in activity {
+        output.add_section(repo);
+        if activity.is_empty() {
+            output.add_box_content("No activity.");
+            continue;
+        }
+
+        let mut lines = Vec::new();
+        for (label, prs) in [
+            ("Opened", &activity.opened),
+            ("Merged", &activity.merged),
+            ("Closed without merging", &activity.closed),
+            ("Updated", &activity.updated),
+        ] {
+            if prs.is_empty() {
+                continue;
+            }
+            if !lines.is_empty() {
+                lines.push(String::new());
+            }
+            lines.push(format!("{} ({})", label, prs.len()));
+            lines.extend(prs.iter().map(pr_line));
+        }
+        output.add_box_content(&lines.join("\n"));
+    }
+    output.add_diff_separator();
+}
+
+pub async fn run_once(
+    github_token: Option<&str>,
+    config: &Config,
+    output: &mut OutputBuffer,
+) -> Result<(), Box<dyn Error>> {
+    if config.digest.repos.is_empty() {
+        return Err("No repositories configured for the digest ([digest] repos in the config)".into());
+    }
+
+    let now = Utc::now();
+    let since = load_state()?
+        .last_run
+        .unwrap_or_else(|| now - Duration::hours(24));
+
+    let mut activity = Vec::new();
+    for repo in &config.digest.repos {
+        info!("Collecting PR activity for {}...", repo);
+        activity.push((repo.clone(), repo_activity(repo, since, github_token).await?));
+    }
+
+    render(since, &activity, output);
+
+    if let Some(webhook) = &config.digest.slack_webhook {
+        info!("Posting digest to Slack...");
+        let body = serde_json::json!({ "text": slack_text(since, &activity) });
+        http::post_webhook(webhook, &body).await?;
+        // Nothing went out, the next run covers this window again
+        if http::read_only() {
+            output.add_line("Read-only: the digest was not posted to Slack");
+            return Ok(());
+        }
+    }
+
+    save_state(&DigestState { last_run: Some(now) })
+}
+
+// Runs forever, printing (and posting) a digest every time the schedule fires
+pub async fn run_scheduled(
+    expression: &str,
+    github_token: Option<&str>,
+    config: &Config,
+    theme: Theme,
+) -> Result<(), Box<dyn Error>> {
+    let cron = Cron::parse(expression)?;
+    loop {
+        let next = cron
+            .next_after(Local::now())
+            .ok_or_else(|| format!("Cron expression \"{}\" never fires", expression))?;
+        info!("Next digest at {}", next.format("%Y-%m-%d %H:%M"));
+        let wait = (next - Local::now()).to_std().unwrap_or_default();
+        tokio::time::sleep(wait).await;
+
+        let mut output = OutputBuffer::new(theme);
+        match run_once(github_token, config, &mut output).await {
+            Ok(()) => print!("{}", output.content),
+            // A failed run is retried at the next slot instead of stopping the schedule
+            Err(e) => warn!("Digest failed: {}", e),
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use log::warn;
+use std::error::Error;
+
+// Per-PR review settings written by the author in the PR description, either
+// as a fenced block:
+//
+//   ```rubber
+//   focus = security, error handling
+//   skip = docs/**
+//   persona = linus
+//   ```
+//
+// or as a hidden comment: <!-- rubber: focus=security, skip=docs/** -->
+#[derive(Debug, Default)]
+pub struct Directives {
+    pub focus: Vec<String>,
+    pub skip: Vec<String>,
+    skip_set: GlobSet,
+    // Some(true) asks for the Linus persona, Some(false) for the standard one
+    pub linus_mode: Option<bool>,
+}
+
+impl Directives {
+    pub fn is_skipped(&self, path: &str) -> bool {
+        self.skip_set.is_match(path)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.focus.is_empty() && self.skip.is_empty() && self.linus_mode.is_none()
+    }
+
+    // Prompt instruction for the requested focus
+    pub fn focus_instruction(&self) -> Option<String> {
+        (!self.focus.is_empty()).then(|| {
+            format!(
+                "The author asked for this review to focus on: {}. Prioritize those concerns.",
+                self.focus.join(", ")
+            )
+        })
+    }
+
+    fn apply(&mut self, key: &str, value: &str) {
+        let values = value
+            .split(',')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(str::to_string);
+        match key {
+            "focus" => self.focus.extend(values),
+            "skip" => self.skip.extend(values),
+            "persona" => match value.trim().to_lowercase().as_str() {
+                "linus" | "linus_torvalds" | "torvalds" => self.linus_mode = Some(true),
+                "default" | "standard" => self.linus_mode = Some(false),
+                other => warn!("Ignoring unknown rubber persona in PR description: {}", other),
+            },
+            other => warn!("Ignoring unknown rubber directive in PR description: {}", other),
+        }
+    }
+}
+
+// The comment form puts everything on one line, commas separate both
+// directives and list values: "focus=security, tests, skip=docs/**"
+fn parse_inline(directives: &mut Directives, content: &str) {
+    let mut key: Option<&str> = None;
+    let mut values: Vec<&str> = Vec::new();
+    for part in content.split(',') {
+        match part.split_once('=') {
+            Some((k, v)) => {
+                if let Some(key) = key {
+                    directives.apply(key, &values.join(","));
+                }
+                key = Some(k.trim());
+                values = vec![v];
+            }
+            None => values.push(part),
+        }
+    }
+    if let Some(key) = key {
+        directives.apply(key, &values.join(","));
+    }
+}
+
+pub fn parse(body: Option<&str>) -> Result<Directives, Box<dyn Error>> {
+    let mut directives = Directives::default();
+    let Some(body) = body else {
+        return Ok(directives);
+    };
+
+    let mut in_block = false;
+    for line in body.lines() {
+        let trimmed = line.trim();
+        if in_block {
+            if trimmed.starts_with("```") {
+                in_block = false;
+            } else if let Some((key, value)) = trimmed.split_once(['=', ':']) {
+                directives.apply(key.trim(), value);
+            }
+        } else if trimmed == "```rubber" || trimmed == "``` rubber" {
+            in_block = true;
+        }
+    }
+
+    let mut rest = body;
+    while let Some(start) = rest.find("<!-- rubber:") {
+        let after = &rest[start + "<!-- rubber:".len()..];
+        let Some(end) = after.find("-->") else {
+            break;
+        };
+        parse_inline(&mut directives, &after[..end]);
+        rest = &after[end..];
+    }
+
+    // A typo in the description shouldn't stop the review
+    let mut builder = GlobSetBuilder::new();
+    for pattern in &directives.skip {
+        match Glob::new(pattern) {
+            Ok(glob) => {
+                builder.add(glob);
+            }
+            Err(e) => warn!("Ignoring invalid skip pattern in PR description: {}", e),
+        }
+    }
+    directives.skip_set = builder.build()?;
+    Ok(directives)
+}
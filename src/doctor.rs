@@ -0,0 +1,453 @@
+use crate::ai;
+use crate::aws;
+use crate::config::{self, Config};
+use crate::encryption;
+use crate::http;
+use crate::state;
+use crate::terminal::Color;
+use crate::OutputBuffer;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+struct Check {
+    name: &'static str,
+    status: Status,
+    detail: String,
+    hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Warn,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: Status::Fail,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+// A check reports what it sees at once: one attempt, with a timeout, and an
+// error status is an answer like any other
+struct Probe {
+    status: u16,
+    headers: reqwest::header::HeaderMap,
+    body: String,
+}
+
+impl Probe {
+    fn header(&self, name: &str) -> Option<String> {
+        Some(String::from_utf8_lossy(self.headers.get(name)?.as_bytes()).into_owned())
+    }
+}
+
+fn probe(request: reqwest::RequestBuilder) -> Result<Probe, String> {
+    http::wait(async {
+        let response = request.timeout(TIMEOUT).send().await.map_err(|e| e.to_string())?;
+        let status = response.status().as_u16();
+        let headers = response.headers().clone();
+        let body = response.text().await.map_err(|e| e.to_string())?;
+        Ok(Probe { status, headers, body })
+    })
+}
+
+fn check_proxy() -> Option<Check> {
+    let proxies: Vec<String> = ["HTTPS_PROXY", "https_proxy", "HTTP_PROXY", "http_proxy", "ALL_PROXY"]
+        .iter()
+        .filter_map(|name| env::var(name).ok().map(|value| format!("{}={}", name, value)))
+        .collect();
+
+    (!proxies.is_empty()).then(|| Check::pass("Proxy", proxies.join(", ")))
+}
+
+fn check_github_reachable() -> Check {
+    match probe(
+        http::client()
+            .get(format!("{}/", http::github_api()))
+            .header("X-GitHub-Api-Version", http::GITHUB_API_VERSION),
+    ) {
+        Ok(response) if response.status < 400 => {
+            Check::pass("GitHub API", format!("{} is reachable", http::github_api()))
+        }
+        Ok(response) => Check::pass("GitHub API", format!("reachable (HTTP {})", response.status)),
+        Err(e) => Check::fail(
+            "GitHub API",
+            e.to_string(),
+            "Check your network connection, or set HTTPS_PROXY if you are behind a proxy",
+        ),
+    }
+}
+
+fn check_github_token(github_token: Option<&str>) -> Check {
+    let Some(token) = github_token else {
+        return Check::warn(
+            "GitHub token",
+            "GITHUB_TOKEN is not set",
+            "Unauthenticated requests are limited to 60 per hour and can't see private repos; \
+            export GITHUB_TOKEN with a personal access token",
+        );
+    };
+
+    let response = probe(
+        http::client()
+            .get(format!("{}/user", http::github_api()))
+            .header("X-GitHub-Api-Version", http::GITHUB_API_VERSION)
+            .bearer_auth(token),
+    );
+
+    match response {
+        Ok(response) if response.status == 401 => Check::fail(
+            "GitHub token",
+            "GitHub rejected the token (401)",
+            "The token is invalid or expired, generate a new one at https://github.com/settings/tokens",
+        ),
+        Ok(response) if response.status >= 400 => Check::fail(
+            "GitHub token",
+            format!("GitHub answered {}", response.status),
+            "Could not validate the token",
+        ),
+        Ok(response) => {
+            let scopes = response.header("x-oauth-scopes");
+            let expiration = response.header("github-authentication-token-expiration");
+            let login = serde_json::from_str::<serde_json::Value>(&response.body)
+                .ok()
+                .and_then(|user| user["login"].as_str().map(str::to_string))
+                .unwrap_or_else(|| "unknown user".to_string());
+
+            let mut detail = format!("authenticated as {}", login);
+            match scopes.as_deref() {
+                Some("") => detail.push_str(", no scopes"),
+                Some(scopes) => detail.push_str(&format!(", scopes: {}", scopes)),
+                // Fine-grained tokens don't report scopes
+                None => detail.push_str(", fine-grained token"),
+            }
+            if let Some(expiration) = &expiration {
+                detail.push_str(&format!(", expires {}", expiration));
+            }
+
+            match scopes {
+                Some(scopes) if !scopes.split(',').any(|s| matches!(s.trim(), "repo" | "public_repo")) => {
+                    Check::warn(
+                        "GitHub token",
+                        detail,
+                        "Add the `repo` scope (or `public_repo` for public repositories only)",
+                    )
+                }
+                _ => Check::pass("GitHub token", detail),
+            }
+        }
+        Err(e) => Check::fail("GitHub token", e, "Could not validate the token"),
+    }
+}
+
+fn check_anthropic(api_key: Option<&str>, model: &str) -> Vec<Check> {
+    let Some(api_key) = api_key else {
+        return vec![Check::fail(
+            "Anthropic key",
+            "ANTHROPIC_API_KEY is not set",
+            "export ANTHROPIC_API_KEY, AI reviews need it",
+        )];
+    };
+
+    let response = probe(
+        http::client()
+            .get(format!("https://api.anthropic.com/v1/models/{}", model))
+            .header("x-api-key", api_key)
+            .header("anthropic-version", "2023-06-01"),
+    );
+
+    match response.map(|response| response.status) {
+        Ok(401 | 403) => vec![Check::fail(
+            "Anthropic key",
+            "the API rejected the key",
+            "Create a new key at https://console.anthropic.com/settings/keys",
+        )],
+        Ok(404) => vec![
+            Check::pass("Anthropic key", "accepted by the API"),
+            Check::fail(
+                "Model",
+                format!("{} is not available for this key", model),
+                "Pick a model listed at https://docs.anthropic.com/en/docs/about-claude/models",
+            ),
+        ],
+        Ok(status) if status < 400 => vec![
+            Check::pass("Anthropic key", "accepted by the API"),
+            Check::pass("Model", format!("{} is available", model)),
+        ],
+        Ok(status) => vec![Check::fail(
+            "Anthropic API",
+            format!("the API answered {}", status),
+            "Try again later, the API may be unavailable",
+        )],
+        Err(e) => vec![Check::fail(
+            "Anthropic API",
+            e,
+            "Check your network connection, or set HTTPS_PROXY if you are behind a proxy",
+        )],
+    }
+}
+
+fn check_openai(api_key: Option<&str>, model: &str) -> Vec<Check> {
+    let Some(api_key) = api_key else {
+        return vec![Check::fail(
+            "OpenAI key",
+            "OPENAI_API_KEY is not set",
+            "export OPENAI_API_KEY, AI reviews need it",
+        )];
+    };
+    let base_url = env::var("OPENAI_BASE_URL").unwrap_or_else(|_| "https://api.openai.com/v1".to_string());
+
+    let response = probe(
+        http::client()
+            .get(format!("{}/models/{}", base_url.trim_end_matches('/'), model))
+            .bearer_auth(api_key),
+    );
+
+    match response.map(|response| response.status) {
+        Ok(401 | 403) => vec![Check::fail(
+            "OpenAI key",
+            "the API rejected the key",
+            "Create a new key at https://platform.openai.com/api-keys",
+        )],
+        Ok(404) => vec![
+            Check::pass("OpenAI key", "accepted by the API"),
+            Check::fail(
+                "Model",
+                format!("{} is not available for this key", model),
+                "Pick a model listed at https://platform.openai.com/docs/models",
+            ),
+        ],
+        Ok(status) if status < 400 => vec![
+            Check::pass("OpenAI key", "accepted by the API"),
+            Check::pass("Model", format!("{} is available", model)),
+        ],
+        Ok(status) => vec![Check::fail(
+            "OpenAI API",
+            format!("the API answered {}", status),
+            "Try again later, the API may be unavailable",
+        )],
+        Err(e) => vec![Check::fail(
+            "OpenAI API",
+            e,
+            "Check your network connection, or set HTTPS_PROXY if you are behind a proxy",
+        )],
+    }
+}
+
+// No key to check, only whether the server is up and has the model pulled
+fn check_ollama(model: &str) -> Vec<Check> {
+    let base_url = ai::ollama_url();
+    let tags = probe(http::client().get(format!("{}/api/tags", base_url))).and_then(|response| match response.status {
+        status if status >= 400 => Err(format!("status code {}", status)),
+        _ => serde_json::from_str::<serde_json::Value>(&response.body).map_err(|e| e.to_string()),
+    });
+    let tags = match tags {
+        Ok(tags) => tags,
+        Err(e) => {
+            return vec![Check::fail(
+                "Ollama",
+                format!("{} is not reachable: {}", base_url, e),
+                "Start it with ollama serve, or set OLLAMA_HOST to where it runs",
+            )];
+        }
+    };
+    // Models are listed with their tag, codellama stands for codellama:latest
+    let wanted = if model.contains(':') { model.to_string() } else { format!("{}:latest", model) };
+    let pulled = tags["models"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|m| m["name"].as_str())
+        .any(|name| name == wanted);
+    let server = Check::pass("Ollama", format!("running at {}", base_url));
+    if pulled {
+        vec![server, Check::pass("Model", format!("{} is pulled", model))]
+    } else {
+        vec![
+            server,
+            Check::fail("Model", format!("{} is not pulled", model), format!("Run ollama pull {}", model)),
+        ]
+    }
+}
+
+// Only what can be told without a signed request: a region, and credentials
+// somewhere along the chain
+fn check_bedrock() -> Vec<Check> {
+    let region = match aws::region() {
+        Some(region) => Check::pass("AWS region", format!("{} ({})", region, ai::bedrock_url(&region))),
+        None => Check::fail("AWS region", "no region is set", "export AWS_REGION, or set region in ~/.aws/config"),
+    };
+    let credentials = match aws::credentials() {
+        Ok(credentials) => {
+            let kind = if credentials.session_token.is_some() { "temporary" } else { "long-term" };
+            Check::pass("AWS credentials", format!("{} key {}", kind, credentials.access_key_id))
+        }
+        Err(e) => Check::fail(
+            "AWS credentials",
+            e.to_string(),
+            "Run aws configure, or aws sso login with a credential_process in the profile",
+        ),
+    };
+    vec![region, credentials]
+}
+
+fn check_encryption() -> Check {
+    match encryption::check() {
+        Ok(()) if env::var("RUBBER_ENCRYPTION_KEY").is_ok() => Check::pass("Encryption", "key from RUBBER_ENCRYPTION_KEY"),
+        Ok(()) => Check::pass("Encryption", "key in the OS keyring"),
+        Err(e) => Check::fail(
+            "Encryption",
+            e.to_string(),
+            "Unlock the keyring, or set RUBBER_ENCRYPTION_KEY to a key from openssl rand -base64 32",
+        ),
+    }
+}
+
+fn check_config(path: Option<&Path>, profile: Option<&str>) -> (Check, Option<Config>) {
+    let shown = path
+        .map(Path::to_path_buf)
+        .or_else(config::default_path)
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|| "config file".to_string());
+
+    let mut config = match Config::load(path) {
+        Ok(config) => config,
+        Err(e) => {
+            let check = Check::fail("Config", e.to_string(), "Fix the syntax error or unknown key in the config file");
+            return (check, None);
+        }
+    };
+    if let Err(e) = config.select_profile(profile, None) {
+        let check = Check::fail("Config", e.to_string(), "Check the [profile.<name>] sections of the config file");
+        return (check, None);
+    }
+
+    let mut detail = if path.is_none() && !config::default_path().is_some_and(|p| p.exists()) {
+        format!("{} not found, using defaults", shown)
+    } else {
+        format!("{} is valid", shown)
+    };
+    if let Some(name) = &config.active_profile {
+        detail.push_str(&format!(", profile {}", name));
+    }
+    (Check::pass("Config", detail), Some(config))
+}
+
+fn check_data_dir() -> Check {
+    let dir = match state::ensure_data_dir() {
+        Ok(dir) => dir,
+        Err(e) => {
+            return Check::fail(
+                "Data directory",
+                e.to_string(),
+                "Set RUBBER_DATA_DIR to a writable directory",
+            );
+        }
+    };
+
+    let probe = dir.join(".doctor");
+    match fs::write(&probe, b"ok").and_then(|_| fs::remove_file(&probe)) {
+        Ok(()) => Check::pass("Data directory", format!("{} is writable", dir.display())),
+        Err(e) => Check::fail(
+            "Data directory",
+            format!("{} is not writable: {}", dir.display(), e),
+            "Fix the directory permissions or set RUBBER_DATA_DIR",
+        ),
+    }
+}
+
+// Returns the number of failed checks
+pub fn run(
+    config_path: Option<&Path>,
+    profile: Option<&str>,
+    ai_provider: Option<ai::Provider>,
+    model: Option<&str>,
+    output: &mut OutputBuffer,
+) -> usize {
+    let (config_check, config) = check_config(config_path, profile);
+    // Fall back to the environment so the other checks still run with a broken config
+    let mut config = config.unwrap_or_default();
+    if ai_provider.is_some() {
+        config.ai_provider = ai_provider;
+    }
+    if let Some(model) = model {
+        config.model = Some(model.to_string());
+    }
+    let provider = config.ai_provider.unwrap_or_default();
+    if let Some(suffix) = &config.user_agent {
+        http::set_user_agent_suffix(suffix);
+    }
+    let github_token = config.github_token();
+    let api_key = config.ai_api_key();
+    let model = config.model_id(config.model.as_deref().unwrap_or(provider.default_model()));
+
+    let mut checks = vec![config_check, check_data_dir()];
+    if config.encrypt_data {
+        checks.push(check_encryption());
+    }
+    checks.extend(check_proxy());
+    checks.push(check_github_reachable());
+    checks.push(check_github_token(github_token.as_deref()));
+    if let Err(e) = provider.check_model(&model) {
+        checks.push(Check::fail("Model", e, "Pick another model with --model, or model in the config"));
+    }
+    checks.extend(match provider {
+        ai::Provider::Anthropic => check_anthropic(api_key.as_deref(), &model),
+        ai::Provider::Openai => check_openai(api_key.as_deref(), &model),
+        ai::Provider::Ollama => check_ollama(&model),
+        ai::Provider::Bedrock => check_bedrock(),
+    });
+
+    let theme = output.theme;
+    output.add_header("Rubber Doctor");
+    output.add_line(theme.glyphs().vertical);
+    for check in &checks {
+        let (label, color) = match (&check.status, theme.unicode) {
+            (Status::Pass, true) => ("✔", Color::Green),
+            (Status::Warn, true) => ("!", Color::Yellow),
+            (Status::Fail, true) => ("✘", Color::Red),
+            (Status::Pass, false) => ("[ok]", Color::Green),
+            (Status::Warn, false) => ("[warn]", Color::Yellow),
+            (Status::Fail, false) => ("[FAIL]", Color::Red),
+        };
+        output.add_row(&format!("{} {:<15} {}", theme.paint(label, color), check.name, check.detail));
+        if let Some(hint) = &check.hint {
+            output.add_row(&format!("  {:<15} {}", "", hint));
+        }
+    }
+    output.add_line(theme.glyphs().vertical);
+    output.add_diff_separator();
+
+    checks
+        .iter()
+        .filter(|c| matches!(c.status, Status::Fail))
+        .count()
+}
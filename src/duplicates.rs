@@ -0,0 +1,210 @@
+use crate::diff::{self, LineKind};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Files bigger than this are almost certainly generated or vendored
+const MAX_INDEXED_FILE_BYTES: u64 = 1024 * 1024;
+// Lines this common ("}", "Ok(())") say nothing about duplication
+const MAX_OCCURRENCES_PER_LINE: usize = 64;
+const MIN_MEANINGFUL_CHARS: usize = 4;
+
+#[derive(Debug)]
+pub struct Duplicate {
+    pub start: u32,
+    pub end: u32,
+    pub other_file: String,
+    pub other_start: u32,
+    pub similarity: f64,
+}
+
+struct IndexedFile {
+    path: String,
+    // (line number, normalized content) for every meaningful line
+    lines: Vec<(u32, String)>,
+}
+
+pub struct RepoIndex {
+    files: Vec<IndexedFile>,
+    occurrences: HashMap<String, Vec<(usize, usize)>>,
+}
+
+fn normalize(line: &str) -> Option<String> {
+    let trimmed = line.trim();
+    if trimmed.len() < MIN_MEANINGFUL_CHARS {
+        None
+    } else {
+        Some(trimmed.split_whitespace().collect::<Vec<_>>().join(" "))
+    }
+}
+
+// Prefer the git index so ignored build output never gets scanned
+pub fn list_files(root: &Path) -> io::Result<Vec<PathBuf>> {
+    let git = Command::new("git")
+        .arg("-C")
+        .arg(root)
+        .args(["ls-files", "-z"])
+        .output();
+
+    if let Ok(output) = git
+        && output.status.success()
+    {
+        return Ok(String::from_utf8_lossy(&output.stdout)
+            .split('\0')
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from)
+            .collect());
+    }
+
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(root.join(&dir))? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with('.') || name == "target" || name == "node_modules" {
+                continue;
+            }
+            let relative = dir.join(&*name);
+            if entry.file_type()?.is_dir() {
+                pending.push(relative);
+            } else {
+                files.push(relative);
+            }
+        }
+    }
+    Ok(files)
+}
+
+impl RepoIndex {
+    pub fn build(root: &Path) -> io::Result<Self> {
+        let mut index = RepoIndex {
+            files: Vec::new(),
+            occurrences: HashMap::new(),
+        };
+
+        for relative in list_files(root)? {
+            let path = root.join(&relative);
+            let Ok(metadata) = fs::metadata(&path) else {
+                continue;
+            };
+            if !metadata.is_file() || metadata.len() > MAX_INDEXED_FILE_BYTES {
+                continue;
+            }
+            // Skips binaries and anything that isn't UTF-8
+            let Ok(content) = fs::read_to_string(&path) else {
+                continue;
+            };
+
+            let file_idx = index.files.len();
+            let lines: Vec<(u32, String)> = content
+                .lines()
+                .enumerate()
+                .filter_map(|(i, l)| normalize(l).map(|n| (i as u32 + 1, n)))
+                .collect();
+            for (line_idx, (_, text)) in lines.iter().enumerate() {
+                index
+                    .occurrences
+                    .entry(text.clone())
+                    .or_default()
+                    .push((file_idx, line_idx));
+            }
+            index.files.push(IndexedFile {
+                path: relative.to_string_lossy().replace('\\', "/"),
+                lines,
+            });
+        }
+
+        Ok(index)
+    }
+
+    pub fn file_count(&self) -> usize {
+        self.files.len()
+    }
+
+    // Finds added blocks whose lines line up with an existing stretch of code.
+    // Every added line votes for the alignments it appears in; the best
+    // alignment's share of votes is the block's similarity.
+    pub fn find_duplicates(
+        &self,
+        filename: &str,
+        patch: &str,
+        min_lines: usize,
+        threshold: f64,
+    ) -> Vec<Duplicate> {
+        let mut duplicates = Vec::new();
+
+        for hunk in diff::parse_hunks(patch) {
+            let mut block: Vec<(u32, String)> = Vec::new();
+            let lines = hunk.numbered_lines();
+
+            for (pos, line) in lines.iter().enumerate() {
+                if line.kind == LineKind::Added
+                    && let Some(normalized) = normalize(&line.text[1..])
+                {
+                    block.push((line.number, normalized));
+                }
+
+                let block_ended = line.kind != LineKind::Added || pos + 1 == lines.len();
+                if block_ended && !block.is_empty() {
+                    let finished = std::mem::take(&mut block);
+                    if finished.len() >= min_lines
+                        && let Some(duplicate) = self.best_match(filename, &finished, threshold)
+                    {
+                        duplicates.push(duplicate);
+                    }
+                }
+            }
+        }
+
+        duplicates
+    }
+
+    fn best_match(&self, filename: &str, block: &[(u32, String)], threshold: f64) -> Option<Duplicate> {
+        let mut votes: HashMap<(usize, isize), usize> = HashMap::new();
+
+        for (i, (_, text)) in block.iter().enumerate() {
+            let Some(found) = self.occurrences.get(text) else {
+                continue;
+            };
+            if found.len() > MAX_OCCURRENCES_PER_LINE {
+                continue;
+            }
+            for &(file_idx, line_idx) in found {
+                *votes.entry((file_idx, line_idx as isize - i as isize)).or_default() += 1;
+            }
+        }
+
+        let (first_line, last_line) = (block[0].0, block[block.len() - 1].0);
+
+        votes
+            .into_iter()
+            .filter_map(|((file_idx, offset), count)| {
+                let file = &self.files[file_idx];
+                let start_idx = offset.clamp(0, file.lines.len() as isize - 1) as usize;
+                let other_start = file.lines[start_idx].0;
+
+                // A checkout of the PR head contains the added block itself
+                let is_itself = file.path == filename
+                    && other_start >= first_line
+                    && other_start <= last_line;
+                if is_itself {
+                    return None;
+                }
+
+                let similarity = count as f64 / block.len() as f64;
+                (similarity >= threshold).then_some((similarity, file, other_start))
+            })
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(similarity, file, other_start)| Duplicate {
+                start: first_line,
+                end: last_line,
+                other_file: file.path.clone(),
+                other_start,
+                similarity,
+            })
+    }
+}
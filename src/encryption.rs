@@ -0,0 +1,109 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Starts every encrypted file, so files written before encryption was turned
+// on (or after it was turned off) are still read as they are
+const MAGIC: &[u8] = b"rubber-aes-256-gcm\n";
+const NONCE_LEN: usize = 12;
+const KEYRING_SERVICE: &str = "rubber";
+const KEYRING_USER: &str = "data-encryption-key";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static KEY: OnceLock<Key<Aes256Gcm>> = OnceLock::new();
+
+// From here on, stored files are written encrypted
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+fn decode(key: &str) -> Result<Key<Aes256Gcm>, Box<dyn Error>> {
+    let bytes = STANDARD.decode(key.trim())?;
+    if bytes.len() != 32 {
+        return Err(format!("the encryption key must be 32 bytes, not {}", bytes.len()).into());
+    }
+    Ok(*Key::<Aes256Gcm>::from_slice(&bytes))
+}
+
+// RUBBER_ENCRYPTION_KEY for machines without a keyring, else the key kept in
+// the OS keyring, created the first time something is encrypted
+fn key(create: bool) -> Result<Key<Aes256Gcm>, Box<dyn Error>> {
+    if let Some(key) = KEY.get() {
+        return Ok(*key);
+    }
+    let key = match env::var("RUBBER_ENCRYPTION_KEY") {
+        Ok(key) => decode(&key).map_err(|e| format!("RUBBER_ENCRYPTION_KEY is not usable: {}", e))?,
+        Err(_) => {
+            let entry = keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER)?;
+            match entry.get_password() {
+                Ok(key) => decode(&key).map_err(|e| format!("The key in the keyring is not usable: {}", e))?,
+                Err(keyring::Error::NoEntry) if create => {
+                    let key = Aes256Gcm::generate_key(OsRng);
+                    entry.set_password(&STANDARD.encode(key))?;
+                    key
+                }
+                Err(keyring::Error::NoEntry) => {
+                    return Err("The encryption key is not in the keyring, set RUBBER_ENCRYPTION_KEY".into());
+                }
+                Err(e) => {
+                    return Err(format!("Could not read the keyring ({}), set RUBBER_ENCRYPTION_KEY instead", e).into());
+                }
+            }
+        }
+    };
+    Ok(*KEY.get_or_init(|| key))
+}
+
+fn seal(plain: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    if !enabled() {
+        return Ok(plain.to_vec());
+    }
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let sealed = Aes256Gcm::new(&key(true)?)
+        .encrypt(&nonce, plain)
+        .map_err(|_| "Could not encrypt")?;
+    Ok([MAGIC, nonce.as_slice(), &sealed].concat())
+}
+
+fn open(data: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error>> {
+    let Some(sealed) = data.strip_prefix(MAGIC) else {
+        return Ok(data);
+    };
+    if sealed.len() < NONCE_LEN {
+        return Err("The encrypted file is truncated".into());
+    }
+    let (nonce, sealed) = sealed.split_at(NONCE_LEN);
+    Ok(Aes256Gcm::new(&key(false)?)
+        .decrypt(Nonce::from_slice(nonce), sealed)
+        .map_err(|_| "Could not decrypt, the file was encrypted with another key or is damaged")?)
+}
+
+pub fn is_encrypted(path: &Path) -> Result<bool, Box<dyn Error>> {
+    Ok(fs::read(path)?.starts_with(MAGIC))
+}
+
+// Whether the key can be had, creating it if need be
+pub fn check() -> Result<(), Box<dyn Error>> {
+    key(true).map(|_| ())
+}
+
+// fs::write, encrypted when enabled
+pub fn write(path: &Path, contents: impl AsRef<[u8]>) -> Result<(), Box<dyn Error>> {
+    Ok(fs::write(path, seal(contents.as_ref())?)?)
+}
+
+// fs::read_to_string for files that may be encrypted
+pub fn read_to_string(path: &Path) -> Result<String, Box<dyn Error>> {
+    Ok(String::from_utf8(open(fs::read(path)?)?)?)
+}
@@ -0,0 +1,124 @@
+use crate::diff::{self, Hunk};
+use crate::github;
+use crate::provider;
+use crate::{DiffAnnotations, OutputBuffer, ReviewOptions, get_pr_details, send_prompt};
+use log::{info, warn};
+use std::error::Error;
+
+// Lines of the new file shown around the hunk
+const CONTEXT_LINES: u32 = 30;
+
+pub struct Explanation {
+    pub patch: String,
+    pub text: String,
+}
+
+fn surrounding_lines(content: &str, hunk: &Hunk) -> String {
+    let start = hunk.new_start.saturating_sub(CONTEXT_LINES).max(1);
+    let end = hunk.new_start + hunk.new_len() + CONTEXT_LINES;
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i as u32 + 1, line))
+        .filter(|(number, _)| *number >= start && *number <= end)
+        .map(|(number, line)| format!("{:>5} {}", number, line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Explains one hunk (1-based) of a file, or the file's whole patch
+pub async fn explain(
+    owner: &str,
+    repo: &str,
+    pr_number: u32,
+    file: &str,
+    hunk: Option<usize>,
+    github_token: Option<&str>,
+    options: &ReviewOptions,
+) -> Result<Explanation, Box<dyn Error>> {
+    let (details, _) = get_pr_details(pr_number, owner, repo, github_token).await?;
+    let change = details
+        .files
+        .iter()
+        .find(|f| f.filename == file)
+        .ok_or_else(|| format!("{} is not part of PR #{}", file, pr_number))?;
+    if change.withheld {
+        return Err(format!("{} is kept from the AI by llm.deny_paths", file).into());
+    }
+    let patch = change
+        .ai_patch()
+        .ok_or_else(|| format!("GitHub doesn't provide a diff for {} (binary or too large)", file))?;
+
+    let hunks = diff::parse_hunks(&patch);
+    let Some(number) = hunk else {
+        let prompt = format!(
+            "Explain in plain English what this change to {} does and why it might have been made, for a \
+            reviewer who doesn't know this code. Keep it under 200 words and don't review it.\n\n```\n{}\n```",
+            file, patch
+        );
+        return Ok(Explanation {
+            patch: patch.to_string(),
+            text: send_prompt(prompt, options).await?,
+        });
+    };
+    let selected = number
+        .checked_sub(1)
+        .and_then(|i| hunks.get(i))
+        .ok_or_else(|| format!("{} has {} hunk(s), there is no hunk {}", file, hunks.len(), number))?;
+
+    // The surrounding code explains far more than the hunk alone
+    let context = if change.status == "removed" || !provider::current().is_github() {
+        None
+    } else {
+        info!("Fetching {} for context...", file);
+        match details.head_file(owner, repo, file, github_token).await {
+            Ok(content) => content.map(|content| surrounding_lines(&content, selected)),
+            Err(e) => {
+                warn!("Could not fetch {}: {}", file, e);
+                None
+            }
+        }
+    };
+
+    let mut prompt = format!(
+        "Explain in plain English what this hunk of a change to {} does and why it might have been made, \
+        for a reviewer who doesn't know this code. Keep it under 200 words and don't review it.\n\n\
+        Hunk:\n```\n{}```\n",
+        file,
+        selected.to_patch()
+    );
+    if let Some(context) = context {
+        prompt.push_str(&format!(
+            "\nThe file around the hunk after the change, with line numbers:\n```\n{}\n```\n",
+            context
+        ));
+    }
+
+    Ok(Explanation {
+        patch: selected.to_patch(),
+        text: send_prompt(prompt, options).await?,
+    })
+}
+
+pub async fn show(
+    reference: &str,
+    file: &str,
+    hunk: Option<usize>,
+    github_token: Option<&str>,
+    options: &ReviewOptions,
+    output: &mut OutputBuffer,
+) -> Result<(), Box<dyn Error>> {
+    let (owner, repo, pr_number) = github::parse_pr_ref(reference)?;
+    let explanation = explain(&owner, &repo, pr_number, file, hunk, github_token, options).await?;
+
+    output.add_header(&match hunk {
+        Some(hunk) => format!("{} (hunk {})", file, hunk),
+        None => file.to_string(),
+    });
+    output.add_diff_header(file);
+    output.add_diff_content(&explanation.patch, &DiffAnnotations::default());
+    output.add_section("Explanation");
+    output.add_box_content(explanation.text.trim());
+    output.add_diff_separator();
+    Ok(())
+}
@@ -0,0 +1,151 @@
+use crate::{http, provider};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+
+// Hidden in every comment rubber posts, so its own comments can be found
+// again and never trigger it
+pub const MARKER: &str = "<!-- rubber -->";
+
+// A line of a PR's diff, by its number in the new version of the file
+#[derive(Debug, Clone)]
+pub struct Anchor {
+    pub commit_id: String,
+    pub path: String,
+    pub line: u32,
+}
+
+async fn create(url: &str, payload: &Value, github_token: Option<&str>) -> Result<Value, Box<dyn Error>> {
+    Ok(serde_json::from_str(&http::GithubClient::new(github_token).post(url, payload).await?)?)
+}
+
+async fn post(url: &str, body: &str, github_token: Option<&str>) -> Result<Value, Box<dyn Error>> {
+    let body = format!("{}\n{}", body, MARKER);
+    let posted = http::GithubClient::new(github_token).post(url, &json!({ "body": body })).await?;
+    Ok(serde_json::from_str(&posted)?)
+}
+
+pub async fn post_issue_comment(
+    owner: &str,
+    repo: &str,
+    number: u32,
+    body: &str,
+    github_token: Option<&str>,
+) -> Result<Value, Box<dyn Error>> {
+    let url = format!(
+        "{}/repos/{}/{}/issues/{}/comments",
+        http::github_api(), owner, repo, number
+    );
+    post(&url, body, github_token).await
+}
+
+// Answers inside the thread of a review comment on the diff
+pub async fn reply_to_review_comment(
+    owner: &str,
+    repo: &str,
+    number: u32,
+    comment_id: u64,
+    body: &str,
+    github_token: Option<&str>,
+) -> Result<Value, Box<dyn Error>> {
+    let url = format!(
+        "{}/repos/{}/{}/pulls/{}/comments/{}/replies",
+        http::github_api(), owner, repo, number, comment_id
+    );
+    post(&url, body, github_token).await
+}
+
+// A comment the user wrote with `rubber comment`. It goes out without the
+// marker: it's theirs, not rubber's
+pub async fn post_user_comment(
+    owner: &str,
+    repo: &str,
+    number: u32,
+    body: &str,
+    anchor: Option<&Anchor>,
+    github_token: Option<&str>,
+) -> Result<Value, Box<dyn Error>> {
+    match anchor {
+        Some(anchor) => {
+            let url = format!("{}/repos/{}/{}/pulls/{}/comments", http::github_api(), owner, repo, number);
+            let payload = json!({
+                "body": body,
+                "commit_id": anchor.commit_id,
+                "path": anchor.path,
+                "line": anchor.line,
+                "side": "RIGHT",
+            });
+            create(&url, &payload, github_token).await
+        }
+        None => {
+            let url = format!("{}/repos/{}/{}/issues/{}/comments", http::github_api(), owner, repo, number);
+            create(&url, &json!({ "body": body }), github_token).await
+        }
+    }
+}
+
+// Submits a whole review at once: verdict, summary and inline comments, as
+// built by `rubber comment --pending`
+pub async fn submit_review(
+    owner: &str,
+    repo: &str,
+    number: u32,
+    review: &Value,
+    github_token: Option<&str>,
+) -> Result<Value, Box<dyn Error>> {
+    let url = format!("{}/repos/{}/{}/pulls/{}/reviews", http::github_api(), owner, repo, number);
+    Ok(serde_json::from_str(&http::GithubClient::new(github_token).post(&url, review).await?)?)
+}
+
+// "owner/repo#123" as used on GitHub itself
+pub fn parse_pr_ref(reference: &str) -> Result<(String, String, u32), Box<dyn Error>> {
+    let invalid = || format!("Expected owner/repo#number, got {}", reference);
+    let (repository, number) = reference.split_once('#').ok_or_else(invalid)?;
+    let (owner, repo) = parse_repo_ref(repository).map_err(|_| invalid())?;
+    let number = number.parse().map_err(|_| invalid())?;
+    Ok((owner, repo, number))
+}
+
+// "owner/repo". On GitLab the owner may be a nested group, "group/subgroup/repo"
+pub fn parse_repo_ref(reference: &str) -> Result<(String, String), Box<dyn Error>> {
+    match reference.rsplit_once('/') {
+        Some((owner, repo))
+            if !repo.is_empty()
+                && !owner.split('/').any(str::is_empty)
+                && (!owner.contains('/') || provider::current().nested_groups()) =>
+        {
+            Ok((owner.to_string(), repo.to_string()))
+        }
+        _ => Err(format!("Expected owner/repo, got {}", reference).into()),
+    }
+}
+
+// File contents at a commit, None when the file doesn't exist there
+pub async fn file_at(
+    owner: &str,
+    repo: &str,
+    path: &str,
+    sha: &str,
+    github_token: Option<&str>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let url = format!(
+        "{}/repos/{}/{}/contents/{}?ref={}",
+        http::github_api(), owner, repo, path, sha
+    );
+    match http::GithubClient::new(github_token).get_raw(&url).await {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if http::is_not_found(e.as_ref()) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// Link to lines on the PR's "Files changed" tab. GitHub names each file's
+// anchor after the SHA-256 of its path, R marks lines of the new version.
+pub fn diff_link(pr_url: &str, path: &str, start: u32, end: u32) -> String {
+    let anchor = format!("diff-{:x}", Sha256::digest(path.as_bytes()));
+    if start == end {
+        format!("{}/files#{}R{}", pr_url, anchor, start)
+    } else {
+        format!("{}/files#{}R{}-R{}", pr_url, anchor, start, end)
+    }
+}
@@ -0,0 +1,97 @@
+// Pattern checks run on every patch alongside the AI review
+
+// First line of the patch containing any of the needles
+fn line_with<'a>(patch: &'a str, needles: &[&str]) -> Option<&'a str> {
+    patch.lines().find(|line| needles.iter().any(|needle| line.contains(needle)))
+}
+
+// Every check that fires, as (rule, line that triggered it, message)
+pub fn check<'a>(patch: &'a str) -> Vec<(&'static str, &'a str, String)> {
+    let mut feedback = Vec::new();
+    let mut flag = |rule: &'static str, line: Option<&'a str>, message: &str| {
+        if let Some(line) = line {
+            feedback.push((rule, line, message.to_string()));
+        }
+    };
+
+    // Basic code hygiene
+    flag(
+        "todo",
+        line_with(patch, &["TODO", "FIXME"]),
+        "Outstanding TODOs/FIXMEs should be addressed before merging",
+    );
+    flag(
+        "debug-print",
+        line_with(patch, &["println!", "dbg!"]),
+        "Remove debug print statements before merging",
+    );
+
+    // Error handling patterns
+    flag(
+        "unwrap",
+        line_with(patch, &["unwrap()"]),
+        "Replace unwrap() calls with proper error handling",
+    );
+    flag(
+        "expect",
+        line_with(patch, &["expect("]),
+        "Consider replacing expect() with more graceful error handling",
+    );
+    flag(
+        "panic",
+        line_with(patch, &["panic!"]),
+        "Consider replacing panic! with Result/Option for graceful error handling",
+    );
+
+    // Memory and performance patterns
+    flag(
+        "clone",
+        line_with(patch, &["Clone", "clone()"]),
+        "Review clone() usage - consider using references where possible",
+    );
+    flag(
+        "box",
+        line_with(patch, &["Box::new"]),
+        "Verify if heap allocation via Box is necessary",
+    );
+    flag(
+        "vec-capacity",
+        line_with(patch, &["Vec::new()"]).filter(|_| !patch.contains("with_capacity")),
+        "Consider using Vec::with_capacity() if the size is known",
+    );
+
+    // Concurrency and async patterns
+    flag(
+        "mutex",
+        line_with(patch, &["Mutex"]).filter(|_| !patch.contains("RwLock")),
+        "Consider if RwLock would be more appropriate than Mutex",
+    );
+    flag(
+        "sequential-await",
+        line_with(patch, &[".await"]).filter(|_| patch.contains("Vec")),
+        "Review concurrent operations on Vec - consider using join_all() for parallel execution",
+    );
+
+    // Security considerations
+    flag(
+        "unsafe",
+        line_with(patch, &["unsafe"]),
+        "Unsafe block detected - ensure safety guarantees are documented",
+    );
+    flag(
+        "raw-pointer",
+        line_with(patch, &["as_ptr", "as_mut_ptr"]),
+        "Raw pointer usage detected - verify memory safety",
+    );
+
+    // Testing patterns
+    let new_fn = patch.lines().find(|l| l.contains("fn ") && !l.contains("test"));
+    let has_test = patch.contains("#[test]");
+    flag(
+        "untested-fn",
+        new_fn.filter(|_| !has_test),
+        "New functions added without corresponding tests",
+    );
+
+    feedback
+}
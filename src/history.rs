@@ -0,0 +1,397 @@
+use crate::OutputBuffer;
+use crate::cache;
+use crate::encryption;
+use crate::github;
+use crate::state;
+use crate::terminal::Color;
+use chrono::{DateTime, Local, Utc};
+use log::info;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const HISTORY_DIR: &str = "history";
+
+// Reviews kept per PR, older ones are dropped
+const MAX_SNAPSHOTS: usize = 20;
+
+// One finding of a review, "" as file for findings about the whole PR
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReviewFinding {
+    // Same finding, same id, across pushes: see ReviewFinding::new
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub rule: String,
+    pub file: String,
+    pub message: String,
+}
+
+impl ReviewFinding {
+    // The id hashes the rule, the file and the line that triggered the
+    // finding with its whitespace normalized, so it survives reformatting
+    // and the code moving around within the file
+    pub fn new(rule: &str, file: &str, line: &str, message: &str) -> Self {
+        let line = line.trim_start_matches(['+', '-']).split_whitespace().collect::<Vec<_>>().join(" ");
+        let hash = Sha256::digest(format!("{}\0{}\0{}", rule, file, line).as_bytes());
+        Self {
+            id: format!("{:x}", hash)[..12].to_string(),
+            rule: rule.to_string(),
+            file: file.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    // Reviews stored before ids existed are matched on their text
+    fn key(&self) -> String {
+        if self.id.is_empty() {
+            format!("{}\0{}", self.file, self.message)
+        } else {
+            self.id.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Status {
+    Open,
+    Resolved,
+    // Resolved by an earlier push and back again
+    Regressed,
+}
+
+// Status of every finding ever reported on the PR, in order of first appearance
+fn lifecycle(snapshots: &[Snapshot]) -> Vec<(&ReviewFinding, Status)> {
+    let Some(latest) = snapshots.last() else {
+        return Vec::new();
+    };
+    let present: Vec<HashSet<String>> = snapshots
+        .iter()
+        .map(|s| s.findings.iter().map(ReviewFinding::key).collect())
+        .collect();
+
+    let mut seen = HashSet::new();
+    let mut statuses = Vec::new();
+    for finding in snapshots.iter().flat_map(|s| &s.findings) {
+        let key = finding.key();
+        if !seen.insert(key.clone()) {
+            continue;
+        }
+        let first = present.iter().position(|p| p.contains(&key)).unwrap_or_default();
+        let status = if !present[present.len() - 1].contains(&key) {
+            Status::Resolved
+        } else if present[first..].iter().any(|p| !p.contains(&key)) {
+            Status::Regressed
+        } else {
+            Status::Open
+        };
+        // Show the latest wording of the finding
+        let finding = latest.findings.iter().find(|f| f.key() == key).unwrap_or(finding);
+        statuses.push((finding, status));
+    }
+    statuses
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Snapshot {
+    taken_at: DateTime<Utc>,
+    head_sha: String,
+    // The PR's title at the time, for rubber recent
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    title: String,
+    findings: Vec<ReviewFinding>,
+    // Build output sizes in bytes, by output
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    sizes: BTreeMap<String, u64>,
+}
+
+fn history_path(owner: &str, repo: &str, pr_number: u32) -> Option<PathBuf> {
+    state::data_dir().map(|dir| {
+        dir.join(HISTORY_DIR)
+            .join(format!("{}__{}.json", state::file_key(owner, repo), pr_number))
+    })
+}
+
+fn load(owner: &str, repo: &str, pr_number: u32) -> Result<Vec<Snapshot>, Box<dyn Error>> {
+    match history_path(owner, repo, pr_number) {
+        Some(path) if path.exists() => Ok(serde_json::from_str(&encryption::read_to_string(&path)?)?),
+        _ => Ok(Vec::new()),
+    }
+}
+
+// Stores the findings and sizes of a review report, newest last
+pub fn save(
+    owner: &str,
+    repo: &str,
+    pr_number: u32,
+    title: &str,
+    head_sha: &str,
+    report: &OutputBuffer,
+) -> Result<(), Box<dyn Error>> {
+    let _lock = state::lock()?;
+    let dir = state::ensure_data_dir()?.join(HISTORY_DIR);
+    fs::create_dir_all(&dir)?;
+
+    let mut snapshots = load(owner, repo, pr_number)?;
+    let findings = with_unreviewed(&snapshots, &report.findings, &report.unreviewed);
+    let stored = findings.len();
+    snapshots.push(Snapshot {
+        taken_at: Utc::now(),
+        head_sha: head_sha.to_string(),
+        title: title.to_string(),
+        findings,
+        sizes: report.sizes.clone(),
+    });
+    let excess = snapshots.len().saturating_sub(MAX_SNAPSHOTS);
+    snapshots.drain(..excess);
+
+    let path = history_path(owner, repo, pr_number).ok_or("Could not determine the data directory")?;
+    encryption::write(&path, serde_json::to_string_pretty(&snapshots)?)?;
+    info!("Stored {} finding(s) in the review history", stored);
+    Ok(())
+}
+
+// A review's findings with the AI findings of the last one on the files the
+// AI didn't review this time, which aren't resolved for going unchecked
+fn with_unreviewed(
+    snapshots: &[Snapshot],
+    findings: &[ReviewFinding],
+    unreviewed: &BTreeSet<String>,
+) -> Vec<ReviewFinding> {
+    let mut current = findings.to_vec();
+    if let Some(last) = snapshots.last() {
+        current.extend(
+            last.findings
+                .iter()
+                .filter(|finding| finding.rule == "ai" && unreviewed.contains(&finding.file))
+                .cloned(),
+        );
+    }
+    current
+}
+
+// Drops the snapshots of a history file taken before the cutoff, deleting the
+// file once none are left. Returns how many went and whether the file did.
+// The caller holds the state lock, with dry_run nothing changes
+pub fn prune(path: &Path, cutoff: DateTime<Utc>, dry_run: bool) -> Result<(usize, bool), Box<dyn Error>> {
+    let mut snapshots: Vec<Snapshot> = serde_json::from_str(&encryption::read_to_string(path)?)?;
+    let before = snapshots.len();
+    snapshots.retain(|snapshot| snapshot.taken_at >= cutoff);
+    let dropped = before - snapshots.len();
+    if !dry_run && dropped > 0 {
+        if snapshots.is_empty() {
+            fs::remove_file(path)?;
+        } else {
+            encryption::write(path, serde_json::to_string_pretty(&snapshots)?)?;
+        }
+    }
+    Ok((dropped, snapshots.is_empty()))
+}
+
+// A PR with stored reviews, as of its latest one
+pub struct Reviewed {
+    pub owner: String,
+    pub repo: String,
+    pub number: u32,
+    // Empty for reviews stored before titles were
+    pub title: String,
+    pub reviewed_at: DateTime<Utc>,
+    pub findings: usize,
+}
+
+// Every PR with stored reviews, most recently reviewed first
+pub fn reviewed() -> Result<Vec<Reviewed>, Box<dyn Error>> {
+    let Some(dir) = state::data_dir().map(|dir| dir.join(HISTORY_DIR)).filter(|dir| dir.exists()) else {
+        return Ok(Vec::new());
+    };
+    let _lock = state::lock_shared()?;
+    let mut reviewed = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        // owner__repo__123.json, the repository name may itself contain __
+        let Some(stem) = path.file_name().and_then(|name| name.to_str()?.strip_suffix(".json")) else {
+            continue;
+        };
+        let Some(((owner, repo), number)) = stem
+            .rsplit_once("__")
+            .and_then(|(rest, number)| Some((rest.split_once("__")?, number.parse().ok()?)))
+        else {
+            continue;
+        };
+        let snapshots: Vec<Snapshot> = serde_json::from_str(&encryption::read_to_string(&path)?)?;
+        let Some(latest) = snapshots.last() else {
+            continue;
+        };
+        reviewed.push(Reviewed {
+            owner: owner.replace('~', "/"),
+            repo: repo.to_string(),
+            number,
+            title: snapshots.iter().rev().map(|s| s.title.clone()).find(|t| !t.is_empty()).unwrap_or_default(),
+            reviewed_at: latest.taken_at,
+            findings: latest.findings.len(),
+        });
+    }
+    reviewed.sort_by_key(|r| std::cmp::Reverse(r.reviewed_at));
+    Ok(reviewed)
+}
+
+fn describe(snapshot: &Snapshot) -> String {
+    format!(
+        "{} at {}",
+        snapshot.taken_at.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+        &snapshot.head_sha[..snapshot.head_sha.len().min(7)]
+    )
+}
+
+fn finding_line(finding: &ReviewFinding) -> String {
+    let id = if finding.id.is_empty() { String::new() } else { format!("[{}] ", finding.id) };
+    if finding.file.is_empty() {
+        format!("{}{}", id, finding.message)
+    } else {
+        format!("{}{}: {}", id, finding.file, finding.message)
+    }
+}
+
+// One line for the review report: how the findings moved since the last review
+pub fn summary(
+    owner: &str,
+    repo: &str,
+    pr_number: u32,
+    findings: &[ReviewFinding],
+    unreviewed: &BTreeSet<String>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let mut snapshots = {
+        let _lock = state::lock_shared()?;
+        load(owner, repo, pr_number)?
+    };
+    if snapshots.is_empty() {
+        return Ok(None);
+    }
+    let findings = with_unreviewed(&snapshots, findings, unreviewed);
+    snapshots.push(Snapshot {
+        taken_at: Utc::now(),
+        head_sha: String::new(),
+        title: String::new(),
+        findings,
+        sizes: BTreeMap::new(),
+    });
+    let statuses = lifecycle(&snapshots);
+    let count = |status: Status| statuses.iter().filter(|(_, s)| *s == status).count();
+    Ok(Some(format!(
+        "Across {} reviews of this PR: {} finding(s) open, {} resolved, {} regressed (see rubber history status)",
+        snapshots.len(),
+        count(Status::Open),
+        count(Status::Resolved),
+        count(Status::Regressed)
+    )))
+}
+
+// Every finding ever reported on a PR with its status
+pub fn status(reference: &str, output: &mut OutputBuffer) -> Result<(), Box<dyn Error>> {
+    let (owner, repo, pr_number) = github::parse_pr_ref(reference)?;
+    let snapshots = {
+        let _lock = state::lock_shared()?;
+        load(&owner, &repo, pr_number)?
+    };
+    if snapshots.is_empty() {
+        return Err(format!("No stored reviews for {}", reference).into());
+    }
+
+    let statuses = lifecycle(&snapshots);
+    let theme = output.theme;
+    output.add_header(&format!("{}/{}#{}: findings", owner, repo, pr_number));
+    output.add_box_content(&format!(
+        "{} review(s), from {} to {}",
+        snapshots.len(),
+        describe(&snapshots[0]),
+        describe(&snapshots[snapshots.len() - 1])
+    ));
+    for (title, wanted, color) in [
+        ("Regressed", Status::Regressed, Color::Red),
+        ("Open", Status::Open, Color::Yellow),
+        ("Resolved", Status::Resolved, Color::Green),
+    ] {
+        let lines: Vec<String> = statuses
+            .iter()
+            .filter(|(_, status)| *status == wanted)
+            .map(|(finding, _)| theme.paint(&finding_line(finding), color))
+            .collect();
+        output.add_section(&format!("{} ({})", title, lines.len()));
+        if lines.is_empty() {
+            output.add_box_content("None");
+        } else {
+            output.add_box_content(&lines.join("\n"));
+        }
+    }
+
+    // The size of every output across the pushes to the PR
+    let outputs: BTreeSet<&String> = snapshots.iter().flat_map(|s| s.sizes.keys()).collect();
+    if !outputs.is_empty() {
+        output.add_section("Output Sizes");
+        let mut lines = Vec::new();
+        for name in outputs {
+            lines.push(name.clone());
+            let mut previous = None;
+            for snapshot in snapshots.iter().filter(|s| s.sizes.contains_key(name)) {
+                let size = snapshot.sizes[name];
+                let change = match previous {
+                    Some(previous) if previous != size => {
+                        let color = if size > previous { Color::Red } else { Color::Green };
+                        theme.paint(&format!(" ({:+} B)", size as i64 - previous as i64), color)
+                    }
+                    _ => String::new(),
+                };
+                lines.push(format!("  {}  {}{}", describe(snapshot), cache::human_size(size), change));
+                previous = Some(size);
+            }
+        }
+        output.add_box_content(&lines.join("\n"));
+    }
+    output.add_diff_separator();
+    Ok(())
+}
+
+// Compares the latest review of a PR with the one before it
+pub fn diff(reference: &str, output: &mut OutputBuffer) -> Result<(), Box<dyn Error>> {
+    let (owner, repo, pr_number) = github::parse_pr_ref(reference)?;
+    let snapshots = {
+        let _lock = state::lock_shared()?;
+        load(&owner, &repo, pr_number)?
+    };
+    let [.., previous, latest] = snapshots.as_slice() else {
+        return Err(format!(
+            "{} has {} stored review(s), at least two are needed to compare",
+            reference,
+            snapshots.len()
+        )
+        .into());
+    };
+
+    let before: HashSet<String> = previous.findings.iter().map(ReviewFinding::key).collect();
+    let after: HashSet<String> = latest.findings.iter().map(ReviewFinding::key).collect();
+    let resolved: Vec<&ReviewFinding> = previous.findings.iter().filter(|f| !after.contains(&f.key())).collect();
+    let persisting: Vec<&ReviewFinding> = latest.findings.iter().filter(|f| before.contains(&f.key())).collect();
+    let new: Vec<&ReviewFinding> = latest.findings.iter().filter(|f| !before.contains(&f.key())).collect();
+
+    let theme = output.theme;
+    output.add_header(&format!("{}/{}#{}: review history", owner, repo, pr_number));
+    output.add_box_content(&format!("Previous: {}\nLatest:   {}", describe(previous), describe(latest)));
+    for (title, findings, color) in [
+        ("Resolved", &resolved, Color::Green),
+        ("Still Open", &persisting, Color::Yellow),
+        ("New", &new, Color::Red),
+    ] {
+        output.add_section(&format!("{} ({})", title, findings.len()));
+        if findings.is_empty() {
+            output.add_box_content("None");
+        } else {
+            let lines: Vec<String> = findings.iter().map(|f| theme.paint(&finding_line(f), color)).collect();
+            output.add_box_content(&lines.join("\n"));
+        }
+    }
+    output.add_diff_separator();
+    Ok(())
+}
@@ -0,0 +1,1096 @@
+use crate::audit;
+use crate::config::TokenRoute;
+use crate::encryption;
+use log::warn;
+use serde::Serialize;
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+// Set once by --debug-http, every request made afterwards is written there
+static CAPTURE_DIR: OnceLock<PathBuf> = OnceLock::new();
+static SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+// Cleared by store_patches = false, captures then leave out the PR's diffs
+static STORE_PATCHES: AtomicBool = AtomicBool::new(true);
+// Set by --read-only, writes are shown and logged instead of sent
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+// Credentials loaded from the config rather than the environment
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+// Built once and shared by every request, so connections to GitHub and the
+// AI API stay open and are reused instead of paying a TLS handshake per call
+static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+// Drives the requests of synchronous code called outside the main runtime
+static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+// Tries per request when a server is overloaded or asks to slow down, from
+// the config's max_attempts
+static MAX_ATTEMPTS: AtomicU32 = AtomicU32::new(DEFAULT_MAX_ATTEMPTS);
+// Set after GitHub reports a secondary rate limit, every request waits until then
+static PAUSED_UNTIL: Mutex<Option<Instant>> = Mutex::new(None);
+// Set by the commands that make many requests in a row, spaces out the GitHub reads
+static THROTTLE: AtomicBool = AtomicBool::new(false);
+// When the next GitHub read and write may start
+static NEXT_READ: Mutex<Option<Instant>> = Mutex::new(None);
+static NEXT_WRITE: Mutex<Option<Instant>> = Mutex::new(None);
+
+// The [[token]] routes of the config with their tokens, tried before the
+// token a request was given
+static TOKEN_ROUTES: OnceLock<Vec<(TokenRoute, String)>> = OnceLock::new();
+// Identifies rubber to GitHub and Anthropic, with the config's user_agent
+// appended. Set before the first request
+static USER_AGENT: OnceLock<String> = OnceLock::new();
+// REST API root, another one for GitHub Enterprise Server
+static GITHUB_API: OnceLock<String> = OnceLock::new();
+
+const USER_AGENT_BASE: &str = concat!(
+    "rubber/",
+    env!("CARGO_PKG_VERSION"),
+    " (+",
+    env!("CARGO_PKG_REPOSITORY"),
+    ")"
+);
+// The REST API version the responses are parsed as
+pub const GITHUB_API_VERSION: &str = "2022-11-28";
+// Enough idle connections per host for the concurrent fetches of the PR list
+const IDLE_CONNECTIONS_PER_HOST: usize = 8;
+const IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+// GitHub allows 900 points of REST reads per minute before a secondary limit,
+// and asks for a second between requests that create content
+const THROTTLED_READ_INTERVAL: Duration = Duration::from_millis(70);
+const WRITE_INTERVAL: Duration = Duration::from_secs(1);
+// Waits after a secondary rate limit when GitHub doesn't say how long, doubled
+// on each retry
+const SECONDARY_LIMIT_WAIT: Duration = Duration::from_secs(60);
+const SECONDARY_LIMIT_MAX_WAIT: Duration = Duration::from_secs(300);
+const DEFAULT_MAX_ATTEMPTS: u32 = 4;
+// Rate limited, or a server failing or overloaded for now (529 is Anthropic's
+// overloaded). Writes to GitHub are only retried when refused outright, a 502
+// may come after the comment was posted
+const RETRY_STATUSES: [u16; 6] = [429, 500, 502, 503, 504, 529];
+const REFUSED_STATUSES: [u16; 2] = [429, 503];
+// Cap of the random wait before a retry, doubled from RETRY_WAIT up to RETRY_MAX_WAIT
+const RETRY_WAIT: Duration = Duration::from_secs(1);
+const RETRY_MAX_WAIT: Duration = Duration::from_secs(60);
+// Pages followed by github_get_all and its counterparts for the other forges
+const MAX_PAGES: usize = 30;
+
+const SECRET_HEADERS: [&str; 6] = [
+    "authorization",
+    "x-api-key",
+    "private-token",
+    "cookie",
+    "set-cookie",
+    "x-amz-security-token",
+];
+const TOKEN_PREFIXES: [&str; 10] = [
+    "ghp_",
+    "gho_",
+    "ghu_",
+    "ghs_",
+    "ghr_",
+    "github_pat_",
+    "glpat-",
+    "sk-ant-",
+    "sk-proj-",
+    "sk-svcacct-",
+];
+const REDACTED: &str = "[REDACTED]";
+
+// Why GitHub refused a request for going too fast. A 403 is a permission
+// problem otherwise
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimit {
+    // The hourly quota is used up, see `rubber limits`
+    Primary,
+    // Too many requests in a short time or at once
+    Secondary,
+}
+
+// A response with an error status, kept apart from transport errors so
+// callers can react to specific codes
+#[derive(Debug)]
+pub struct StatusError {
+    pub url: String,
+    pub status: u16,
+    pub message: Option<String>,
+    pub rate_limit: Option<RateLimit>,
+}
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.rate_limit {
+            Some(RateLimit::Primary) => {
+                return write!(
+                    f,
+                    "{}: the GitHub API rate limit is used up, see rubber limits for when it resets",
+                    self.url
+                );
+            }
+            Some(RateLimit::Secondary) => {
+                return write!(
+                    f,
+                    "{}: GitHub's secondary rate limit was still hit after {} attempts, too many requests \
+                    were made in a short time. Wait a few minutes before trying again",
+                    self.url,
+                    max_attempts()
+                );
+            }
+            None => {}
+        }
+        write!(f, "{}: status code {}", self.url, self.status)?;
+        if let Some(message) = &self.message {
+            write!(f, " ({})", message)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for StatusError {}
+
+impl StatusError {
+    // Worth another try: rate limited, or the server failing for now
+    pub fn is_transient(&self) -> bool {
+        RETRY_STATUSES.contains(&self.status)
+    }
+}
+
+pub fn is_not_found(error: &(dyn Error + 'static)) -> bool {
+    error.downcast_ref::<StatusError>().is_some_and(|e| e.status == 404)
+}
+
+pub fn is_rate_limited(error: &(dyn Error + 'static)) -> bool {
+    error.downcast_ref::<StatusError>().is_some_and(|e| e.rate_limit.is_some())
+}
+
+// Whether a response comes from the GitHub API, the only one whose 403s and
+// 429s are told apart by rate_limit(). Other hosts' 429s are retried as such
+fn from_github(url: &reqwest::Url) -> bool {
+    reqwest::Url::parse(github_api()).is_ok_and(|api| {
+        api.host_str() == url.host_str() && api.port_or_known_default() == url.port_or_known_default()
+    })
+}
+
+// Tells rate limits apart from permission errors, which also get a 403.
+// Secondary limits say so in the message, the primary one empties the quota
+fn rate_limit(status: u16, headers: &[(String, String)], message: Option<&str>) -> Option<RateLimit> {
+    if status != 403 && status != 429 {
+        return None;
+    }
+    let message = message.unwrap_or_default().to_lowercase();
+    if message.contains("secondary rate limit") || message.contains("abuse") {
+        return Some(RateLimit::Secondary);
+    }
+    let header = |name: &str| headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str());
+    if header("x-ratelimit-remaining") == Some("0") {
+        return Some(RateLimit::Primary);
+    }
+    // GitHub only sends 429 for secondary limits
+    (status == 429).then_some(RateLimit::Secondary)
+}
+
+pub fn set_max_attempts(attempts: u32) {
+    MAX_ATTEMPTS.store(attempts.max(1), Ordering::Relaxed);
+}
+
+pub fn max_attempts() -> u32 {
+    MAX_ATTEMPTS.load(Ordering::Relaxed)
+}
+
+// Retry-After, in seconds or as an HTTP date
+fn retry_after(headers: &[(String, String)]) -> Option<Duration> {
+    let value = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("retry-after"))
+        .map(|(_, value)| value.trim())?;
+    value.parse::<u64>().ok().map(Duration::from_secs).or_else(|| {
+        let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+        Some((at.to_utc() - chrono::Utc::now()).to_std().unwrap_or_default())
+    })
+}
+
+// Retry-After when it asks for no more than `max`. A server asking for longer
+// isn't waited for, the request fails instead of holding the command up
+fn retry_after_within(headers: &[(String, String)], max: Duration) -> Result<Option<Duration>, Duration> {
+    match retry_after(headers) {
+        Some(wait) if wait > max => Err(wait),
+        wait => Ok(wait),
+    }
+}
+
+// How long to back off after the attempt-th secondary limit in a row:
+// Retry-After when GitHub sends it, otherwise a minute and doubling. Err with
+// the wait asked for when that is more than SECONDARY_LIMIT_MAX_WAIT
+fn secondary_limit_wait(headers: &[(String, String)], attempt: u32) -> Result<Duration, Duration> {
+    let doubled = SECONDARY_LIMIT_WAIT.saturating_mul(2u32.saturating_pow(attempt));
+    Ok(retry_after_within(headers, SECONDARY_LIMIT_MAX_WAIT)?.unwrap_or(doubled.min(SECONDARY_LIMIT_MAX_WAIT)))
+}
+
+// Between half and all of a cap doubling with each failure in a row, so
+// clients that failed together don't all come back together
+pub fn backoff(attempt: u32) -> Duration {
+    let cap = RETRY_WAIT.saturating_mul(2u32.saturating_pow(attempt)).min(RETRY_MAX_WAIT);
+    let random = RandomState::new().build_hasher().finish();
+    cap / 2 + (cap / 2).mul_f64((random % 1000) as f64 / 1000.0)
+}
+
+// How long to back off after the attempt-th failure in a row: Retry-After
+// when the server sends it, otherwise backoff(). Err with the wait asked for
+// when that is more than RETRY_MAX_WAIT
+fn retry_wait(headers: &[(String, String)], attempt: u32) -> Result<Duration, Duration> {
+    Ok(retry_after_within(headers, RETRY_MAX_WAIT)?.unwrap_or_else(|| backoff(attempt)))
+}
+
+// Holds every request back for a while, the concurrent ones included
+fn pause(wait: Duration) {
+    let until = Instant::now() + wait;
+    let mut paused = PAUSED_UNTIL.lock().unwrap_or_else(|e| e.into_inner());
+    if paused.is_none_or(|current| current < until) {
+        *paused = Some(until);
+    }
+}
+
+async fn wait_if_paused() {
+    let until = *PAUSED_UNTIL.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(wait) = until.and_then(|until| until.checked_duration_since(Instant::now())) {
+        tokio::time::sleep(wait).await;
+    }
+}
+
+pub fn throttle() {
+    THROTTLE.store(true, Ordering::Relaxed);
+}
+
+// Takes the next free slot, so requests in flight at once leave at most one
+// per interval
+async fn pace(next: &Mutex<Option<Instant>>, interval: Duration) {
+    let now = Instant::now();
+    let start = {
+        let mut next = next.lock().unwrap_or_else(|e| e.into_inner());
+        let start = next.filter(|n| *n > now).unwrap_or(now);
+        *next = Some(start + interval);
+        start
+    };
+    if start > now {
+        tokio::time::sleep(start - now).await;
+    }
+}
+
+#[derive(Serialize, Clone)]
+struct Message {
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Exchange {
+    method: String,
+    url: String,
+    status: Option<u16>,
+    request: Message,
+    response: Option<Message>,
+    error: Option<String>,
+}
+
+impl Exchange {
+    pub fn new(method: &str, url: &str, headers: Vec<(String, String)>, body: &str) -> Self {
+        Self {
+            method: method.to_string(),
+            url: url.to_string(),
+            status: None,
+            request: Message { headers, body: body.to_string() },
+            response: None,
+            error: None,
+        }
+    }
+
+    pub fn response(mut self, status: u16, headers: Vec<(String, String)>, body: &str) -> Self {
+        self.status = Some(status);
+        self.response = Some(Message { headers, body: body.to_string() });
+        self
+    }
+
+    pub fn error(mut self, error: &str) -> Self {
+        self.error = Some(error.to_string());
+        self
+    }
+}
+
+pub fn set_token_routes(routes: Vec<(TokenRoute, String)>) {
+    let _ = TOKEN_ROUTES.set(routes);
+}
+
+// The token of the first route matching the host of the URL and the
+// repository, read from the URL of REST requests. GraphQL URLs don't name
+// one, so the caller says which repository a query is about
+fn routed_token(url: &str, repository: Option<&str>) -> Option<&'static str> {
+    let routes = TOKEN_ROUTES.get()?;
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let host = rest.split('/').next().unwrap_or_default();
+    let repo = repository.map(str::to_string).or_else(|| {
+        rest.split_once("/repos/").and_then(|(_, path)| {
+            let mut parts = path.split(['/', '?']);
+            Some(format!("{}/{}", parts.next()?, parts.next()?))
+        })
+    });
+    routes
+        .iter()
+        .find(|(route, _)| route.matches(host, repo.as_deref()))
+        .map(|(_, token)| token.as_str())
+}
+
+pub fn set_user_agent_suffix(suffix: &str) {
+    let suffix = suffix.trim();
+    if !suffix.is_empty() && USER_AGENT.set(format!("{} {}", USER_AGENT_BASE, suffix)).is_err() {
+        warn!("The user agent was set after the first request, not adding {}", suffix);
+    }
+}
+
+pub fn user_agent() -> &'static str {
+    USER_AGENT.get_or_init(|| USER_AGENT_BASE.to_string())
+}
+
+// The first one set wins, so --api-url and GITHUB_API_URL, set before the
+// config is read, override its api_url
+pub fn set_github_api(url: &str) {
+    let url = url.trim().trim_end_matches('/');
+    if !url.is_empty() {
+        let _ = GITHUB_API.set(url.to_string());
+    }
+}
+
+// Without a trailing slash: https://api.github.com, or e.g.
+// https://ghe.example.com/api/v3
+pub fn github_api() -> &'static str {
+    GITHUB_API.get().map_or("https://api.github.com", String::as_str)
+}
+
+// The host repositories are cloned from: github.com for the public API, the
+// server itself for GitHub Enterprise
+pub fn github_host() -> Option<String> {
+    let api = reqwest::Url::parse(github_api()).ok()?;
+    let host = api.host_str()?;
+    Some(if host == "api.github.com" { "github.com" } else { host }.to_string())
+}
+
+// GitHub Enterprise Server serves GraphQL at /api/graphql, next to /api/v3
+fn github_graphql_url() -> String {
+    match github_api().strip_suffix("/api/v3") {
+        Some(root) => format!("{}/api/graphql", root),
+        None => format!("{}/graphql", github_api()),
+    }
+}
+
+// Every request, to the forges, the AI API and webhooks, over HTTP/2 when
+// negotiated. Responses are gzip compressed when the server supports it
+pub fn client() -> &'static reqwest::Client {
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .user_agent(user_agent())
+            .gzip(true)
+            .pool_max_idle_per_host(IDLE_CONNECTIONS_PER_HOST)
+            .pool_idle_timeout(IDLE_TIMEOUT)
+            .build()
+            .unwrap_or_else(|_| reqwest::Client::new())
+    })
+}
+
+pub fn capture_to(dir: PathBuf) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(&dir)?;
+    CAPTURE_DIR
+        .set(dir)
+        .map_err(|_| "HTTP capture directory already set".into())
+}
+
+pub fn set_read_only() {
+    READ_ONLY.store(true, Ordering::Relaxed);
+}
+
+pub fn read_only() -> bool {
+    READ_ONLY.load(Ordering::Relaxed)
+}
+
+// What a write would have sent, on stderr so it stays out of the report.
+// Comment bodies are printed as text rather than as a JSON string
+fn preview(method: &str, target: &str, body: Option<&str>) {
+    eprintln!("[read-only] {} {} (not sent)", method, target);
+    let Some(body) = body else {
+        return;
+    };
+    match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(serde_json::Value::Object(fields)) => {
+            for (key, value) in fields.iter().filter(|(key, _)| *key != "body") {
+                eprintln!("  {}: {}", key, value);
+            }
+            if let Some(text) = fields.get("body").and_then(|b| b.as_str()) {
+                eprintln!("  body:");
+                for line in text.lines() {
+                    eprintln!("    {}", line);
+                }
+            }
+        }
+        _ => eprintln!("  {}", body),
+    }
+}
+
+pub fn register_secret(secret: String) {
+    if !secret.is_empty()
+        && let Ok(mut secrets) = SECRETS.lock()
+    {
+        secrets.push(secret);
+    }
+}
+
+pub fn capturing() -> bool {
+    CAPTURE_DIR.get().is_some()
+}
+
+// Masks the tokens rubber itself was given plus anything shaped like a GitHub,
+// Anthropic, OpenAI or AWS credential, so captures can be attached to bug reports as is
+pub fn redact(text: &str) -> String {
+    let mut redacted = text.to_string();
+    for name in [
+        "GITHUB_TOKEN",
+        "GITLAB_TOKEN",
+        "BITBUCKET_APP_PASSWORD",
+        "GITEA_TOKEN",
+        "ANTHROPIC_API_KEY",
+        "OPENAI_API_KEY",
+        "AWS_SECRET_ACCESS_KEY",
+        "AWS_SESSION_TOKEN",
+        "RUBBER_ENCRYPTION_KEY",
+    ] {
+        if let Ok(secret) = env::var(name)
+            && !secret.is_empty()
+        {
+            redacted = redacted.replace(&secret, REDACTED);
+        }
+    }
+    if let Ok(secrets) = SECRETS.lock() {
+        for secret in secrets.iter() {
+            redacted = redacted.replace(secret, REDACTED);
+        }
+    }
+
+    for prefix in TOKEN_PREFIXES {
+        let mut result = String::with_capacity(redacted.len());
+        let mut rest = redacted.as_str();
+        while let Some(start) = rest.find(prefix) {
+            let token_len = rest[start..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+                .unwrap_or(rest.len() - start);
+            result.push_str(&rest[..start]);
+            result.push_str(REDACTED);
+            rest = &rest[start + token_len..];
+        }
+        result.push_str(rest);
+        redacted = result;
+    }
+    redacted
+}
+
+fn sanitize_headers(headers: &[(String, String)]) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            if SECRET_HEADERS.contains(&name.to_lowercase().as_str()) {
+                (name.clone(), REDACTED.to_string())
+            } else {
+                (name.clone(), redact(value))
+            }
+        })
+        .collect()
+}
+
+pub fn omit_patches() {
+    STORE_PATCHES.store(false, Ordering::Relaxed);
+}
+
+const OMITTED: &str = "<omitted, store_patches = false>";
+
+// Diffs as the forges return them (GitHub's files and review comments,
+// GitLab's diffs), file contents from GitHub's contents API and the AI
+// prompts they are sent in
+fn omit_patches_in(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            // A file of the contents API, its content base64 encoded
+            let file = fields.contains_key("encoding");
+            for (key, value) in fields.iter_mut() {
+                match key.as_str() {
+                    "patch" | "diff_hunk" | "diff" if value.is_string() => *value = OMITTED.into(),
+                    "content" if file && value.is_string() => *value = OMITTED.into(),
+                    "messages" if value.is_array() => {
+                        for message in value.as_array_mut().into_iter().flatten() {
+                            if let Some(content) = message.get_mut("content") {
+                                *content = OMITTED.into();
+                            }
+                        }
+                    }
+                    _ => omit_patches_in(value),
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(omit_patches_in),
+        _ => {}
+    }
+}
+
+fn without_patches(body: &str, url: &str) -> String {
+    match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(mut value) => {
+            omit_patches_in(&mut value);
+            value.to_string()
+        }
+        // A diff fetched whole
+        Err(_) if body.starts_with("diff --git") => OMITTED.to_string(),
+        // A file fetched raw from the contents API
+        Err(_) if url.contains("/contents/") => OMITTED.to_string(),
+        Err(_) => body.to_string(),
+    }
+}
+
+fn sanitize(message: &Message, url: &str) -> Message {
+    let body = redact(&message.body);
+    Message {
+        headers: sanitize_headers(&message.headers),
+        body: if STORE_PATCHES.load(Ordering::Relaxed) { body } else { without_patches(&body, url) },
+    }
+}
+
+pub fn capture(exchange: Exchange) {
+    let Some(dir) = CAPTURE_DIR.get() else {
+        return;
+    };
+
+    let sequence = SEQUENCE.fetch_add(1, Ordering::SeqCst) + 1;
+    let endpoint: String = exchange
+        .url
+        .split("://")
+        .last()
+        .unwrap_or_default()
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .take(80)
+        .collect();
+    let path = dir.join(format!("{:04}-{}-{}.json", sequence, exchange.method, endpoint));
+
+    let sanitized = Exchange {
+        method: exchange.method,
+        url: redact(&exchange.url),
+        status: exchange.status,
+        request: sanitize(&exchange.request, &exchange.url),
+        response: exchange.response.as_ref().map(|response| sanitize(response, &exchange.url)),
+        error: exchange.error.as_deref().map(redact),
+    };
+
+    let written = serde_json::to_string_pretty(&sanitized)
+        .map_err(|e| e.to_string())
+        .and_then(|json| encryption::write(&path, json).map_err(|e| e.to_string()));
+    if let Err(e) = written {
+        log::warn!("Could not write HTTP capture {}: {}", path.display(), e);
+    }
+}
+
+type Headers = Vec<(String, String)>;
+
+fn header_pairs(headers: &reqwest::header::HeaderMap) -> Headers {
+    headers
+        .iter()
+        .map(|(name, value)| (name.to_string(), String::from_utf8_lossy(value.as_bytes()).into_owned()))
+        .collect()
+}
+
+// GitHub and Anthropic both explain rejections in the body
+fn error_message(body: &str) -> Option<String> {
+    serde_json::from_str::<serde_json::Value>(body).ok().and_then(|v| {
+        v["message"]
+            .as_str()
+            .or(v["error"]["message"].as_str())
+            .map(str::to_string)
+    })
+}
+
+// Runs a request from the few synchronous commands, like doctor. On the main
+// runtime the worker thread is handed over first, so other tasks carry on
+pub fn wait<F: Future>(future: F) -> F::Output {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => tokio::task::block_in_place(|| handle.block_on(future)),
+        Err(_) => RUNTIME
+            .get_or_init(|| {
+                tokio::runtime::Builder::new_multi_thread()
+                    .worker_threads(2)
+                    .enable_all()
+                    .build()
+                    .expect("Could not start the HTTP runtime")
+            })
+            .block_on(future),
+    }
+}
+
+// Sends a request until it gets an answer worth returning: waits out GitHub's
+// secondary rate limit and retries the statuses in `retry`.
+// The successful response comes back with its capture still to be completed
+// once the body is read, failures as a StatusError
+async fn execute(
+    request: reqwest::Request,
+    retry: &[u16],
+) -> Result<(reqwest::Response, Option<Exchange>), Box<dyn Error>> {
+    let url = request.url().to_string();
+    let exchange = capturing().then(|| {
+        let body = request.body().and_then(|b| b.as_bytes()).unwrap_or_default();
+        Exchange::new(
+            request.method().as_str(),
+            &url,
+            header_pairs(request.headers()),
+            &String::from_utf8_lossy(body),
+        )
+    });
+
+    let mut attempt = 0;
+    loop {
+        wait_if_paused().await;
+        let attempted = request.try_clone().ok_or("The request body can't be sent again")?;
+        let response = match client().execute(attempted).await {
+            Ok(response) => response,
+            Err(e) => {
+                if let Some(exchange) = exchange {
+                    capture(exchange.error(&e.to_string()));
+                }
+                return Err(e.into());
+            }
+        };
+        if response.status().is_success() {
+            return Ok((response, exchange));
+        }
+        let status = response.status().as_u16();
+        let headers = header_pairs(response.headers());
+        let github = from_github(response.url());
+        let body = response.text().await.unwrap_or_default();
+        if let Some(exchange) = &exchange {
+            capture(exchange.clone().response(status, headers.clone(), &body));
+        }
+        let message = error_message(&body);
+        let rate_limit = if github { rate_limit(status, &headers, message.as_deref()) } else { None };
+        let retries_left = attempt + 1 < max_attempts();
+        if rate_limit == Some(RateLimit::Secondary) && retries_left {
+            let wait = match secondary_limit_wait(&headers, attempt) {
+                Ok(wait) => wait,
+                Err(asked) => {
+                    warn!("GitHub asks to wait {}s after its secondary rate limit, not retrying", asked.as_secs());
+                    return Err(Box::new(StatusError { url, status, message, rate_limit }));
+                }
+            };
+            attempt += 1;
+            warn!(
+                "GitHub secondary rate limit hit, waiting {}s before retrying ({}/{})",
+                wait.as_secs(),
+                attempt,
+                max_attempts() - 1
+            );
+            pause(wait);
+            continue;
+        }
+        if rate_limit.is_none() && retry.contains(&status) && retries_left {
+            let wait = match retry_wait(&headers, attempt) {
+                Ok(wait) => wait,
+                Err(asked) => {
+                    warn!("{} answered {} and asks to wait {}s, not retrying", redact(&url), status, asked.as_secs());
+                    return Err(Box::new(StatusError { url, status, message, rate_limit }));
+                }
+            };
+            attempt += 1;
+            warn!(
+                "{} answered {}, retrying in {:.1}s ({}/{})",
+                redact(&url),
+                status,
+                wait.as_secs_f64(),
+                attempt,
+                max_attempts() - 1
+            );
+            tokio::time::sleep(wait).await;
+            continue;
+        }
+        return Err(Box::new(StatusError { url, status, message, rate_limit }));
+    }
+}
+
+// The response body and headers
+async fn fetch(request: reqwest::Request, retry: &[u16]) -> Result<(String, Headers), Box<dyn Error>> {
+    let (response, exchange) = execute(request, retry).await?;
+    let status = response.status().as_u16();
+    let headers = header_pairs(response.headers());
+    let body = response.text().await?;
+    if let Some(exchange) = exchange {
+        capture(exchange.response(status, headers.clone(), &body));
+    }
+    Ok((body, headers))
+}
+
+// A download of at most max_bytes, given up on as soon as it's past them
+// Writes the body to sink as it arrives and returns its length
+async fn fetch_bytes(
+    request: reqwest::Request,
+    max_bytes: usize,
+    sink: &mut impl io::Write,
+) -> Result<usize, Box<dyn Error>> {
+    let (mut response, exchange) = execute(request, &RETRY_STATUSES).await?;
+    let status = response.status().as_u16();
+    let too_large = || format!("larger than {} bytes", max_bytes);
+    if response.content_length().is_some_and(|length| length > max_bytes as u64) {
+        return Err(too_large().into());
+    }
+    let mut written = 0;
+    while let Some(chunk) = response.chunk().await? {
+        if written + chunk.len() > max_bytes {
+            return Err(too_large().into());
+        }
+        sink.write_all(&chunk)?;
+        written += chunk.len();
+    }
+    if let Some(exchange) = exchange {
+        capture(exchange.response(status, Vec::new(), &format!("<{} bytes>", written)));
+    }
+    Ok(written)
+}
+
+fn with_json(request: reqwest::RequestBuilder, body: Option<&str>) -> reqwest::RequestBuilder {
+    match body {
+        Some(body) => request
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_string()),
+        None => request,
+    }
+}
+
+// The AI API: same capture, retries and errors as the forges, and the
+// response headers for the usage records. A successful body is also handed
+// to on_line a line at a time as it streams in
+pub async fn send_async(
+    request: reqwest::RequestBuilder,
+    on_line: &mut dyn FnMut(&str),
+) -> Result<(String, reqwest::header::HeaderMap), Box<dyn Error>> {
+    let (response, exchange) = execute(request.build()?, &RETRY_STATUSES).await?;
+    let status = response.status().as_u16();
+    let headers = response.headers().clone();
+    let body = read_lines(response, on_line).await?;
+    if let Some(exchange) = exchange {
+        capture(exchange.response(status, header_pairs(&headers), &body));
+    }
+    Ok((body, headers))
+}
+
+async fn read_lines(mut response: reqwest::Response, on_line: &mut dyn FnMut(&str)) -> Result<String, Box<dyn Error>> {
+    let mut body = Vec::new();
+    let mut start = 0;
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        // Lines end on a newline byte, which never splits a UTF-8 character
+        while let Some(end) = body[start..].iter().position(|b| *b == b'\n') {
+            on_line(String::from_utf8_lossy(&body[start..start + end]).trim_end_matches('\r'));
+            start += end + 1;
+        }
+    }
+    if start < body.len() {
+        on_line(&String::from_utf8_lossy(&body[start..]));
+    }
+    Ok(String::from_utf8(body)?)
+}
+
+// Sends a request that changes something and records it in the audit log,
+// which every write goes through. Only refusals are retried, a 502 may come
+// after the comment was posted
+async fn write(
+    request: reqwest::RequestBuilder,
+    target: &str,
+    body: Option<&str>,
+) -> Result<String, Box<dyn Error>> {
+    let request = with_json(request, body).build()?;
+    let method = request.method().to_string();
+    // An empty object parses for every caller, as if GitHub had created nothing
+    let result = if read_only() {
+        preview(&method, target, body);
+        Ok("{}".to_string())
+    } else {
+        pace(&NEXT_WRITE, WRITE_INTERVAL).await;
+        fetch(request, &REFUSED_STATUSES).await.map(|(body, _)| body)
+    };
+    if let Err(e) = audit::record_write(&method, target, body, &result) {
+        warn!("Could not record {} {} in the audit log: {}", method, target, e);
+    }
+    result
+}
+
+// Every page of a list endpoint as one JSON array, following the Link
+// headers. Stops after MAX_PAGES, GitHub lists at most 3000 files of a PR.
+// GitLab and Gitea page their lists the same way. Lists wrapped in an object
+// with their count, like check runs and artifacts, are unwrapped. Each item
+// goes through each as its page comes in, before the next one is read
+async fn get_all(
+    url: &str,
+    read: impl AsyncFn(&str) -> Result<(String, Headers), Box<dyn Error>>,
+    each: impl Fn(&mut serde_json::Value),
+) -> Result<String, Box<dyn Error>> {
+    let mut next = Some(if url.contains("per_page=") {
+        url.to_string()
+    } else {
+        format!("{}{}per_page=100", url, if url.contains('?') { '&' } else { '?' })
+    });
+    let mut items: Vec<serde_json::Value> = Vec::new();
+    let mut pages = 0;
+    while let Some(url) = next.take() {
+        let (body, headers) = read(&url).await?;
+        let mut page: Vec<serde_json::Value> = match serde_json::from_str(&body)? {
+            serde_json::Value::Array(page) => page,
+            serde_json::Value::Object(mut wrapper) => {
+                let field = wrapper.iter().find(|(_, value)| value.is_array()).map(|(field, _)| field.clone());
+                let page = field.and_then(|field| wrapper.remove(&field)).ok_or_else(|| format!("Not a list: {}", url))?;
+                serde_json::from_value(page)?
+            }
+            _ => return Err(format!("Not a list: {}", url).into()),
+        };
+        drop(body);
+        page.iter_mut().for_each(&each);
+        items.extend(page);
+        pages += 1;
+        next = next_page(&headers);
+        if next.is_some() && pages == MAX_PAGES {
+            warn!("Stopped after {} pages of {}", MAX_PAGES, url);
+            break;
+        }
+    }
+    Ok(serde_json::to_string(&items)?)
+}
+
+// The rel="next" URL of a Link header: <url>; rel="next", <url>; rel="last"
+fn next_page(headers: &[(String, String)]) -> Option<String> {
+    let (_, link) = headers.iter().find(|(name, _)| name.eq_ignore_ascii_case("link"))?;
+    link.split(',').find_map(|part| {
+        let (url, params) = part.split_once(';')?;
+        params
+            .split(';')
+            .any(|param| param.trim() == "rel=\"next\"")
+            .then(|| url.trim().trim_start_matches('<').trim_end_matches('>').to_string())
+    })
+}
+
+// Every request to GitHub goes through here: the token, or the one routed to
+// the repository, the User-Agent and API version, pacing, rate limits and
+// retries, and the audit log for writes
+pub struct GithubClient {
+    token: Option<String>,
+}
+
+impl GithubClient {
+    pub fn new(github_token: Option<&str>) -> Self {
+        Self {
+            token: github_token.map(str::to_string),
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, url: &str) -> reqwest::RequestBuilder {
+        self.request_for(method, url, None)
+    }
+
+    fn request_for(&self, method: reqwest::Method, url: &str, repository: Option<&str>) -> reqwest::RequestBuilder {
+        let request = client()
+            .request(method, url)
+            .header("X-GitHub-Api-Version", GITHUB_API_VERSION);
+        match routed_token(url, repository).or(self.token.as_deref()) {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    async fn read(&self, request: reqwest::RequestBuilder) -> Result<(String, Headers), Box<dyn Error>> {
+        if THROTTLE.load(Ordering::Relaxed) {
+            pace(&NEXT_READ, THROTTLED_READ_INTERVAL).await;
+        }
+        fetch(request.build()?, &RETRY_STATUSES).await
+    }
+
+    pub async fn get(&self, url: &str) -> Result<String, Box<dyn Error>> {
+        Ok(self.read(self.request(reqwest::Method::GET, url)).await?.0)
+    }
+
+    pub async fn get_all(&self, url: &str) -> Result<String, Box<dyn Error>> {
+        self.get_all_with(url, |_| {}).await
+    }
+
+    // get_all, with each item changed as its page comes in
+    pub async fn get_all_with(
+        &self,
+        url: &str,
+        each: impl Fn(&mut serde_json::Value),
+    ) -> Result<String, Box<dyn Error>> {
+        get_all(url, async |url: &str| self.read(self.request(reqwest::Method::GET, url)).await, each).await
+    }
+
+    // File contents as stored in the repository instead of the base64 JSON envelope
+    pub async fn get_raw(&self, url: &str) -> Result<String, Box<dyn Error>> {
+        let request = self
+            .request(reqwest::Method::GET, url)
+            .header(reqwest::header::ACCEPT, "application/vnd.github.raw+json");
+        Ok(self.read(request).await?.0)
+    }
+
+    // Binary downloads, e.g. artifact archives, written to sink. GitHub
+    // redirects those to a storage host, which doesn't get the token
+    pub async fn download(
+        &self,
+        url: &str,
+        max_bytes: usize,
+        sink: &mut impl io::Write,
+    ) -> Result<usize, Box<dyn Error>> {
+        fetch_bytes(self.request(reqwest::Method::GET, url).build()?, max_bytes, sink).await
+    }
+
+    async fn write(&self, request: reqwest::RequestBuilder, url: &str, body: Option<&str>) -> Result<String, Box<dyn Error>> {
+        if THROTTLE.load(Ordering::Relaxed) {
+            pace(&NEXT_READ, THROTTLED_READ_INTERVAL).await;
+        }
+        write(request, url, body).await
+    }
+
+    pub async fn post(&self, url: &str, body: &serde_json::Value) -> Result<String, Box<dyn Error>> {
+        self.write(self.request(reqwest::Method::POST, url), url, Some(&body.to_string())).await
+    }
+
+    pub async fn delete(&self, url: &str) -> Result<String, Box<dyn Error>> {
+        self.write(self.request(reqwest::Method::DELETE, url), url, None).await
+    }
+
+    pub async fn patch(&self, url: &str) -> Result<String, Box<dyn Error>> {
+        self.write(self.request(reqwest::Method::PATCH, url), url, None).await
+    }
+
+    // GraphQL reports most failures with a 200 and an `errors` array.
+    // `repository`, owner/repo, picks the token routed to it
+    pub async fn graphql(
+        &self,
+        query: &str,
+        variables: serde_json::Value,
+        repository: &str,
+    ) -> Result<serde_json::Value, Box<dyn Error>> {
+        let url = &github_graphql_url();
+        let body = serde_json::json!({ "query": query, "variables": variables }).to_string();
+        let request = self.request_for(reqwest::Method::POST, url, Some(repository));
+        // Queries are POSTed too, only mutations are writes
+        let response = if query.trim_start().starts_with("mutation") {
+            self.write(request, url, Some(&body)).await?
+        } else {
+            self.read(with_json(request, Some(&body))).await?.0
+        };
+        let response: serde_json::Value = serde_json::from_str(&response)?;
+        if let Some(errors) = response["errors"].as_array().filter(|e| !e.is_empty()) {
+            let messages: Vec<&str> = errors.iter().filter_map(|e| e["message"].as_str()).collect();
+            return Err(format!("GraphQL error: {}", messages.join("; ")).into());
+        }
+        Ok(response["data"].clone())
+    }
+}
+
+fn gitlab_request(url: &str, gitlab_token: Option<&str>) -> reqwest::RequestBuilder {
+    let request = client().get(url);
+    match gitlab_token {
+        Some(token) => request.header("PRIVATE-TOKEN", token),
+        None => request,
+    }
+}
+
+async fn read(request: reqwest::RequestBuilder) -> Result<(String, Headers), Box<dyn Error>> {
+    fetch(request.build()?, &RETRY_STATUSES).await
+}
+
+pub async fn gitlab_get(url: &str, gitlab_token: Option<&str>) -> Result<String, Box<dyn Error>> {
+    Ok(read(gitlab_request(url, gitlab_token)).await?.0)
+}
+
+pub async fn gitlab_get_all(url: &str, gitlab_token: Option<&str>) -> Result<String, Box<dyn Error>> {
+    gitlab_get_all_with(url, gitlab_token, |_| {}).await
+}
+
+pub async fn gitlab_get_all_with(
+    url: &str,
+    gitlab_token: Option<&str>,
+    each: impl Fn(&mut serde_json::Value),
+) -> Result<String, Box<dyn Error>> {
+    get_all(url, async |url: &str| read(gitlab_request(url, gitlab_token)).await, each).await
+}
+
+// For Bitbucket and Gitea: `authorization` is the whole header value, Basic
+// with an app password or token followed by the token
+fn authorized_request(url: &str, authorization: Option<&str>) -> reqwest::RequestBuilder {
+    let request = client().get(url);
+    match authorization {
+        Some(authorization) => request.header(reqwest::header::AUTHORIZATION, authorization),
+        None => request,
+    }
+}
+
+pub async fn authorized_get(url: &str, authorization: Option<&str>) -> Result<String, Box<dyn Error>> {
+    Ok(read(authorized_request(url, authorization)).await?.0)
+}
+
+// Gitea and Forgejo page with Link headers, like GitHub
+pub async fn authorized_get_all(url: &str, authorization: Option<&str>) -> Result<String, Box<dyn Error>> {
+    get_all(url, async |url: &str| read(authorized_request(url, authorization)).await, |_| {}).await
+}
+
+// Bitbucket pages in the body instead of the headers: the items are in
+// "values" and the next page's URL in "next"
+pub async fn bitbucket_get_all(url: &str, authorization: Option<&str>) -> Result<String, Box<dyn Error>> {
+    let mut next = Some(if url.contains("pagelen=") {
+        url.to_string()
+    } else {
+        format!("{}{}pagelen=50", url, if url.contains('?') { '&' } else { '?' })
+    });
+    let mut items: Vec<serde_json::Value> = Vec::new();
+    let mut pages = 0;
+    while let Some(url) = next.take() {
+        let mut page: serde_json::Value = serde_json::from_str(&authorized_get(&url, authorization).await?)?;
+        if let serde_json::Value::Array(values) = page["values"].take() {
+            items.extend(values);
+        }
+        pages += 1;
+        next = page["next"].as_str().map(str::to_string);
+        if next.is_some() && pages == MAX_PAGES {
+            warn!("Stopped after {} pages of {}", MAX_PAGES, url);
+            break;
+        }
+    }
+    Ok(serde_json::to_string(&items)?)
+}
+
+// Files outside the API, e.g. images linked from comments
+pub async fn get_bytes(url: &str, max_bytes: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    let request = client().get(url).build()?;
+    let mut bytes = Vec::new();
+    fetch_bytes(request, max_bytes, &mut bytes).await?;
+    Ok(bytes)
+}
+
+// A query POSTed to an API other than the forges, e.g. OSV. It changes
+// nothing, so it isn't a write
+pub async fn post_query(url: &str, body: &serde_json::Value) -> Result<String, Box<dyn Error>> {
+    let request = with_json(client().post(url), Some(&body.to_string()));
+    Ok(read(request).await?.0)
+}
+
+// Slack and other incoming webhooks. The URL is the credential, so only its
+// host goes into the audit log
+pub async fn post_webhook(url: &str, body: &serde_json::Value) -> Result<String, Box<dyn Error>> {
+    let host = url.split('/').take(3).collect::<Vec<_>>().join("/");
+    write(client().post(url), &host, Some(&body.to_string())).await
+}
@@ -0,0 +1,127 @@
+use crate::http;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use log::{info, warn};
+use std::env;
+use std::io::{self, IsTerminal};
+
+// Bigger images are linked instead of drawn
+const MAX_IMAGE_BYTES: usize = 5 * 1024 * 1024;
+// Kitty takes the image data in chunks of at most this size
+const KITTY_CHUNK: usize = 4096;
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    Kitty,
+    Iterm2,
+}
+
+// Terminals announce themselves through the environment. Output that isn't
+// going to a terminal only gets the links
+pub fn detect() -> Option<Protocol> {
+    if !io::stdout().is_terminal() {
+        return None;
+    }
+    let var = |name: &str| env::var(name).unwrap_or_default();
+    if !var("KITTY_WINDOW_ID").is_empty() || var("TERM") == "xterm-kitty" {
+        return Some(Protocol::Kitty);
+    }
+    if matches!(var("TERM_PROGRAM").as_str(), "iTerm.app" | "WezTerm") || var("LC_TERMINAL") == "iTerm2" {
+        return Some(Protocol::Iterm2);
+    }
+    None
+}
+
+// Markdown images and <img> tags, which is how GitHub inserts screenshots
+// pasted into a comment
+pub fn links(body: &str) -> Vec<String> {
+    let mut found = Vec::new();
+    let mut rest = body;
+    while let Some(start) = rest.find("![") {
+        rest = &rest[start + 2..];
+        let Some(target) = rest.find("](").map(|i| &rest[i + 2..]) else {
+            break;
+        };
+        let Some(end) = target.find(')') else {
+            break;
+        };
+        // An optional title follows the URL
+        let url = target[..end].split_whitespace().next().unwrap_or_default();
+        if url.starts_with("http") {
+            found.push(url.to_string());
+        }
+        rest = &target[end..];
+    }
+
+    for tag in body.split("<img").skip(1) {
+        let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+        let Some(value) = tag.find("src=").map(|i| &tag[i + 4..]) else {
+            continue;
+        };
+        let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'');
+        let url = match quote {
+            Some(quote) => value[1..].split(quote).next().unwrap_or_default(),
+            None => value.split_whitespace().next().unwrap_or_default(),
+        };
+        if url.starts_with("http") && !found.iter().any(|f| f == url) {
+            found.push(url.to_string());
+        }
+    }
+    found
+}
+
+// The escape sequence drawing the image, None when the terminal can't show
+// this kind of image
+fn escape(protocol: Protocol, bytes: &[u8]) -> Option<String> {
+    match protocol {
+        // Kitty decodes PNG itself, anything else would have to be converted
+        Protocol::Kitty if bytes.starts_with(PNG_SIGNATURE) => {
+            let encoded = STANDARD.encode(bytes);
+            let chunks: Vec<&[u8]> = encoded.as_bytes().chunks(KITTY_CHUNK).collect();
+            let mut sequence = String::new();
+            for (i, chunk) in chunks.iter().enumerate() {
+                let control = if i == 0 { "a=T,f=100," } else { "" };
+                let more = if i + 1 < chunks.len() { 1 } else { 0 };
+                sequence.push_str(&format!(
+                    "\x1b_G{}m={};{}\x1b\\",
+                    control,
+                    more,
+                    String::from_utf8_lossy(chunk)
+                ));
+            }
+            Some(sequence)
+        }
+        Protocol::Kitty => None,
+        Protocol::Iterm2 => Some(format!(
+            "\x1b]1337;File=inline=1;size={};preserveAspectRatio=1:{}\x07",
+            bytes.len(),
+            STANDARD.encode(bytes)
+        )),
+    }
+}
+
+// One row per image linked from the comment: the image itself when the
+// terminal can draw it, the link otherwise
+pub async fn render(body: &str, protocol: Option<Protocol>) -> Vec<String> {
+    let mut rows = Vec::new();
+    for url in links(body) {
+        let Some(protocol) = protocol else {
+            rows.push(format!("Image: {}", url));
+            continue;
+        };
+        info!("Downloading {}...", url);
+        let drawn = match http::get_bytes(&url, MAX_IMAGE_BYTES).await {
+            Ok(bytes) => escape(protocol, &bytes),
+            Err(e) => {
+                warn!("Could not download {}: {}", url, e);
+                None
+            }
+        };
+        match drawn {
+            Some(sequence) => rows.push(sequence),
+            None => rows.push(format!("Image: {}", url)),
+        }
+    }
+    rows
+}
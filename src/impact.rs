@@ -0,0 +1,218 @@
+use crate::duplicates;
+use crate::{OutputBuffer, PullRequestDetail};
+use log::{info, warn};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Names listed per build system before the rest is only counted
+const MAX_LISTED: usize = 30;
+
+// A crate, package or Bazel package, and the units of the same build
+// system it depends on
+#[derive(Debug)]
+struct Unit {
+    system: &'static str,
+    name: String,
+    dir: PathBuf,
+    deps: BTreeSet<String>,
+}
+
+fn dependency_keys(manifest: &toml::Value) -> Vec<String> {
+    const SECTIONS: [&str; 3] = ["dependencies", "dev-dependencies", "build-dependencies"];
+    let mut tables: Vec<&toml::Value> = SECTIONS.iter().filter_map(|s| manifest.get(s)).collect();
+    // Platform specific ones live under [target.'cfg(...)'.dependencies]
+    if let Some(targets) = manifest.get("target").and_then(toml::Value::as_table) {
+        for target in targets.values() {
+            tables.extend(SECTIONS.iter().filter_map(|s| target.get(s)));
+        }
+    }
+    tables
+        .into_iter()
+        .filter_map(toml::Value::as_table)
+        .flat_map(|table| {
+            table.iter().map(|(key, value)| {
+                // Renamed dependencies name the real package
+                value.get("package").and_then(toml::Value::as_str).unwrap_or(key).to_string()
+            })
+        })
+        .collect()
+}
+
+fn cargo_unit(root: &Path, path: &Path) -> Result<Option<Unit>, Box<dyn Error>> {
+    let manifest: toml::Value = toml::from_str(&fs::read_to_string(root.join(path))?)?;
+    let Some(name) = manifest.get("package").and_then(|p| p.get("name")).and_then(toml::Value::as_str) else {
+        // A virtual workspace manifest
+        return Ok(None);
+    };
+    Ok(Some(Unit {
+        system: "Cargo",
+        name: name.to_string(),
+        dir: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        deps: dependency_keys(&manifest).into_iter().collect(),
+    }))
+}
+
+// package.json for npm, yarn and pnpm workspaces, project.json for Nx
+fn js_unit(root: &Path, path: &Path) -> Result<Option<Unit>, Box<dyn Error>> {
+    let manifest: Value = serde_json::from_str(&fs::read_to_string(root.join(path))?)?;
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let name = match manifest["name"].as_str() {
+        Some(name) => name.to_string(),
+        None => return Ok(None),
+    };
+    let mut deps = BTreeSet::new();
+    for key in ["dependencies", "devDependencies", "peerDependencies", "optionalDependencies"] {
+        deps.extend(manifest[key].as_object().into_iter().flatten().map(|(name, _)| name.clone()));
+    }
+    deps.extend(
+        manifest["implicitDependencies"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|d| d.as_str().map(str::to_string)),
+    );
+    Ok(Some(Unit {
+        system: "JS",
+        name,
+        dir,
+        deps,
+    }))
+}
+
+// Every "//package:target" label in the BUILD file, as "//package"
+fn bazel_unit(root: &Path, path: &Path) -> Result<Option<Unit>, Box<dyn Error>> {
+    let content = fs::read_to_string(root.join(path))?;
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let deps = content
+        .split('"')
+        .skip(1)
+        .step_by(2)
+        .filter_map(|label| label.strip_prefix("//"))
+        .map(|label| format!("//{}", label.split(':').next().unwrap_or_default()))
+        .collect();
+    Ok(Some(Unit {
+        system: "Bazel",
+        name: format!("//{}", dir.to_string_lossy()),
+        dir,
+        deps,
+    }))
+}
+
+fn load(root: &Path) -> Result<Vec<Unit>, Box<dyn Error>> {
+    let files = duplicates::list_files(root)?;
+    let is_bazel = ["MODULE.bazel", "WORKSPACE", "WORKSPACE.bazel"].iter().any(|f| root.join(f).exists());
+    let mut units = Vec::new();
+    for path in &files {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let unit = match name.as_ref() {
+            "Cargo.toml" => cargo_unit(root, path),
+            "package.json" | "project.json" => js_unit(root, path),
+            "BUILD" | "BUILD.bazel" if is_bazel => bazel_unit(root, path),
+            _ => continue,
+        };
+        match unit {
+            Ok(Some(unit)) => units.push(unit),
+            Ok(None) => {}
+            Err(e) => warn!("Skipping {}: {}", path.display(), e),
+        }
+    }
+
+    // Nx keeps project.json next to package.json, both describe the same project
+    let mut merged: Vec<Unit> = Vec::new();
+    for unit in units {
+        match merged.iter_mut().find(|u| u.system == unit.system && u.dir == unit.dir) {
+            Some(existing) => existing.deps.extend(unit.deps),
+            None => merged.push(unit),
+        }
+    }
+    // Only dependencies inside the workspace matter
+    let names: BTreeSet<(&'static str, String)> = merged.iter().map(|u| (u.system, u.name.clone())).collect();
+    for unit in &mut merged {
+        let system = unit.system;
+        unit.deps.retain(|dep| names.contains(&(system, dep.clone())));
+    }
+    Ok(merged)
+}
+
+// The unit with the deepest directory containing the file
+fn owner<'a>(units: &'a [Unit], system: &str, file: &str) -> Option<&'a Unit> {
+    units
+        .iter()
+        .filter(|u| u.system == system && Path::new(file).starts_with(&u.dir))
+        .max_by_key(|u| u.dir.components().count())
+}
+
+fn listed(names: &BTreeSet<&str>) -> String {
+    let mut shown: Vec<String> = names.iter().take(MAX_LISTED).map(|n| n.to_string()).collect();
+    if names.len() > MAX_LISTED {
+        shown.push(format!("and {} more", names.len() - MAX_LISTED));
+    }
+    shown.join(", ")
+}
+
+// Adds the Build Impact section: the workspace members the PR changes and
+// everything that depends on them, directly or not
+pub fn review(root: &Path, details: &PullRequestDetail, output: &mut OutputBuffer) {
+    info!("Computing the build impact in {}...", root.display());
+    let units = match load(root) {
+        Ok(units) => units,
+        Err(e) => {
+            warn!("Could not read the build graph: {}", e);
+            return;
+        }
+    };
+    let systems: BTreeSet<&str> = units.iter().map(|u| u.system).collect();
+    // A single crate or package has no one downstream to affect
+    let systems: Vec<&str> = systems
+        .into_iter()
+        .filter(|s| units.iter().filter(|u| u.system == *s).count() > 1)
+        .collect();
+    if systems.is_empty() {
+        return;
+    }
+
+    let mut lines = Vec::new();
+    for system in systems {
+        let changed: BTreeSet<&str> = details
+            .files
+            .iter()
+            .filter_map(|f| owner(&units, system, &f.filename))
+            .map(|u| u.name.as_str())
+            .collect();
+        if changed.is_empty() {
+            continue;
+        }
+
+        let mut dependents: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for unit in units.iter().filter(|u| u.system == system) {
+            for dep in &unit.deps {
+                dependents.entry(dep.as_str()).or_default().push(unit.name.as_str());
+            }
+        }
+        let mut affected = BTreeSet::new();
+        let mut pending: Vec<&str> = changed.iter().copied().collect();
+        while let Some(name) = pending.pop() {
+            for dependent in dependents.get(name).into_iter().flatten() {
+                if !changed.contains(dependent) && affected.insert(*dependent) {
+                    pending.push(dependent);
+                }
+            }
+        }
+
+        lines.push(format!("{}: {} changed: {}", system, changed.len(), listed(&changed)));
+        if affected.is_empty() {
+            lines.push("  Nothing else in the workspace depends on them".to_string());
+        } else {
+            lines.push(format!("  {} affected downstream: {}", affected.len(), listed(&affected)));
+        }
+    }
+    if lines.is_empty() {
+        return;
+    }
+    output.add_header("Build Impact");
+    output.add_box_content(&lines.join("\n"));
+    output.add_diff_separator();
+}
@@ -0,0 +1,193 @@
+use crate::findings::{Finding, Severity};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+// File stems too generic to point at a specific change
+const GENERIC_STEMS: &[&str] = &["mod", "lib", "main", "index", "init", "__init__", "test", "tests", "utils"];
+
+#[derive(Debug, Default)]
+pub struct TestCase {
+    pub name: String,
+    pub classname: String,
+    pub file: Option<String>,
+    pub failed: bool,
+    // Failed at least once but passed on a rerun
+    pub flaky: bool,
+    pub message: String,
+}
+
+impl TestCase {
+    pub fn display_name(&self) -> String {
+        if self.classname.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{}::{}", self.classname, self.name)
+        }
+    }
+
+    // Checks the evidence linking this failure to a change in `filename`,
+    // strongest evidence first
+    fn relates_to(&self, filename: &str) -> bool {
+        if let Some(file) = &self.file
+            && (file.ends_with(filename) || filename.ends_with(file.as_str()))
+        {
+            return true;
+        }
+        if self.message.contains(filename) {
+            return true;
+        }
+
+        let path = Path::new(filename);
+        let Some(stem) = path.file_stem().map(|s| s.to_string_lossy().to_lowercase()) else {
+            return false;
+        };
+        if GENERIC_STEMS.contains(&stem.as_str()) {
+            return false;
+        }
+
+        let identifiers = format!("{} {}", self.classname, self.name).to_lowercase();
+        identifiers
+            .split(|c: char| !c.is_alphanumeric() && c != '_')
+            .any(|token| token == stem || token.strip_prefix("test_") == Some(&stem))
+    }
+}
+
+fn attribute(element: &BytesStart, name: &str) -> Option<String> {
+    element
+        .try_get_attribute(name)
+        .ok()
+        .flatten()
+        .and_then(|a| a.unescape_value().ok())
+        .map(|v| v.into_owned())
+}
+
+fn start_case(element: &BytesStart) -> TestCase {
+    TestCase {
+        name: attribute(element, "name").unwrap_or_default(),
+        classname: attribute(element, "classname").unwrap_or_default(),
+        file: attribute(element, "file"),
+        ..TestCase::default()
+    }
+}
+
+fn mark_outcome(case: &mut TestCase, element: &BytesStart) {
+    match element.local_name().as_ref() {
+        b"failure" | b"error" => {
+            case.failed = true;
+            if let Some(message) = attribute(element, "message") {
+                case.message.push_str(&message);
+                case.message.push('\n');
+            }
+        }
+        b"flakyFailure" | b"flakyError" => case.flaky = true,
+        _ => {}
+    }
+}
+
+pub fn load(path: &Path) -> Result<Vec<TestCase>, Box<dyn Error>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let mut reader = Reader::from_str(&content);
+    let mut cases = Vec::new();
+    let mut current: Option<TestCase> = None;
+    let mut in_failure = false;
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => {
+                if e.local_name().as_ref() == b"testcase" {
+                    current = Some(start_case(&e));
+                } else if let Some(case) = current.as_mut() {
+                    mark_outcome(case, &e);
+                    in_failure = matches!(e.local_name().as_ref(), b"failure" | b"error");
+                }
+            }
+            Event::Empty(e) => {
+                if e.local_name().as_ref() == b"testcase" {
+                    cases.push(start_case(&e));
+                } else if let Some(case) = current.as_mut() {
+                    mark_outcome(case, &e);
+                }
+            }
+            Event::Text(text) if in_failure => {
+                if let (Some(case), Ok(text)) = (current.as_mut(), text.unescape()) {
+                    case.message.push_str(&text);
+                }
+            }
+            Event::End(e) => match e.local_name().as_ref() {
+                b"testcase" => cases.extend(current.take()),
+                b"failure" | b"error" => in_failure = false,
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(cases)
+}
+
+// Groups failing tests under the changed file most likely responsible for them
+pub fn correlate(cases: &[TestCase], changed_files: &[&str]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let failing: Vec<&TestCase> = cases.iter().filter(|c| c.failed && !c.flaky).collect();
+    let mut explained = vec![false; failing.len()];
+
+    for filename in changed_files {
+        let related: Vec<String> = failing
+            .iter()
+            .enumerate()
+            .filter(|(_, case)| case.relates_to(filename))
+            .map(|(i, case)| {
+                explained[i] = true;
+                case.display_name()
+            })
+            .collect();
+
+        if !related.is_empty() {
+            findings.push(Finding::new(
+                Severity::High,
+                format!(
+                    "Your change to {} likely broke {} test(s): {}",
+                    filename,
+                    related.len(),
+                    related.join(", ")
+                ),
+            ));
+        }
+    }
+
+    let unexplained: Vec<String> = failing
+        .iter()
+        .zip(&explained)
+        .filter(|(_, explained)| !**explained)
+        .map(|(case, _)| case.display_name())
+        .collect();
+    if !unexplained.is_empty() {
+        findings.push(Finding::new(
+            Severity::Medium,
+            format!(
+                "{} failing test(s) not linked to a changed file: {}",
+                unexplained.len(),
+                unexplained.join(", ")
+            ),
+        ));
+    }
+
+    let flaky: Vec<String> = cases
+        .iter()
+        .filter(|c| c.flaky)
+        .map(|c| c.display_name())
+        .collect();
+    if !flaky.is_empty() {
+        findings.push(Finding::new(
+            Severity::Info,
+            format!("Flaky, passed on rerun: {}", flaky.join(", ")),
+        ));
+    }
+
+    findings
+}
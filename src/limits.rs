@@ -0,0 +1,136 @@
+use crate::http;
+use crate::usage::{self, UsageRecord};
+use crate::OutputBuffer;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+
+const DAY: u64 = 24 * 60 * 60;
+
+#[derive(Deserialize, Debug)]
+struct RateLimit {
+    limit: u64,
+    remaining: u64,
+    reset: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct RateLimitResponse {
+    resources: HashMap<String, RateLimit>,
+}
+
+pub fn format_duration(seconds: u64) -> String {
+    match seconds {
+        s if s < 60 => format!("{}s", s),
+        s if s < 60 * 60 => format!("{}m {}s", s / 60, s % 60),
+        s if s < DAY => format!("{}h {}m", s / 3600, (s % 3600) / 60),
+        s => format!("{}d {}h", s / DAY, (s % DAY) / 3600),
+    }
+}
+
+async fn github_limits(github_token: Option<&str>) -> Result<RateLimitResponse, Box<dyn Error>> {
+    // Querying the rate limit doesn't count against it
+    let response = http::GithubClient::new(github_token).get(&format!("{}/rate_limit", http::github_api())).await?;
+    Ok(serde_json::from_str(&response)?)
+}
+
+fn spend_summary(records: &[&UsageRecord]) -> String {
+    let input: u64 = records.iter().map(|r| r.input_tokens).sum();
+    let output: u64 = records.iter().map(|r| r.output_tokens).sum();
+    let cost: f64 = records.iter().filter_map(|r| r.cost()).sum();
+    let unpriced = records.iter().filter(|r| r.cost().is_none()).count();
+
+    let mut summary = format!(
+        "${:.2} ({} requests, {} input / {} output tokens)",
+        cost,
+        records.len(),
+        input,
+        output
+    );
+    if unpriced > 0 {
+        summary.push_str(&format!(", {} requests to models without known pricing", unpriced));
+    }
+    summary
+}
+
+fn anthropic_rate_limits(record: &UsageRecord) -> Vec<String> {
+    let header = |name: &str| {
+        record
+            .rate_limits
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+
+    ["requests", "tokens", "input-tokens", "output-tokens"]
+        .iter()
+        .filter_map(|kind| {
+            let remaining = header(&format!("anthropic-ratelimit-{}-remaining", kind))?;
+            let limit = header(&format!("anthropic-ratelimit-{}-limit", kind)).unwrap_or("?");
+            let reset = header(&format!("anthropic-ratelimit-{}-reset", kind))
+                .map(|reset| format!(", resets at {}", reset))
+                .unwrap_or_default();
+            Some(format!("{:<14} {}/{} remaining{}", kind, remaining, limit, reset))
+        })
+        .collect()
+}
+
+pub async fn show(github_token: Option<&str>, output: &mut OutputBuffer) -> Result<(), Box<dyn Error>> {
+    let now = usage::now();
+
+    output.add_header("API Limits");
+
+    output.add_section("GitHub");
+    match github_limits(github_token).await {
+        Ok(limits) => {
+            let mut lines = Vec::new();
+            for resource in ["core", "graphql", "search"] {
+                if let Some(limit) = limits.resources.get(resource) {
+                    lines.push(format!(
+                        "{:<8} {:>5}/{:<5} remaining, resets in {}",
+                        resource,
+                        limit.remaining,
+                        limit.limit,
+                        format_duration(limit.reset.saturating_sub(now))
+                    ));
+                }
+            }
+            if github_token.is_none() {
+                lines.push("GITHUB_TOKEN is not set, showing the unauthenticated quota".to_string());
+            }
+            output.add_box_content(&lines.join("\n"));
+        }
+        Err(e) => output.add_box_content(&format!("Could not fetch GitHub rate limits: {}", e)),
+    }
+
+    output.add_section("Anthropic");
+    let records = usage::load()?;
+    if records.is_empty() {
+        output.add_box_content("No AI usage recorded yet.");
+    } else {
+        let since = |seconds: u64| -> Vec<&UsageRecord> {
+            records
+                .iter()
+                .filter(|r| r.timestamp + seconds >= now)
+                .collect()
+        };
+
+        let mut lines = vec![
+            format!("Last 24 hours: {}", spend_summary(&since(DAY))),
+            format!("Last 30 days:  {}", spend_summary(&since(30 * DAY))),
+        ];
+
+        if let Some(last) = records.iter().rev().find(|r| !r.rate_limits.is_empty()) {
+            lines.push(String::new());
+            lines.push(format!(
+                "Rate limits as of the last request ({} ago):",
+                format_duration(now.saturating_sub(last.timestamp))
+            ));
+            lines.extend(anthropic_rate_limits(last));
+        }
+        output.add_box_content(&lines.join("\n"));
+    }
+
+    output.add_diff_separator();
+    Ok(())
+}
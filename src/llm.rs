@@ -0,0 +1,35 @@
+use crate::config::Llm;
+use log::info;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Set once from the config's [llm] section, everything is allowed without it
+static GATE: OnceLock<Llm> = OnceLock::new();
+// Set while the PR last loaded belongs to a repository kept from the AI. PRs
+// are reviewed one after the other, so it covers every prompt built from it
+static WITHHELD: AtomicBool = AtomicBool::new(false);
+
+pub fn configure(llm: Llm) {
+    let _ = GATE.set(llm);
+}
+
+// Called for every PR loaded, live or from a bundle
+pub fn select_repository(owner: &str, repo: &str) {
+    let repository = format!("{}/{}", owner, repo);
+    let allowed = GATE.get().is_none_or(|gate| gate.allows_repo(&repository));
+    if !allowed {
+        info!("{} is kept from the AI by the [llm] config", repository);
+    }
+    WITHHELD.store(!allowed, Ordering::Relaxed);
+}
+
+// Why the AI may not see the current PR, in the words of ReviewOptions::no_ai
+pub fn withheld() -> Option<&'static str> {
+    WITHHELD
+        .load(Ordering::Relaxed)
+        .then_some("the [llm] config keeps this repository's code from the AI")
+}
+
+pub fn allows_path(path: &str) -> bool {
+    GATE.get().is_none_or(|gate| gate.allows_path(path))
+}
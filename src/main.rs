@@ -1,28 +1,741 @@
 use log::{error, info, trace, warn};
-use reqwest::header::{CONTENT_TYPE, HeaderMap, HeaderValue};
 use serde::Deserialize;
 use serde::Serialize;
 use serde_json::Value;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::error::Error;
-use std::io::{self, BufRead, Write};
-use clap::Parser;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::iter;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
+use futures_util::{StreamExt, stream};
+use output::{DiffAnnotations, LineComments, OutputBuffer};
+use terminal::Theme;
+
+mod ai;
+mod artifacts;
+mod ask;
+mod assets;
+mod assign;
+mod audit;
+mod automerge;
+mod aws;
+mod bench;
+mod breaking;
+mod bundle;
+mod cache;
+mod changelog;
+mod checklist;
+mod checkout;
+mod checks;
+mod compose;
+mod config;
+mod coverage;
+mod cron;
+mod dependencies;
+mod deployments;
+mod diff;
+mod directives;
+mod digest;
+mod doctor;
+mod explain;
+mod duplicates;
+mod encryption;
+mod findings;
+mod github;
+mod heuristics;
+mod history;
+mod http;
+mod images;
+mod impact;
+mod junit;
+mod limits;
+mod llm;
+mod memory;
+mod notebook;
+mod notifications;
+mod onboarding;
+mod output;
+mod paths;
+mod pending;
+mod picker;
+mod post;
+mod prefilter;
+mod profile;
+mod provider;
+mod recent;
+mod repo_config;
+mod retention;
+mod retract;
+mod risk;
+mod schema;
+mod scope;
+mod share;
+mod sizes;
+mod slash;
+mod state;
+mod submodules;
+mod teams;
+mod templates;
+mod terminal;
+mod terraform;
+mod timestamps;
+mod usage;
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
-    /// Repository owner
-    owner: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Repository owner (defaults to the repository in the config)
+    owner: Option<String>,
 
     /// Repository name
-    repo: String,
+    #[arg(requires = "owner")]
+    repo: Option<String>,
 
     /// Optional PR number
     pr_number: Option<u32>,
 
+    /// Pick open PRs by typing part of their title, author or labels instead of the table and prompt
+    #[arg(long, conflicts_with = "pr_number")]
+    pick: bool,
+
+    /// Language for the AI output, e.g. es, ja or de (code stays in English)
+    #[arg(long, global = true)]
+    lang: Option<String>,
+
+    /// How blunt the AI feedback is
+    #[arg(long, value_enum)]
+    tone: Option<config::Tone>,
+
+    /// How long and thorough the AI feedback is
+    #[arg(long, value_enum)]
+    detail: Option<config::Detail>,
+
+    /// Output token budget of each AI request (default 1000, 2000 with --detail deep)
+    #[arg(long, global = true, value_parser = clap::value_parser!(u32).range(1..))]
+    max_tokens: Option<u32>,
+
+    /// Sampling temperature of the AI, lower is more focused (0-1 for Anthropic and Bedrock, 0-2
+    /// for OpenAI and Ollama; defaults to temperature in the config, then the provider's default)
+    #[arg(long, global = true)]
+    temperature: Option<f64>,
+
+    /// Let this model (an ID or an alias such as fast) pick the files worth an AI review, the
+    /// others only get the static analysis
+    #[arg(long, global = true)]
+    prefilter: Option<String>,
+
     /// Get feedback in Linus Torvalds style
     #[arg(long)]
     linus_torvalds: bool,
+
+    /// Only review changed files matching this glob, prefix with ! to exclude (can be repeated)
+    #[arg(long)]
+    paths: Vec<String>,
+
+    /// Post to the Slack webhook of every configured team whose paths the PR touches
+    #[arg(long)]
+    notify_teams: bool,
+
+    /// Exclude whitespace/reformat-only hunks from the review
+    #[arg(long)]
+    skip_formatting: bool,
+
+    /// Local checkout of the repository, used to flag code duplicated from elsewhere
+    #[arg(long)]
+    repo_path: Option<PathBuf>,
+
+    /// Minimum similarity (0.0-1.0) for an added block to be reported as a duplicate
+    #[arg(long, default_value_t = 0.9)]
+    dup_threshold: f64,
+
+    /// Minimum number of added lines in a block before checking it for duplication
+    #[arg(long, default_value_t = 6)]
+    dup_min_lines: usize,
+
+    /// Skip every AI request: only the details, diff and static analysis, labeled heuristic-only
+    #[arg(long, global = true)]
+    no_ai: bool,
+
+    /// GitHub token to use instead of GITHUB_TOKEN or the profile's
+    #[arg(long, global = true)]
+    token: Option<String>,
+
+    /// GitHub API root, e.g. https://ghe.example.com/api/v3 for GitHub Enterprise Server (defaults
+    /// to GITHUB_API_URL, then api_url in the config, then https://api.github.com)
+    #[arg(long, global = true)]
+    api_url: Option<String>,
+
+    /// Path to the config file (defaults to ~/.config/rubber/config.toml)
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Config profile to use (defaults to the profile whose repos match the repository)
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
+    /// Criterion comparison results (cargo criterion --message-format=json) to include in the report
+    #[arg(long)]
+    bench_json: Option<PathBuf>,
+
+    /// Coverage report (lcov or Cobertura XML) used to flag uncovered changed lines
+    #[arg(long)]
+    coverage: Option<PathBuf>,
+
+    /// Fail when the percentage of covered changed lines is below this value
+    #[arg(long, requires = "coverage")]
+    min_patch_coverage: Option<f64>,
+
+    /// JUnit XML test results from CI (can be repeated)
+    #[arg(long)]
+    junit: Vec<PathBuf>,
+
+    /// Terraform plan (terraform plan -json, or terraform show -json of a plan file) to summarize
+    #[arg(long)]
+    terraform_plan: Option<PathBuf>,
+
+    /// Summarize the discussion when a PR has at least this many comments (0 disables it)
+    #[arg(long, default_value_t = 20)]
+    summarize_comments: usize,
+
+    /// Hide the individual comments when a discussion summary is shown
+    #[arg(long)]
+    collapse_comments: bool,
+
+    /// Ask the AI whether the diff does what the PR description says: missing pieces and
+    /// undescribed changes
+    #[arg(long, global = true)]
+    check_description: bool,
+
+    /// Draw images linked in comments (kitty and iTerm2 graphics), or list their links elsewhere
+    #[arg(long)]
+    show_images: bool,
+
+    /// Use plain ASCII borders and no colors
+    #[arg(long, global = true)]
+    ascii: bool,
+
+    /// Screen reader friendly output: no drawing or colors, labeled sections and linear tables
+    #[arg(long, global = true)]
+    plain: bool,
+
+    /// Where AI requests go (defaults to ai_provider in the config, then anthropic). OpenAI reads
+    /// OPENAI_API_KEY, Ollama runs locally at OLLAMA_HOST, Bedrock uses the AWS credentials
+    #[arg(long, global = true, value_enum)]
+    ai_provider: Option<ai::Provider>,
+
+    /// The model reviews go to, an ID or an alias such as fast (defaults to model in the config)
+    #[arg(long, global = true)]
+    model: Option<String>,
+
+    /// Where the PRs live, by default inferred from the origin remote of the current directory
+    #[arg(long, global = true, value_enum)]
+    provider: Option<provider::Provider>,
+
+    /// Show absolute times in the local timezone next to the relative ones ("3 days ago")
+    #[arg(long, global = true)]
+    local_time: bool,
+
+    /// Write every HTTP request/response to this directory, with credentials redacted
+    #[arg(long, global = true)]
+    debug_http: Option<PathBuf>,
+
+    /// Show and log every write (comments, reviews, auto-merge, webhooks) instead of sending it
+    #[arg(long, global = true)]
+    read_only: bool,
+
+    /// Print the time spent in each phase (fetch, config, render, heuristics, AI, history) to stderr
+    #[arg(long, global = true)]
+    profile_run: bool,
+
+    /// Append a footer with the time spent fetching, analyzing, waiting on the AI (per file) and rendering
+    #[arg(long, global = true)]
+    timings: bool,
+
+    /// Upload the report as a secret gist and print its URL, to share it with someone who doesn't
+    /// run rubber (needs a token with the gist scope)
+    #[arg(long, global = true)]
+    share: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List the most recent PRs of a repository, without prompting
+    List {
+        /// Repository as owner/repo (defaults to the repository in the config)
+        repo: Option<String>,
+        /// Number of PRs to list (at most 100)
+        #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u8).range(1..=100))]
+        limit: u8,
+        /// One line per PR from a single request, without comment counts. Tab-separated when piped
+        #[arg(long)]
+        fast: bool,
+        /// Columns to show, in order [default: number,title,author,age,comments, or without comments with --fast]
+        #[arg(long, value_enum, value_delimiter = ',')]
+        columns: Option<Vec<ListColumn>>,
+        /// Order of the PRs: newest first (age), most comments, most changed lines (size) or by title
+        #[arg(long, value_enum)]
+        sort: Option<ListSort>,
+    },
+    /// Show a PR's details, diff and static analysis, without the AI review
+    Show {
+        /// Pull request as owner/repo#number, or a number in the configured repository
+        pr: String,
+    },
+    /// Review a PR, with the AI review
+    Review {
+        /// Pull request as owner/repo#number, or a number in the configured repository
+        pr: String,
+    },
+    /// Submit the AI review to GitHub as a PR review: a summary and comments on the diff lines
+    Post {
+        /// Pull request as owner/repo#number, or a number in the configured repository
+        pr: String,
+        /// Print the review that would be submitted instead
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Show remaining GitHub API quota and recorded Anthropic usage
+    Limits,
+    /// Check tokens, connectivity, config and local state
+    Doctor,
+    /// Request a review from the next reviewer in the repository's rotation
+    Assign {
+        /// Pull request as owner/repo#number
+        pr: String,
+        /// Show who would be picked without requesting the review
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Enable GitHub auto-merge when CI, approvals and the risk score allow it
+    Automerge {
+        /// Pull request as owner/repo#number
+        pr: String,
+        /// Evaluate the policy without enabling auto-merge
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Explain in plain English what a change to a file does
+    Explain {
+        /// Pull request as owner/repo#number
+        pr: String,
+        /// File to explain
+        #[arg(long)]
+        file: String,
+        /// Only explain this hunk (1-based), with the surrounding code as context
+        #[arg(long)]
+        hunk: Option<usize>,
+    },
+    /// Answer a question about a PR from its diff, citing the lines the answer relies on
+    Ask {
+        /// Pull request as owner/repo#number
+        pr: String,
+        /// The question, e.g. "does this change affect the retry logic?"
+        question: String,
+    },
+    /// Write a comment on a PR in your editor, preview it and post it
+    Comment {
+        /// Pull request as owner/repo#number
+        pr: String,
+        /// Comment on a line of the diff instead, as path:line (line number in the new version)
+        #[arg(long)]
+        line: Option<String>,
+        /// Add the comment to the PR's pending review instead of posting it
+        #[arg(long)]
+        pending: bool,
+    },
+    /// Delete the comments rubber posted on a PR, e.g. when an AI review turned out to be noise
+    Retract {
+        /// Pull request as owner/repo#number
+        pr: String,
+        /// Hide the comments as outdated instead of deleting them
+        #[arg(long)]
+        minimize: bool,
+        /// List the comments without touching them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// List what rubber posted, deleted and decided on GitHub and webhooks
+    Audit {
+        /// Only records from this long ago, e.g. 12h, 30d or 2w
+        #[arg(long, value_parser = cache::parse_age)]
+        since: Option<chrono::Duration>,
+        /// Only this kind of record: write or automerge
+        #[arg(long)]
+        action: Option<String>,
+        /// Only records whose target contains this, e.g. owner/repo
+        #[arg(long)]
+        target: Option<String>,
+    },
+    /// Remember feedback a repository's team accepts or rejects, for future reviews
+    Memory {
+        #[command(subcommand)]
+        action: MemoryAction,
+    },
+    /// Show, submit or discard a review built with rubber comment --pending
+    Pending {
+        #[command(subcommand)]
+        action: PendingAction,
+    },
+    /// Answer a /rubber command from a PR comment webhook payload and reply on the PR
+    Reply {
+        /// issue_comment or pull_request_review_comment event payload, e.g. $GITHUB_EVENT_PATH
+        #[arg(long)]
+        event: PathBuf,
+    },
+    /// Summarize PR activity across the configured repositories since the last digest
+    Digest {
+        /// Keep running and produce a digest on this schedule, e.g. "0 9 * * 1-5"
+        #[arg(long)]
+        cron: Option<String>,
+    },
+    /// Save a PR's diff, comments, changed files and .rubber config to a bundle for offline review
+    Export {
+        /// Pull request as owner/repo#number
+        pr: String,
+        /// Bundle to write, e.g. pr123.tar.zst
+        #[arg(long)]
+        bundle: PathBuf,
+    },
+    /// Review a PR from a bundle written by rubber export, without GitHub access
+    Import {
+        /// Bundle written by rubber export
+        bundle: PathBuf,
+    },
+    /// Show or free the space used by rubber's review history and CI check outcomes
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+    /// List unread GitHub notifications about PRs and review the ones picked
+    Notifications {
+        /// Only these kinds of notifications, e.g. review-requested,mention
+        #[arg(long, value_enum, value_delimiter = ',')]
+        reason: Vec<notifications::Reason>,
+        /// Mark each picked notification as read once its review is done
+        #[arg(long)]
+        mark_read: bool,
+    },
+    /// List the PRs reviewed last, and review one of them again by its number in the list
+    Recent {
+        /// Words to look for in the PR reference or title, matched loosely, e.g. "rubber retry"
+        query: Vec<String>,
+        /// Number of PRs to list
+        #[arg(long, default_value_t = 10)]
+        limit: usize,
+    },
+    /// Inspect the stored reviews of a PR
+    History {
+        #[command(subcommand)]
+        action: HistoryAction,
+    },
+    /// Delete the stored files older than the ages set under [retention] in the config
+    Purge {
+        /// List what would be deleted without deleting it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Print stored files (history, CI outcomes, pending reviews, --debug-http captures) written with encrypt_data
+    Decrypt {
+        /// Files to print, in order
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheAction {
+    /// Size, file count and age of each store
+    Stats,
+    /// Delete everything in the stores
+    Clear,
+    /// Delete files not used for a while
+    Prune {
+        /// Age after which files are deleted, e.g. 30d, 12h or 2w
+        #[arg(long, value_parser = cache::parse_age)]
+        older_than: chrono::Duration,
+    },
+    /// Encrypt the files stored before encrypt_data was turned on
+    Encrypt,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum ListColumn {
+    Number,
+    Title,
+    Author,
+    Age,
+    Comments,
+    Size,
+}
+
+const TABLE_COLUMNS: [ListColumn; 5] = [
+    ListColumn::Number,
+    ListColumn::Title,
+    ListColumn::Author,
+    ListColumn::Age,
+    ListColumn::Comments,
+];
+const FAST_COLUMNS: [ListColumn; 4] = [ListColumn::Number, ListColumn::Title, ListColumn::Author, ListColumn::Age];
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum ListSort {
+    Age,
+    Comments,
+    Size,
+    Title,
+}
+
+#[derive(Subcommand, Debug)]
+enum MemoryAction {
+    /// Show what is remembered for a repository
+    List {
+        /// Repository as owner/repo
+        repo: String,
+    },
+    /// Feedback the team disagrees with, e.g. "unwrap in tests is intentional"
+    Reject {
+        /// Repository as owner/repo
+        repo: String,
+        theme: String,
+    },
+    /// Feedback the team wants to keep getting, e.g. "missing error context"
+    Accept {
+        /// Repository as owner/repo
+        repo: String,
+        theme: String,
+    },
+    /// Forget a preference, by its number in the list
+    Forget {
+        /// Repository as owner/repo
+        repo: String,
+        number: usize,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum PendingAction {
+    /// Show the comments of the pending review
+    List {
+        /// Pull request as owner/repo#number
+        pr: String,
+    },
+    /// Submit the pending review as one review, with a verdict
+    Submit {
+        /// Pull request as owner/repo#number
+        pr: String,
+        #[arg(long, value_enum)]
+        verdict: pending::Verdict,
+    },
+    /// Throw the pending review away
+    Discard {
+        /// Pull request as owner/repo#number
+        pr: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum HistoryAction {
+    /// List every finding reported on a PR as open, resolved or regressed
+    Status {
+        /// Pull request as owner/repo#number
+        pr: String,
+    },
+    /// Compare the latest review with the previous one: resolved, still open and new findings
+    Diff {
+        /// Pull request as owner/repo#number
+        pr: String,
+    },
+}
+
+// Overloaded and unavailable, worth retrying with the fallback model
+const FALLBACK_STATUSES: [u16; 2] = [529, 503];
+
+struct ReviewOptions {
+    linus_mode: bool,
+    skip_formatting: bool,
+    paths: paths::PathFilter,
+    notify_teams: bool,
+    // Ends the report with the time spent in each phase
+    timings: bool,
+    share: bool,
+    repo_path: Option<PathBuf>,
+    dup_threshold: f64,
+    dup_min_lines: usize,
+    config: config::Config,
+    // Why no request reaches the AI, whose sections are then left out
+    no_ai: Option<&'static str>,
+    model: String,
+    fallback_model: Option<String>,
+    // Set once the model was overloaded, the rest of the run uses the fallback
+    use_fallback: AtomicBool,
+    // Small model triaging the files before the review
+    prefilter_model: Option<String>,
+    // Full language name the AI answers in, None for English
+    language: Option<String>,
+    tone: Option<config::Tone>,
+    detail: config::Detail,
+    max_tokens: u32,
+    // None leaves it to the provider
+    temperature: Option<f64>,
+    // Added to the AI prompt of every file
+    instructions: Vec<String>,
+    templates: templates::Templates,
+    summarize_comments: usize,
+    collapse_comments: bool,
+    check_description: bool,
+    show_images: bool,
+    benchmarks: Option<Vec<bench::BenchChange>>,
+    coverage: Option<coverage::Coverage>,
+    min_patch_coverage: Option<f64>,
+    test_results: Option<Vec<junit::TestCase>>,
+    terraform_plan: Option<Vec<terraform::ResourceChange>>,
+    theme: Theme,
+    // Print reviews while they're produced instead of returning them
+    stream: bool,
+}
+
+impl ReviewOptions {
+    fn from_args(args: &Args, config: config::Config) -> Result<Self, Box<dyn Error>> {
+        let benchmarks = args.bench_json.as_deref().map(bench::load).transpose()?;
+        let coverage = args.coverage.as_deref().map(coverage::load).transpose()?;
+        let detail = args.detail.or(config.detail).unwrap_or_default();
+        // Deep reviews don't fit the usual budget
+        let default_max_tokens = if detail == config::Detail::Deep { 2000 } else { 1000 };
+        let test_results = if args.junit.is_empty() {
+            None
+        } else {
+            let mut cases = Vec::new();
+            for path in &args.junit {
+                cases.extend(junit::load(path)?);
+            }
+            Some(cases)
+        };
+        let terraform_plan = args.terraform_plan.as_deref().map(terraform::load).transpose()?;
+        let mut theme = Theme::detect(args.ascii, args.plain);
+        if config.color == Some(false) {
+            theme.color = false;
+        }
+        if let Some(width) = config.width {
+            theme.width = width;
+        }
+        theme.local_time = args.local_time;
+
+        let provider = config.ai_provider.unwrap_or_default();
+        let no_ai = if args.no_ai {
+            Some("--no-ai was given")
+        } else if matches!(args.command, Some(Command::Show { .. })) {
+            Some("rubber show leaves the AI out")
+        } else if !provider.needs_key() {
+            None
+        } else if provider == ai::Provider::Openai && config.ai_api_key().is_none() {
+            Some("no OpenAI API key is set")
+        } else if config.anthropic_api_key_command.is_none() && config.ai_api_key().is_none() {
+            Some("no Anthropic API key is set")
+        } else {
+            None
+        };
+        let model = config.model_id(config.model.as_deref().unwrap_or(provider.default_model()));
+        let fallback_model = config.fallback_model.as_deref().map(|name| config.model_id(name));
+        let prefilter_model = args
+            .prefilter
+            .as_deref()
+            .or(config.prefilter_model.as_deref())
+            .map(|name| config.model_id(name));
+        let temperature = args.temperature.or(config.temperature);
+        // A typo or a leftover from another provider fails here rather than on every file
+        if no_ai.is_none() {
+            for model in iter::once(&model).chain(&fallback_model).chain(&prefilter_model) {
+                provider.check_model(model)?;
+            }
+            if let Some(temperature) = temperature {
+                provider.check_temperature(temperature)?;
+            }
+        }
+
+        Ok(Self {
+            linus_mode: args.linus_torvalds,
+            skip_formatting: args.skip_formatting,
+            paths: paths::PathFilter::parse(&args.paths)?,
+            notify_teams: args.notify_teams,
+            timings: args.timings,
+            share: args.share,
+            repo_path: args.repo_path.clone(),
+            dup_threshold: args.dup_threshold,
+            dup_min_lines: args.dup_min_lines,
+            no_ai,
+            model,
+            fallback_model,
+            use_fallback: AtomicBool::new(false),
+            prefilter_model,
+            language: args.lang.as_deref().or(config.language.as_deref()).and_then(language_name),
+            tone: args.tone.or(config.tone),
+            detail,
+            max_tokens: args.max_tokens.or(config.max_tokens).unwrap_or(default_max_tokens),
+            temperature,
+            instructions: Vec::new(),
+            templates: templates::load(config.templates.as_deref())?,
+            summarize_comments: args.summarize_comments,
+            collapse_comments: args.collapse_comments,
+            check_description: args.check_description,
+            show_images: args.show_images,
+            config,
+            benchmarks,
+            coverage,
+            min_patch_coverage: args.min_patch_coverage,
+            test_results,
+            terraform_plan,
+            theme,
+            stream: true,
+        })
+    }
+
+    // Why no request may reach the AI, for the whole run or the PR being reviewed
+    fn no_ai(&self) -> Option<&'static str> {
+        self.no_ai.or_else(llm::withheld)
+    }
+
+    fn current_model(&self) -> &str {
+        match &self.fallback_model {
+            Some(fallback) if self.use_fallback.load(Ordering::Relaxed) => fallback,
+            _ => &self.model,
+        }
+    }
+}
+
+// Per-file additions to the review, decided before the file is analyzed
+#[derive(Default)]
+struct FileReview {
+    // Extra instructions appended to the AI prompt
+    instructions: Vec<String>,
+    // Findings that apply to the file as a whole
+    notes: Vec<String>,
+    // Baseline messages, matching heuristic findings are not shown
+    ignored: Vec<String>,
+    linus_mode: bool,
+    // Replaces the configured tone for this file
+    tone: Option<config::Tone>,
+    // The pre-filter found only mechanical changes, the AI review is skipped
+    trivial: bool,
+    // Matched by llm.deny_paths
+    withheld: bool,
 }
 
 #[derive(Deserialize, Debug)]
@@ -30,9 +743,15 @@ struct PullRequest {
     number: u32,
     title: String,
     user: User,
-    created_at: String,
+    created_at: DateTime<Utc>,
     html_url: String,
     comments_url: String,
+    // Known up front for merge requests, counted separately for GitHub
+    #[serde(default)]
+    comments: Option<usize>,
+    // Not on Bitbucket
+    #[serde(default)]
+    labels: Vec<Label>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -43,128 +762,182 @@ struct User {
 #[derive(Deserialize, Debug)]
 struct Comment {
     user: User,
-    created_at: String,
+    created_at: DateTime<Utc>,
+    body: String,
+}
+
+// A comment on a line of the diff
+#[derive(Deserialize, Debug)]
+struct ReviewComment {
+    user: User,
     body: String,
+    path: String,
+    // Null once the line is no longer in the diff
+    line: Option<u32>,
+    // RIGHT for the new version of the file, LEFT for the old one
+    #[serde(default)]
+    side: String,
 }
 
 #[derive(Serialize, Debug)]
 struct ClaudeMessage {
     role: String,
-    content: String,
+    content: MessageContent,
 }
 
+// Plain text, or content blocks when the message carries images
 #[derive(Serialize, Debug)]
-struct ClaudeRequest {
-    model: String,
-    messages: Vec<ClaudeMessage>,
-    max_tokens: u32,
-}
-
-#[derive(Default)]
-struct OutputBuffer {
-    content: String,
+#[serde(untagged)]
+enum MessageContent {
+    Text(String),
+    Blocks(Vec<Value>),
 }
 
-impl OutputBuffer {
-    fn new() -> Self {
-        Self::default()
-    }
-
-    fn add_line(&mut self, line: impl AsRef<str>) {
-        self.content.push_str(line.as_ref());
-        self.content.push('\n');
-    }
-
-    fn add_separator(&mut self, ch: char, count: usize) {
-        self.add_line(ch.to_string().repeat(count));
-    }
-
-    fn add_header(&mut self, text: &str) {
-        self.add_line("");
-        let padding = 76_usize.saturating_sub(text.len());
-        self.add_line(format!("┏━━ {} {}", text, "━".repeat(padding)));
-    }
-
-    fn add_section(&mut self, text: &str) {
-        let padding = 76_usize.saturating_sub(text.len());
-        self.add_line(format!("┣━━ {} {}", text, "━".repeat(padding)));
-    }
-
-    fn add_box_content(&mut self, content: &str) {
-        self.add_line("┃");
-        self.add_box_inner_content(content);
-        self.add_line("┃");
-    }
-
-    fn add_box_inner_content(&mut self, content: &str) {
-        for line in content.lines() {
-            self.add_line(format!("┃  {}", line));
-        }
-    }
-
-    fn add_diff_header(&mut self, filename: &str) {
-        self.add_line("");
-        let padding = 70_usize.saturating_sub(filename.len());
-        self.add_line(format!("┏━━ Diff: {} {}", filename, "━".repeat(padding)));
-    }
-
-    fn add_diff_content(&mut self, content: &str) {
-        for line in content.lines() {
-            let formatted_line = match line.chars().next() {
-                Some('+') => format!("┃  \x1b[32m{}\x1b[0m", line), // Green for additions
-                Some('-') => format!("┃  \x1b[31m{}\x1b[0m", line), // Red for deletions
-                _ => format!("┃  {}", line),
-            };
-            self.add_line(&formatted_line);
-        }
-    }
-
-    fn add_diff_separator(&mut self) {
-        self.add_line(
-            "┗━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━",
-        );
+// Times a reply cut at max_tokens is continued before giving up
+const MAX_CONTINUATIONS: usize = 3;
+
+// Requests in flight at once when fetching comment counts, sizes and the
+// like for each PR of a list
+const PR_FETCH_CONCURRENCY: usize = 8;
+
+// Fetches something for every PR, several at once, in the PRs' order. None
+// where it failed
+async fn for_each_pr<'a, T, F: Future<Output = Result<T, Box<dyn Error>>>>(
+    prs: &'a [PullRequest],
+    what: &str,
+    fetch: impl Fn(&'a PullRequest) -> F,
+) -> Vec<Option<T>> {
+    // Once GitHub says to slow down, the remaining requests would fail the same way
+    let rate_limited = Cell::new(false);
+    let mut fetches = stream::iter(prs.iter().enumerate())
+        .map(|(index, pr)| {
+            let rate_limited = &rate_limited;
+            let fetch = &fetch;
+            async move { (index, pr, if rate_limited.get() { None } else { Some(fetch(pr).await) }) }
+        })
+        .buffer_unordered(PR_FETCH_CONCURRENCY);
+    let mut results: Vec<Option<T>> = prs.iter().map(|_| None).collect();
+    while let Some((index, pr, result)) = fetches.next().await {
+        let Some(result) = result else {
+            continue;
+        };
+        results[index] = match result {
+            Ok(result) => Some(result),
+            Err(e) if http::is_rate_limited(&*e) => {
+                if !rate_limited.replace(true) {
+                    warn!("Not fetching the remaining {}: {}", what, e);
+                }
+                None
+            }
+            Err(e) => {
+                warn!("Could not fetch the {} of PR #{}: {}", what, pr.number, e);
+                None
+            }
+        };
     }
+    results
 }
 
-fn get_comments_count(
+async fn get_comments_count(
     comments_url: &str,
     github_token: Option<&str>,
 ) -> Result<usize, Box<dyn Error>> {
-    let mut request = ureq::get(comments_url).set("User-Agent", "rubber");
-
-    if let Some(token) = github_token {
-        request = request.set("Authorization", &format!("Bearer {}", token));
-    }
-
-    let response = request.call()?.into_string()?;
+    let response = http::GithubClient::new(github_token).get_all(comments_url).await?;
 
     let comments: Vec<Value> = serde_json::from_str(&response)?;
     Ok(comments.len())
 }
 
-fn get_pr_comments(
-    comments_url: &str,
-    github_token: Option<&str>,
-) -> Result<Vec<Comment>, Box<dyn Error>> {
-    let mut request = ureq::get(comments_url).set("User-Agent", "rubber");
+// PRs with this label are left alone by automated runs
+const SKIP_LABEL: &str = "rubber-skip";
 
-    if let Some(token) = github_token {
-        request = request.set("Authorization", &format!("Bearer {}", token));
-    }
+#[derive(Deserialize, Debug, Default)]
+struct Label {
+    name: String,
+}
 
-    let response = request.call()?.into_string()?;
+#[derive(Deserialize, Debug, Default)]
+struct GitRef {
+    sha: String,
+    #[serde(default, rename = "ref")]
+    name: String,
+    // Where the commit lives, null once a PR's fork is deleted
+    #[serde(default)]
+    repo: Option<Repository>,
+}
 
-    let comments: Vec<Comment> = serde_json::from_str(&response)?;
-    Ok(comments)
+#[derive(Deserialize, Debug, Default)]
+struct Repository {
+    full_name: String,
+    #[serde(default)]
+    clone_url: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Default)]
 struct PullRequestDetail {
+    #[serde(default)]
+    number: u32,
     title: String,
     body: Option<String>,
-    comments_url: String,
+    #[serde(default)]
+    html_url: String,
+    #[serde(default)]
+    base: GitRef,
+    #[serde(default)]
+    head: GitRef,
+    #[serde(default)]
+    labels: Vec<Label>,
+    #[serde(default)]
+    author_association: String,
+    created_at: Option<DateTime<Utc>>,
+    updated_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    directives: directives::Directives,
+    // Changed files left out by --paths
+    #[serde(skip)]
+    filtered_out: usize,
     #[serde(default)]
     files: Vec<FileChange>,
+    // Comments left on lines of the diff, from a separate endpoint
+    #[serde(skip)]
+    review_comments: Vec<ReviewComment>,
+    // Changed files at the head commit, by path, from the bundle the PR was
+    // imported from
+    #[serde(skip)]
+    head_files: HashMap<String, String>,
+}
+
+impl PullRequestDetail {
+    // The repository the head commit's files are read from: the fork for PRs
+    // opened from one, the base repository otherwise or when the fork is gone
+    fn head_repo<'a>(&'a self, owner: &'a str, repo: &'a str) -> (&'a str, &'a str) {
+        self.head
+            .repo
+            .as_ref()
+            .and_then(|head| head.full_name.split_once('/'))
+            .unwrap_or((owner, repo))
+    }
+
+    // A file at the head commit, from the bundle when it has it so imported
+    // reviews don't go back to GitHub for it
+    async fn head_file(
+        &self,
+        owner: &str,
+        repo: &str,
+        path: &str,
+        github_token: Option<&str>,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        if let Some(content) = self.head_files.get(path) {
+            return Ok(Some(content.clone()));
+        }
+        let (head_owner, head_repo) = self.head_repo(owner, repo);
+        github::file_at(head_owner, head_repo, path, &self.head.sha, github_token).await
+    }
+
+    fn is_fork(&self, owner: &str, repo: &str) -> bool {
+        let (head_owner, head_repo) = self.head_repo(owner, repo);
+        !head_owner.eq_ignore_ascii_case(owner) || !head_repo.eq_ignore_ascii_case(repo)
+    }
 }
 
 #[derive(Deserialize, Debug, Default)]
@@ -174,15 +947,87 @@ struct FileChange {
     additions: u32,
     deletions: u32,
     patch: Option<String>,
+    // Set for renamed files
+    previous_filename: Option<String>,
+    // Size of the patch before it was cut to MAX_PATCH_BYTES
+    #[serde(default)]
+    truncated_from: Option<usize>,
+    // The patch shows notebook cell sources instead of the raw JSON
+    #[serde(skip)]
+    notebook: bool,
+    // Matched by llm.deny_paths, nothing of it goes to the AI
+    #[serde(skip)]
+    withheld: bool,
 }
 
-async fn get_code_review(patch: &str, linus_mode: bool) -> Result<String, Box<dyn Error>> {
-    info!("Generating AI review for patch...");
+impl FileChange {
+    // The patch as prompts may include it, without the rubber:off regions
+    fn ai_patch(&self) -> Option<Cow<'_, str>> {
+        let patch = self.patch.as_deref().filter(|_| !self.withheld)?;
+        if patch.contains(diff::DISABLE_MARKER) || patch.contains(diff::ENABLE_MARKER) {
+            Some(Cow::Owned(diff::without_disabled_regions(patch).0))
+        } else {
+            Some(Cow::Borrowed(patch))
+        }
+    }
+}
 
-    let api_key =
-        env::var("ANTHROPIC_API_KEY").expect("ANTHROPIC_API_KEY environment variable not set");
+// Larger patches are cut: beyond this they can't be reviewed usefully anyway,
+// and a few generated files shouldn't take gigabytes of memory
+const MAX_PATCH_BYTES: usize = 512 * 1024;
+// Patches beyond this many tokens are reviewed in parts, well within every
+// model's context and small enough for a review that goes into the code.
+// Counted roughly, code runs at about four bytes a token
+const MAX_CHUNK_TOKENS: usize = 24_000;
+const BYTES_PER_TOKEN: usize = 4;
+
+// Prompt guidance for --tone and --detail, the Linus persona brings its own tone
+fn review_style(options: &ReviewOptions, file_review: &FileReview) -> Vec<&'static str> {
+    let mut style = Vec::new();
+    match file_review.tone.or(options.tone) {
+        _ if file_review.linus_mode => {}
+        Some(config::Tone::Strict) => style.push(
+            "Be strict and direct: point out every problem, minor ones included, without softening the wording.",
+        ),
+        Some(config::Tone::Friendly) => style.push(
+            "Be friendly and encouraging: mention what is done well and phrase issues as suggestions.",
+        ),
+        Some(config::Tone::Terse) => {
+            style.push("Be terse: short bullet points, no pleasantries or filler.")
+        }
+        None => {}
+    }
+    match options.detail {
+        config::Detail::Brief => style.push(
+            "Keep it brief: a one sentence summary and at most the three most important issues.",
+        ),
+        config::Detail::Normal => {}
+        config::Detail::Deep => style.push(
+            "Go deep: consider edge cases, error paths, concurrency and API design, and explain the reasoning \
+            behind each issue.",
+        ),
+    }
+    style
+}
+
+// The review request for a patch, also used to frame the example patches
+fn review_prompt(patch: &str, options: &ReviewOptions, file_review: &FileReview) -> String {
+    let mut patch_block = String::new();
+    let style = review_style(options, file_review);
+    if !style.is_empty() {
+        patch_block.push_str(&style.join("\n"));
+        patch_block.push_str("\n\n");
+    }
+    if !file_review.instructions.is_empty() {
+        patch_block.push_str("Additional review instructions:\n");
+        for instruction in &file_review.instructions {
+            patch_block.push_str(&format!("- {}\n", instruction));
+        }
+        patch_block.push('\n');
+    }
+    patch_block.push_str(&format!("```\n{}\n```", patch));
 
-    let prompt = if linus_mode {
+    if file_review.linus_mode {
         format!(
             "Review this code patch in the style of Linus Torvalds - be brutally honest, sarcastic, and passionate \
             about code quality, but make technically valid points. Channel his famous rants about poor code quality. \
@@ -196,8 +1041,8 @@ async fn get_code_review(patch: &str, linus_mode: bool) -> Result<String, Box<dy
             \n\
             Format the response with a '## Summary' section followed by a '## Linus Rant' section.\n\
             Stay in character as Linus throughout. If there are no major issues, express pleasant surprise.\n\
-            ```\n{}\n```",
-            patch
+            {}",
+            patch_block
         )
     } else {
         format!(
@@ -213,223 +1058,892 @@ async fn get_code_review(patch: &str, linus_mode: bool) -> Result<String, Box<dy
             Only provide feedback if there are concrete issues to address.\n\
             If the patch lacks sufficient context to make meaningful suggestions, indicate which additional files or \
             information would be helpful to review in a '## Additional Context Needed' section.\n\n\
-            ```\n{}\n```",
-            patch
+            {}",
+            patch_block
         )
-    };
-
-    let client = reqwest::Client::new();
-    let mut headers = HeaderMap::new();
-    headers.insert("x-api-key", HeaderValue::from_str(&api_key)?);
-    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-    headers.insert("anthropic-version", HeaderValue::from_static("2023-06-01"));
+    }
+}
 
-    let messages = vec![ClaudeMessage {
-        role: "user".to_string(),
-        content: prompt,
-    }];
+// part is which piece of a patch split by get_chunked_review this is, and of how many
+async fn get_code_review(
+    patch: &str,
+    options: &ReviewOptions,
+    file_review: &FileReview,
+    part: Option<(usize, usize)>,
+    on_text: &mut dyn FnMut(&str),
+) -> Result<String, Box<dyn Error>> {
+    info!("Generating AI review for patch...");
 
-    let request = ClaudeRequest {
-        model: "claude-3-5-sonnet-20241022".to_string(),
-        messages,
-        max_tokens: 1000,
-    };
+    // Examples go first as earlier turns of the conversation
+    let mut messages = Vec::new();
+    for example in &options.templates.examples {
+        messages.push(ClaudeMessage {
+            role: "user".to_string(),
+            content: MessageContent::Text(review_prompt(&example.patch, options, file_review)),
+        });
+        messages.push(ClaudeMessage {
+            role: "assistant".to_string(),
+            content: MessageContent::Text(example.review.clone()),
+        });
+    }
 
-    let response = client
-        .post("https://api.anthropic.com/v1/messages")
-        .headers(headers)
-        .json(&request)
-        .send()
-        .await?
-        .json::<serde_json::Value>()
-        .await?;
+    let mut prompt = review_prompt(patch, options, file_review);
+    if let Some((part, parts)) = part {
+        prompt.push_str(&format!(
+            "\n\nThis is part {} of {} of the file's patch, split because of its size. Review only this part, \
+            the others are reviewed separately.",
+            part, parts
+        ));
+    }
+    send_messages(messages, prompt, options, on_text).await
+}
 
-    trace!("Request: {:?}", request);
-    trace!("Response: {:?}", response);
+// Reviews the pieces of a patch too large for one prompt one after the
+// other, and merges their reviews into one
+async fn get_chunked_review(
+    chunks: &[String],
+    options: &ReviewOptions,
+    file_review: &FileReview,
+) -> Result<String, Box<dyn Error>> {
+    let mut reviews = Vec::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        info!("Reviewing part {} of {}...", i + 1, chunks.len());
+        let part = Some((i + 1, chunks.len()));
+        let review = get_code_review(chunk, options, file_review, part, &mut |_| {})
+            .await
+            .map_err(|e| format!("part {} of {}: {}", i + 1, chunks.len(), e))?;
+        reviews.push(review);
+    }
+    Ok(merge_reviews(&reviews, file_review.linus_mode))
+}
 
-    let review = response["content"][0]["text"]
-        .as_str()
-        .ok_or("Failed to get response text")?
-        .to_string();
+// One review from the reviews of each part: the summaries one after the
+// other, and every list item once, however many parts pointed it out at the
+// same line. Sections the report doesn't show are kept all the same
+fn merge_reviews(reviews: &[String], linus_mode: bool) -> String {
+    // (heading, body lines), in the order the headings first come up
+    let mut sections: Vec<(String, Vec<String>)> = Vec::new();
+    let mut seen = HashSet::new();
+    for section in reviews.iter().flat_map(|review| markdown_sections(review)) {
+        let (heading, body) = section.split_once('\n').unwrap_or((section, ""));
+        let title = section_title(section, linus_mode).unwrap_or(heading.trim());
+        let existing = sections
+            .iter()
+            .position(|(existing, _)| section_title(existing, linus_mode).unwrap_or(existing) == title);
+        let index = match existing {
+            Some(index) => index,
+            None => {
+                sections.push((heading.trim().to_string(), Vec::new()));
+                sections.len() - 1
+            }
+        };
+        let items = list_items(body);
+        if items.is_empty() {
+            let body = body.trim().to_string();
+            if !body.is_empty() && !sections[index].1.contains(&body) {
+                sections[index].1.push(body);
+            }
+        } else {
+            for item in items {
+                if seen.insert((title, ai_finding_key(&item))) {
+                    sections[index].1.push(format!("- {}", item));
+                }
+            }
+        }
+    }
+    sections
+        .into_iter()
+        .map(|(heading, lines)| {
+            let separator = if lines.iter().all(|line| line.starts_with("- ")) { "\n" } else { "\n\n" };
+            format!("## {}\n{}\n", heading, lines.join(separator))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
 
-    Ok(review)
+fn language_name(code: &str) -> Option<String> {
+    let name = match code.to_lowercase().as_str() {
+        "en" | "english" => return None,
+        "es" => "Spanish",
+        "ja" => "Japanese",
+        "de" => "German",
+        "fr" => "French",
+        "pt" => "Portuguese",
+        "it" => "Italian",
+        "nl" => "Dutch",
+        "pl" => "Polish",
+        "ru" => "Russian",
+        "uk" => "Ukrainian",
+        "tr" => "Turkish",
+        "zh" => "Chinese",
+        "ko" => "Korean",
+        "hi" => "Hindi",
+        // Anything else is taken as the name of the language
+        _ => code,
+    };
+    Some(name.to_string())
 }
 
-async fn analyze_patch(patch: &str, output: &mut OutputBuffer, linus_mode: bool) -> Result<(), Box<dyn Error>> {
-    let additions = patch.lines().filter(|l| l.starts_with('+')).count();
-    let deletions = patch.lines().filter(|l| l.starts_with('-')).count();
+// Sends a single user message and returns the text of the reply
+async fn send_prompt(prompt: String, options: &ReviewOptions) -> Result<String, Box<dyn Error>> {
+    send_messages(Vec::new(), prompt, options, &mut |_| {}).await
+}
 
-    output.add_box_content(&format!(
-        "Changed {} lines ({} additions, {} deletions)",
-        additions + deletions,
-        additions,
-        deletions
-    ));
+// Sends the prompt after earlier turns of the conversation, with the
+// configured system prompt. on_text gets the reply in pieces as it arrives
+async fn send_messages(
+    messages: Vec<ClaudeMessage>,
+    mut prompt: String,
+    options: &ReviewOptions,
+    on_text: &mut dyn FnMut(&str),
+) -> Result<String, Box<dyn Error>> {
+    if let Some(language) = &options.language {
+        // Headings stay in English, the report is split on them
+        prompt.push_str(&format!(
+            "\n\nWrite your response in {}. Keep the markdown section headings exactly as requested above, \
+            in English, and leave code, identifiers, file paths and quoted error messages untranslated.",
+            language
+        ));
+    }
+    let system = options.templates.system.clone();
+    send_to_model(options.current_model(), system, messages, prompt, options, on_text).await
+}
 
-    // Get Claude's review
-    if let Ok(review) = get_code_review(patch, linus_mode).await {
-        // Split the review into sections
-        let sections: Vec<&str> = review.split("## ").collect();
+// Sends the prompt to a given model, as is. Only the configured model falls
+// back when it is overloaded
+async fn send_to_model(
+    model: &str,
+    system: Option<String>,
+    mut messages: Vec<ClaudeMessage>,
+    prompt: String,
+    options: &ReviewOptions,
+    on_text: &mut dyn FnMut(&str),
+) -> Result<String, Box<dyn Error>> {
+    if let Some(reason) = options.no_ai() {
+        return Err(format!("AI requests are disabled: {}", reason).into());
+    }
+    let provider = options.config.ai_provider.unwrap_or_default();
+    let api_key = match (provider, options.config.ai_api_key()) {
+        (_, Some(key)) => key,
+        (ai::Provider::Ollama | ai::Provider::Bedrock, None) => String::new(),
+        (ai::Provider::Anthropic, None) => {
+            return Err("No Anthropic API key, set ANTHROPIC_API_KEY or anthropic_api_key_command in the config".into());
+        }
+        (ai::Provider::Openai, None) => return Err("No OpenAI API key, set OPENAI_API_KEY".into()),
+    };
+    let client = provider.client(&api_key)?;
 
-        for section in sections {
-            if section.starts_with("Summary") {
-                output.add_section("Change Summary");
-                output.add_box_content(section.replace("Summary\n", "").trim());
-            } else if section.starts_with("Feedback") || section.starts_with("Linus Rant") {
-                output.add_section(if linus_mode { "Linus Says" } else { "AI Suggestions" });
-                output.add_box_content(section.replace("Feedback\n", "").replace("Linus Rant\n", "").trim());
-            } else if section.starts_with("Additional Context Needed") {
-                output.add_section("Additional Context Needed");
-                output.add_box_content(section.replace("Additional Context Needed\n", "").trim());
+    messages.push(ClaudeMessage {
+        role: "user".to_string(),
+        content: MessageContent::Text(prompt),
+    });
+    // Continuations replace each other after these
+    let turns = messages.len();
+    let mut model = model.to_string();
+
+    // A reply cut at max_tokens is continued by sending it back as the start
+    // of the assistant turn, which the model picks up from
+    let mut text = String::new();
+    for continuation in 0..=MAX_CONTINUATIONS {
+        let request = |model: &str| {
+            client.request(model, system.as_deref(), &messages, options.max_tokens, options.temperature)
+        };
+        let reply = match send_request(&*client, request(&model), &model, on_text).await {
+            Err(e) if is_overloaded(&*e) && model == options.model => {
+                let Some(fallback) = options.fallback_model.as_ref().filter(|f| **f != model) else {
+                    return Err(e);
+                };
+                warn!("{} is unavailable ({}), using {} for the rest of the run", model, e, fallback);
+                options.use_fallback.store(true, Ordering::Relaxed);
+                model = fallback.clone();
+                send_request(&*client, request(&model), &model, on_text).await?
             }
+            result => result?,
+        };
+        text.push_str(&reply.text);
+
+        if !reply.truncated {
+            break;
         }
+        if continuation == MAX_CONTINUATIONS {
+            warn!("The AI response was still incomplete after {} continuations", MAX_CONTINUATIONS);
+            text.push_str("\n\n[response truncated]");
+            on_text("\n\n[response truncated]");
+            break;
+        }
+        info!("AI response hit max_tokens, continuing...");
+        // The API rejects an assistant turn ending in whitespace
+        let prefix = text.trim_end().to_string();
+        text.truncate(prefix.len());
+        messages.truncate(turns);
+        messages.extend(client.continuation(prefix));
     }
 
-    // Prepare to collect feedback
-    let mut feedback: Vec<String> = Vec::new();
+    Ok(text)
+}
 
-    // Basic code hygiene
-    if patch.contains("TODO") || patch.contains("FIXME") {
-        feedback.push("Outstanding TODOs/FIXMEs should be addressed before merging".to_string());
-    }
+fn is_overloaded(error: &(dyn Error + 'static)) -> bool {
+    error
+        .downcast_ref::<http::StatusError>()
+        .is_some_and(|e| FALLBACK_STATUSES.contains(&e.status))
+}
 
-    if patch.contains("println!") || patch.contains("dbg!") {
-        feedback.push("Remove debug print statements before merging".to_string());
-    }
+// Shown after the part of an answer that streamed in before the API failed
+const INTERRUPTED: &str = "\n\n[interrupted, starting the answer over]\n\n";
+
+// Sends a request and reads the reply. An API failing after it started
+// streaming the answer is retried like one failing up front, the part shown
+// so far marked as interrupted and dropped from the reply
+async fn send_request(
+    client: &dyn ai::AiProvider,
+    request: reqwest::RequestBuilder,
+    model: &str,
+    on_text: &mut dyn FnMut(&str),
+) -> Result<ai::Reply, Box<dyn Error>> {
+    let mut attempt = 0;
+    let (body, response_headers, reply) = loop {
+        let attempted = request.try_clone().ok_or("The request body can't be sent again")?;
+        let mut streamed = false;
+        let mut on_line = |line: &str| {
+            if let Some(text) = client.delta(line) {
+                streamed |= !text.is_empty();
+                on_text(&text);
+            }
+        };
+        let (body, response_headers) = http::send_async(attempted, &mut on_line).await?;
+        match client.reply(&body) {
+            Ok(reply) => break (body, response_headers, reply),
+            Err(e) => {
+                let transient = e.downcast_ref::<http::StatusError>().is_some_and(http::StatusError::is_transient);
+                if !transient || attempt + 1 >= http::max_attempts() {
+                    if streamed {
+                        on_text("\n\n[interrupted]\n");
+                    }
+                    return Err(e);
+                }
+                if streamed {
+                    on_text(INTERRUPTED);
+                }
+                let wait = http::backoff(attempt);
+                attempt += 1;
+                warn!(
+                    "The answer broke off ({}), retrying in {:.1}s ({}/{})",
+                    e,
+                    wait.as_secs_f64(),
+                    attempt,
+                    http::max_attempts() - 1
+                );
+                tokio::time::sleep(wait).await;
+            }
+        }
+    };
 
-    // Error handling patterns
-    if patch.contains("unwrap()") {
-        feedback.push("Replace unwrap() calls with proper error handling".to_string());
-    }
+    trace!("Response: {}", body);
 
-    if patch.contains("expect(") {
-        feedback.push("Consider replacing expect() with more graceful error handling".to_string());
+    let model = reply.model.as_deref().unwrap_or(model);
+    if let Err(e) = usage::record(model, reply.input_tokens, reply.output_tokens, &response_headers) {
+        warn!("Could not record AI usage: {}", e);
     }
+    Ok(reply)
+}
 
-    if patch.contains("panic!") {
-        feedback.push(
-            "Consider replacing panic! with Result/Option for graceful error handling".to_string(),
-        );
-    }
+// Top level items of a markdown list, each one a finding
+fn list_items(markdown: &str) -> Vec<String> {
+    markdown
+        .lines()
+        .filter_map(|line| {
+            let item = line
+                .strip_prefix("- ")
+                .or_else(|| line.strip_prefix("* "))
+                .or_else(|| line.split_once(". ").filter(|(n, _)| n.parse::<u32>().is_ok()).map(|(_, item)| item))?;
+            Some(item.trim().to_string())
+        })
+        .filter(|item| !item.is_empty())
+        .collect()
+}
 
-    // Memory and performance patterns
-    if patch.contains("Clone") || patch.contains("clone()") {
-        feedback
-            .push("Review clone() usage - consider using references where possible".to_string());
+// The sections of a markdown reply, each from its `## ` heading to the next,
+// without the `## `. Text before the first heading isn't one, and a `## `
+// within a line doesn't start one
+fn markdown_sections(text: &str) -> Vec<&str> {
+    let mut starts = Vec::new();
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        if line.starts_with("## ") {
+            starts.push(offset);
+        }
+        offset += line.len();
     }
+    starts
+        .iter()
+        .enumerate()
+        .map(|(i, start)| &text[start + 3..starts.get(i + 1).copied().unwrap_or(text.len())])
+        .collect()
+}
 
-    if patch.contains("Box::new") {
-        feedback.push("Verify if heap allocation via Box is necessary".to_string());
+// What tells an AI finding apart across runs, whose wording changes every
+// time: the line it points at, else its title (bold or before a colon), else
+// its words lowercased without the punctuation
+fn ai_finding_key(item: &str) -> String {
+    let normalize = |text: &str| {
+        text.to_lowercase()
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let words = normalize(item);
+    let mut rest = words.split(' ');
+    while let Some(word) = rest.next() {
+        if (word == "line" || word == "lines")
+            && let Some(number) = rest.next().filter(|n| n.parse::<u32>().is_ok())
+        {
+            return format!("line {}", number);
+        }
     }
-
-    if patch.contains("Vec::new()") && !patch.contains("with_capacity") {
-        feedback.push("Consider using Vec::with_capacity() if the size is known".to_string());
+    let title = item
+        .strip_prefix("**")
+        .and_then(|rest| rest.split_once("**"))
+        .map(|(title, _)| title)
+        .or_else(|| item.split_once(": ").map(|(title, _)| title))
+        .filter(|title| (1..=8).contains(&title.split_whitespace().count()));
+    match title {
+        Some(title) => normalize(title),
+        None => words,
     }
+}
 
-    // Concurrency and async patterns
-    if patch.contains("Mutex") && !patch.contains("RwLock") {
-        feedback.push("Consider if RwLock would be more appropriate than Mutex".to_string());
+// The report's title for a section of the AI review, from its heading. Other
+// sections are left out
+fn section_title(heading: &str, linus_mode: bool) -> Option<&'static str> {
+    if heading.starts_with("Summary") {
+        Some("Change Summary")
+    } else if heading.starts_with("Feedback") || heading.starts_with("Linus Rant") {
+        Some(if linus_mode { "Linus Says" } else { "AI Suggestions" })
+    } else if heading.starts_with("Additional Context Needed") {
+        Some("Additional Context Needed")
+    } else {
+        None
     }
+}
 
-    if patch.contains(".await") && patch.contains("Vec") {
-        feedback.push("Review concurrent operations on Vec - consider using join_all() for parallel execution".to_string());
+// Shows the AI review of a file while it streams in, a row per complete
+// line, laid out like a finished review. Does nothing unless the report
+// itself is streamed
+struct ReviewStream<'a> {
+    output: &'a mut OutputBuffer,
+    linus_mode: bool,
+    // Some text came in, which the providers that don't stream never send
+    shown: bool,
+    // Text after the last complete line
+    partial: String,
+    // Inside a section that's shown
+    open: bool,
+    rows: usize,
+    // Blank lines held back, a section's trailing ones aren't shown
+    blanks: usize,
+}
+
+impl<'a> ReviewStream<'a> {
+    fn new(output: &'a mut OutputBuffer, linus_mode: bool) -> Self {
+        Self {
+            output,
+            linus_mode,
+            shown: false,
+            partial: String::new(),
+            open: false,
+            rows: 0,
+            blanks: 0,
+        }
     }
 
-    // Security considerations
-    if patch.contains("unsafe") {
-        feedback
-            .push("Unsafe block detected - ensure safety guarantees are documented".to_string());
+    fn push(&mut self, text: &str) {
+        if !self.output.stream {
+            return;
+        }
+        self.shown = true;
+        self.partial.push_str(text);
+        while let Some(end) = self.partial.find('\n') {
+            let line: String = self.partial.drain(..=end).collect();
+            self.line(line.trim_end());
+        }
     }
 
-    if patch.contains("as_ptr") || patch.contains("as_mut_ptr") {
-        feedback.push("Raw pointer usage detected - verify memory safety".to_string());
+    fn line(&mut self, line: &str) {
+        if let Some(heading) = line.strip_prefix("## ") {
+            self.close();
+            if let Some(title) = section_title(heading, self.linus_mode) {
+                self.output.add_section(title);
+                self.output.add_box_edge();
+                self.open = true;
+                self.rows = 0;
+            }
+        } else if self.open && line.trim().is_empty() {
+            if self.rows > 0 {
+                self.blanks += 1;
+            }
+        } else if self.open {
+            for _ in 0..self.blanks {
+                self.output.add_row("");
+            }
+            self.blanks = 0;
+            self.output.add_row(line);
+            self.rows += 1;
+        }
     }
 
-    // Testing patterns
-    let has_new_fn = patch
-        .lines()
-        .any(|l| l.contains("fn ") && !l.contains("test"));
-    let has_test = patch.contains("#[test]");
-    if has_new_fn && !has_test {
-        feedback.push("New functions added without corresponding tests".to_string());
+    fn close(&mut self) {
+        if self.open {
+            self.output.add_box_edge();
+            self.open = false;
+            self.blanks = 0;
+        }
+    }
+
+    // Whether the review was shown while it came in
+    fn finish(&mut self) -> bool {
+        let rest = std::mem::take(&mut self.partial);
+        if !rest.is_empty() {
+            self.line(rest.trim_end());
+        }
+        self.close();
+        self.shown
+    }
+}
+
+// Renders the analysis of one file and returns its findings
+async fn analyze_patch(
+    filename: &str,
+    patch: &str,
+    output: &mut OutputBuffer,
+    options: &ReviewOptions,
+    file_review: &FileReview,
+) -> Result<Vec<history::ReviewFinding>, Box<dyn Error>> {
+    let linus_mode = file_review.linus_mode;
+
+    let additions = patch.lines().filter(|l| l.starts_with('+')).count();
+    let deletions = patch.lines().filter(|l| l.starts_with('-')).count();
+
+    output.add_box_content(&format!(
+        "Changed {} lines ({} additions, {} deletions)",
+        additions + deletions,
+        additions,
+        deletions
+    ));
+
+    let mut findings = Vec::new();
+    let mut streamed = false;
+
+    // Get Claude's review, None when it isn't asked
+    let review = if options.no_ai().is_some() {
+        None
+    } else if file_review.withheld {
+        output.add_box_content("Kept from the AI by llm.deny_paths, heuristics only");
+        None
+    } else if file_review.trivial {
+        output.add_box_content("Only mechanical changes according to the pre-filter, AI review skipped");
+        None
+    } else {
+        let started = Instant::now();
+        let chunks = diff::chunk(patch, MAX_CHUNK_TOKENS * BYTES_PER_TOKEN);
+        let review = if chunks.len() > 1 {
+            output.add_box_content(&format!("Too large for one prompt, reviewed in {} parts", chunks.len()));
+            get_chunked_review(&chunks, options, file_review).await
+        } else {
+            let mut stream = ReviewStream::new(output, linus_mode);
+            let review = get_code_review(patch, options, file_review, None, &mut |text| stream.push(text)).await;
+            streamed = stream.finish();
+            review
+        };
+        profile::record_file("ai", filename, started);
+        Some(review)
+    };
+    if !matches!(review, Some(Ok(_))) {
+        output.unreviewed.insert(filename.to_string());
+    }
+    if let Some(Err(e)) = &review {
+        // Still failing after the retries, the heuristics below are all there is
+        warn!("The AI review of {} failed: {}", filename, e);
+        output.add_section("AI Review Failed");
+        output.add_box_content(&e.to_string());
+    }
+    if let Some(Ok(review)) = review {
+        for section in markdown_sections(&review) {
+            let Some(title) = section_title(section, linus_mode) else {
+                continue;
+            };
+            let (_, body) = section.split_once('\n').unwrap_or_default();
+            // A streamed review was shown as it came in
+            if !streamed {
+                output.add_section(title);
+                output.add_box_content(body.trim());
+            }
+            if section.starts_with("Feedback") || section.starts_with("Linus Rant") {
+                // The AI words things differently every run, see ai_finding_key
+                findings.extend(
+                    list_items(body)
+                        .iter()
+                        .map(|item| history::ReviewFinding::new("ai", filename, &ai_finding_key(item), item)),
+                );
+            }
+        }
     }
 
+    // Prepare to collect feedback, with the line that triggered each heuristic.
+    // Notes have no line, their wording is rubber's own and the same every run
+    let mut feedback: Vec<(&str, &str, String)> =
+        file_review.notes.iter().map(|n| ("note", n.as_str(), n.clone())).collect();
+    let started = Instant::now();
+    feedback.extend(heuristics::check(patch));
+    profile::record("heuristics", started);
+
+    feedback.retain(|(_, _, f)| !file_review.ignored.iter().any(|ignored| f.contains(ignored.as_str())));
+
     // Display feedback if any exists
     if !feedback.is_empty() {
-        output.add_section("AI Suggestions");
-        output.add_box_content(&feedback.join("\n"));
+        let messages: Vec<&str> = feedback.iter().map(|(_, _, message)| message.as_str()).collect();
+        output.add_section(if options.no_ai().is_some() { "Heuristic Suggestions" } else { "AI Suggestions" });
+        output.add_box_content(&messages.join("\n"));
     }
+    findings.extend(
+        feedback
+            .into_iter()
+            .map(|(rule, line, message)| history::ReviewFinding::new(rule, filename, line, &message)),
+    );
 
-    Ok(())
+    Ok(findings)
 }
 
-fn display_comments(comments: &[Comment], output: &mut OutputBuffer) {
+async fn display_comments(comments: &[Comment], output: &mut OutputBuffer, show_images: bool) {
     if comments.is_empty() {
         output.add_box_content("No comments found for this PR.");
     } else {
+        // Screen readers and ASCII output only get the links
+        let protocol = if show_images && output.theme.color { images::detect() } else { None };
         for comment in comments {
             output.add_section(&format!(
-                "Author: {} (at {})",
-                comment.user.login, comment.created_at
+                "Author: {} ({})",
+                comment.user.login,
+                timestamps::show(comment.created_at, &output.theme)
             ));
             output.add_box_content(&comment.body);
+            if show_images {
+                for row in images::render(&comment.body, protocol).await {
+                    output.add_row(&row);
+                }
+            }
         }
     }
 }
 
-fn get_pr_details(
+// GitHub's responses for a PR, kept as they came so they can be bundled
+struct PrResponses {
+    pr: String,
+    files: String,
+    comments: String,
+    review_comments: String,
+}
+
+async fn fetch_pr(
     pr_number: u32,
     owner: &str,
     repo: &str,
     github_token: Option<&str>,
+) -> Result<PrResponses, Box<dyn Error>> {
+    provider::current().fetch(owner, repo, pr_number, github_token).await
+}
+
+// Cuts the patch of a file from the provider's JSON as it is fetched, so a
+// huge one is never held whole alongside the rest of the PR
+fn cap_patch(file: &mut Value) {
+    let Some(Value::String(patch)) = file.get_mut("patch") else {
+        return;
+    };
+    let size = patch.len();
+    if diff::truncate(patch, MAX_PATCH_BYTES) {
+        warn!("Truncated the {} KB patch of {}", size / 1024, file["filename"].as_str().unwrap_or_default());
+        file["truncated_from"] = Value::from(size);
+    }
+}
+
+// Every PR goes through here before anything is built from it, which is
+// where the [llm] config decides what the AI may see
+fn parse_pr(
+    owner: &str,
+    repo: &str,
+    responses: &PrResponses,
 ) -> Result<(PullRequestDetail, Vec<Comment>), Box<dyn Error>> {
-    info!("Downloading PR #{} details...", pr_number);
+    llm::select_repository(owner, repo);
+    let mut details: PullRequestDetail = serde_json::from_str(&responses.pr)?;
+    details.directives = directives::parse(details.body.as_deref())?;
+    details.files = serde_json::from_str(&responses.files)?;
+    for file in &mut details.files {
+        file.withheld = !llm::allows_path(&file.filename)
+            || file.previous_filename.as_deref().is_some_and(|path| !llm::allows_path(path));
+        // Providers already cut patches as they fetch them, this catches
+        // bundles saved before they did
+        if let Some(patch) = &mut file.patch {
+            let size = patch.len();
+            if diff::truncate(patch, MAX_PATCH_BYTES) {
+                warn!("Truncated the {} KB patch of {}", size / 1024, file.filename);
+                file.truncated_from = Some(size);
+            }
+        }
+    }
+    details.review_comments = serde_json::from_str(&responses.review_comments)?;
+    let comments: Vec<Comment> = serde_json::from_str(&responses.comments)?;
+    Ok((details, comments))
+}
 
-    let url = format!(
-        "https://api.github.com/repos/{}/{}/pulls/{}",
-        owner, repo, pr_number
-    );
+async fn get_pr_details(
+    pr_number: u32,
+    owner: &str,
+    repo: &str,
+    github_token: Option<&str>,
+) -> Result<(PullRequestDetail, Vec<Comment>), Box<dyn Error>> {
+    let started = Instant::now();
+    let responses = fetch_pr(pr_number, owner, repo, github_token).await?;
+    profile::record("fetch", started);
+    parse_pr(owner, repo, &responses)
+}
 
-    let mut request = ureq::get(&url).set("User-Agent", "rubber");
-    if let Some(token) = github_token {
-        request = request.set("Authorization", &format!("Bearer {}", token));
+// The review comments on a file, split into those shown under their line and
+// the rest: outdated ones, and all of a notebook's since its patch is rewritten
+fn line_comments<'a>(details: &'a PullRequestDetail, file: &FileChange) -> (LineComments<'a>, Vec<&'a ReviewComment>) {
+    let mut placed = LineComments::default();
+    let mut others = Vec::new();
+    for comment in details.review_comments.iter().filter(|c| c.path == file.filename) {
+        match comment.line {
+            Some(line) if !file.notebook => {
+                let side = if comment.side == "LEFT" { &mut placed.left } else { &mut placed.right };
+                side.entry(line)
+                    .or_default()
+                    .push((comment.user.login.as_str(), comment.body.as_str()));
+            }
+            _ => others.push(comment),
+        }
     }
+    (placed, others)
+}
 
-    let response = request.call()?.into_string()?;
-    let mut details: PullRequestDetail = serde_json::from_str(&response)?;
+fn file_patches(details: &PullRequestDetail) -> impl Iterator<Item = (&str, &str)> {
+    details
+        .files
+        .iter()
+        .filter_map(|f| f.patch.as_deref().map(|p| (f.filename.as_str(), p)))
+}
 
-    // Fetch files data from a different endpoint
-    info!("Downloading PR file changes...");
+// Quality gates that turn the run into a failure once the report is rendered
+fn check_gates(details: &PullRequestDetail, options: &ReviewOptions) -> Result<(), Box<dyn Error>> {
+    if let (Some(coverage), Some(minimum)) = (&options.coverage, options.min_patch_coverage) {
+        let percentage = coverage
+            .patch_coverage(file_patches(details))
+            .percentage()
+            .unwrap_or(100.0);
+        if percentage < minimum {
+            return Err(format!(
+                "Patch coverage {:.1}% is below the required {:.1}%",
+                percentage, minimum
+            )
+            .into());
+        }
+    }
 
-    let files_url = format!(
-        "https://api.github.com/repos/{}/{}/pulls/{}/files",
-        owner, repo, pr_number
-    );
+    Ok(())
+}
 
-    let mut files_request = ureq::get(&files_url).set("User-Agent", "rubber");
-    if let Some(token) = github_token {
-        files_request = files_request.set("Authorization", &format!("Bearer {}", token));
+async fn review_pr(
+    pr_number: u32,
+    owner: &str,
+    repo: &str,
+    github_token: Option<&str>,
+    options: &ReviewOptions,
+) -> Result<String, Box<dyn Error>> {
+    match get_pr_details(pr_number, owner, repo, github_token).await {
+        Ok((details, comments)) => {
+            let started = Instant::now();
+            let repo_config = match &options.repo_path {
+                Some(path) => repo_config::load_local(path)?,
+                None if provider::current().is_github() => {
+                    repo_config::load_remote(owner, repo, &details.base.sha, github_token).await?
+                }
+                // .rubber/ is read through GitHub's contents API, --repo-path reads it elsewhere
+                None => repo_config::RepoConfig::default(),
+            };
+            profile::record("config", started);
+            review_details(owner, repo, details, &comments, &repo_config, github_token, options).await
+        }
+        Err(e) => {
+            error!("Error fetching PR details: {}", e);
+            Ok("Error fetching PR details.".to_string())
+        }
     }
+}
 
-    let files_response = files_request.call()?.into_string()?;
-    let files: Vec<FileChange> = serde_json::from_str(&files_response)?;
-    details.files = files;
+// Everything after fetching: shared by live reviews and imported bundles
+async fn review_details(
+    owner: &str,
+    repo: &str,
+    mut details: PullRequestDetail,
+    comments: &[Comment],
+    repo_config: &repo_config::RepoConfig,
+    github_token: Option<&str>,
+    options: &ReviewOptions,
+) -> Result<String, Box<dyn Error>> {
+    let mut output = if options.stream {
+        OutputBuffer::streaming(options.theme)
+    } else {
+        OutputBuffer::new(options.theme)
+    };
+    if options.share {
+        output.copy = Some(String::new());
+    }
+    let pr_number = details.number;
 
-    // Get comments
-    info!("Downloading PR comments...");
-    let comments = get_pr_comments(&details.comments_url, github_token)?;
+    if !options.paths.is_empty() {
+        let total = details.files.len();
+        details.files.retain(|f| options.paths.matches(&f.filename));
+        details.filtered_out = total - details.files.len();
+        info!("Reviewing {} of {} changed files", details.files.len(), total);
+    }
+    // The file contents, checks and the like come from GitHub's API, which for
+    // the other providers would be an unrelated repository of the same name
+    let github = provider::current().is_github();
+    if github {
+        notebook::simplify(owner, repo, &mut details, github_token).await;
+    }
+    let first_timer = onboarding::first_timer(
+        &details.author_association,
+        repo_config.onboarding.as_ref(),
+        owner,
+        repo,
+        &details.base.sha,
+        options.repo_path.as_deref(),
+        github_token,
+    )
+    .await;
+    // What the team said about earlier reviews
+    let preferences = memory::instructions(owner, repo);
+    display_pr_details(
+        &details,
+        comments,
+        &mut output,
+        options,
+        repo_config,
+        first_timer.as_ref(),
+        &preferences,
+    )
+    .await?;
+    if let Some(root) = &options.repo_path
+        && let Some(problem) = checkout::verify(root, owner, repo, &details)
+    {
+        warn!("{}", problem.lines().next().unwrap_or_default());
+        output.add_section("Checkout");
+        output.add_box_content(&problem);
+    }
+    if github {
+        checks::review(owner, repo, &details, github_token, &mut output).await;
+        deployments::review(owner, repo, &details, github_token, &mut output).await;
+        // The repository's own artifacts replace the user's, like its policy
+        let artifacts = if repo_config.artifacts.is_empty() {
+            &options.config.artifacts
+        } else {
+            &repo_config.artifacts
+        };
+        artifacts::review(owner, repo, &details, artifacts, github_token, &mut output).await;
+        if let Some(sizes) = &options.config.sizes {
+            let repo_path = options.repo_path.as_deref();
+            sizes::review(owner, repo, &details, sizes, repo_path, github_token, &mut output).await;
+        }
+        assets::review(owner, repo, &details, github_token, options, &mut output).await;
+        dependencies::review(owner, repo, &details, github_token, &mut output).await;
+        submodules::review(owner, repo, &details, github_token, &mut output).await;
+        schema::review(owner, repo, &details, github_token, &mut output).await;
+    }
+    breaking::review(&details, options, &mut output).await;
+    let policy = repo_config.policy.as_ref().unwrap_or(&options.config.policy);
+    changelog::review(owner, repo, &details, comments, policy, github_token, options, &mut output).await;
+    if let Some(root) = &options.repo_path {
+        impact::review(root, &details, &mut output);
+    }
+    // A failure to store history shouldn't cost the review
+    match history::summary(owner, repo, pr_number, &output.findings, &output.unreviewed) {
+        Ok(Some(summary)) => {
+            output.add_header("History");
+            output.add_box_content(&summary);
+            output.add_diff_separator();
+        }
+        Ok(None) => {}
+        Err(e) => warn!("Could not read the review history: {}", e),
+    }
+    let started = Instant::now();
+    let stored = history::save(owner, repo, pr_number, &details.title, &details.head.sha, &output);
+    if let Err(e) = stored {
+        warn!("Could not store the review history: {}", e);
+    }
+    profile::record("history", started);
+    // Nobody hearing about the PR shouldn't cost the review either
+    if options.notify_teams
+        && let Err(e) = teams::notify(owner, repo, pr_number, &details, &options.config).await
+    {
+        warn!("Could not notify the owning teams: {}", e);
+    }
+    if options.timings {
+        output.add_header("Timings");
+        output.add_box_content(&profile::lines().join("\n"));
+        output.add_diff_separator();
+    }
+    output.close_spill();
+    if options.share {
+        let report = output.copy.take().unwrap_or_else(|| output.content.clone());
+        let title = &details.title;
+        match share::gist(owner, repo, pr_number, title, &details.html_url, &report, github_token).await {
+            Ok(Some(url)) => output.add_line(format!("\nShared at {}", url)),
+            Ok(None) => output.add_line("\nRead-only: the report was not shared"),
+            Err(e) => {
+                print!("{}", output.content);
+                return Err(format!("Could not share the report: {}", e).into());
+            }
+        }
+    }
 
-    Ok((details, comments))
+    if let Err(e) = check_gates(&details, options) {
+        // Still show the report that explains the failure
+        print!("{}", output.content);
+        return Err(e);
+    }
+    Ok(output.content)
 }
 
 async fn display_pr_details(
     details: &PullRequestDetail,
     comments: &[Comment],
     output: &mut OutputBuffer,
-    linus_mode: bool,
+    options: &ReviewOptions,
+    repo_config: &repo_config::RepoConfig,
+    first_timer: Option<&onboarding::FirstTimer>,
+    preferences: &[String],
 ) -> Result<(), Box<dyn Error>> {
+    // The repository's own policy wins over the user's
+    let policy = repo_config.policy.as_ref().unwrap_or(&options.config.policy);
+
     // Title header
     output.add_header(&details.title);
+    if let (Some(created), Some(updated)) = (details.created_at, details.updated_at) {
+        output.add_box_content(&format!(
+            "Opened {}, last updated {}",
+            timestamps::show(created, &output.theme),
+            timestamps::show(updated, &output.theme)
+        ));
+    }
+    if let Some(reason) = options.no_ai() {
+        output.add_section("Heuristic-Only Report");
+        output.add_box_content(&format!(
+            "No AI requests were made, {}.\nEverything below comes from static analysis: the AI \
+            summaries, suggestions and checks are left out.",
+            reason
+        ));
+    }
 
     // Description section
     output.add_section("Description");
@@ -442,6 +1956,142 @@ async fn display_pr_details(
     } else {
         output.add_box_content("No description provided.");
     }
+    if options.check_description && options.no_ai().is_none() {
+        scope::review(details, options, output).await;
+    }
+    checklist::review(details, options, output).await;
+
+    if details.labels.iter().any(|l| l.name == SKIP_LABEL) {
+        output.add_section("Note");
+        output.add_box_content(&format!(
+            "This PR is labeled {}, automated runs (slash commands, CI) skip it.",
+            SKIP_LABEL
+        ));
+    }
+
+    if details.filtered_out > 0 {
+        output.add_section("Note");
+        output.add_box_content(&format!(
+            "{} changed file(s) outside of --paths {} are not shown",
+            details.filtered_out,
+            options.paths.patterns.join(" ")
+        ));
+    }
+
+    if !details.directives.is_empty() {
+        let directives = &details.directives;
+        let mut applied = Vec::new();
+        if !directives.focus.is_empty() {
+            applied.push(format!("focus: {}", directives.focus.join(", ")));
+        }
+        if !directives.skip.is_empty() {
+            applied.push(format!("skip: {}", directives.skip.join(", ")));
+        }
+        if let Some(linus_mode) = directives.linus_mode {
+            applied.push(format!("persona: {}", if linus_mode { "linus" } else { "default" }));
+        }
+        output.add_section("Review Instructions");
+        output.add_box_content(&applied.join("\n"));
+    }
+
+    if !repo_config.is_empty() {
+        let mut loaded = Vec::new();
+        if repo_config.prompt.is_some() {
+            loaded.push("prompt".to_string());
+        }
+        if !repo_config.rules.is_empty() {
+            loaded.push(format!("{} rule(s)", repo_config.rules.len()));
+        }
+        if repo_config.policy.is_some() {
+            loaded.push("policy".to_string());
+        }
+        if repo_config.onboarding.is_some() {
+            loaded.push("onboarding".to_string());
+        }
+        if !repo_config.baseline.is_empty() {
+            loaded.push(format!("{} baseline entries", repo_config.baseline.len()));
+        }
+        if !repo_config.artifacts.is_empty() {
+            loaded.push(format!("{} artifact(s)", repo_config.artifacts.len()));
+        }
+        output.add_section("Repository Config");
+        output.add_box_content(&format!(
+            "Using {} from {}",
+            loaded.join(", "),
+            repo_config.source.as_deref().unwrap_or(repo_config::DIR)
+        ));
+    }
+
+    // Benchmark comparison from CI
+    if let Some(benchmarks) = &options.benchmarks {
+        output.add_section("Benchmarks");
+        if benchmarks.is_empty() {
+            output.add_box_content("No significant performance changes detected.");
+        } else {
+            let mut lines = Vec::new();
+            for finding in benchmarks.iter().map(|b| b.to_finding()) {
+                let recorded = history::ReviewFinding::new("bench", "", &finding.message, &finding.message);
+                output.findings.push(recorded);
+                lines.push(output.format_finding(&finding));
+            }
+            output.add_box_content(&lines.join("\n"));
+        }
+    }
+
+    // Coverage of the changed lines
+    let patch_coverage = options
+        .coverage
+        .as_ref()
+        .map(|c| c.patch_coverage(file_patches(details)));
+    if let Some(patch_coverage) = &patch_coverage {
+        output.add_section("Coverage");
+        match patch_coverage.percentage() {
+            Some(percentage) => {
+                let mut lines = vec![format!(
+                    "Patch coverage: {:.1}% ({} of {} changed lines covered)",
+                    percentage, patch_coverage.covered, patch_coverage.instrumented
+                )];
+                let mut files: Vec<_> = patch_coverage.uncovered.iter().collect();
+                files.sort_by_key(|(filename, _)| filename.as_str());
+                for (filename, uncovered) in files {
+                    lines.push(format!(
+                        "Uncovered in {}: {}",
+                        filename,
+                        coverage::format_ranges(uncovered)
+                    ));
+                }
+                output.add_box_content(&lines.join("\n"));
+            }
+            None => output.add_box_content("No changed lines are covered by the coverage report."),
+        }
+    }
+
+    // Failing tests, linked to the changes that likely caused them
+    if let Some(cases) = &options.test_results {
+        output.add_section("Test Results");
+        let failing = cases.iter().filter(|c| c.failed).count();
+        let mut lines = vec![format!("{} tests, {} failing", cases.len(), failing)];
+        let changed: Vec<&str> = details.files.iter().map(|f| f.filename.as_str()).collect();
+        for finding in junit::correlate(cases, &changed) {
+            let recorded = history::ReviewFinding::new("test", "", &finding.message, &finding.message);
+            output.findings.push(recorded);
+            lines.push(output.format_finding(&finding));
+        }
+        output.add_box_content(&lines.join("\n"));
+    }
+
+    // What applying the PR's infrastructure changes would do
+    if let Some(changes) = &options.terraform_plan {
+        output.add_section("Infrastructure Changes");
+        let mut lines = vec![terraform::summary(changes)];
+        for finding in changes.iter().filter_map(|c| c.to_finding()) {
+            let recorded = history::ReviewFinding::new("terraform", "", &finding.message, &finding.message);
+            output.findings.push(recorded);
+            lines.push(output.format_finding(&finding));
+        }
+        lines.extend(terraform::listing(changes));
+        output.add_box_content(&lines.join("\n"));
+    }
 
     // Files section
     output.add_section("Modified Files");
@@ -449,35 +2099,109 @@ async fn display_pr_details(
     if details.files.is_empty() {
         output.add_box_content("No files modified in this PR.");
     } else {
-        // File summary table
-        output.add_line(format!(
-            "┃  {:<50} {:<10} {:<10} {:<10}",
-            "Filename", "Status", "Additions", "Deletions"
-        ));
-        output.add_line(format!("┃  {}", "─".repeat(80)));
-
         let mut first = true;
-        for file in &details.files {
-            output.add_line(format!(
-                "┃  {:<50} {:<10} {:<10} {:<10}",
-                file.filename, file.status, file.additions, file.deletions
+        if options.theme.plain {
+            // A table read cell by cell makes no sense, one sentence per file instead
+            for file in &details.files {
+                output.add_row(&format!(
+                    "{}: {}, {} additions, {} deletions",
+                    file.filename, file.status, file.additions, file.deletions
+                ));
+            }
+        } else {
+            // File summary table
+            output.add_row(&format!(
+                "{:<50} {:<10} {:<10} {:<10}",
+                "Filename", "Status", "Additions", "Deletions"
             ));
+            output.add_row(&options.theme.glyphs().rule.repeat(80));
+            for file in &details.files {
+                output.add_row(&format!(
+                    "{:<50} {:<10} {:<10} {:<10}",
+                    file.filename, file.status, file.additions, file.deletions
+                ));
+            }
+            output.add_row(&options.theme.glyphs().rule.repeat(80));
+        }
+        let risk = risk::score(&details.files, policy);
+        output.add_row(&format!("Risk score: {}/100", risk.score));
+        for reason in &risk.reasons {
+            output.add_row(&format!("  - {}", reason));
+        }
+        for (team, files) in teams::touched(&options.config, &details.files) {
+            output.add_row(&format!("Team {}: {} file(s)", team.name, files.len()));
         }
         output.add_diff_separator();
 
+        let patches: Vec<(&str, &str)> = file_patches(details).collect();
+        let moves = diff::detect_moves(&patches);
+
+        // Mechanical changes don't need the expensive model
+        let trivial = match &options.prefilter_model {
+            Some(model) if options.no_ai().is_none() => {
+                let patches: Vec<(&str, Cow<str>)> = details
+                    .files
+                    .iter()
+                    .filter_map(|f| f.ai_patch().map(|p| (f.filename.as_str(), p)))
+                    .filter(|(filename, _)| {
+                        !dependencies::is_lockfile(filename) && !details.directives.is_skipped(filename)
+                    })
+                    .collect();
+                let reviewed: Vec<(&str, &str)> = patches.iter().map(|(f, p)| (*f, p.as_ref())).collect();
+                info!("Pre-filtering {} files with {}...", reviewed.len(), model);
+                match prefilter::trivial_files(model, &reviewed, options).await {
+                    Ok(trivial) => {
+                        info!("{} of {} files only have mechanical changes", trivial.len(), reviewed.len());
+                        trivial
+                    }
+                    Err(e) => {
+                        warn!("Could not pre-filter the files, reviewing all of them: {}", e);
+                        HashSet::new()
+                    }
+                }
+            }
+            _ => HashSet::new(),
+        };
+
+        let repo_index = match &options.repo_path {
+            Some(path) => {
+                info!("Indexing {} for duplicate detection...", path.display());
+                match duplicates::RepoIndex::build(path) {
+                    Ok(index) => {
+                        info!("Indexed {} files", index.file_count());
+                        Some(index)
+                    }
+                    Err(e) => {
+                        warn!("Could not index {}: {}", path.display(), e);
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+
         for file in &details.files {
             if let Some(patch) = &file.patch {
-                // Skip analysis for Cargo.lock files
-                if file.filename == "Cargo.lock" {
+                // Skip analysis for lockfiles and submodule pointers, their changes are
+                // summarized under Dependency Changes and Submodule Changes
+                let submodule = submodules::is_pointer(patch);
+                if dependencies::is_lockfile(&file.filename) || submodule {
                     if !first {
                         output.add_diff_separator();
                     }
                     first = false;
 
                     output.add_diff_header(&file.filename);
-                    output.add_diff_content(patch);
+                    output.add_diff_content(patch, &DiffAnnotations::default());
                     output.add_section("Note");
-                    output.add_box_content("Skipping analysis for Cargo.lock file");
+                    if submodule {
+                        output.add_box_content("Skipping analysis for submodule pointer");
+                    } else {
+                        output.add_box_content(&format!(
+                            "Skipping analysis for {} file",
+                            file.filename.rsplit('/').next().unwrap_or(&file.filename)
+                        ));
+                    }
                     continue;
                 }
 
@@ -486,15 +2210,169 @@ async fn display_pr_details(
                 }
                 first = false;
 
+                if details.directives.is_skipped(&file.filename) {
+                    output.add_diff_header(&file.filename);
+                    output.add_diff_content(patch, &DiffAnnotations::default());
+                    output.add_section("Note");
+                    output.add_box_content("Skipped at the author's request (PR description)");
+                    continue;
+                }
+
+                let file_moves = moves.get(&file.filename);
+                let (file_comments, other_comments) = line_comments(details, file);
+                let annotations = DiffAnnotations {
+                    moves: file_moves,
+                    uncovered: patch_coverage
+                        .as_ref()
+                        .and_then(|c| c.uncovered.get(&file.filename)),
+                    comments: Some(&file_comments),
+                };
+                let started = Instant::now();
                 output.add_diff_header(&file.filename);
-                output.add_diff_content(patch);
+                if file.notebook {
+                    output.add_box_content("Notebook: showing changes to cell sources, outputs and execution counts are left out");
+                }
+                if let Some(size) = file.truncated_from {
+                    output.add_box_content(&format!(
+                        "Patch truncated to its first {} KB of {} KB, the rest isn't shown or reviewed",
+                        patch.len() / 1024,
+                        size / 1024
+                    ));
+                }
+                output.add_diff_content(patch, &annotations);
+                if !other_comments.is_empty() {
+                    output.add_section("Other Review Comments");
+                    let lines: Vec<String> = other_comments
+                        .iter()
+                        .map(|c| match c.line {
+                            Some(line) => format!("{} (line {}): {}", c.user.login, line, c.body),
+                            None => format!("{} (outdated): {}", c.user.login, c.body),
+                        })
+                        .collect();
+                    output.add_box_content(&lines.join("\n"));
+                }
+                profile::record("render", started);
 
                 // Add info message before analysis
                 info!("Analyzing changes in {}...", file.filename);
 
                 // Analysis section for this file
                 output.add_section("Static Analysis");
-                analyze_patch(patch, output, linus_mode).await?;
+
+                let mut review_patch = patch.clone();
+                if options.skip_formatting {
+                    let (filtered, skipped) = diff::without_formatting_hunks(&review_patch);
+                    if skipped > 0 {
+                        output.add_box_content(&format!(
+                            "Excluded {} formatting-only hunk(s) from review",
+                            skipped
+                        ));
+                    }
+                    review_patch = filtered;
+                }
+
+                let (filtered, excluded) = diff::without_disabled_regions(&review_patch);
+                if excluded > 0 {
+                    output.add_box_content(&format!(
+                        "Excluded {} changed line(s) inside {} regions from review",
+                        excluded,
+                        diff::DISABLE_MARKER
+                    ));
+                    review_patch = filtered;
+                }
+
+                // Moved code was already reviewed where it came from
+                if let Some(file_moves) = file_moves {
+                    let notes: Vec<String> = file_moves
+                        .added
+                        .iter()
+                        .map(|range| {
+                            format!(
+                                "Lines {}-{} moved unchanged from {}:{} (not re-reviewed)",
+                                range.start,
+                                range.start as usize + range.len - 1,
+                                range.other_file,
+                                range.other_start
+                            )
+                        })
+                        .collect();
+                    if !notes.is_empty() {
+                        output.add_box_content(&notes.join("\n"));
+                    }
+                    review_patch = diff::without_moved_lines(&review_patch, file_moves);
+                }
+
+                if let Some(index) = &repo_index {
+                    let found = index.find_duplicates(
+                        &file.filename,
+                        &review_patch,
+                        options.dup_min_lines,
+                        options.dup_threshold,
+                    );
+                    if !found.is_empty() {
+                        let notes: Vec<String> = found
+                            .iter()
+                            .map(|d| {
+                                format!(
+                                    "Lines {}-{} closely duplicate {}:{} ({:.0}% similar) - consider extracting a shared function\n  {}",
+                                    d.start,
+                                    d.end,
+                                    d.other_file,
+                                    d.other_start,
+                                    d.similarity * 100.0,
+                                    github::diff_link(&details.html_url, &file.filename, d.start, d.end)
+                                )
+                            })
+                            .collect();
+                        output.add_section("Duplication");
+                        output.add_box_content(&notes.join("\n"));
+                    }
+                }
+
+                if !review_patch.lines().any(|l| l.starts_with('+') || l.starts_with('-')) {
+                    output.add_box_content("No remaining changes to review, skipping analysis");
+                    continue;
+                }
+                let mut file_review = FileReview {
+                    instructions: repo_config.instructions(),
+                    ignored: repo_config.ignored(&file.filename),
+                    linus_mode: details.directives.linus_mode.unwrap_or(options.linus_mode),
+                    trivial: trivial.contains(&file.filename),
+                    withheld: file.withheld,
+                    ..Default::default()
+                };
+                // Newcomers get a gentle review whatever the configured style
+                if let Some(first_timer) = first_timer {
+                    file_review.linus_mode = false;
+                    file_review.tone = Some(config::Tone::Friendly);
+                    file_review.instructions.extend(first_timer.instructions.iter().cloned());
+                }
+                file_review.instructions.extend(options.instructions.iter().cloned());
+                file_review.instructions.extend(preferences.iter().cloned());
+                file_review.instructions.extend(details.directives.focus_instruction());
+                // A team's standards apply to its own subtree, the repository's policy still wins
+                let team = options.config.team_for(&file.filename);
+                file_review.instructions.extend(team.and_then(|t| t.prompt.clone()));
+                let policy = repo_config
+                    .policy
+                    .as_ref()
+                    .or(team.and_then(|t| t.policy.as_ref()))
+                    .unwrap_or(policy);
+                if policy.is_performance_sensitive(&file.filename) {
+                    file_review.instructions.push(
+                        "This file is on a performance-sensitive path. Focus on algorithmic complexity, \
+                        allocations and copies in hot paths, and call out anything that could regress performance."
+                            .to_string(),
+                    );
+                    file_review.notes.push(format!(
+                        "Performance-sensitive path touched - run `{}` and compare against the base branch before merging",
+                        policy.bench_command
+                    ));
+                }
+                file_review.instructions.extend(schema::instruction(&file.filename));
+
+                let findings = analyze_patch(&file.filename, &review_patch, output, options, &file_review).await?;
+                output.findings.extend(findings);
             }
         }
     }
@@ -502,104 +2380,637 @@ async fn display_pr_details(
     output.add_diff_separator();
     output.add_line("");
 
+    // Long discussions get a summary, kept apart from what people actually wrote
+    let summary = if options.no_ai().is_none()
+        && options.summarize_comments > 0
+        && comments.len() >= options.summarize_comments
+    {
+        match summarize_discussion(comments, options).await {
+            Ok(summary) => Some(summary),
+            Err(e) => {
+                warn!("Could not summarize the discussion: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    if let Some(summary) = &summary {
+        output.add_header("Discussion Summary");
+        for section in summary.split("## ").filter(|s| !s.trim().is_empty()) {
+            let (title, body) = section.split_once('\n').unwrap_or((section, ""));
+            output.add_section(title.trim());
+            output.add_box_content(body.trim());
+        }
+        output.add_diff_separator();
+        output.add_line("");
+    }
+
     // Comments section
     output.add_header("Comments");
-    display_comments(comments, output);
+    if summary.is_some() && options.collapse_comments {
+        output.add_box_content(&format!(
+            "{} comments collapsed (run without --collapse-comments to show them)",
+            comments.len()
+        ));
+    } else {
+        display_comments(comments, output, options.show_images).await;
+    }
 
     output.add_diff_separator();
     output.add_line("");
 
+    if let Some(first_timer) = first_timer {
+        output.add_header("Welcome");
+        output.add_box_content(&first_timer.message);
+        output.add_diff_separator();
+        output.add_line("");
+    }
+
     Ok(())
 }
 
-fn find_pr_by_number(prs: &[PullRequest], number: u32) -> Option<&PullRequest> {
-    prs.iter().find(|pr| pr.number == number)
+async fn summarize_discussion(comments: &[Comment], options: &ReviewOptions) -> Result<String, Box<dyn Error>> {
+    info!("Summarizing {} comments...", comments.len());
+    let transcript: Vec<String> = comments
+        .iter()
+        .map(|c| format!("[{} at {}]\n{}", c.user.login, c.created_at, c.body))
+        .collect();
+
+    let prompt = format!(
+        "Condense this pull request discussion for someone who hasn't read it. Use only what the \
+        comments say and attribute positions to their authors. Format the response with a '## Positions' \
+        section (who argued for what), a '## Decisions' section (what was agreed) and an '## Open Questions' \
+        section (what is still unresolved), each a short markdown list. Write 'None' in a section with \
+        nothing to report.\n\n{}",
+        transcript.join("\n\n")
+    );
+    send_prompt(prompt, options).await
 }
 
-impl Default for PullRequestDetail {
-    fn default() -> Self {
-        Self {
-            title: String::new(),
-            body: None,
-            comments_url: String::new(),
-            files: Vec::new(),
+// The numbers picked at a prompt out of those listed, in order: "12" or
+// "10-12,15". Ranges take the listed numbers that fall in them, single
+// numbers must be listed. `what` names the listed things in errors
+fn parse_selection(input: &str, listed: &[u32], what: &str) -> Result<Vec<u32>, String> {
+    let number = |text: &str| text.trim().parse::<u32>().map_err(|_| format!("Invalid number: {}", text.trim()));
+    let mut selected = Vec::new();
+    for part in input.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (number(start)?, number(end)?);
+                let mut range: Vec<u32> = listed
+                    .iter()
+                    .copied()
+                    .filter(|n| (start.min(end)..=start.max(end)).contains(n))
+                    .collect();
+                range.sort_unstable();
+                if start > end {
+                    range.reverse();
+                }
+                selected.extend(range);
+            }
+            None => {
+                let picked = number(part)?;
+                if !listed.contains(&picked) {
+                    return Err(format!("#{} is not in the list of {}.", picked, what));
+                }
+                selected.push(picked);
+            }
         }
     }
+    let mut seen = HashSet::new();
+    selected.retain(|n| seen.insert(*n));
+    if selected.is_empty() {
+        return Err(format!("None of the {} listed was selected.", what));
+    }
+    Ok(selected)
 }
 
-async fn run() -> Result<String, Box<dyn std::error::Error>> {
-    // Initialize logger
-    env_logger::init();
+const NO_REPOSITORY: &str = "No repository given, and none set with repository in the config";
 
-    let github_token = env::var("GITHUB_TOKEN").ok();
-    let args = Args::parse();
-    let mut output = OutputBuffer::new();
+// A bare 123 or #123 is a PR of the configured repository
+fn resolve_pr(pr: &str, repository: Option<&str>) -> Result<(String, String, u32), Box<dyn Error>> {
+    match repository {
+        Some(repository) if !pr.contains('/') => {
+            github::parse_pr_ref(&format!("{}#{}", repository, pr.trim_start_matches('#')))
+        }
+        _ => github::parse_pr_ref(pr),
+    }
+}
 
-    // If PR number is provided, show its details directly
-    if let Some(number) = args.pr_number {
-        match get_pr_details(number, &args.owner, &args.repo, github_token.as_deref()) {
-            Ok((details, comments)) => {
-                display_pr_details(&details, &comments, &mut output, args.linus_torvalds).await?;
-                return Ok(output.content);
-            }
-            Err(e) => {
-                error!("Error fetching PR details: {}", e);
-                return Ok("Error fetching PR details.".to_string());
-            }
+impl ListColumn {
+    fn heading(self) -> &'static str {
+        match self {
+            ListColumn::Number => "PR#",
+            ListColumn::Title => "Title",
+            ListColumn::Author => "Author",
+            ListColumn::Age => "Created",
+            ListColumn::Comments => "Comments",
+            ListColumn::Size => "Size",
         }
     }
 
-    output.add_line(format!(
-        "Fetching the 10 most recent PRs for {}/{}",
-        args.owner, args.repo
-    ));
+    fn width(self) -> usize {
+        match self {
+            ListColumn::Number => 6,
+            ListColumn::Title => 50,
+            ListColumn::Author => 20,
+            ListColumn::Age | ListColumn::Comments | ListColumn::Size => 15,
+        }
+    }
+}
 
-    let url = format!(
-        "https://api.github.com/repos/{}/{}/pulls?state=all&sort=created&direction=desc&per_page=10",
-        args.owner, args.repo
-    );
+// A row of the PR list, with what was fetched beyond the list itself
+struct ListedPr<'a> {
+    pr: &'a PullRequest,
+    comments: Option<usize>,
+    // Lines added and deleted
+    size: Option<(u32, u32)>,
+}
+
+impl ListedPr<'_> {
+    fn cell(&self, column: ListColumn, theme: &Theme, missing: &str) -> String {
+        match column {
+            ListColumn::Number => self.pr.number.to_string(),
+            ListColumn::Title => self.pr.title.clone(),
+            ListColumn::Author => self.pr.user.login.clone(),
+            ListColumn::Age => timestamps::show(self.pr.created_at, theme),
+            ListColumn::Comments => self.comments.map_or(missing.to_string(), |count| count.to_string()),
+            ListColumn::Size => self.size.map_or(missing.to_string(), |(added, deleted)| format!("+{} -{}", added, deleted)),
+        }
+    }
+}
 
-    let mut request = ureq::get(&url).set("User-Agent", "rubbery");
-    if let Some(token) = &github_token {
-        request = request.set("Authorization", &format!("Bearer {}", token));
+// Orders the rows. PRs come newest first, which is also the order by age.
+// Counts that couldn't be fetched sort last
+fn sort_listed(rows: &mut [ListedPr], sort: Option<ListSort>) {
+    match sort {
+        None | Some(ListSort::Age) => {}
+        Some(ListSort::Title) => rows.sort_by_key(|row| row.pr.title.to_lowercase()),
+        Some(ListSort::Comments) => rows.sort_by_key(|row| Reverse(row.comments)),
+        Some(ListSort::Size) => rows.sort_by_key(|row| Reverse(row.size.map(|(added, deleted)| added + deleted))),
     }
+}
 
-    let response = request.call()?.into_json::<Vec<PullRequest>>()?;
+// The PR list from the one request that fetches it: no banner, no header and
+// no comment counts. Columns are aligned on a terminal and separated by tabs
+// otherwise, for cut and awk
+async fn list_prs_fast(
+    owner: &str,
+    repo: &str,
+    limit: u8,
+    columns: &[ListColumn],
+    sort: Option<ListSort>,
+    github_token: Option<&str>,
+    output: &mut OutputBuffer,
+) -> Result<(), Box<dyn Error>> {
+    let extra = [ListColumn::Comments, ListColumn::Size];
+    if columns.iter().any(|c| extra.contains(c)) || matches!(sort, Some(ListSort::Comments | ListSort::Size)) {
+        return Err("--fast lists PRs from a single request, without comment counts or sizes".into());
+    }
+    let prs = provider::current().list(owner, repo, limit, false, github_token).await?;
+    let mut listed: Vec<ListedPr> = prs.iter().map(|pr| ListedPr { pr, comments: None, size: None }).collect();
+    sort_listed(&mut listed, sort);
+    let theme = output.theme;
+    let rows: Vec<Vec<String>> = listed
+        .iter()
+        .map(|row| {
+            columns
+                .iter()
+                .map(|&column| match column {
+                    ListColumn::Age => timestamps::compact(row.pr.created_at),
+                    _ => row.cell(column, &theme, ""),
+                })
+                .collect()
+        })
+        .collect();
+
+    if !io::stdout().is_terminal() {
+        for row in rows {
+            output.add_line(row.join("\t"));
+        }
+        return Ok(());
+    }
+    let widths: Vec<usize> = (0..columns.len())
+        .map(|i| rows.iter().map(|row| row[i].chars().count()).max().unwrap_or(0))
+        .collect();
+    for row in rows {
+        let cells: Vec<String> = row.iter().zip(&widths).map(|(cell, width)| format!("{:<width$}", cell)).collect();
+        output.add_line(cells.join("  ").trim_end());
+    }
+    Ok(())
+}
+
+// Prints the table of a repository's most recent PRs and returns them
+async fn list_prs(
+    owner: &str,
+    repo: &str,
+    limit: u8,
+    columns: &[ListColumn],
+    sort: Option<ListSort>,
+    github_token: Option<&str>,
+    output: &mut OutputBuffer,
+) -> Result<Vec<PullRequest>, Box<dyn Error>> {
+    output.add_line(format!(
+        "Fetching the {} most recent PRs for {}/{}",
+        limit, owner, repo
+    ));
+
+    let response = provider::current().list(owner, repo, limit, false, github_token).await?;
 
     if response.is_empty() {
         output.add_line("No pull requests found.");
-        return Ok(output.content);
+        return Ok(response);
+    }
+    let plain = output.theme.plain;
+    let wanted = |column: ListColumn, key: ListSort| columns.contains(&column) || sort == Some(key);
+
+    let counts = if !wanted(ListColumn::Comments, ListSort::Comments) {
+        vec![None; response.len()]
+    } else if response.iter().all(|pr| pr.comments.is_some()) {
+        response.iter().map(|pr| pr.comments).collect()
     } else {
-        output.add_line(format!(
-            "{:<6} {:<50} {:<20} {:<15} {:<15}",
-            "PR#", "Title", "Author", "Created At", "Comments"
-        ));
-        output.add_line("-".repeat(106));
-
-        for pr in &response {
-            // Truncate title if too long
-            let title = if pr.title.len() > 47 {
-                format!("{}...", &pr.title[..44])
-            } else {
-                pr.title.clone()
-            };
+        // One request per PR, several in flight at once
+        http::throttle();
+        for_each_pr(&response, "comment counts", |pr| get_comments_count(&pr.comments_url, github_token)).await
+    };
+    let sizes = if wanted(ListColumn::Size, ListSort::Size) {
+        info!("Fetching the size of {} PRs...", response.len());
+        http::throttle();
+        for_each_pr(&response, "sizes", |pr| provider::current().size(owner, repo, pr.number, github_token)).await
+    } else {
+        vec![None; response.len()]
+    };
+    let mut listed: Vec<ListedPr> = response
+        .iter()
+        .zip(counts)
+        .zip(sizes)
+        .map(|((pr, comments), size)| ListedPr { pr, comments, size })
+        .collect();
+    sort_listed(&mut listed, sort);
+
+    // A failed count shouldn't stand out more than the PR itself
+    let missing = match (plain, output.theme.unicode) {
+        (true, _) => "unknown",
+        (false, true) => "—",
+        (false, false) => "-",
+    };
+    let theme = output.theme;
+
+    if plain {
+        for row in &listed {
+            let mut line = String::new();
+            let mut previous = None;
+            for &column in columns {
+                let cell = row.cell(column, &theme, missing);
+                let part = match column {
+                    ListColumn::Number => format!("PR {}", cell),
+                    ListColumn::Author => format!("by {}", cell),
+                    ListColumn::Age => format!("created {}", cell),
+                    ListColumn::Comments => format!("{} comments", cell),
+                    ListColumn::Size => format!("{} lines", cell),
+                    ListColumn::Title => cell,
+                };
+                match (previous, column) {
+                    (None, _) => {}
+                    (Some(ListColumn::Number), ListColumn::Title) => line.push_str(": "),
+                    _ => line.push_str(", "),
+                }
+                line.push_str(&part);
+                previous = Some(column);
+            }
+            output.add_line(line);
+            output.add_line(format!("URL: {}", row.pr.html_url));
+        }
+        return Ok(response);
+    }
 
-            // Fetch comment count for this PR
-            let comments_count = match get_comments_count(&pr.comments_url, github_token.as_deref())
-            {
-                Ok(count) => count.to_string(),
-                Err(_) => "Error".to_string(),
-            };
+    let row_line = |cells: Vec<String>| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(columns)
+            .map(|(cell, column)| format!("{:<width$}", cell, width = column.width()))
+            .collect();
+        padded.join(" ").trim_end().to_string()
+    };
+    output.add_line(row_line(columns.iter().map(|c| c.heading().to_string()).collect()));
+    output.add_line("-".repeat(columns.iter().map(|c| c.width() + 1).sum::<usize>()));
+
+    for row in &listed {
+        let cells = columns
+            .iter()
+            .map(|&column| match row.cell(column, &theme, missing) {
+                // Truncate title if too long
+                title if column == ListColumn::Title && title.len() > 47 => {
+                    format!("{}...", &title[..title.floor_char_boundary(44)])
+                }
+                cell => cell,
+            })
+            .collect();
+        output.add_line(row_line(cells));
 
-            output.add_line(format!(
-                "{:<6} {:<50} {:<20} {:<15} {:<15}",
-                pr.number, title, pr.user.login, pr.created_at, comments_count
-            ));
+        // Print the PR URL on a separate line
+        output.add_line(format!("       URL: {}", row.pr.html_url));
+    }
+    Ok(response)
+}
+
+async fn run() -> Result<String, Box<dyn std::error::Error>> {
+    // Initialize logger
+    env_logger::init();
+
+    let args = Args::parse();
+    if let Some(dir) = &args.debug_http {
+        http::capture_to(dir.clone())?;
+    }
+    if args.read_only {
+        http::set_read_only();
+    }
+    if args.profile_run || args.timings {
+        profile::enable(args.profile_run);
+    }
+    if let Some(url) = args.api_url.clone().or_else(|| env::var("GITHUB_API_URL").ok()) {
+        http::set_github_api(&url);
+    }
+
+    // Runs before loading the config so a broken config can be diagnosed
+    if let Some(Command::Doctor) = &args.command {
+        let mut output = OutputBuffer::new(Theme::detect(args.ascii, args.plain));
+        let failures = doctor::run(
+            args.config.as_deref(),
+            args.profile.as_deref(),
+            args.ai_provider,
+            args.model.as_deref(),
+            &mut output,
+        );
+        if failures > 0 {
+            print!("{}", output.content);
+            return Err(format!("{} check(s) failed", failures).into());
+        }
+        return Ok(output.content);
+    }
+
+    let repo_key = match &args.command {
+        Some(Command::List { repo, .. }) => repo.clone(),
+        Some(Command::Show { pr })
+        | Some(Command::Review { pr })
+        | Some(Command::Post { pr, .. })
+        | Some(Command::Assign { pr, .. })
+        | Some(Command::Automerge { pr, .. })
+        | Some(Command::Explain { pr, .. })
+        | Some(Command::Ask { pr, .. })
+        | Some(Command::Comment { pr, .. })
+        | Some(Command::Retract { pr, .. })
+        | Some(Command::Export { pr, .. })
+        | Some(Command::Pending {
+            action: PendingAction::List { pr } | PendingAction::Submit { pr, .. } | PendingAction::Discard { pr },
+        })
+        | Some(Command::History {
+            action: HistoryAction::Status { pr } | HistoryAction::Diff { pr },
+        }) => Some(pr.split_once('#').map_or(pr.as_str(), |(repo, _)| repo).to_string()),
+        Some(Command::Reply { event }) => slash::event_repository(event),
+        _ => args.owner.as_ref().zip(args.repo.as_ref()).map(|(o, r)| format!("{}/{}", o, r)),
+    };
+    let mut config = config::Config::load(args.config.as_deref())?;
+    config.select_profile(args.profile.as_deref(), repo_key.as_deref())?;
+    if let Some(name) = &config.active_profile {
+        info!("Using profile {}", name);
+    }
+    if let Some(suffix) = &config.user_agent {
+        http::set_user_agent_suffix(suffix);
+    }
+    if let Some(url) = &config.api_url {
+        http::set_github_api(url);
+    }
+    if let Some(attempts) = config.max_attempts {
+        http::set_max_attempts(attempts);
+    }
+    if config.encrypt_data {
+        encryption::enable();
+    }
+    llm::configure(std::mem::take(&mut config.llm));
+    if !config.retention.store_patches {
+        http::omit_patches();
+    }
+    // `rubber purge` does the same in the foreground
+    if !matches!(args.command, Some(Command::Purge { .. })) {
+        retention::start(&config.retention)?;
+    }
+    // A token given on the command line is used for everything
+    match &args.token {
+        Some(token) => config.set_github_token(token),
+        None => {
+            let mut routes = Vec::new();
+            for route in std::mem::take(&mut config.tokens) {
+                match route.resolve() {
+                    Some(token) => {
+                        http::register_secret(token.clone());
+                        routes.push((route, token));
+                    }
+                    None => warn!("{} is not set, its [[token]] is skipped", route.token_env.as_deref().unwrap_or_default()),
+                }
+            }
+            http::set_token_routes(routes);
+        }
+    }
+    let github_token = config.github_token();
+    provider::select(args.provider)?;
+    if args.ai_provider.is_some() {
+        config.ai_provider = args.ai_provider;
+    }
+    if args.model.is_some() {
+        config.model = args.model.clone();
+    }
+    // A key from anthropic_api_key_command is only fetched, and registered, once it's needed
+    let api_key = config.anthropic_api_key_command.is_none().then(|| config.ai_api_key()).flatten();
+    for secret in [github_token.clone(), api_key].into_iter().flatten() {
+        http::register_secret(secret);
+    }
+
+    let options = ReviewOptions::from_args(&args, config)?;
+    let mut output = OutputBuffer::new(options.theme);
+
+    if let Some(command) = &args.command {
+        match command {
+            Command::List {
+                repo,
+                limit,
+                fast,
+                columns,
+                sort,
+            } => {
+                let repository = repo.as_deref().or(options.config.repository.as_deref()).ok_or(NO_REPOSITORY)?;
+                let (owner, repo) = github::parse_repo_ref(repository)?;
+                let token = github_token.as_deref();
+                if *fast {
+                    let columns = columns.as_deref().unwrap_or(&FAST_COLUMNS);
+                    list_prs_fast(&owner, &repo, *limit, columns, *sort, token, &mut output).await?;
+                } else {
+                    let columns = columns.as_deref().unwrap_or(&TABLE_COLUMNS);
+                    list_prs(&owner, &repo, *limit, columns, *sort, token, &mut output).await?;
+                }
+            }
+            Command::Show { pr } | Command::Review { pr } => {
+                let (owner, repo, number) = resolve_pr(pr, options.config.repository.as_deref())?;
+                return review_pr(number, &owner, &repo, github_token.as_deref(), &options).await;
+            }
+            Command::Post { pr, dry_run } => {
+                provider::require_github("post")?;
+                let (owner, repo, number) = resolve_pr(pr, options.config.repository.as_deref())?;
+                post::post(&owner, &repo, number, *dry_run, github_token.as_deref(), &options, &mut output).await?
+            }
+            Command::Limits => limits::show(github_token.as_deref(), &mut output).await?,
+            Command::Doctor => unreachable!("handled before loading the config"),
+            Command::Assign { pr, dry_run } => {
+                provider::require_github("assign")?;
+                let (owner, repo, pr_number) = github::parse_pr_ref(pr)?;
+                assign::assign(
+                    &owner,
+                    &repo,
+                    pr_number,
+                    github_token.as_deref(),
+                    &options.config,
+                    *dry_run,
+                    &mut output,
+                )
+                .await?
+            }
+            Command::Explain { pr, file, hunk } => {
+                explain::show(pr, file, *hunk, github_token.as_deref(), &options, &mut output).await?
+            }
+            Command::Ask { pr, question } => {
+                ask::ask(pr, question, github_token.as_deref(), &options, &mut output).await?
+            }
+            Command::Comment { pr, line, pending } => {
+                provider::require_github("comment")?;
+                compose::compose(
+                    pr,
+                    line.as_deref(),
+                    *pending,
+                    github_token.as_deref(),
+                    options.theme,
+                    &mut output,
+                )
+                .await?
+            }
+            Command::Retract { pr, minimize, dry_run } => {
+                provider::require_github("retract")?;
+                retract::retract(pr, *minimize, *dry_run, github_token.as_deref(), &mut output).await?
+            }
+            Command::Audit { since, action, target } => {
+                audit::show(*since, action.as_deref(), target.as_deref(), &mut output)?
+            }
+            Command::Memory { action } => match action {
+                MemoryAction::List { repo } => memory::list(repo, &mut output)?,
+                MemoryAction::Reject { repo, theme } => {
+                    memory::remember(repo, memory::Stance::Rejected, theme, &mut output)?
+                }
+                MemoryAction::Accept { repo, theme } => {
+                    memory::remember(repo, memory::Stance::Accepted, theme, &mut output)?
+                }
+                MemoryAction::Forget { repo, number } => memory::forget(repo, *number, &mut output)?,
+            },
+            Command::Pending { action } => match action {
+                PendingAction::List { pr } => pending::list(pr, &mut output)?,
+                PendingAction::Submit { pr, verdict } => {
+                    provider::require_github("pending submit")?;
+                    pending::submit(pr, *verdict, github_token.as_deref(), &mut output).await?
+                }
+                PendingAction::Discard { pr } => pending::discard(pr, &mut output)?,
+            },
+            Command::Reply { event } => {
+                provider::require_github("reply")?;
+                return slash::handle_event(event, github_token.as_deref(), options).await;
+            }
+            // Digests walk every PR of several repositories
+            Command::Digest { cron: Some(expression) } => {
+                http::throttle();
+                digest::run_scheduled(expression, github_token.as_deref(), &options.config, options.theme).await?
+            }
+            Command::Digest { cron: None } => {
+                http::throttle();
+                digest::run_once(github_token.as_deref(), &options.config, &mut output).await?
+            }
+            Command::Export { pr, bundle } => {
+                return bundle::export(pr, bundle, github_token.as_deref()).await;
+            }
+            Command::Import { bundle } => {
+                return bundle::import(bundle, &options).await;
+            }
+            Command::Cache { action } => match action {
+                CacheAction::Stats => cache::show_stats(&mut output)?,
+                CacheAction::Clear => cache::remove(None, &mut output)?,
+                CacheAction::Prune { older_than } => cache::remove(Some(*older_than), &mut output)?,
+                CacheAction::Encrypt => cache::encrypt(&mut output)?,
+            },
+            Command::Notifications { reason, mark_read } => {
+                provider::require_github("notifications")?;
+                let token = github_token.as_deref();
+                return notifications::notifications(reason, *mark_read, token, &options, &mut output).await;
+            }
+            Command::Recent { query, limit } => {
+                let query = query.join(" ");
+                return recent::recent(&query, *limit, github_token.as_deref(), &options, &mut output).await;
+            }
+            Command::Purge { dry_run } => retention::purge(&options.config.retention, *dry_run, &mut output)?,
+            Command::Decrypt { files } => {
+                for file in files {
+                    output.add_line(encryption::read_to_string(file)?);
+                }
+            }
+            Command::History {
+                action: HistoryAction::Status { pr },
+            } => history::status(pr, &mut output)?,
+            Command::History {
+                action: HistoryAction::Diff { pr },
+            } => history::diff(pr, &mut output)?,
+            Command::Automerge { pr, dry_run } => {
+                provider::require_github("automerge")?;
+                let (owner, repo, pr_number) = github::parse_pr_ref(pr)?;
+                automerge::run(
+                    &owner,
+                    &repo,
+                    pr_number,
+                    github_token.as_deref(),
+                    &options.config,
+                    *dry_run,
+                    &mut output,
+                )
+                .await?
+            }
+        }
+        return Ok(output.content);
+    }
+
+    let (owner, repo) = match (&args.owner, &args.repo) {
+        (Some(owner), Some(repo)) => (owner.clone(), repo.clone()),
+        (Some(_), None) => return Err("The repository name is missing".into()),
+        (None, _) => github::parse_repo_ref(options.config.repository.as_deref().ok_or(NO_REPOSITORY)?)?,
+    };
+    let (owner, repo) = (owner.as_str(), repo.as_str());
 
-            // Print the PR URL on a separate line
-            output.add_line(format!("       URL: {}", pr.html_url));
+    // If PR number is provided, show its details directly
+    if let Some(number) = args.pr_number {
+        return review_pr(number, owner, repo, github_token.as_deref(), &options).await;
+    }
+
+    if args.pick {
+        info!("Fetching the open PRs of {}/{}...", owner, repo);
+        let prs = provider::current().list(owner, repo, 100, true, github_token.as_deref()).await?;
+        if prs.is_empty() {
+            output.add_line("No open pull requests found.");
+            return Ok(output.content);
         }
+        let selected = picker::pick(&prs, &options.theme)?;
+        review_selected(selected, owner, repo, github_token.as_deref(), &options).await?;
+        return Ok(String::new());
+    }
 
+    loop {
+        let response = list_prs(owner, repo, 10, &TABLE_COLUMNS, None, github_token.as_deref(), &mut output).await?;
+        if response.is_empty() {
+            return Ok(output.content);
+        }
         // Print the accumulated output before asking for input
         print!("{}", output.content);
         io::stdout().flush()?;
@@ -607,43 +3018,56 @@ async fn run() -> Result<String, Box<dyn std::error::Error>> {
         // Clear the output buffer since we've printed it
         output.content.clear();
 
-        output.add_line("\nEnter PR number to view details (or 'q' to quit): ");
+        output.add_line("\nEnter PR numbers to view, e.g. 12 or 10-12,15 ('r' to refresh, 'q' to quit): ");
         print!("{}", output.content);
         io::stdout().flush()?;
 
         let stdin = io::stdin();
         let mut input = String::new();
-        stdin.lock().read_line(&mut input)?;
+        // Nothing more to read, e.g. stdin is closed
+        if stdin.lock().read_line(&mut input)? == 0 {
+            return Ok(String::new());
+        }
 
         // Clear the output buffer again for the next phase
         output.content.clear();
 
         let input = input.trim();
-        if input.to_lowercase() != "q" {
-            match input.parse::<u32>() {
-                Ok(pr_number) => {
-                    if let Some(_pr) = find_pr_by_number(&response, pr_number) {
-                        match get_pr_details(pr_number, &args.owner, &args.repo, github_token.as_deref()) {
-                            Ok((details, comments)) => {
-                                display_pr_details(&details, &comments, &mut output, args.linus_torvalds).await?;
-                                return Ok(output.content);
-                            }
-                            Err(e) => {
-                                error!("Error fetching PR details: {}", e);
-                                return Ok("Error fetching PR details.".to_string());
-                            }
-                        }
-                    } else {
-                        warn!("PR #{} not found in the current list.", pr_number);
-                        return Ok(format!("PR #{} not found in the current list.", pr_number));
-                    }
-                }
-                Err(_) => warn!("Invalid PR number."),
-            }
+        match input.to_lowercase().as_str() {
+            "q" => return Ok(output.content),
+            "r" => continue,
+            _ => {}
         }
+        let listed: Vec<u32> = response.iter().map(|pr| pr.number).collect();
+        let selected = match parse_selection(input, &listed, "PRs") {
+            Ok(selected) => selected,
+            Err(e) => {
+                warn!("{}", e);
+                return Ok(e);
+            }
+        };
+        review_selected(selected, owner, repo, github_token.as_deref(), &options).await?;
+        return Ok(String::new());
     }
+}
 
-    Ok(output.content)
+// One after the other, each report printed as soon as it's done
+async fn review_selected(
+    selected: Vec<u32>,
+    owner: &str,
+    repo: &str,
+    github_token: Option<&str>,
+    options: &ReviewOptions,
+) -> Result<(), Box<dyn Error>> {
+    let count = selected.len();
+    for (i, pr_number) in selected.into_iter().enumerate() {
+        if count > 1 {
+            info!("Reviewing PR #{} ({} of {})", pr_number, i + 1, count);
+        }
+        print!("{}", review_pr(pr_number, owner, repo, github_token, options).await?);
+        io::stdout().flush()?;
+    }
+    Ok(())
 }
 
 #[tokio::main]
@@ -655,6 +3079,9 @@ async fn main() {
             print!("{}", output);
             // Flush stdout to ensure everything is printed
             io::stdout().flush().unwrap();
+            if let Some(timings) = profile::report() {
+                eprint!("{}", timings);
+            }
         }
         Err(e) => {
             log::error!("Error: {}", e);
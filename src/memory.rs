@@ -0,0 +1,175 @@
+use crate::OutputBuffer;
+use crate::encryption;
+use crate::github;
+use crate::state;
+use crate::terminal::Color;
+use chrono::{DateTime, Local, Utc};
+use clap::ValueEnum;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+// Kept apart from the history, which `rubber cache clear` deletes: this is
+// what the team told rubber, not something that can be rebuilt
+const MEMORY_DIR: &str = "memory";
+
+// Preferences of each kind added to a prompt, the most recent ones. Older
+// ones stay listed but would crowd out the review instructions
+const MAX_IN_PROMPT: usize = 10;
+
+// A theme names a kind of feedback, anything longer is more likely an attempt
+// to slip instructions into every future prompt
+const MAX_THEME_CHARS: usize = 200;
+
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Stance {
+    // Feedback the team wants to keep getting
+    Accepted,
+    // Feedback the team disagreed with, not to be raised again
+    Rejected,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Preference {
+    stance: Stance,
+    // The kind of feedback, in the team's words
+    theme: String,
+    recorded_at: DateTime<Utc>,
+    // Where it was recorded: a PR and who commented for /rubber commands, "cli" otherwise
+    source: String,
+}
+
+fn memory_path(owner: &str, repo: &str) -> Option<PathBuf> {
+    state::data_dir().map(|dir| dir.join(MEMORY_DIR).join(format!("{}.json", state::file_key(owner, repo))))
+}
+
+fn load(owner: &str, repo: &str) -> Result<Vec<Preference>, Box<dyn Error>> {
+    match memory_path(owner, repo) {
+        Some(path) if path.exists() => Ok(serde_json::from_str(&encryption::read_to_string(&path)?)?),
+        _ => Ok(Vec::new()),
+    }
+}
+
+fn save(owner: &str, repo: &str, preferences: &[Preference]) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(state::ensure_data_dir()?.join(MEMORY_DIR))?;
+    let path = memory_path(owner, repo).ok_or("Could not determine the data directory")?;
+    encryption::write(&path, serde_json::to_string_pretty(preferences)?)?;
+    Ok(())
+}
+
+// Records how the team feels about a kind of feedback. Recording the same
+// theme again replaces the earlier stance
+pub fn record(owner: &str, repo: &str, stance: Stance, theme: &str, source: &str) -> Result<(), Box<dyn Error>> {
+    let theme = theme.trim();
+    if theme.is_empty() {
+        return Err("The feedback theme is empty".into());
+    }
+    if theme.lines().count() > 1 || theme.chars().count() > MAX_THEME_CHARS {
+        return Err(format!("The feedback theme must be a single line of at most {} characters", MAX_THEME_CHARS).into());
+    }
+    let _lock = state::lock()?;
+    let mut preferences = load(owner, repo)?;
+    preferences.retain(|p| !p.theme.eq_ignore_ascii_case(theme));
+    preferences.push(Preference {
+        stance,
+        theme: theme.to_string(),
+        recorded_at: Utc::now(),
+        source: source.to_string(),
+    });
+    save(owner, repo, &preferences)
+}
+
+// The team's preferences as review instructions, newest first
+pub fn instructions(owner: &str, repo: &str) -> Vec<String> {
+    let loaded = state::lock_shared().map_err(Box::from).and_then(|_lock| load(owner, repo));
+    let preferences = match loaded {
+        Ok(preferences) => preferences,
+        Err(e) => {
+            warn!("Could not read the team preferences of {}/{}: {}", owner, repo, e);
+            return Vec::new();
+        }
+    };
+    let mut instructions = Vec::new();
+    for (stance, lead) in [
+        (Stance::Rejected, "The team has rejected this feedback before, don't raise it"),
+        (Stance::Accepted, "The team values this feedback, keep pointing it out"),
+    ] {
+        instructions.extend(
+            preferences
+                .iter()
+                .rev()
+                .filter(|p| p.stance == stance)
+                .take(MAX_IN_PROMPT)
+                .map(|p| format!("{}: {}", lead, p.theme)),
+        );
+    }
+    instructions
+}
+
+pub fn remember(
+    repository: &str,
+    stance: Stance,
+    theme: &str,
+    output: &mut OutputBuffer,
+) -> Result<(), Box<dyn Error>> {
+    let (owner, repo) = github::parse_repo_ref(repository)?;
+    record(&owner, &repo, stance, theme, "cli")?;
+    let verb = match stance {
+        Stance::Accepted => "keep raising",
+        Stance::Rejected => "stop raising",
+    };
+    output.add_line(format!("Reviews of {} will {}: {}", repository, verb, theme.trim()));
+    Ok(())
+}
+
+pub fn list(repository: &str, output: &mut OutputBuffer) -> Result<(), Box<dyn Error>> {
+    let (owner, repo) = github::parse_repo_ref(repository)?;
+    let preferences = {
+        let _lock = state::lock_shared()?;
+        load(&owner, &repo)?
+    };
+    let theme = output.theme;
+    output.add_header(&format!("Team preferences: {}", repository));
+    if preferences.is_empty() {
+        output.add_box_content("Nothing remembered yet (rubber memory reject|accept)");
+    } else {
+        let lines: Vec<String> = preferences
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let (label, color) = match p.stance {
+                    Stance::Accepted => ("accepted", Color::Green),
+                    Stance::Rejected => ("rejected", Color::Red),
+                };
+                format!(
+                    "{:>3}. {}  {}  ({}, {})",
+                    i + 1,
+                    theme.paint(label, color),
+                    p.theme,
+                    p.source,
+                    p.recorded_at.with_timezone(&Local).format("%Y-%m-%d")
+                )
+            })
+            .collect();
+        output.add_box_content(&lines.join("\n"));
+    }
+    output.add_diff_separator();
+    Ok(())
+}
+
+// Drops a preference by its number in `rubber memory list`
+pub fn forget(repository: &str, number: usize, output: &mut OutputBuffer) -> Result<(), Box<dyn Error>> {
+    let (owner, repo) = github::parse_repo_ref(repository)?;
+    let _lock = state::lock()?;
+    let mut preferences = load(&owner, &repo)?;
+    if number == 0 || number > preferences.len() {
+        return Err(format!("{} has no preference number {}", repository, number).into());
+    }
+    let forgotten = preferences.remove(number - 1);
+    save(&owner, &repo, &preferences)?;
+    output.add_line(format!("Forgot: {}", forgotten.theme));
+    Ok(())
+}
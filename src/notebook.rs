@@ -0,0 +1,95 @@
+use crate::{FileChange, PullRequestDetail};
+use crate::github;
+use log::{info, warn};
+use serde_json::Value;
+use similar::TextDiff;
+use std::collections::HashMap;
+use std::error::Error;
+
+pub fn is_notebook(path: &str) -> bool {
+    path.ends_with(".ipynb")
+}
+
+// The cells of a notebook as a script in the percent format, with their
+// outputs and execution counts left out
+fn cell_sources(notebook: &str) -> Result<String, Box<dyn Error>> {
+    let notebook: Value = serde_json::from_str(notebook)?;
+    let mut script = String::new();
+    for cell in notebook["cells"].as_array().ok_or("Notebook has no cells")? {
+        match cell["cell_type"].as_str() {
+            Some("code") => script.push_str("# %%\n"),
+            Some(other) => script.push_str(&format!("# %% [{}]\n", other)),
+            None => continue,
+        }
+        // Either one string or a list of lines
+        match &cell["source"] {
+            Value::String(source) => script.push_str(source),
+            Value::Array(lines) => lines.iter().filter_map(Value::as_str).for_each(|l| script.push_str(l)),
+            _ => {}
+        }
+        if !script.ends_with('\n') {
+            script.push('\n');
+        }
+        script.push('\n');
+    }
+    Ok(script)
+}
+
+// A unified diff of the cell sources, empty when only outputs changed
+fn source_patch(before: Option<&str>, after: Option<&str>) -> Result<String, Box<dyn Error>> {
+    let before = before.map(cell_sources).transpose()?.unwrap_or_default();
+    let after = after.map(cell_sources).transpose()?.unwrap_or_default();
+    Ok(TextDiff::from_lines(&before, &after)
+        .unified_diff()
+        .context_radius(3)
+        .to_string())
+}
+
+async fn fetch_source_patch(
+    owner: &str,
+    repo: &str,
+    file: &FileChange,
+    base: &str,
+    head: (&str, &str, &str),
+    head_files: &HashMap<String, String>,
+    github_token: Option<&str>,
+) -> Result<String, Box<dyn Error>> {
+    let before_path = file.previous_filename.as_deref().unwrap_or(&file.filename);
+    let before = match file.status.as_str() {
+        "added" => None,
+        _ => github::file_at(owner, repo, before_path, base, github_token).await?,
+    };
+    let after = match file.status.as_str() {
+        "removed" => None,
+        // From the bundle the PR was imported from
+        _ if let Some(content) = head_files.get(&file.filename) => Some(content.clone()),
+        _ => {
+            let (head_owner, head_repo, head_sha) = head;
+            github::file_at(head_owner, head_repo, &file.filename, head_sha, github_token).await?
+        }
+    };
+    source_patch(before.as_deref(), after.as_deref())
+}
+
+// Replaces the JSON patch of each notebook with the changes to its cell
+// sources. Raw notebook diffs are mostly outputs and metadata, unreadable and
+// expensive to send to the AI
+pub async fn simplify(owner: &str, repo: &str, details: &mut PullRequestDetail, github_token: Option<&str>) {
+    let (head_owner, head_repo) = details.head_repo(owner, repo);
+    let (head_owner, head_repo) = (head_owner.to_string(), head_repo.to_string());
+    let (base, head) = (&details.base.sha, (head_owner.as_str(), head_repo.as_str(), details.head.sha.as_str()));
+    for file in details.files.iter_mut().filter(|f| is_notebook(&f.filename)) {
+        match fetch_source_patch(owner, repo, file, base, head, &details.head_files, github_token).await {
+            Ok(patch) if patch.is_empty() => {
+                info!("Only outputs or metadata changed in {}", file.filename);
+                file.patch = None;
+            }
+            Ok(patch) => {
+                file.patch = Some(patch);
+                file.notebook = true;
+            }
+            // The raw diff is still better than nothing
+            Err(e) => warn!("Could not read the cells of {}: {}", file.filename, e),
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use crate::http;
+use crate::terminal::Color;
+use crate::timestamps;
+use crate::{OutputBuffer, ReviewOptions, parse_selection, review_pr};
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::Deserialize;
+use std::error::Error;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+// Why GitHub notified, grouped the way reviewers triage them
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Reason {
+    ReviewRequested,
+    Mention,
+    Ci,
+    // Comments, assignments, state changes and subscriptions
+    Other,
+}
+
+impl Reason {
+    fn of(reason: &str) -> Self {
+        match reason {
+            "review_requested" => Reason::ReviewRequested,
+            "mention" | "team_mention" => Reason::Mention,
+            "ci_activity" => Reason::Ci,
+            _ => Reason::Other,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Reason::ReviewRequested => "review requested",
+            Reason::Mention => "mentioned",
+            Reason::Ci => "CI",
+            Reason::Other => "activity",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Subject {
+    title: String,
+    // API URL of the PR, none for CI runs
+    url: Option<String>,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Repository {
+    full_name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Thread {
+    id: String,
+    reason: String,
+    updated_at: DateTime<Utc>,
+    subject: Subject,
+    repository: Repository,
+}
+
+impl Thread {
+    // CI runs are notified on their check suite, the rest on the PR itself
+    fn relevant(&self) -> bool {
+        self.subject.kind == "PullRequest" || (self.subject.kind == "CheckSuite" && self.reason == "ci_activity")
+    }
+
+    // owner, repo and number of the PR, None for CI runs
+    fn pull_request(&self) -> Option<(&str, &str, u32)> {
+        let path = self.subject.url.as_deref()?.split("/repos/").nth(1)?;
+        let mut parts = path.split('/');
+        let (owner, repo, kind, number) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        (kind == "pulls").then_some((owner, repo, number.parse().ok()?))
+    }
+
+    fn shown(&self) -> String {
+        match self.pull_request() {
+            Some((owner, repo, number)) => format!("{}/{}#{} {}", owner, repo, number, self.subject.title),
+            None => format!("{} {}", self.repository.full_name, self.subject.title),
+        }
+    }
+}
+
+async fn mark_read(thread: &Thread, github: &http::GithubClient) {
+    let url = format!("{}/notifications/threads/{}", http::github_api(), thread.id);
+    if let Err(e) = github.patch(&url).await {
+        warn!("Could not mark the notification for {} as read: {}", thread.shown(), e);
+    }
+}
+
+// Unread notifications about PRs, newest first. At a terminal the picked ones
+// are reviewed one after the other, and marked read afterwards with --mark-read
+pub async fn notifications(
+    reasons: &[Reason],
+    mark_read_after: bool,
+    github_token: Option<&str>,
+    options: &ReviewOptions,
+    output: &mut OutputBuffer,
+) -> Result<String, Box<dyn Error>> {
+    if github_token.is_none() {
+        return Err("Notifications are only visible with a token, set GITHUB_TOKEN".into());
+    }
+    info!("Fetching unread notifications...");
+    let url = format!("{}/notifications?per_page=50", http::github_api());
+    let github = http::GithubClient::new(github_token);
+    let threads: Vec<Thread> = serde_json::from_str(&github.get_all(&url).await?)?;
+    let threads: Vec<Thread> = threads
+        .into_iter()
+        .filter(|t| t.relevant() && (reasons.is_empty() || reasons.contains(&Reason::of(&t.reason))))
+        .collect();
+    if threads.is_empty() {
+        output.add_line("No unread PR notifications.");
+        return Ok(output.content.clone());
+    }
+
+    let theme = output.theme;
+    let width = threads.len().to_string().len();
+    for (i, thread) in threads.iter().enumerate() {
+        let reason = Reason::of(&thread.reason);
+        let color = match reason {
+            Reason::ReviewRequested => Color::Yellow,
+            Reason::Mention => Color::Cyan,
+            Reason::Ci => Color::Red,
+            Reason::Other => Color::Dim,
+        };
+        output.add_line(format!(
+            "{:>width$}  {} ({}, {})",
+            i + 1,
+            thread.shown(),
+            theme.paint(reason.label(), color),
+            timestamps::show(thread.updated_at, &theme),
+        ));
+    }
+    if !io::stdin().is_terminal() {
+        return Ok(output.content.clone());
+    }
+
+    print!("{}", output.content);
+    output.content.clear();
+    print!("\nEnter notifications to review, e.g. 1 or 1-3,5 (Enter to quit): ");
+    io::stdout().flush()?;
+    let mut input = String::new();
+    io::stdin().lock().read_line(&mut input)?;
+    if input.trim().is_empty() || input.trim().eq_ignore_ascii_case("q") {
+        return Ok(String::new());
+    }
+    // Positions in the list, 1-based
+    let positions: Vec<u32> = (1..=threads.len() as u32).collect();
+    let picked = parse_selection(&input, &positions, "notifications")?;
+
+    for position in picked {
+        let thread = &threads[position as usize - 1];
+        // A CI run has no PR to review, reading it is all there is to do. A
+        // review that fails doesn't keep the rest from being reviewed or read
+        if let Some((owner, repo, number)) = thread.pull_request() {
+            match review_pr(number, owner, repo, github_token, options).await {
+                Ok(report) => print!("{}", report),
+                Err(e) => warn!("Could not review {}: {}", thread.shown(), e),
+            }
+            io::stdout().flush()?;
+        }
+        if mark_read_after {
+            mark_read(thread, &github).await;
+        }
+    }
+    Ok(String::new())
+}
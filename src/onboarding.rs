@@ -0,0 +1,132 @@
+use crate::github;
+use crate::provider;
+use log::{info, warn};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+// Where GitHub itself looks for contribution guidelines
+const CONTRIBUTING_PATHS: [&str; 3] = ["CONTRIBUTING.md", ".github/CONTRIBUTING.md", "docs/CONTRIBUTING.md"];
+
+// Characters of CONTRIBUTING.md passed to the AI review
+const EXCERPT_LEN: usize = 3000;
+
+const INSTRUCTION: &str = "The author is contributing to this project for the first time. Be welcoming \
+    and patient: explain the reason behind each suggestion, point to the project's conventions instead of \
+    assuming they are known, and leave out nitpicks.";
+
+// The [onboarding] table of a repository's .rubber/config.toml
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Onboarding {
+    pub enabled: bool,
+    pub welcome: String,
+    pub checklist: Vec<String>,
+}
+
+impl Default for Onboarding {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            welcome: "Welcome, and thanks for your first contribution! A maintainer will take a look soon. \
+                In the meantime, please check the list below."
+                .to_string(),
+            checklist: vec![
+                "Read the contributing guidelines".to_string(),
+                "Added or updated tests for the change".to_string(),
+                "Ran the formatter and linter locally".to_string(),
+                "Linked the issue this PR addresses, if any".to_string(),
+            ],
+        }
+    }
+}
+
+impl Onboarding {
+    // Welcome message with a markdown checklist
+    fn message(&self) -> String {
+        let mut message = self.welcome.trim().to_string();
+        if !self.checklist.is_empty() {
+            message.push_str("\n\n");
+            let items: Vec<String> = self.checklist.iter().map(|item| format!("- [ ] {}", item)).collect();
+            message.push_str(&items.join("\n"));
+        }
+        message
+    }
+}
+
+// What changes in a review when the author is new to the project
+pub struct FirstTimer {
+    pub instructions: Vec<String>,
+    pub message: String,
+}
+
+// GitHub's author_association for people without a merged PR yet
+pub fn is_first_time(author_association: &str) -> bool {
+    matches!(author_association, "FIRST_TIME_CONTRIBUTOR" | "FIRST_TIMER")
+}
+
+fn excerpt(content: &str) -> String {
+    match content.char_indices().nth(EXCERPT_LEN) {
+        Some((end, _)) => format!("{}\n[...]", &content[..end]),
+        None => content.to_string(),
+    }
+}
+
+// None unless the author is a first-time contributor and the repository
+// didn't turn onboarding off
+pub async fn first_timer(
+    author_association: &str,
+    settings: Option<&Onboarding>,
+    owner: &str,
+    repo: &str,
+    base: &str,
+    repo_path: Option<&Path>,
+    github_token: Option<&str>,
+) -> Option<FirstTimer> {
+    let default = Onboarding::default();
+    let settings = settings.unwrap_or(&default);
+    if !settings.enabled || !is_first_time(author_association) {
+        return None;
+    }
+
+    let mut instructions = vec![INSTRUCTION.to_string()];
+    instructions.extend(contributing_instruction(owner, repo, base, repo_path, github_token).await);
+    Some(FirstTimer {
+        instructions,
+        message: settings.message(),
+    })
+}
+
+// Prompt instruction quoting the start of the contributing guidelines, read
+// from the local checkout or the PR's base commit
+async fn contributing_instruction(
+    owner: &str,
+    repo: &str,
+    base: &str,
+    repo_path: Option<&Path>,
+    github_token: Option<&str>,
+) -> Option<String> {
+    let mut guide = None;
+    for path in CONTRIBUTING_PATHS {
+        guide = match repo_path {
+            Some(root) => fs::read_to_string(root.join(path)).ok(),
+            // Read through GitHub's contents API
+            None if !provider::current().is_github() => None,
+            None => {
+                info!("Looking for {}...", path);
+                github::file_at(owner, repo, path, base, github_token).await.unwrap_or_else(|e| {
+                    warn!("Could not fetch {}: {}", path, e);
+                    None
+                })
+            }
+        };
+        if guide.is_some() {
+            break;
+        }
+    }
+    let guide = guide?;
+    Some(format!(
+        "Point out where the change doesn't follow the project's contributing guidelines, quoted here:\n\"\"\"\n{}\n\"\"\"",
+        excerpt(guide.trim())
+    ))
+}
@@ -0,0 +1,322 @@
+use crate::diff;
+use crate::encryption;
+use crate::findings;
+use crate::history;
+use crate::state;
+use crate::terminal::{Color, Theme};
+use log::{info, warn};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::env;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+// A report that isn't streamed is kept in memory up to this, all of it goes
+// to a temp file past it. Slash-command replies only post its start anyway
+const SPILL_BYTES: usize = 1024 * 1024;
+
+// Extra context rendered alongside a file's diff
+#[derive(Default)]
+pub struct DiffAnnotations<'a> {
+    pub moves: Option<&'a diff::FileMoves>,
+    pub uncovered: Option<&'a HashSet<u32>>,
+    pub comments: Option<&'a LineComments<'a>>,
+}
+
+// Review comments of a file as (author, body), by the line they're on in the
+// new version of the file (right) or the old one (left)
+#[derive(Default)]
+pub struct LineComments<'a> {
+    pub right: HashMap<u32, Vec<(&'a str, &'a str)>>,
+    pub left: HashMap<u32, Vec<(&'a str, &'a str)>>,
+}
+
+impl<'a> LineComments<'a> {
+    fn on(&self, line: &diff::DiffLine) -> Vec<(&'a str, &'a str)> {
+        let right = line.new_number.and_then(|n| self.right.get(&n));
+        let left = match line.kind {
+            diff::LineKind::Added => None,
+            _ => self.left.get(&line.number),
+        };
+        right.into_iter().chain(left).flatten().copied().collect()
+    }
+}
+
+#[derive(Default)]
+pub struct OutputBuffer {
+    pub content: String,
+    pub theme: Theme,
+    // Findings shown in the report, kept in the review history
+    pub findings: Vec<history::ReviewFinding>,
+    // Build output sizes in bytes, also kept in the review history
+    pub sizes: BTreeMap<String, u64>,
+    // Files the AI review skipped or failed on, their earlier AI findings
+    // stay open in the history
+    pub unreviewed: BTreeSet<String>,
+    // Lines go straight to stdout instead of into content
+    pub stream: bool,
+    // What was streamed, kept when the report is shared afterwards
+    pub copy: Option<String>,
+    // The temp file a report past SPILL_BYTES goes on in
+    spill: Option<(PathBuf, BufWriter<File>)>,
+    spill_failed: bool,
+}
+
+impl OutputBuffer {
+    pub fn new(theme: Theme) -> Self {
+        Self {
+            content: String::new(),
+            theme,
+            findings: Vec::new(),
+            sizes: BTreeMap::new(),
+            unreviewed: BTreeSet::new(),
+            stream: false,
+            copy: None,
+            spill: None,
+            spill_failed: false,
+        }
+    }
+
+    // Shows the report as it's produced, so the header and diffs don't wait
+    // for the AI reviews of every file
+    pub fn streaming(theme: Theme) -> Self {
+        Self {
+            stream: true,
+            ..Self::new(theme)
+        }
+    }
+
+    pub fn add_line(&mut self, line: impl AsRef<str>) {
+        if self.stream {
+            let mut stdout = io::stdout().lock();
+            // A closed pipe (e.g. `| head`) isn't worth failing the review for
+            let _ = writeln!(stdout, "{}", line.as_ref()).and_then(|_| stdout.flush());
+            if let Some(copy) = &mut self.copy {
+                copy.push_str(line.as_ref());
+                copy.push('\n');
+            }
+            return;
+        }
+        if let Some((path, file)) = &mut self.spill {
+            if let Err(e) = writeln!(file, "{}", line.as_ref()) {
+                warn!("Could not write the report to {}: {}", path.display(), e);
+            }
+            return;
+        }
+        self.content.push_str(line.as_ref());
+        self.content.push('\n');
+        if self.content.len() > SPILL_BYTES && !self.spill_failed {
+            self.start_spill();
+        }
+    }
+
+    // Moves the report to a temp file, its start staying in content
+    fn start_spill(&mut self) {
+        // The file would hold the report in plaintext
+        if encryption::enabled() {
+            info!("Keeping the report in memory, encrypt_data is on");
+            self.spill_failed = true;
+            return;
+        }
+        let started = state::create_private(&env::temp_dir(), "report", "txt").and_then(|(path, file)| {
+            let mut file = BufWriter::new(file);
+            file.write_all(self.content.as_bytes())?;
+            Ok((path, file))
+        });
+        match started {
+            Ok(spill) => self.spill = Some(spill),
+            Err(e) => {
+                warn!("Could not move the report to a temp file, keeping it in memory: {}", e);
+                self.spill_failed = true;
+            }
+        }
+    }
+
+    // Ends a report that went to a temp file with where to read the rest
+    pub fn close_spill(&mut self) {
+        let Some((path, mut file)) = self.spill.take() else {
+            return;
+        };
+        if let Err(e) = file.flush() {
+            warn!("Could not write the report to {}: {}", path.display(), e);
+        }
+        self.content.push_str(&format!(
+            "\n[The report goes on past {} KB, read all of it in {}]\n",
+            SPILL_BYTES / 1024,
+            path.display()
+        ));
+    }
+
+    pub fn add_row(&mut self, text: &str) {
+        if self.theme.plain {
+            self.add_line(text);
+            return;
+        }
+        let vertical = self.theme.glyphs().vertical;
+        self.add_line(format!("{}  {}", vertical, text));
+    }
+
+    pub fn add_header(&mut self, text: &str) {
+        self.add_line("");
+        if self.theme.plain {
+            self.add_line(format!("HEADING: {}", text));
+            return;
+        }
+        let glyphs = self.theme.glyphs();
+        let padding = (self.theme.width - 4).saturating_sub(text.len());
+        self.add_line(format!("{} {} {}", glyphs.header, text, glyphs.horizontal.repeat(padding)));
+    }
+
+    pub fn add_section(&mut self, text: &str) {
+        if self.theme.plain {
+            self.add_line(format!("SECTION: {}", text));
+            return;
+        }
+        let glyphs = self.theme.glyphs();
+        let padding = (self.theme.width - 4).saturating_sub(text.len());
+        self.add_line(format!("{} {} {}", glyphs.section, text, glyphs.horizontal.repeat(padding)));
+    }
+
+    pub fn add_box_content(&mut self, content: &str) {
+        self.add_box_edge();
+        self.add_box_inner_content(content);
+        self.add_box_edge();
+    }
+
+    // The empty row that opens and closes box content, for content added
+    // row by row
+    pub fn add_box_edge(&mut self) {
+        if !self.theme.plain {
+            self.add_line(self.theme.glyphs().vertical);
+        }
+    }
+
+    pub fn add_box_inner_content(&mut self, content: &str) {
+        for line in content.lines() {
+            self.add_row(line);
+        }
+    }
+
+    pub fn add_diff_header(&mut self, filename: &str) {
+        self.add_line("");
+        if self.theme.plain {
+            self.add_line(format!("FILE: {}", filename));
+            return;
+        }
+        let glyphs = self.theme.glyphs();
+        let padding = (self.theme.width - 10).saturating_sub(filename.len());
+        self.add_line(format!(
+            "{} Diff: {} {}",
+            glyphs.header,
+            filename,
+            glyphs.horizontal.repeat(padding)
+        ));
+    }
+
+    pub fn add_diff_content(&mut self, content: &str, annotations: &DiffAnnotations) {
+        let theme = self.theme;
+        let glyphs = theme.glyphs();
+
+        for hunk in diff::parse_hunks(content) {
+            if !hunk.header.is_empty() {
+                self.add_row(hunk.header);
+            }
+
+            // Collapse hunks that only shuffle whitespace around
+            if hunk.is_formatting_only() {
+                self.add_row(&theme.paint(
+                    &format!(
+                        "{} formatting-only change ({} lines) collapsed",
+                        glyphs.collapsed,
+                        hunk.change_count()
+                    ),
+                    Color::Dim,
+                ));
+                if let Some(comments) = annotations.comments {
+                    for line in hunk.numbered_lines() {
+                        for (author, body) in comments.on(&line) {
+                            self.add_review_comment(author, body);
+                        }
+                    }
+                }
+                continue;
+            }
+
+            for line in hunk.numbered_lines() {
+                match annotations.moves.and_then(|m| m.find(&line)) {
+                    Some(range) => {
+                        if range.start == line.number {
+                            let direction = match line.kind {
+                                diff::LineKind::Added => "from",
+                                _ => "to",
+                            };
+                            self.add_row(&theme.paint(
+                                &format!(
+                                    "{} {} lines moved {} {}:{}",
+                                    glyphs.moved, range.len, direction, range.other_file, range.other_start
+                                ),
+                                Color::Dim,
+                            ));
+                        }
+                        self.add_row(&theme.paint(line.text, Color::Cyan)); // Cyan for moved code
+                    }
+                    None if line.kind == diff::LineKind::Added
+                        && annotations.uncovered.is_some_and(|u| u.contains(&line.number)) =>
+                    {
+                        self.add_row(&format!(
+                            "{}  {}",
+                            theme.paint(line.text, Color::Green),
+                            theme.paint(&format!("{} not covered", glyphs.uncovered), Color::Yellow)
+                        ));
+                    }
+                    None => self.add_diff_line(line.text),
+                }
+                if let Some(comments) = annotations.comments {
+                    for (author, body) in comments.on(&line) {
+                        self.add_review_comment(author, body);
+                    }
+                }
+            }
+        }
+    }
+
+    // A review comment under the line it was left on, continuation lines
+    // indented past the author
+    pub fn add_review_comment(&mut self, author: &str, body: &str) {
+        let theme = self.theme;
+        let glyph = theme.glyphs().comment;
+        let mut lines = body.lines();
+        let first = lines.next().unwrap_or_default();
+        self.add_row(&theme.paint(&format!("{} {}: {}", glyph, author, first), Color::Yellow));
+        let indent = " ".repeat(glyph.chars().count() + 1);
+        for line in lines {
+            self.add_row(&theme.paint(&format!("{}{}", indent, line), Color::Yellow));
+        }
+    }
+
+    pub fn format_finding(&self, finding: &findings::Finding) -> String {
+        if self.theme.plain {
+            format!("FINDING [{}]: {}", finding.severity, finding.message)
+        } else {
+            finding.to_string()
+        }
+    }
+
+    pub fn add_diff_line(&mut self, line: &str) {
+        let formatted_line = match line.chars().next() {
+            Some('+') => self.theme.paint(line, Color::Green), // Green for additions
+            Some('-') => self.theme.paint(line, Color::Red),   // Red for deletions
+            _ => line.to_string(),
+        };
+        self.add_row(&formatted_line);
+    }
+
+    pub fn add_diff_separator(&mut self) {
+        if self.theme.plain {
+            self.add_line("");
+            return;
+        }
+        let glyphs = self.theme.glyphs();
+        self.add_line(format!("{}{}", glyphs.footer, glyphs.horizontal.repeat(self.theme.width)));
+    }
+}
@@ -0,0 +1,45 @@
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::error::Error;
+
+// Restricts a review to some of the changed files. Patterns are globs, those
+// starting with ! exclude: ["src/**", "!src/generated/**"]. Without any
+// including pattern every file is included unless excluded.
+#[derive(Debug, Default)]
+pub struct PathFilter {
+    pub patterns: Vec<String>,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl PathFilter {
+    pub fn parse(patterns: &[String]) -> Result<Self, Box<dyn Error>> {
+        let mut include = GlobSetBuilder::new();
+        let mut exclude = GlobSetBuilder::new();
+        let mut has_include = false;
+        for pattern in patterns {
+            match pattern.strip_prefix('!') {
+                Some(pattern) => {
+                    exclude.add(Glob::new(pattern).map_err(|e| format!("Invalid --paths pattern: {}", e))?);
+                }
+                None => {
+                    include.add(Glob::new(pattern).map_err(|e| format!("Invalid --paths pattern: {}", e))?);
+                    has_include = true;
+                }
+            }
+        }
+
+        Ok(Self {
+            patterns: patterns.to_vec(),
+            include: if has_include { Some(include.build()?) } else { None },
+            exclude: exclude.build()?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        self.include.as_ref().is_none_or(|set| set.is_match(path)) && !self.exclude.is_match(path)
+    }
+}
@@ -0,0 +1,242 @@
+use crate::OutputBuffer;
+use crate::encryption;
+use crate::github::{self, Anchor};
+use crate::http;
+use crate::state;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+const PENDING_DIR: &str = "pending";
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum Verdict {
+    Approve,
+    RequestChanges,
+    Comment,
+}
+
+impl Verdict {
+    fn event(self) -> &'static str {
+        match self {
+            Verdict::Approve => "APPROVE",
+            Verdict::RequestChanges => "REQUEST_CHANGES",
+            Verdict::Comment => "COMMENT",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct InlineComment {
+    path: String,
+    line: u32,
+    body: String,
+}
+
+// A review being built up across runs, submitted to GitHub in one go like
+// the web UI's pending review
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Review {
+    // The head the inline comments were written against
+    commit_id: Option<String>,
+    // General comments, joined into the review's summary
+    notes: Vec<String>,
+    comments: Vec<InlineComment>,
+    // Set before the review is first submitted and hidden in its body, so a
+    // submit that reached GitHub but wasn't recorded here isn't posted twice
+    #[serde(default)]
+    submission: Option<String>,
+}
+
+fn submission_tag(submission: &str) -> String {
+    format!("<!-- rubber pending {} -->", submission)
+}
+
+// A review of the PR already submitted from this pending review
+async fn find_submitted(
+    owner: &str,
+    repo: &str,
+    number: u32,
+    tag: &str,
+    github_token: Option<&str>,
+) -> Result<Option<Value>, Box<dyn Error>> {
+    let url = format!("{}/repos/{}/{}/pulls/{}/reviews", http::github_api(), owner, repo, number);
+    let reviews = http::GithubClient::new(github_token).get_all(&url).await?;
+    let reviews: Vec<Value> = serde_json::from_str(&reviews)?;
+    Ok(reviews.into_iter().find(|review| review["body"].as_str().is_some_and(|body| body.contains(tag))))
+}
+
+fn review_path(owner: &str, repo: &str, number: u32) -> Option<PathBuf> {
+    state::data_dir().map(|dir| dir.join(PENDING_DIR).join(format!("{}__{}.json", state::file_key(owner, repo), number)))
+}
+
+fn load(owner: &str, repo: &str, number: u32) -> Result<Review, Box<dyn Error>> {
+    match review_path(owner, repo, number) {
+        Some(path) if path.exists() => Ok(serde_json::from_str(&encryption::read_to_string(&path)?)?),
+        _ => Ok(Review::default()),
+    }
+}
+
+// The caller holds the lock from the load the review came from, so a
+// comment added meanwhile isn't overwritten
+fn save(owner: &str, repo: &str, number: u32, review: &Review) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(state::ensure_data_dir()?.join(PENDING_DIR))?;
+    let path = review_path(owner, repo, number).ok_or("Could not determine the data directory")?;
+    encryption::write(&path, serde_json::to_string_pretty(review)?)?;
+    Ok(())
+}
+
+fn remove(owner: &str, repo: &str, number: u32) -> Result<bool, Box<dyn Error>> {
+    let _lock = state::lock()?;
+    match review_path(owner, repo, number) {
+        Some(path) if path.exists() => {
+            fs::remove_file(path)?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+// Adds a comment written with `rubber comment --pending`. Returns how many
+// comments the review holds
+pub fn add(
+    owner: &str,
+    repo: &str,
+    number: u32,
+    body: &str,
+    anchor: Option<&Anchor>,
+) -> Result<usize, Box<dyn Error>> {
+    let _lock = state::lock()?;
+    let mut review = load(owner, repo, number)?;
+    match anchor {
+        Some(anchor) => {
+            // GitHub places every comment of a review on the same commit
+            if let Some(commit_id) = &review.commit_id
+                && *commit_id != anchor.commit_id
+            {
+                return Err(format!(
+                    "{}/{}#{} has new commits since its pending review was started, submit or discard \
+                    it first (rubber pending submit|discard)",
+                    owner, repo, number
+                )
+                .into());
+            }
+            review.commit_id = Some(anchor.commit_id.clone());
+            review.comments.push(InlineComment {
+                path: anchor.path.clone(),
+                line: anchor.line,
+                body: body.to_string(),
+            });
+        }
+        None => review.notes.push(body.to_string()),
+    }
+    save(owner, repo, number, &review)?;
+    Ok(review.notes.len() + review.comments.len())
+}
+
+fn show(review: &Review, output: &mut OutputBuffer) {
+    output.add_section("Summary");
+    if review.notes.is_empty() {
+        output.add_box_content("None");
+    } else {
+        output.add_box_content(&review.notes.join("\n\n"));
+    }
+    for comment in &review.comments {
+        output.add_section(&format!("{}:{}", comment.path, comment.line));
+        output.add_box_content(&comment.body);
+    }
+}
+
+pub fn list(reference: &str, output: &mut OutputBuffer) -> Result<(), Box<dyn Error>> {
+    let (owner, repo, number) = github::parse_pr_ref(reference)?;
+    let review = {
+        let _lock = state::lock_shared()?;
+        load(&owner, &repo, number)?
+    };
+    if review.notes.is_empty() && review.comments.is_empty() {
+        return Err(format!("No pending review for {}", reference).into());
+    }
+    output.add_header(&format!("Pending review: {}", reference));
+    show(&review, output);
+    output.add_diff_separator();
+    Ok(())
+}
+
+// Submits the pending review with a verdict, then forgets it
+pub async fn submit(
+    reference: &str,
+    verdict: Verdict,
+    github_token: Option<&str>,
+    output: &mut OutputBuffer,
+) -> Result<(), Box<dyn Error>> {
+    let (owner, repo, number) = github::parse_pr_ref(reference)?;
+    let review = {
+        let _lock = state::lock()?;
+        let mut review = load(&owner, &repo, number)?;
+        if review.submission.is_none() && !(review.notes.is_empty() && review.comments.is_empty()) {
+            let seed = format!("{}/{}#{} {} {:?}", owner, repo, number, std::process::id(), SystemTime::now());
+            review.submission = Some(format!("{:x}", Sha256::digest(seed.as_bytes()))[..16].to_string());
+            save(&owner, &repo, number, &review)?;
+        }
+        review
+    };
+    if review.notes.is_empty() && review.comments.is_empty() && !matches!(verdict, Verdict::Approve) {
+        return Err(format!("No pending review for {}", reference).into());
+    }
+
+    let mut body = review.notes.join("\n\n");
+    if let Some(submission) = &review.submission {
+        let tag = submission_tag(submission);
+        if let Some(submitted) = find_submitted(&owner, &repo, number, &tag, github_token).await? {
+            remove(&owner, &repo, number)?;
+            output.add_line(format!(
+                "The pending review of {} was already submitted, it was not posted again: {}",
+                reference,
+                submitted["html_url"].as_str().unwrap_or_default()
+            ));
+            return Ok(());
+        }
+        body = if body.is_empty() { tag } else { format!("{}\n\n{}", body, tag) };
+    }
+
+    let mut payload = json!({
+        "event": verdict.event(),
+        "body": body,
+        "comments": review
+            .comments
+            .iter()
+            .map(|c| json!({ "path": c.path, "line": c.line, "side": "RIGHT", "body": c.body }))
+            .collect::<Vec<_>>(),
+    });
+    if let Some(commit_id) = &review.commit_id {
+        payload["commit_id"] = json!(commit_id);
+    }
+    let submitted = github::submit_review(&owner, &repo, number, &payload, github_token).await?;
+    // Nothing was submitted, the review is still pending
+    if http::read_only() {
+        output.add_line(format!("Read-only: the pending review of {} was kept", reference));
+        return Ok(());
+    }
+    remove(&owner, &repo, number)?;
+    output.add_line(format!(
+        "Submitted a review with {} inline comment(s): {}",
+        review.comments.len(),
+        submitted["html_url"].as_str().unwrap_or_default()
+    ));
+    Ok(())
+}
+
+pub fn discard(reference: &str, output: &mut OutputBuffer) -> Result<(), Box<dyn Error>> {
+    let (owner, repo, number) = github::parse_pr_ref(reference)?;
+    if remove(&owner, &repo, number)? {
+        output.add_line(format!("Discarded the pending review of {}", reference));
+    } else {
+        output.add_line(format!("No pending review for {}", reference));
+    }
+    Ok(())
+}
@@ -0,0 +1,184 @@
+use crate::PullRequest;
+use crate::terminal::{Color, Theme};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::{cursor, execute, queue, terminal};
+use log::warn;
+use nucleo_matcher::pattern::{CaseMatching, Normalization, Pattern};
+use nucleo_matcher::{Config, Matcher};
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::io::{self, IsTerminal, Write};
+
+// Lines above the matches: the query and the counts
+const PROMPT_LINES: u16 = 2;
+
+// What the query is matched against: number, title, author and labels
+struct Entry {
+    index: usize,
+    text: String,
+}
+
+impl AsRef<str> for Entry {
+    fn as_ref(&self) -> &str {
+        &self.text
+    }
+}
+
+struct Picker<'a> {
+    entries: Vec<Entry>,
+    query: String,
+    // Indices of the entries matching the query, best first
+    matches: Vec<usize>,
+    cursor: usize,
+    marked: BTreeSet<usize>,
+    matcher: Matcher,
+    theme: &'a Theme,
+}
+
+impl Picker<'_> {
+    fn filter(&mut self) {
+        let pattern = Pattern::parse(&self.query, CaseMatching::Smart, Normalization::Smart);
+        self.matches = pattern
+            .match_list(&self.entries, &mut self.matcher)
+            .into_iter()
+            .map(|(entry, _)| entry.index)
+            .collect();
+        self.cursor = self.cursor.min(self.matches.len().saturating_sub(1));
+    }
+
+    fn draw(&self, out: &mut impl Write) -> io::Result<()> {
+        // Some terminals, serial consoles among them, don't report a size
+        let (width, height) = match terminal::size()? {
+            (0, _) | (_, 0) => (80, 24),
+            size => size,
+        };
+        let width = width as usize;
+        queue!(out, cursor::MoveTo(0, 0), terminal::Clear(terminal::ClearType::All))?;
+        write!(out, "> {}\r\n", self.query)?;
+        let counts = format!(
+            "  {}/{}{}  (Tab to mark, Enter to review, Esc to cancel)",
+            self.matches.len(),
+            self.entries.len(),
+            if self.marked.is_empty() { String::new() } else { format!(", {} marked", self.marked.len()) }
+        );
+        write!(out, "{}\r\n", self.theme.paint(&truncate(&counts, width), Color::Dim))?;
+
+        // The cursor stays on screen when there are more matches than lines
+        let rows = height.saturating_sub(PROMPT_LINES) as usize;
+        let first = (self.cursor + 1).saturating_sub(rows);
+        let pointer = if self.theme.unicode { "▶" } else { ">" };
+        for (row, &index) in self.matches.iter().enumerate().skip(first).take(rows) {
+            let current = if row == self.cursor { pointer } else { " " };
+            let mark = if self.marked.contains(&index) { "*" } else { " " };
+            let line = truncate(&format!("{}{} {}", current, mark, self.entries[index].text), width);
+            if row == self.cursor {
+                write!(out, "{}\r\n", self.theme.paint(&line, Color::Cyan))?;
+            } else {
+                write!(out, "{}\r\n", line)?;
+            }
+        }
+        queue!(out, cursor::MoveTo((2 + self.query.chars().count()).min(width) as u16, 0))?;
+        out.flush()
+    }
+
+    // The picked PRs once the key settles it: marked ones in list order, or
+    // the one under the cursor. Empty when cancelled
+    fn key(&mut self, key: KeyEvent) -> Option<Vec<usize>> {
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Some(Vec::new()),
+            KeyCode::Char('c' | 'd') if control => return Some(Vec::new()),
+            KeyCode::Enter if !self.marked.is_empty() => return Some(self.marked.iter().copied().collect()),
+            KeyCode::Enter => return Some(self.matches.get(self.cursor).copied().into_iter().collect()),
+            KeyCode::Up => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Char('p' | 'k') if control => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Down => self.cursor = (self.cursor + 1).min(self.matches.len().saturating_sub(1)),
+            KeyCode::Char('n' | 'j') if control => self.cursor = (self.cursor + 1).min(self.matches.len().saturating_sub(1)),
+            KeyCode::Tab => {
+                if let Some(&index) = self.matches.get(self.cursor) {
+                    if !self.marked.remove(&index) {
+                        self.marked.insert(index);
+                    }
+                    self.cursor = (self.cursor + 1).min(self.matches.len().saturating_sub(1));
+                }
+            }
+            KeyCode::Char('u') if control => {
+                self.query.clear();
+                self.filter();
+            }
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.filter();
+            }
+            KeyCode::Char(c) if !control => {
+                self.query.push(c);
+                self.cursor = 0;
+                self.filter();
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+fn truncate(line: &str, width: usize) -> String {
+    line.chars().take(width).collect()
+}
+
+fn run(picker: &mut Picker, out: &mut impl Write) -> Result<Vec<usize>, Box<dyn Error>> {
+    loop {
+        picker.draw(out)?;
+        if let Event::Key(key) = event::read()?
+            && key.kind != KeyEventKind::Release
+            && let Some(picked) = picker.key(key)
+        {
+            return Ok(picked);
+        }
+    }
+}
+
+// Lets the user narrow the PRs down by typing, fzf style, and returns the
+// numbers of the ones picked, none when cancelled. Drawn on stderr so the
+// reports can still be piped
+pub fn pick(prs: &[PullRequest], theme: &Theme) -> Result<Vec<u32>, Box<dyn Error>> {
+    if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+        return Err("--pick needs a terminal, use rubber list to list PRs in scripts".into());
+    }
+    let entries = prs
+        .iter()
+        .enumerate()
+        .map(|(index, pr)| {
+            let labels: Vec<&str> = pr.labels.iter().map(|l| l.name.as_str()).collect();
+            let labels = if labels.is_empty() { String::new() } else { format!("  [{}]", labels.join(", ")) };
+            Entry {
+                index,
+                text: format!("#{} {}  @{}{}", pr.number, pr.title, pr.user.login, labels),
+            }
+        })
+        .collect();
+    let mut picker = Picker {
+        entries,
+        query: String::new(),
+        matches: Vec::new(),
+        cursor: 0,
+        marked: BTreeSet::new(),
+        matcher: Matcher::new(Config::DEFAULT),
+        theme,
+    };
+    picker.filter();
+
+    let mut out = io::stderr();
+    terminal::enable_raw_mode()?;
+    let picked = execute!(out, terminal::EnterAlternateScreen)
+        .map_err(Box::<dyn Error>::from)
+        .and_then(|_| run(&mut picker, &mut out));
+    // Whatever happened, the terminal goes back to normal. Raw mode first, a
+    // terminal stuck in it is unusable while a leftover screen is only untidy
+    if let Err(e) = terminal::disable_raw_mode() {
+        warn!("Could not leave raw mode: {}", e);
+    }
+    if let Err(e) = execute!(out, terminal::LeaveAlternateScreen) {
+        warn!("Could not leave the alternate screen: {}", e);
+    }
+    Ok(picked?.into_iter().map(|index| prs[index].number).collect())
+}
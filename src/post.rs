@@ -0,0 +1,150 @@
+use crate::ask;
+use crate::dependencies;
+use crate::github;
+use crate::http;
+use crate::{OutputBuffer, ReviewOptions, get_pr_details, list_items, markdown_sections, send_prompt};
+use log::{info, warn};
+use serde_json::{Value, json};
+use std::collections::HashSet;
+use std::error::Error;
+
+// A comment of the model's review, on a line of the new version of a file
+struct InlineComment {
+    path: String,
+    line: u32,
+    body: String,
+}
+
+// "src/lib.rs:42: message", as asked for in the prompt
+fn inline_comment(item: &str) -> Option<InlineComment> {
+    let (location, body) = item.trim_matches('`').split_once(": ")?;
+    let (path, line) = location.trim_matches('`').rsplit_once(':')?;
+    Some(InlineComment {
+        path: path.to_string(),
+        line: line.trim().parse().ok()?,
+        body: body.trim().to_string(),
+    })
+}
+
+// Reviews the PR with the model and submits the result as a GitHub review:
+// the summary as its body, the comments anchored to their lines. Comments on
+// lines outside the diff can't be anchored, they're listed in the body
+pub async fn post(
+    owner: &str,
+    repo: &str,
+    pr_number: u32,
+    dry_run: bool,
+    github_token: Option<&str>,
+    options: &ReviewOptions,
+    output: &mut OutputBuffer,
+) -> Result<(), Box<dyn Error>> {
+    let (mut details, _) = get_pr_details(pr_number, owner, repo, github_token).await?;
+    // After loading the PR, its repository may be kept from the AI
+    if let Some(reason) = options.no_ai() {
+        return Err(format!("rubber post needs the AI, but {}", reason).into());
+    }
+    details
+        .files
+        .retain(|f| !dependencies::is_lockfile(&f.filename) && !details.directives.is_skipped(&f.filename));
+    let (diff, known_lines, left_out) = ask::numbered_diff(&details);
+    if known_lines.is_empty() {
+        return Err(format!("PR #{} has no diff to review", pr_number).into());
+    }
+    if !left_out.is_empty() {
+        warn!("Left {} out of the review for size", left_out.join(", "));
+    }
+    // Running post again after a push shouldn't repeat what's on the PR already
+    let posted: HashSet<(&str, u32)> = details
+        .review_comments
+        .iter()
+        .filter(|c| c.body.contains(github::MARKER) && c.side != "LEFT")
+        .filter_map(|c| Some((c.path.as_str(), c.line?)))
+        .collect();
+
+    info!("Reviewing PR #{} for posting...", pr_number);
+    let prompt = format!(
+        "Review pull request \"{}\" for bugs, security issues, error handling and maintainability.\n\
+        Format the response with a '## Summary' section (two to four sentences on the change and its \
+        overall state, written to the author) and a '## Comments' section with one list item per issue: \
+        the file path, a colon, the line number, a colon and the comment, e.g. `- src/lib.rs:42: message`. \
+        Use the line numbers shown in the left column (lines of the file after the change), removed lines \
+        have no number and can't be commented on. Write 'None' when there is nothing worth a comment.\n\n\
+        Description:\n{}\n\n{}",
+        details.title,
+        details.body.as_deref().unwrap_or_default(),
+        diff
+    );
+    let reply = send_prompt(prompt, options).await?;
+
+    let mut summary = String::new();
+    let mut anchored = Vec::new();
+    let mut unanchored = Vec::new();
+    let mut repeated = 0;
+    for section in markdown_sections(&reply) {
+        let (title, body) = section.split_once('\n').unwrap_or((section, ""));
+        match title.trim() {
+            "Summary" => summary = body.trim().to_string(),
+            "Comments" => {
+                for item in list_items(body) {
+                    match inline_comment(&item) {
+                        Some(comment) if posted.contains(&(comment.path.as_str(), comment.line)) => repeated += 1,
+                        Some(comment)
+                            if known_lines.get(&comment.path).is_some_and(|lines| lines.contains(&comment.line)) =>
+                        {
+                            anchored.push(comment)
+                        }
+                        _ => unanchored.push(item),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if repeated > 0 {
+        info!("Skipped {} comment(s) on lines rubber already commented on", repeated);
+    }
+
+    let mut body = if summary.is_empty() {
+        format!("{} comment(s) on the diff.", anchored.len())
+    } else {
+        summary
+    };
+    if !unanchored.is_empty() {
+        body.push_str("\n\n**Also noted**\n");
+        for item in &unanchored {
+            body.push_str(&format!("\n- {}", item));
+        }
+    }
+    let payload = json!({
+        "commit_id": details.head.sha,
+        "event": "COMMENT",
+        "body": format!("{}\n{}", body, github::MARKER),
+        "comments": anchored
+            .iter()
+            .map(|c| json!({
+                "path": c.path,
+                "line": c.line,
+                "side": "RIGHT",
+                "body": format!("{}\n{}", c.body, github::MARKER),
+            }))
+            .collect::<Vec<Value>>(),
+    });
+
+    if dry_run {
+        output.add_header(&format!("Review for {}/{}#{} (dry run, not posted)", owner, repo, pr_number));
+        output.add_box_content(&serde_json::to_string_pretty(&payload)?);
+        output.add_diff_separator();
+        return Ok(());
+    }
+    let submitted = github::submit_review(owner, repo, pr_number, &payload, github_token).await?;
+    if http::read_only() {
+        output.add_line(format!("Read-only: the review of {}/{}#{} was not posted", owner, repo, pr_number));
+        return Ok(());
+    }
+    output.add_line(format!(
+        "Posted a review with {} inline comment(s): {}",
+        anchored.len(),
+        submitted["html_url"].as_str().unwrap_or_default()
+    ));
+    Ok(())
+}
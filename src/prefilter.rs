@@ -0,0 +1,54 @@
+use crate::{ReviewOptions, send_to_model};
+use std::collections::HashSet;
+use std::error::Error;
+
+// Longer patches always get the full review, that much change is rarely
+// mechanical and would make the triage request expensive
+const MAX_PATCH_CHARS: usize = 8000;
+
+const PROMPT: &str = "You triage the files of a pull request before a code review. For each file below, \
+decide whether its change is mechanical or could change behavior.
+
+TRIVIAL: formatting and whitespace, reordered imports, typos in comments or documentation, version \
+bumps, generated code, and renames with nothing else changed.
+REVIEW: anything that could change behavior, however small, and anything you are unsure about.
+
+Answer with one line per file and nothing else: TRIVIAL or REVIEW, a space, and the file path exactly \
+as given.
+";
+
+// The files a small model found only mechanical changes in, which the
+// expensive model can skip. One request for the whole PR
+pub async fn trivial_files(
+    model: &str,
+    patches: &[(&str, &str)],
+    options: &ReviewOptions,
+) -> Result<HashSet<String>, Box<dyn Error>> {
+    let candidates: Vec<&str> = patches
+        .iter()
+        .filter(|(_, patch)| patch.len() <= MAX_PATCH_CHARS)
+        .map(|(filename, _)| *filename)
+        .collect();
+    if candidates.is_empty() {
+        return Ok(HashSet::new());
+    }
+
+    let mut prompt = PROMPT.to_string();
+    for (filename, patch) in patches.iter().filter(|(filename, _)| candidates.contains(filename)) {
+        prompt.push_str(&format!("\n### {}\n```\n{}\n```\n", filename, patch));
+    }
+    let reply = send_to_model(model, None, Vec::new(), prompt, options, &mut |_| {}).await?;
+    Ok(parse(&reply, &candidates))
+}
+
+// Only files named exactly and marked TRIVIAL are skipped, anything the
+// reply leaves unclear is reviewed
+fn parse(reply: &str, candidates: &[&str]) -> HashSet<String> {
+    reply
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("TRIVIAL "))
+        .map(|filename| filename.trim().trim_matches('`'))
+        .filter(|filename| candidates.contains(filename))
+        .map(str::to_string)
+        .collect()
+}
@@ -0,0 +1,91 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+// Wall time spent in each phase of a run, for --profile-run and --timings.
+// Only --profile-run prints it to stderr at the end
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static REPORTED: AtomicBool = AtomicBool::new(false);
+static PHASES: Mutex<Vec<Phase>> = Mutex::new(Vec::new());
+
+struct Phase {
+    name: &'static str,
+    millis: f64,
+    runs: usize,
+    // Time per file, for phases where it varies enough to matter
+    files: Vec<(String, f64)>,
+}
+
+pub fn enable(reported: bool) {
+    ENABLED.store(true, Ordering::Relaxed);
+    REPORTED.store(reported, Ordering::Relaxed);
+}
+
+fn add(name: &'static str, file: Option<&str>, started: Instant) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let millis = started.elapsed().as_secs_f64() * 1000.0;
+    let mut phases = PHASES.lock().unwrap_or_else(|e| e.into_inner());
+    let index = match phases.iter().position(|p| p.name == name) {
+        Some(index) => index,
+        None => {
+            phases.push(Phase {
+                name,
+                millis: 0.0,
+                runs: 0,
+                files: Vec::new(),
+            });
+            phases.len() - 1
+        }
+    };
+    let phase = &mut phases[index];
+    phase.millis += millis;
+    phase.runs += 1;
+    if let Some(file) = file {
+        phase.files.push((file.to_string(), millis));
+    }
+}
+
+// Adds the time since started to the phase, which can run many times (once
+// per file)
+pub fn record(name: &'static str, started: Instant) {
+    add(name, None, started);
+}
+
+// Same, also keeping the file's own time, e.g. to see which file the AI was
+// slow on
+pub fn record_file(name: &'static str, file: &str, started: Instant) {
+    add(name, Some(file), started);
+}
+
+// One line per phase in the order they first ran, files below their phase
+pub fn lines() -> Vec<String> {
+    let phases = PHASES.lock().unwrap_or_else(|e| e.into_inner());
+    let mut lines = Vec::new();
+    for phase in phases.iter() {
+        lines.push(format!(
+            "{:<12} {:>10.1} ms  ({} run{})",
+            phase.name,
+            phase.millis,
+            phase.runs,
+            if phase.runs == 1 { "" } else { "s" }
+        ));
+        for (file, millis) in &phase.files {
+            lines.push(format!("  {:>21.1} ms  {}", millis, file));
+        }
+    }
+    lines
+}
+
+// None unless --profile-run asked for it
+pub fn report() -> Option<String> {
+    if !REPORTED.load(Ordering::Relaxed) {
+        return None;
+    }
+    let mut report = String::from("Phase timings:\n");
+    for line in lines() {
+        report.push_str(&format!("  {}\n", line));
+    }
+    Some(report)
+}
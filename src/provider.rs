@@ -0,0 +1,777 @@
+use crate::http;
+use crate::{PrResponses, PullRequest, cap_patch};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use clap::ValueEnum;
+use log::info;
+use serde_json::{Value, json};
+use std::env;
+use std::error::Error;
+use std::future::Future;
+use std::pin::Pin;
+use std::cmp::Reverse;
+use std::process::Command;
+use std::sync::OnceLock;
+
+const DEFAULT_GITLAB_HOST: &str = "gitlab.com";
+const BITBUCKET_HOST: &str = "bitbucket.org";
+// The largest page Bitbucket serves for pull requests
+const BITBUCKET_MAX_PAGE: u8 = 50;
+
+static PROVIDER: OnceLock<Box<dyn VcsProvider + Send + Sync>> = OnceLock::new();
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Provider {
+    Github,
+    Gitlab,
+    Bitbucket,
+    #[value(alias = "forgejo")]
+    Gitea,
+}
+
+// A provider's requests, boxed as the provider is only picked at run time
+pub type Fetching<'a, T> = Pin<Box<dyn Future<Output = Result<T, Box<dyn Error>>> + 'a>>;
+
+// Where pull requests come from. Other providers convert their responses to
+// the shape of GitHub's, so parsing, bundles and the report only know one
+pub trait VcsProvider {
+    // The most recent pull requests, newest first, merged and closed ones
+    // too unless open_only
+    fn list<'a>(
+        &'a self,
+        owner: &'a str,
+        repo: &'a str,
+        limit: u8,
+        open_only: bool,
+        github_token: Option<&'a str>,
+    ) -> Fetching<'a, Vec<PullRequest>>;
+
+    // A pull request with its changed files, comments and review comments
+    fn fetch<'a>(
+        &'a self,
+        owner: &'a str,
+        repo: &'a str,
+        number: u32,
+        github_token: Option<&'a str>,
+    ) -> Fetching<'a, PrResponses>;
+
+    // Lines added and deleted
+    fn size<'a>(
+        &'a self,
+        owner: &'a str,
+        repo: &'a str,
+        number: u32,
+        github_token: Option<&'a str>,
+    ) -> Fetching<'a, (u32, u32)>;
+
+    // Checks, deployments and workflow artifacts only exist on GitHub
+    fn is_github(&self) -> bool {
+        false
+    }
+
+    // Owners may be groups within groups, "group/subgroup"
+    fn nested_groups(&self) -> bool {
+        false
+    }
+}
+
+pub struct GitHub;
+
+impl VcsProvider for GitHub {
+    fn list<'a>(
+        &'a self,
+        owner: &'a str,
+        repo: &'a str,
+        limit: u8,
+        open_only: bool,
+        github_token: Option<&'a str>,
+    ) -> Fetching<'a, Vec<PullRequest>> {
+        Box::pin(async move {
+            let url = format!(
+                "{}/repos/{}/{}/pulls?state={}&sort=created&direction=desc&per_page={}",
+                http::github_api(),
+                owner,
+                repo,
+                if open_only { "open" } else { "all" },
+                limit
+            );
+            Ok(serde_json::from_str(&http::GithubClient::new(github_token).get(&url).await?)?)
+        })
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        owner: &'a str,
+        repo: &'a str,
+        number: u32,
+        github_token: Option<&'a str>,
+    ) -> Fetching<'a, PrResponses> {
+        Box::pin(async move {
+            info!("Downloading PR #{} details...", number);
+
+            let url = format!("{}/repos/{}/{}/pulls/{}", http::github_api(), owner, repo, number);
+
+            let github = http::GithubClient::new(github_token);
+            let pr = github.get(&url).await?;
+            let comments_url = serde_json::from_str::<Value>(&pr)?["comments_url"]
+                .as_str()
+                .ok_or("PR has no comments_url")?
+                .to_string();
+
+            // Fetch files data from a different endpoint
+            info!("Downloading PR file changes...");
+            let files = github.get_all_with(&format!("{}/files", url), cap_patch).await?;
+
+            // Get comments
+            info!("Downloading PR comments...");
+            let comments = github.get_all(&comments_url).await?;
+
+            info!("Downloading PR review comments...");
+            let review_comments = github.get_all(&format!("{}/comments", url)).await?;
+
+            Ok(PrResponses {
+                pr,
+                files,
+                comments,
+                review_comments,
+            })
+        })
+    }
+
+    fn size<'a>(
+        &'a self,
+        owner: &'a str,
+        repo: &'a str,
+        number: u32,
+        github_token: Option<&'a str>,
+    ) -> Fetching<'a, (u32, u32)> {
+        Box::pin(async move {
+            let url = format!("{}/repos/{}/{}/pulls/{}", http::github_api(), owner, repo, number);
+            let pr: Value = serde_json::from_str(&http::GithubClient::new(github_token).get(&url).await?)?;
+            let count = |field: &str| pr[field].as_u64().unwrap_or_default() as u32;
+            Ok((count("additions"), count("deletions")))
+        })
+    }
+
+    fn is_github(&self) -> bool {
+        true
+    }
+}
+
+// Merge requests, read with GITLAB_TOKEN. owner/repo is the project's path
+pub struct GitLab {
+    host: String,
+    token: Option<String>,
+}
+
+impl GitLab {
+    fn new(host: String) -> Self {
+        let token = env::var("GITLAB_TOKEN").ok().filter(|t| !t.is_empty());
+        if let Some(token) = &token {
+            http::register_secret(token.clone());
+        }
+        Self { host, token }
+    }
+
+    fn project_url(&self, owner: &str, repo: &str) -> String {
+        format!("https://{}/api/v4/projects/{}%2F{}", self.host, owner.replace('/', "%2F"), repo)
+    }
+}
+
+// The changed file of a merge request diff, as GitHub lists it
+// The patch is cut like GitHub's are as they are fetched
+fn file_change(diff: &Value) -> Value {
+    let patch = diff["diff"].as_str().unwrap_or_default().trim_end_matches('\n');
+    let count = |marker: char| patch.lines().filter(|l| l.starts_with(marker)).count();
+    let renamed = diff["renamed_file"].as_bool() == Some(true);
+    let status = if diff["new_file"].as_bool() == Some(true) {
+        "added"
+    } else if diff["deleted_file"].as_bool() == Some(true) {
+        "removed"
+    } else if renamed {
+        "renamed"
+    } else {
+        "modified"
+    };
+    let mut file = json!({
+        "filename": diff["new_path"],
+        "status": status,
+        "additions": count('+'),
+        "deletions": count('-'),
+        // Empty for binary files and diffs too large for the API
+        "patch": (!patch.is_empty()).then_some(patch),
+        "previous_filename": renamed.then(|| diff["old_path"].clone()),
+    });
+    cap_patch(&mut file);
+    file
+}
+
+impl VcsProvider for GitLab {
+    fn nested_groups(&self) -> bool {
+        true
+    }
+
+    fn list<'a>(
+        &'a self,
+        owner: &'a str,
+        repo: &'a str,
+        limit: u8,
+        open_only: bool,
+        _: Option<&'a str>,
+    ) -> Fetching<'a, Vec<PullRequest>> {
+        Box::pin(async move {
+            let url = format!(
+                "{}/merge_requests?state={}&order_by=created_at&sort=desc&per_page={}",
+                self.project_url(owner, repo),
+                if open_only { "opened" } else { "all" },
+                limit
+            );
+            let requests: Vec<Value> = serde_json::from_str(&http::gitlab_get(&url, self.token.as_deref()).await?)?;
+            let mut prs = Vec::new();
+            for mr in requests {
+                prs.push(serde_json::from_value(json!({
+                    "number": mr["iid"],
+                    "title": mr["title"],
+                    "user": { "login": mr["author"]["username"] },
+                    "created_at": mr["created_at"],
+                    "html_url": mr["web_url"],
+                    "comments_url": "",
+                    "comments": mr["user_notes_count"],
+                    "labels": mr["labels"]
+                        .as_array()
+                        .map(|labels| labels.iter().map(|name| json!({ "name": name })).collect::<Vec<_>>())
+                        .unwrap_or_default(),
+                }))?);
+            }
+            Ok(prs)
+        })
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        owner: &'a str,
+        repo: &'a str,
+        number: u32,
+        _: Option<&'a str>,
+    ) -> Fetching<'a, PrResponses> {
+        Box::pin(async move {
+            let token = self.token.as_deref();
+            let url = format!("{}/merge_requests/{}", self.project_url(owner, repo), number);
+            info!("Downloading MR !{} details...", number);
+            let mr: Value = serde_json::from_str(&http::gitlab_get(&url, token).await?)?;
+            let labels: Vec<Value> = mr["labels"]
+                .as_array()
+                .map(|labels| labels.iter().map(|name| json!({ "name": name })).collect())
+                .unwrap_or_default();
+            let pr = json!({
+                "number": mr["iid"],
+                "title": mr["title"],
+                "body": mr["description"],
+                "html_url": mr["web_url"],
+                "base": {
+                    "ref": mr["target_branch"],
+                    "sha": mr["diff_refs"]["base_sha"].as_str().unwrap_or_default(),
+                },
+                "head": { "ref": mr["source_branch"], "sha": mr["sha"].as_str().unwrap_or_default() },
+                "labels": labels,
+                "created_at": mr["created_at"],
+                "updated_at": mr["updated_at"],
+            });
+
+            info!("Downloading MR diffs...");
+            let files =
+                http::gitlab_get_all_with(&format!("{}/diffs", url), token, |diff| *diff = file_change(diff)).await?;
+
+            // Notes on a position are on a line of the diff, the others on the MR itself
+            info!("Downloading MR discussions...");
+            let discussions: Vec<Value> =
+                serde_json::from_str(&http::gitlab_get_all(&format!("{}/discussions", url), token).await?)?;
+            let mut comments = Vec::new();
+            let mut review_comments = Vec::new();
+            for note in discussions.iter().filter_map(|d| d["notes"].as_array()).flatten() {
+                if note["system"].as_bool() == Some(true) {
+                    continue;
+                }
+                let user = json!({ "login": note["author"]["username"] });
+                let position = &note["position"];
+                if position.is_object() {
+                    let (line, side) = match position["new_line"].as_u64() {
+                        Some(line) => (json!(line), "RIGHT"),
+                        None => (position["old_line"].clone(), "LEFT"),
+                    };
+                    review_comments.push(json!({
+                        "user": user,
+                        "body": note["body"],
+                        "path": position["new_path"],
+                        "line": line,
+                        "side": side,
+                    }));
+                } else {
+                    comments.push(json!({ "user": user, "created_at": note["created_at"], "body": note["body"] }));
+                }
+            }
+
+            Ok(PrResponses {
+                pr: pr.to_string(),
+                files,
+                comments: serde_json::to_string(&comments)?,
+                review_comments: serde_json::to_string(&review_comments)?,
+            })
+        })
+    }
+
+    // Merge requests only carry a count of changed files, the lines come from the diffs
+    fn size<'a>(
+        &'a self,
+        owner: &'a str,
+        repo: &'a str,
+        number: u32,
+        _: Option<&'a str>,
+    ) -> Fetching<'a, (u32, u32)> {
+        Box::pin(async move {
+            let url = format!("{}/merge_requests/{}/diffs", self.project_url(owner, repo), number);
+            let diffs: Vec<Value> = serde_json::from_str(&http::gitlab_get_all(&url, self.token.as_deref()).await?)?;
+            let lines =
+                |field: &str| diffs.iter().map(|d| file_change(d)[field].as_u64().unwrap_or_default() as u32).sum();
+            Ok((lines("additions"), lines("deletions")))
+        })
+    }
+}
+
+// A unified diff of several files, cut into entries shaped like GitLab's
+// diffs so file_change applies to them too
+fn split_diff(diff: &str) -> Vec<Value> {
+    let mut sections: Vec<Vec<&str>> = Vec::new();
+    for line in diff.lines() {
+        if line.starts_with("diff --git ") {
+            sections.push(Vec::new());
+        }
+        if let Some(section) = sections.last_mut() {
+            section.push(line);
+        }
+    }
+
+    let mut files = Vec::new();
+    for section in sections {
+        // diff --git a/old/path b/new/path
+        let (old, new) = section[0]["diff --git ".len()..].split_once(" b/").unwrap_or_default();
+        let (mut old_path, mut new_path) = (old.trim_start_matches("a/").to_string(), new.to_string());
+        let (mut new_file, mut deleted_file, mut renamed_file) = (false, false, false);
+        let hunks = section.iter().position(|l| l.starts_with("@@")).unwrap_or(section.len());
+        for line in &section[1..hunks] {
+            if line.starts_with("new file mode") {
+                new_file = true;
+            } else if line.starts_with("deleted file mode") {
+                deleted_file = true;
+            } else if let Some(path) = line.strip_prefix("rename from ") {
+                renamed_file = true;
+                old_path = path.to_string();
+            } else if let Some(path) = line.strip_prefix("rename to ") {
+                new_path = path.to_string();
+            }
+        }
+        files.push(json!({
+            "old_path": old_path,
+            "new_path": new_path,
+            "new_file": new_file,
+            "deleted_file": deleted_file,
+            "renamed_file": renamed_file,
+            "diff": section[hunks..].join("\n"),
+        }));
+    }
+    files
+}
+
+// Bitbucket Cloud pull requests, read with BITBUCKET_USERNAME and
+// BITBUCKET_APP_PASSWORD. owner/repo is the workspace and the repository slug
+pub struct Bitbucket {
+    authorization: Option<String>,
+}
+
+impl Bitbucket {
+    fn new() -> Self {
+        let username = env::var("BITBUCKET_USERNAME").ok().filter(|u| !u.is_empty());
+        let password = env::var("BITBUCKET_APP_PASSWORD").ok().filter(|p| !p.is_empty());
+        let authorization = username.zip(password).map(|(username, password)| {
+            let encoded = STANDARD.encode(format!("{}:{}", username, password));
+            http::register_secret(encoded.clone());
+            format!("Basic {}", encoded)
+        });
+        Self { authorization }
+    }
+
+    fn repository_url(owner: &str, repo: &str) -> String {
+        format!("https://api.{}/2.0/repositories/{}/{}", BITBUCKET_HOST, owner, repo)
+    }
+}
+
+// Accounts have a nickname, the display name is the fallback
+fn bitbucket_user(user: &Value) -> Value {
+    json!({ "login": user["nickname"].as_str().or(user["display_name"].as_str()).unwrap_or_default() })
+}
+
+impl VcsProvider for Bitbucket {
+    fn list<'a>(
+        &'a self,
+        owner: &'a str,
+        repo: &'a str,
+        limit: u8,
+        open_only: bool,
+        _: Option<&'a str>,
+    ) -> Fetching<'a, Vec<PullRequest>> {
+        Box::pin(async move {
+            // Without states Bitbucket only lists open pull requests
+            let states = if open_only { "" } else { "state=OPEN&state=MERGED&state=DECLINED&state=SUPERSEDED&" };
+            let url = format!(
+                "{}/pullrequests?{}sort=-created_on&pagelen={}",
+                Self::repository_url(owner, repo),
+                states,
+                limit.min(BITBUCKET_MAX_PAGE)
+            );
+            let mut page: Value =
+                serde_json::from_str(&http::authorized_get(&url, self.authorization.as_deref()).await?)?;
+            let Value::Array(requests) = page["values"].take() else {
+                return Err(format!("Unexpected response from {}", url).into());
+            };
+            let mut prs = Vec::new();
+            for pr in requests {
+                prs.push(serde_json::from_value(json!({
+                    "number": pr["id"],
+                    "title": pr["title"],
+                    "user": bitbucket_user(&pr["author"]),
+                    "created_at": pr["created_on"],
+                    "html_url": pr["links"]["html"]["href"],
+                    "comments_url": "",
+                    "comments": pr["comment_count"],
+                }))?);
+            }
+            Ok(prs)
+        })
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        owner: &'a str,
+        repo: &'a str,
+        number: u32,
+        _: Option<&'a str>,
+    ) -> Fetching<'a, PrResponses> {
+        Box::pin(async move {
+            let authorization = self.authorization.as_deref();
+            let url = format!("{}/pullrequests/{}", Self::repository_url(owner, repo), number);
+            info!("Downloading PR #{} details...", number);
+            let pr: Value = serde_json::from_str(&http::authorized_get(&url, authorization).await?)?;
+            let details = json!({
+                "number": pr["id"],
+                "title": pr["title"],
+                "body": pr["description"],
+                "html_url": pr["links"]["html"]["href"],
+                "base": {
+                    "ref": pr["destination"]["branch"]["name"],
+                    "sha": pr["destination"]["commit"]["hash"].as_str().unwrap_or_default(),
+                },
+                "head": {
+                    "ref": pr["source"]["branch"]["name"],
+                    "sha": pr["source"]["commit"]["hash"].as_str().unwrap_or_default(),
+                },
+                "created_at": pr["created_on"],
+                "updated_at": pr["updated_on"],
+            });
+
+            info!("Downloading PR diff...");
+            let diff = http::authorized_get(&format!("{}/diff", url), authorization).await?;
+            let files: Vec<Value> = split_diff(&diff).iter().map(file_change).collect();
+
+            // Inline comments are on a line of the diff, the others on the PR itself
+            info!("Downloading PR comments...");
+            let notes: Vec<Value> =
+                serde_json::from_str(&http::bitbucket_get_all(&format!("{}/comments", url), authorization).await?)?;
+            let mut comments = Vec::new();
+            let mut review_comments = Vec::new();
+            for note in notes.iter().filter(|n| n["deleted"].as_bool() != Some(true)) {
+                let user = bitbucket_user(&note["user"]);
+                let body = &note["content"]["raw"];
+                let inline = &note["inline"];
+                if inline.is_object() {
+                    let (line, side) = match inline["to"].as_u64() {
+                        Some(line) => (json!(line), "RIGHT"),
+                        None => (inline["from"].clone(), "LEFT"),
+                    };
+                    review_comments.push(json!({
+                        "user": user,
+                        "body": body,
+                        "path": inline["path"],
+                        "line": line,
+                        "side": side,
+                    }));
+                } else {
+                    comments.push(json!({ "user": user, "created_at": note["created_on"], "body": body }));
+                }
+            }
+
+            Ok(PrResponses {
+                pr: details.to_string(),
+                files: serde_json::to_string(&files)?,
+                comments: serde_json::to_string(&comments)?,
+                review_comments: serde_json::to_string(&review_comments)?,
+            })
+        })
+    }
+
+    fn size<'a>(
+        &'a self,
+        owner: &'a str,
+        repo: &'a str,
+        number: u32,
+        _: Option<&'a str>,
+    ) -> Fetching<'a, (u32, u32)> {
+        Box::pin(async move {
+            let url = format!("{}/pullrequests/{}/diffstat", Self::repository_url(owner, repo), number);
+            let stats: Vec<Value> =
+                serde_json::from_str(&http::bitbucket_get_all(&url, self.authorization.as_deref()).await?)?;
+            let lines = |field: &str| stats.iter().map(|s| s[field].as_u64().unwrap_or_default() as u32).sum();
+            Ok((lines("lines_added"), lines("lines_removed")))
+        })
+    }
+}
+
+// A self-hosted Gitea or Forgejo instance at GITEA_URL, read with
+// GITEA_TOKEN. Its API mostly mirrors GitHub's
+pub struct Gitea {
+    base_url: String,
+    authorization: Option<String>,
+}
+
+impl Gitea {
+    fn new(base_url: String) -> Self {
+        let authorization = env::var("GITEA_TOKEN").ok().filter(|t| !t.is_empty()).map(|token| {
+            http::register_secret(token.clone());
+            format!("token {}", token)
+        });
+        Self {
+            base_url,
+            authorization,
+        }
+    }
+
+    fn repository_url(&self, owner: &str, repo: &str) -> String {
+        format!("{}/api/v1/repos/{}/{}", self.base_url, owner, repo)
+    }
+
+    async fn get(&self, url: &str) -> Result<Value, Box<dyn Error>> {
+        Ok(serde_json::from_str(&http::authorized_get(url, self.authorization.as_deref()).await?)?)
+    }
+
+    async fn get_all(&self, url: &str) -> Result<Vec<Value>, Box<dyn Error>> {
+        Ok(serde_json::from_str(&http::authorized_get_all(url, self.authorization.as_deref()).await?)?)
+    }
+}
+
+impl VcsProvider for Gitea {
+    fn list<'a>(
+        &'a self,
+        owner: &'a str,
+        repo: &'a str,
+        limit: u8,
+        open_only: bool,
+        _: Option<&'a str>,
+    ) -> Fetching<'a, Vec<PullRequest>> {
+        Box::pin(async move {
+            let url = format!(
+                "{}/pulls?state={}&limit={}",
+                self.repository_url(owner, repo),
+                if open_only { "open" } else { "all" },
+                limit
+            );
+            let Value::Array(requests) = self.get(&url).await? else {
+                return Err(format!("Unexpected response from {}", url).into());
+            };
+            let mut prs = Vec::new();
+            for pr in requests {
+                prs.push(serde_json::from_value::<PullRequest>(json!({
+                    "number": pr["number"],
+                    "title": pr["title"],
+                    "user": { "login": pr["user"]["login"] },
+                    "created_at": pr["created_at"],
+                    "html_url": pr["html_url"],
+                    "comments_url": "",
+                    "comments": pr["comments"],
+                    "labels": pr["labels"]
+                        .as_array()
+                        .map(|labels| labels.iter().map(|label| json!({ "name": label["name"] })).collect::<Vec<_>>())
+                        .unwrap_or_default(),
+                }))?);
+            }
+            // The order Gitea lists in depends on its version
+            prs.sort_by_key(|pr| Reverse(pr.created_at));
+            Ok(prs)
+        })
+    }
+
+    fn fetch<'a>(
+        &'a self,
+        owner: &'a str,
+        repo: &'a str,
+        number: u32,
+        _: Option<&'a str>,
+    ) -> Fetching<'a, PrResponses> {
+        Box::pin(async move {
+            let url = format!("{}/pulls/{}", self.repository_url(owner, repo), number);
+            info!("Downloading PR #{} details...", number);
+            let pr = self.get(&url).await?;
+            let labels: Vec<Value> = pr["labels"]
+                .as_array()
+                .map(|labels| labels.iter().map(|label| json!({ "name": label["name"] })).collect())
+                .unwrap_or_default();
+            let details = json!({
+                "number": pr["number"],
+                "title": pr["title"],
+                "body": pr["body"],
+                "html_url": pr["html_url"],
+                "base": { "ref": pr["base"]["ref"], "sha": pr["base"]["sha"].as_str().unwrap_or_default() },
+                "head": { "ref": pr["head"]["ref"], "sha": pr["head"]["sha"].as_str().unwrap_or_default() },
+                "labels": labels,
+                "created_at": pr["created_at"],
+                "updated_at": pr["updated_at"],
+            });
+
+            // The files endpoint has no patches, the whole diff has them
+            info!("Downloading PR diff...");
+            let diff = http::authorized_get(&format!("{}.diff", url), self.authorization.as_deref()).await?;
+            let files: Vec<Value> = split_diff(&diff).iter().map(file_change).collect();
+
+            info!("Downloading PR comments...");
+            let comments: Vec<Value> = self
+                .get_all(&format!("{}/issues/{}/comments", self.repository_url(owner, repo), number))
+                .await?
+                .iter()
+                .map(|c| {
+                    json!({ "user": { "login": c["user"]["login"] }, "created_at": c["created_at"], "body": c["body"] })
+                })
+                .collect();
+
+            // Comments on the diff are listed review by review
+            info!("Downloading PR review comments...");
+            let mut review_comments = Vec::new();
+            for review in self.get_all(&format!("{}/reviews", url)).await? {
+                let Some(id) = review["id"].as_u64() else {
+                    continue;
+                };
+                for comment in self.get_all(&format!("{}/reviews/{}/comments", url, id)).await? {
+                    // 0 when the comment isn't on that side
+                    let (line, side) = match (comment["position"].as_u64(), comment["original_position"].as_u64()) {
+                        (Some(line), _) if line > 0 => (json!(line), "RIGHT"),
+                        (_, Some(line)) if line > 0 => (json!(line), "LEFT"),
+                        _ => (Value::Null, "RIGHT"),
+                    };
+                    review_comments.push(json!({
+                        "user": { "login": comment["user"]["login"] },
+                        "body": comment["body"],
+                        "path": comment["path"],
+                        "line": line,
+                        "side": side,
+                    }));
+                }
+            }
+
+            Ok(PrResponses {
+                pr: details.to_string(),
+                files: serde_json::to_string(&files)?,
+                comments: serde_json::to_string(&comments)?,
+                review_comments: serde_json::to_string(&review_comments)?,
+            })
+        })
+    }
+
+    fn size<'a>(
+        &'a self,
+        owner: &'a str,
+        repo: &'a str,
+        number: u32,
+        _: Option<&'a str>,
+    ) -> Fetching<'a, (u32, u32)> {
+        Box::pin(async move {
+            let pr = self.get(&format!("{}/pulls/{}", self.repository_url(owner, repo), number)).await?;
+            let count = |field: &str| pr[field].as_u64().unwrap_or_default() as u32;
+            Ok((count("additions"), count("deletions")))
+        })
+    }
+}
+
+// The host of a remote URL: https://host/group/project.git,
+// git@host:group/project.git or ssh://git@host:22/group/project.git
+fn remote_host(url: &str) -> Option<&str> {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let rest = match rest.split_once('@') {
+        Some((user, host)) if !user.contains('/') => host,
+        _ => rest,
+    };
+    rest.split([':', '/']).next().filter(|host| !host.is_empty())
+}
+
+fn origin_host() -> Option<String> {
+    let output = Command::new("git").args(["remote", "get-url", "origin"]).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    remote_host(String::from_utf8_lossy(&output.stdout).trim()).map(str::to_string)
+}
+
+// --provider wins. Without it, an origin remote on bitbucket.org picks
+// Bitbucket, one on GITLAB_HOST or on a host named like GitLab's picks
+// GitLab, one on the host of GITEA_URL picks Gitea, anything else GitHub
+pub fn select(provider: Option<Provider>) -> Result<(), Box<dyn Error>> {
+    let configured_host = env::var("GITLAB_HOST")
+        .ok()
+        .map(|host| host.trim_start_matches("https://").trim_end_matches('/').to_string())
+        .filter(|host| !host.is_empty());
+    let gitea_url = env::var("GITEA_URL")
+        .ok()
+        .map(|url| url.trim_end_matches('/').to_string())
+        .filter(|url| !url.is_empty());
+    let (provider, gitlab_host) = match provider {
+        Some(provider) => (provider, configured_host),
+        None => match origin_host() {
+            Some(host) if host == BITBUCKET_HOST => {
+                info!("Using Bitbucket, as the origin remote points there");
+                (Provider::Bitbucket, None)
+            }
+            Some(host) if configured_host.as_deref() == Some(host.as_str()) || host.contains("gitlab") => {
+                info!("Using GitLab at {}, as the origin remote points there", host);
+                (Provider::Gitlab, Some(host))
+            }
+            Some(host) if gitea_url.as_deref().and_then(remote_host) == Some(host.as_str()) => {
+                info!("Using Gitea at {}, as the origin remote points there", host);
+                (Provider::Gitea, None)
+            }
+            _ => (Provider::Github, None),
+        },
+    };
+    let chosen: Box<dyn VcsProvider + Send + Sync> = match provider {
+        Provider::Github => Box::new(GitHub),
+        Provider::Gitlab => Box::new(GitLab::new(
+            gitlab_host.unwrap_or_else(|| DEFAULT_GITLAB_HOST.to_string()),
+        )),
+        Provider::Bitbucket => Box::new(Bitbucket::new()),
+        Provider::Gitea => match gitea_url {
+            Some(url) => Box::new(Gitea::new(url)),
+            None => return Err("Set GITEA_URL to the address of the Gitea or Forgejo instance".into()),
+        },
+    };
+    let _ = PROVIDER.set(chosen);
+    Ok(())
+}
+
+pub fn current() -> &'static (dyn VcsProvider + Send + Sync) {
+    PROVIDER.get_or_init(|| Box::new(GitHub)).as_ref()
+}
+
+// For the commands that post to GitHub, which would otherwise post to the
+// GitHub PR of the same owner, repository and number
+pub fn require_github(command: &str) -> Result<(), Box<dyn Error>> {
+    if current().is_github() {
+        Ok(())
+    } else {
+        Err(format!("rubber {} only works with GitHub pull requests", command).into())
+    }
+}
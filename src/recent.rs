@@ -0,0 +1,97 @@
+use crate::history::{self, Reviewed};
+use crate::timestamps;
+use crate::{OutputBuffer, ReviewOptions, review_pr};
+use std::error::Error;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+// How loosely a term matches the text: None when its characters don't
+// appear in order, 0 when it's a substring, more the further apart they are
+fn fuzzy_score(text: &str, term: &str) -> Option<usize> {
+    if text.contains(term) {
+        return Some(0);
+    }
+    let text: Vec<char> = text.chars().collect();
+    let term: Vec<char> = term.chars().collect();
+    let first = *term.first()?;
+    // The tightest match over every place the term could start
+    (0..text.len())
+        .filter(|&start| text[start] == first)
+        .filter_map(|start| {
+            let mut end = start;
+            for &c in &term[1..] {
+                end += 1 + text[end + 1..].iter().position(|&t| t == c)?;
+            }
+            Some(end + 1 - start - term.len())
+        })
+        .min()
+}
+
+// Every word of the query must match the PR reference or its title
+fn matches(reviewed: &Reviewed, query: &str) -> Option<usize> {
+    let text = format!("{}/{}#{} {}", reviewed.owner, reviewed.repo, reviewed.number, reviewed.title).to_lowercase();
+    query.to_lowercase().split_whitespace().map(|term| fuzzy_score(&text, term)).sum()
+}
+
+// Lists the last PRs reviewed, best matches of the query first, and reviews
+// again the one picked by its number in the list
+pub async fn recent(
+    query: &str,
+    limit: usize,
+    github_token: Option<&str>,
+    options: &ReviewOptions,
+    output: &mut OutputBuffer,
+) -> Result<String, Box<dyn Error>> {
+    let mut scored: Vec<(usize, Reviewed)> = history::reviewed()?
+        .into_iter()
+        .filter_map(|reviewed| Some((matches(&reviewed, query)?, reviewed)))
+        .collect();
+    if scored.is_empty() {
+        if query.trim().is_empty() {
+            output.add_line("No reviews stored yet.");
+        } else {
+            output.add_line(format!("No reviewed PR matches \"{}\".", query));
+        }
+        return Ok(output.content.clone());
+    }
+    // Stable, so equally good matches stay most recent first
+    scored.sort_by_key(|(score, _)| *score);
+    scored.truncate(limit);
+
+    let theme = output.theme;
+    let width = scored.len().to_string().len();
+    for (i, (_, reviewed)) in scored.iter().enumerate() {
+        let title = if reviewed.title.is_empty() { String::new() } else { format!(" {}", reviewed.title) };
+        output.add_line(format!(
+            "{:>width$}  {}/{}#{}{} (reviewed {}, {} finding(s))",
+            i + 1,
+            reviewed.owner,
+            reviewed.repo,
+            reviewed.number,
+            title,
+            timestamps::show(reviewed.reviewed_at, &theme),
+            reviewed.findings,
+        ));
+    }
+    // Piped, the list is all there is
+    if !io::stdin().is_terminal() {
+        return Ok(output.content.clone());
+    }
+
+    print!("{}", output.content);
+    output.content.clear();
+    print!("\nEnter a number to review that PR again (Enter to quit): ");
+    io::stdout().flush()?;
+    let mut input = String::new();
+    io::stdin().lock().read_line(&mut input)?;
+    let input = input.trim();
+    if input.is_empty() || input.eq_ignore_ascii_case("q") {
+        return Ok(String::new());
+    }
+    let picked = input
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| scored.get(n.checked_sub(1)?))
+        .map(|(_, reviewed)| reviewed)
+        .ok_or_else(|| format!("Pick a number between 1 and {}", scored.len()))?;
+    review_pr(picked.number, &picked.owner, &picked.repo, github_token, options).await
+}
@@ -0,0 +1,189 @@
+use crate::config::{Artifact, Policy};
+use crate::http;
+use crate::onboarding::Onboarding;
+use globset::Glob;
+use log::info;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+// Committed to the reviewed repository so the review setup travels with the code
+pub const DIR: &str = ".rubber";
+
+const FILES: [&str; 4] = ["prompt.md", "rules.md", "config.toml", "baseline.toml"];
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RepoFile {
+    // Credentials and bot settings stay in the user config, a repository
+    // only gets to shape its own review
+    policy: Option<Policy>,
+    onboarding: Option<Onboarding>,
+    #[serde(rename = "artifact")]
+    artifacts: Vec<Artifact>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct BaselineFile {
+    ignore: Vec<BaselineEntry>,
+}
+
+// A known finding the team accepted, hidden from future reviews
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct BaselineEntry {
+    // Glob of the files it applies to
+    #[serde(default = "any_path")]
+    pub path: String,
+    // Findings containing this text are dropped
+    pub message: String,
+}
+
+fn any_path() -> String {
+    "**".to_string()
+}
+
+#[derive(Debug, Default)]
+pub struct RepoConfig {
+    // Where the files were read from, for the report
+    pub source: Option<String>,
+    pub prompt: Option<String>,
+    pub rules: Vec<String>,
+    pub policy: Option<Policy>,
+    pub onboarding: Option<Onboarding>,
+    pub baseline: Vec<BaselineEntry>,
+    pub artifacts: Vec<Artifact>,
+}
+
+impl RepoConfig {
+    fn from_files(source: String, read: impl Fn(&str) -> Result<Option<String>, Box<dyn Error>>) -> Result<Self, Box<dyn Error>> {
+        let mut config = RepoConfig {
+            source: Some(source),
+            ..Default::default()
+        };
+
+        config.prompt = read("prompt.md")?.filter(|p| !p.trim().is_empty());
+
+        // One rule per bullet or line
+        if let Some(rules) = read("rules.md")? {
+            config.rules = rules
+                .lines()
+                .map(|l| l.trim().trim_start_matches(['-', '*']).trim())
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .map(str::to_string)
+                .collect();
+        }
+
+        if let Some(content) = read("config.toml")? {
+            let file: RepoFile = toml::from_str(&content)
+                .map_err(|e| format!("Invalid {}/config.toml: {}", DIR, e))?;
+            if let Some(mut policy) = file.policy {
+                policy.compile()?;
+                config.policy = Some(policy);
+            }
+            config.onboarding = file.onboarding;
+            config.artifacts = file.artifacts;
+        }
+
+        if let Some(content) = read("baseline.toml")? {
+            let file: BaselineFile = toml::from_str(&content)
+                .map_err(|e| format!("Invalid {}/baseline.toml: {}", DIR, e))?;
+            config.baseline = file.ignore;
+        }
+
+        Ok(config)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prompt.is_none()
+            && self.rules.is_empty()
+            && self.policy.is_none()
+            && self.onboarding.is_none()
+            && self.baseline.is_empty()
+            && self.artifacts.is_empty()
+    }
+
+    // Instructions added to the AI prompt of every file
+    pub fn instructions(&self) -> Vec<String> {
+        let mut instructions = Vec::new();
+        if let Some(prompt) = &self.prompt {
+            instructions.push(prompt.trim().to_string());
+        }
+        instructions.extend(self.rules.iter().cloned());
+        instructions
+    }
+
+    // Baseline messages that apply to a file
+    pub fn ignored(&self, filename: &str) -> Vec<String> {
+        self.baseline
+            .iter()
+            .filter(|entry| Glob::new(&entry.path).is_ok_and(|g| g.compile_matcher().is_match(filename)))
+            .map(|entry| entry.message.clone())
+            .collect()
+    }
+}
+
+// Reads .rubber/ from a local checkout
+pub fn load_local(root: &Path) -> Result<RepoConfig, Box<dyn Error>> {
+    let dir = root.join(DIR);
+    if !dir.is_dir() {
+        return Ok(RepoConfig::default());
+    }
+    RepoConfig::from_files(dir.display().to_string(), |name| {
+        let path = dir.join(name);
+        Ok(path.exists().then(|| fs::read_to_string(path)).transpose()?)
+    })
+}
+
+// The .rubber/ files present at a commit, by name
+pub async fn fetch_remote(
+    owner: &str,
+    repo: &str,
+    base: &str,
+    github_token: Option<&str>,
+) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let listing_url = format!(
+        "{}/repos/{}/{}/contents/{}?ref={}",
+        http::github_api(), owner, repo, DIR, base
+    );
+    let github = http::GithubClient::new(github_token);
+    let listing: Vec<serde_json::Value> = match github.get(&listing_url).await {
+        Ok(listing) => serde_json::from_str(&listing)?,
+        Err(e) if http::is_not_found(e.as_ref()) => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+    let present: Vec<&str> = listing.iter().filter_map(|entry| entry["name"].as_str()).collect();
+
+    info!("Loading {} from {}/{}@{}...", DIR, owner, repo, base);
+    let mut files = HashMap::new();
+    for name in FILES.into_iter().filter(|name| present.contains(name)) {
+        let url = format!(
+            "{}/repos/{}/{}/contents/{}/{}?ref={}",
+            http::github_api(), owner, repo, DIR, name, base
+        );
+        files.insert(name.to_string(), github.get_raw(&url).await?);
+    }
+    Ok(files)
+}
+
+// Builds the config from files fetched earlier, e.g. out of a bundle
+pub fn load_files(source: String, files: &HashMap<String, String>) -> Result<RepoConfig, Box<dyn Error>> {
+    if files.is_empty() {
+        return Ok(RepoConfig::default());
+    }
+    RepoConfig::from_files(source, |name| Ok(files.get(name).cloned()))
+}
+
+// Reads .rubber/ from the PR's base commit, so a PR can't loosen its own review
+pub async fn load_remote(
+    owner: &str,
+    repo: &str,
+    base: &str,
+    github_token: Option<&str>,
+) -> Result<RepoConfig, Box<dyn Error>> {
+    let files = fetch_remote(owner, repo, base, github_token).await?;
+    load_files(format!("{}/{}@{}", owner, repo, base), &files)
+}
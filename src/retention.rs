@@ -0,0 +1,175 @@
+use crate::OutputBuffer;
+use crate::audit;
+use crate::cache::{self, human_size, parse_age};
+use crate::config::Retention;
+use crate::history;
+use crate::state;
+use chrono::{Duration, Utc};
+use log::{info, warn};
+use serde_json::json;
+use std::error::Error;
+use std::fs;
+use std::thread;
+use std::time::SystemTime;
+
+// Touched after every purge, the one on startup runs at most once a day
+const PURGED_FILE: &str = "purged";
+
+struct Limit {
+    store: &'static str,
+    age: String,
+    max_age: Duration,
+}
+
+struct Purged {
+    store: &'static str,
+    age: String,
+    files: usize,
+    bytes: u64,
+    // History files keep a snapshot per review, the old ones are dropped
+    // from files still in use
+    snapshots: usize,
+}
+
+fn limits(retention: &Retention) -> Result<Vec<Limit>, String> {
+    let stores = [
+        ("history", &retention.history),
+        ("checks", &retention.checks),
+        ("pending", &retention.pending),
+        ("drafts", &retention.drafts),
+        ("memory", &retention.memory),
+    ];
+    stores
+        .into_iter()
+        .filter_map(|(store, age)| age.as_ref().map(|age| (store, age)))
+        .map(|(store, age)| {
+            let max_age = parse_age(age).map_err(|e| format!("retention.{}: {}", store, e))?;
+            Ok(Limit { store, age: age.clone(), max_age })
+        })
+        .collect()
+}
+
+// Deletes the files of each store last written longer ago than its limit, and
+// records what went in the audit log. The review history goes by when each
+// review was taken instead, a file written yesterday may hold year-old ones.
+// With dry_run only counts them
+fn purge_stores(limits: &[Limit], dry_run: bool) -> Result<Vec<Purged>, Box<dyn Error>> {
+    let data_dir = state::data_dir().ok_or("Could not determine the data directory")?;
+    let mut purged = Vec::new();
+    {
+        let _lock = state::lock()?;
+        for limit in limits {
+            let cutoff = SystemTime::now() - limit.max_age.to_std()?;
+            let mut files = Vec::new();
+            cache::walk(&data_dir.join(limit.store), &mut files)?;
+            let mut store = Purged {
+                store: limit.store,
+                age: limit.age.clone(),
+                files: 0,
+                bytes: 0,
+                snapshots: 0,
+            };
+            for (path, metadata) in files {
+                if limit.store == "history" {
+                    match history::prune(&path, Utc::now() - limit.max_age, dry_run) {
+                        Ok((dropped, emptied)) => {
+                            store.snapshots += dropped;
+                            if emptied && dropped > 0 {
+                                store.files += 1;
+                                store.bytes += metadata.len();
+                            }
+                        }
+                        Err(e) => warn!("Could not prune {}: {}", path.display(), e),
+                    }
+                    continue;
+                }
+                if metadata.modified().is_ok_and(|modified| modified >= cutoff) {
+                    continue;
+                }
+                if !dry_run {
+                    fs::remove_file(path)?;
+                }
+                store.files += 1;
+                store.bytes += metadata.len();
+            }
+            purged.push(store);
+        }
+        if !dry_run {
+            fs::write(data_dir.join(PURGED_FILE), "")?;
+        }
+    }
+    if !dry_run {
+        for store in purged.iter().filter(|store| store.files > 0 || store.snapshots > 0) {
+            audit::record(
+                "purge",
+                store.store,
+                &json!({
+                    "older_than": store.age,
+                    "files": store.files,
+                    "bytes": store.bytes,
+                    "snapshots": store.snapshots,
+                }),
+            )?;
+        }
+    }
+    Ok(purged)
+}
+
+// Whether a day went by since the last purge
+fn due() -> bool {
+    let Some(path) = state::data_dir().map(|dir| dir.join(PURGED_FILE)) else {
+        return false;
+    };
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_none_or(|elapsed| elapsed.as_secs() >= 86400)
+}
+
+// Checks the configured ages and, once a day, purges on a background thread
+// while the command runs. A purge cut short by the command ending is redone
+// on the next run
+pub fn start(retention: &Retention) -> Result<(), Box<dyn Error>> {
+    let limits = limits(retention)?;
+    if limits.is_empty() || !due() {
+        return Ok(());
+    }
+    thread::spawn(move || match purge_stores(&limits, false) {
+        Ok(purged) => {
+            for store in purged.iter().filter(|store| store.files > 0 || store.snapshots > 0) {
+                if store.snapshots > 0 {
+                    info!("Purged {} reviews older than {} from {}", store.snapshots, store.age, store.store);
+                } else {
+                    info!("Purged {} files older than {} from {}", store.files, store.age, store.store);
+                }
+            }
+        }
+        Err(e) => warn!("Could not purge the data directory: {}", e),
+    });
+    Ok(())
+}
+
+pub fn purge(retention: &Retention, dry_run: bool, output: &mut OutputBuffer) -> Result<(), Box<dyn Error>> {
+    let limits = limits(retention)?;
+    if limits.is_empty() {
+        return Err("Nothing to purge, set how long stores are kept under [retention] in the config".into());
+    }
+    let verb = if dry_run { "Would delete" } else { "Deleted" };
+    for store in purge_stores(&limits, dry_run)? {
+        let line = if store.store == "history" {
+            format!(
+                "{} {} reviews older than {}, emptying {} files ({})",
+                verb,
+                store.snapshots,
+                store.age,
+                store.files,
+                human_size(store.bytes)
+            )
+        } else {
+            format!("{} {} files ({}) older than {}", verb, store.files, human_size(store.bytes), store.age)
+        };
+        output.add_line(format!("{:<8} {}", store.store, line));
+    }
+    Ok(())
+}
@@ -0,0 +1,109 @@
+use crate::OutputBuffer;
+use crate::github;
+use crate::http;
+use crate::terminal::Color;
+use log::{info, warn};
+use serde_json::{Value, json};
+use std::error::Error;
+
+// Hidden like an outdated comment: still there for the record, folded away.
+// Reviews are minimizable too
+const MINIMIZE_COMMENT: &str = "mutation($id: ID!) {
+  minimizeComment(input: { subjectId: $id, classifier: OUTDATED }) {
+    minimizedComment { isMinimized }
+  }
+}";
+
+// Comments on the conversation tab, comments on the diff, and the reviews
+// `rubber post` submits, as section, listing and kind
+const KINDS: [(&str, &str, &str); 3] = [
+    ("issues", "comments", "comment"),
+    ("pulls", "comments", "review comment"),
+    ("pulls", "reviews", "review"),
+];
+
+// The login rubber posts as. Tokens of GitHub Apps, such as the one of GitHub
+// Actions, can't read /user; their comments come from a bot account
+async fn identity(github: &http::GithubClient) -> Option<String> {
+    let user = github.get(&format!("{}/user", http::github_api())).await.ok()?;
+    let user: Value = serde_json::from_str(&user).ok()?;
+    user["login"].as_str().map(str::to_string)
+}
+
+// Deletes, or with `minimize` hides, the comments rubber posted on a PR:
+// the ones carrying its marker, written by the identity of the token. Its
+// reviews are always hidden
+pub async fn retract(
+    reference: &str,
+    minimize: bool,
+    dry_run: bool,
+    github_token: Option<&str>,
+    output: &mut OutputBuffer,
+) -> Result<(), Box<dyn Error>> {
+    let (owner, repo, number) = github::parse_pr_ref(reference)?;
+    let github = http::GithubClient::new(github_token);
+    let login = identity(&github).await;
+    match &login {
+        Some(login) => info!("Looking for comments posted as {}...", login),
+        None => info!("Looking for comments posted by a bot..."),
+    }
+
+    let theme = output.theme;
+    let mut lines = Vec::new();
+    for (section, listing, kind) in KINDS {
+        let url = format!(
+            "{}/repos/{}/{}/{}/{}/{}",
+            http::github_api(), owner, repo, section, number, listing
+        );
+        let comments: Vec<Value> = serde_json::from_str(&github.get_all(&url).await?)?;
+        for comment in comments {
+            let body = comment["body"].as_str().unwrap_or_default();
+            let author = comment["user"]["login"].as_str().unwrap_or_default();
+            let ours = match &login {
+                Some(login) => author == login,
+                None => comment["user"]["type"] == "Bot",
+            };
+            if !body.contains(github::MARKER) || !ours {
+                continue;
+            }
+
+            let link = comment["html_url"].as_str().unwrap_or_default();
+            // A submitted review can't be deleted, only hidden
+            let minimize = minimize || listing == "reviews";
+            let (verb, done) = if minimize { ("minimize", "minimized") } else { ("delete", "deleted") };
+            if dry_run {
+                lines.push(format!("{} {} {}", theme.paint(&format!("would {}", verb), Color::Yellow), kind, link));
+                continue;
+            }
+            let result = if minimize {
+                github
+                    .graphql(MINIMIZE_COMMENT, json!({ "id": comment["node_id"] }), &format!("{}/{}", owner, repo))
+                    .await
+                    .map(|_| ())
+            } else {
+                let id = comment["id"].as_u64().unwrap_or_default();
+                let url = format!("{}/repos/{}/{}/{}/comments/{}", http::github_api(), owner, repo, section, id);
+                github.delete(&url).await.map(|_| ())
+            };
+            match result {
+                Ok(()) if http::read_only() => {
+                    lines.push(format!("{} {} {}", theme.paint("read-only, kept", Color::Yellow), kind, link))
+                }
+                Ok(()) => lines.push(format!("{} {} {}", theme.paint(done, Color::Green), kind, link)),
+                Err(e) => {
+                    warn!("Could not {} {}: {}", verb, link, e);
+                    lines.push(format!("{} {} {}", theme.paint("failed", Color::Red), kind, link));
+                }
+            }
+        }
+    }
+
+    output.add_header(&format!("Retract: {}", reference));
+    if lines.is_empty() {
+        output.add_box_content("No comments or reviews posted by rubber on this PR");
+    } else {
+        output.add_box_content(&lines.join("\n"));
+    }
+    output.add_diff_separator();
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use crate::config::Policy;
+use crate::FileChange;
+
+// Files whose changes affect the build, CI or data rather than just code
+const SENSITIVE_FILES: [&str; 7] = [
+    "Cargo.toml",
+    "Cargo.lock",
+    "build.rs",
+    ".github/workflows/",
+    "migrations/",
+    "Dockerfile",
+    ".gitmodules",
+];
+
+#[derive(Debug)]
+pub struct Risk {
+    // 0 (trivial) to 100 (needs a careful human look)
+    pub score: u32,
+    pub reasons: Vec<String>,
+}
+
+pub fn is_test(path: &str) -> bool {
+    path.starts_with("tests/")
+        || path.contains("/tests/")
+        || path.ends_with("_test.rs")
+        || path.ends_with("_tests.rs")
+        || path.starts_with("benches/")
+}
+
+pub fn score(files: &[FileChange], policy: &Policy) -> Risk {
+    let mut score = 0;
+    let mut reasons = Vec::new();
+
+    let changed: u32 = files.iter().map(|f| f.additions + f.deletions).sum();
+    let size = (changed / 25).min(30);
+    if size > 0 {
+        score += size;
+        reasons.push(format!("{} changed lines (+{})", changed, size));
+    }
+
+    let spread = (files.len() as u32 * 2).min(20);
+    if files.len() > 1 {
+        score += spread;
+        reasons.push(format!("{} files touched (+{})", files.len(), spread));
+    }
+
+    let sensitive: Vec<&str> = files
+        .iter()
+        .map(|f| f.filename.as_str())
+        .filter(|name| {
+            SENSITIVE_FILES
+                .iter()
+                .any(|s| name.ends_with(s) || name.starts_with(s) || name.contains(&format!("/{}", s)))
+        })
+        .collect();
+    if !sensitive.is_empty() {
+        let points = (sensitive.len() as u32 * 10).min(30);
+        score += points;
+        reasons.push(format!("build, CI or data files changed: {} (+{})", sensitive.join(", "), points));
+    }
+
+    if files.iter().any(|f| policy.is_performance_sensitive(&f.filename)) {
+        score += 15;
+        reasons.push("performance sensitive paths changed (+15)".to_string());
+    }
+
+    let removed = files.iter().filter(|f| f.status == "removed").count() as u32;
+    if removed > 0 {
+        let points = (removed * 5).min(15);
+        score += points;
+        reasons.push(format!("{} file(s) deleted (+{})", removed, points));
+    }
+
+    let touches_code = files
+        .iter()
+        .any(|f| f.filename.ends_with(".rs") && !is_test(&f.filename));
+    let touches_tests = files.iter().any(|f| is_test(&f.filename))
+        || files
+            .iter()
+            .filter_map(|f| f.patch.as_deref())
+            .any(|patch| patch.contains("#[test]") || patch.contains("#[cfg(test)]"));
+    if touches_code && !touches_tests {
+        score += 15;
+        reasons.push("code changed without test changes (+15)".to_string());
+    }
+
+    Risk {
+        score: score.min(100),
+        reasons,
+    }
+}
@@ -0,0 +1,496 @@
+use crate::findings::{Finding, Severity};
+use crate::{FileChange, OutputBuffer, PullRequestDetail, github, history};
+use log::{info, warn};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Protobuf,
+    OpenApi,
+    GraphQl,
+}
+
+fn kind(path: &str) -> Option<Kind> {
+    let name = path.rsplit('/').next().unwrap_or(path).to_lowercase();
+    let (stem, extension) = name.rsplit_once('.')?;
+    match extension {
+        "proto" => Some(Kind::Protobuf),
+        "graphql" | "graphqls" | "gql" => Some(Kind::GraphQl),
+        "yaml" | "yml" | "json" if stem.starts_with("openapi") || stem.starts_with("swagger") => Some(Kind::OpenApi),
+        _ => None,
+    }
+}
+
+// Added to the AI prompt of schema files
+pub fn instruction(path: &str) -> Option<String> {
+    let schema = match kind(path)? {
+        Kind::Protobuf => "a protobuf schema",
+        Kind::OpenApi => "an OpenAPI specification",
+        Kind::GraphQl => "a GraphQL schema",
+    };
+    Some(format!(
+        "This file is {}. Focus on compatibility with existing clients and stored data: removed or renamed \
+        fields, changed types, reused or renumbered field tags, new required fields or parameters, and removed \
+        endpoints, operations or enum values. Suggest the compatible way to make each breaking change.",
+        schema
+    ))
+}
+
+// Protobuf: fields by "Message.name" with their tag and type, enum values,
+// rpcs, and the tags each message reserves
+#[derive(Default)]
+struct Proto {
+    messages: BTreeSet<String>,
+    fields: BTreeMap<String, (u32, String)>,
+    values: BTreeMap<String, String>,
+    rpcs: BTreeMap<String, String>,
+    reserved: BTreeMap<String, BTreeSet<u32>>,
+}
+
+fn parse_reserved(statement: &str, reserved: &mut BTreeSet<u32>) {
+    for part in statement.split(',') {
+        let part = part.trim();
+        let range = match part.split_once(" to ").map(|(start, end)| (start, end.trim())) {
+            Some((start, "max")) => (start.trim().parse(), Ok(u32::MAX)),
+            Some((start, end)) => (start.trim().parse(), end.trim().parse()),
+            None => (part.parse(), part.parse()),
+        };
+        // Reserving a range reserves every tag in it, large ranges are capped
+        if let (Ok(start), Ok(end)) = range {
+            reserved.extend((start..=end).take(10_000));
+        }
+    }
+}
+
+// "Name(Request) returns (Response)"
+fn record_rpc(proto: &mut Proto, service: &str, rpc: &str) {
+    let (name, signature) = rpc.split_once('(').unwrap_or((rpc, ""));
+    let signature = format!("({}", signature).split_whitespace().collect::<Vec<_>>().join(" ");
+    proto.rpcs.insert(format!("{}.{}", service, name.trim()), signature);
+}
+
+fn parse_proto(content: &str) -> Proto {
+    let mut proto = Proto::default();
+    // Messages, enums and services nest, oneofs belong to their message
+    let mut scopes: Vec<(String, String)> = Vec::new();
+    let uncommented: String = content
+        .lines()
+        .map(|l| l.split("//").next().unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let split = uncommented.replace('{', " {\n").replace('}', "\n}\n").replace(';', ";\n");
+
+    for line in split.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if line == "}" {
+            scopes.pop();
+            continue;
+        }
+        if let Some(header) = line.strip_suffix('{') {
+            let mut words = header.split_whitespace();
+            let keyword = words.next().unwrap_or_default().to_string();
+            let name = words.next().unwrap_or_default().to_string();
+            // An rpc with options has a body of its own
+            if keyword == "rpc" {
+                if let Some((_, service)) = scopes.last() {
+                    record_rpc(&mut proto, service, header.trim_start_matches("rpc "));
+                }
+                scopes.push((keyword, String::new()));
+                continue;
+            }
+            let path = match keyword.as_str() {
+                // Fields of a oneof are fields of the enclosing message
+                "oneof" => scopes.last().map(|(_, p)| p.clone()).unwrap_or_default(),
+                _ => match scopes.last() {
+                    Some((_, parent)) if !parent.is_empty() => format!("{}.{}", parent, name),
+                    _ => name,
+                },
+            };
+            let keyword = if keyword == "oneof" { "message".to_string() } else { keyword };
+            if keyword == "message" {
+                proto.messages.insert(path.clone());
+            }
+            scopes.push((keyword, path));
+            continue;
+        }
+
+        let Some((scope, path)) = scopes.last() else {
+            continue;
+        };
+        let statement = line.trim_end_matches(';').trim();
+        if let Some(reserved) = statement.strip_prefix("reserved ") {
+            parse_reserved(reserved, proto.reserved.entry(path.clone()).or_default());
+            continue;
+        }
+        if ["option ", "extensions "].iter().any(|p| statement.starts_with(p)) {
+            continue;
+        }
+        match scope.as_str() {
+            "message" => {
+                let declaration = statement.split('[').next().unwrap_or_default();
+                let Some((left, tag)) = declaration.rsplit_once('=') else {
+                    continue;
+                };
+                let Ok(tag) = tag.trim().parse() else {
+                    continue;
+                };
+                let Some((field_type, name)) = left.trim().rsplit_once(char::is_whitespace) else {
+                    continue;
+                };
+                let field_type = field_type.split_whitespace().collect::<Vec<_>>().join(" ");
+                proto.fields.insert(format!("{}.{}", path, name), (tag, field_type));
+            }
+            "enum" => {
+                if let Some((name, number)) = statement.split('[').next().unwrap_or_default().split_once('=') {
+                    proto
+                        .values
+                        .insert(format!("{}.{}", path, name.trim()), number.trim().to_string());
+                }
+            }
+            "service" => {
+                if let Some(rpc) = statement.strip_prefix("rpc ") {
+                    record_rpc(&mut proto, path, rpc);
+                }
+            }
+            _ => {}
+        }
+    }
+    proto
+}
+
+fn message_of(field: &str) -> &str {
+    field.rsplit_once('.').map(|(message, _)| message).unwrap_or_default()
+}
+
+fn proto_breaking(before: &str, after: &str) -> Vec<String> {
+    let (before, after) = (parse_proto(before), parse_proto(after));
+    let mut breaking = Vec::new();
+
+    for message in before.messages.difference(&after.messages) {
+        breaking.push(format!("Message {} removed", message));
+    }
+    for (field, (tag, field_type)) in &before.fields {
+        let message = message_of(field);
+        if !after.messages.contains(message) {
+            continue;
+        }
+        let same_tag = after
+            .fields
+            .iter()
+            .find(|(other, (other_tag, _))| message_of(other) == message && other_tag == tag);
+        match (same_tag, after.fields.get(field)) {
+            (Some((_, (_, new_type))), _) if new_type != field_type => breaking.push(format!(
+                "{} (tag {}) changed type from {} to {}",
+                field, tag, field_type, new_type
+            )),
+            (Some(_), _) => {}
+            (None, Some((new_tag, _))) => {
+                breaking.push(format!("{} renumbered from tag {} to {}", field, tag, new_tag))
+            }
+            (None, None) if !after.reserved.get(message).is_some_and(|r| r.contains(tag)) => breaking.push(format!(
+                "{} (tag {}) removed without reserving its tag",
+                field, tag
+            )),
+            (None, None) => {}
+        }
+    }
+    for (value, number) in &before.values {
+        match after.values.get(value) {
+            None => breaking.push(format!("Enum value {} removed", value)),
+            Some(new_number) if new_number != number => {
+                breaking.push(format!("Enum value {} renumbered from {} to {}", value, number, new_number))
+            }
+            Some(_) => {}
+        }
+    }
+    for (rpc, signature) in &before.rpcs {
+        match after.rpcs.get(rpc) {
+            None => breaking.push(format!("RPC {} removed", rpc)),
+            Some(new_signature) if new_signature != signature => {
+                breaking.push(format!("RPC {} changed from {} to {}", rpc, signature, new_signature))
+            }
+            Some(_) => {}
+        }
+    }
+    breaking
+}
+
+// GraphQL without its comments, descriptions and other strings, which can
+// hold anything. Block strings keep their line breaks
+fn graphql_code(content: &str) -> String {
+    let mut code = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(c) = rest.chars().next() {
+        if let Some(block) = rest.strip_prefix(r#"""""#) {
+            // Only \""" escapes inside a block string
+            let mut end = 0;
+            loop {
+                match block[end..].find(r#"""""#) {
+                    Some(at) if block[..end + at].ends_with('\\') => end += at + 3,
+                    Some(at) => break end += at,
+                    None => break end = block.len(),
+                }
+            }
+            code.extend(block[..end].chars().filter(|c| *c == '\n'));
+            rest = block.get(end + 3..).unwrap_or_default();
+        } else if c == '"' {
+            // Up to the closing quote, or the end of the line for one that
+            // isn't closed on it
+            let mut escaped = false;
+            let end = rest[1..]
+                .find(|next: char| {
+                    let closes = (next == '"' && !escaped) || next == '\n';
+                    escaped = next == '\\' && !escaped;
+                    closes
+                })
+                .map_or(rest.len(), |end| end + 1);
+            rest = &rest[end..];
+            rest = rest.strip_prefix('"').unwrap_or(rest);
+        } else if c == '#' {
+            rest = &rest[rest.find('\n').unwrap_or(rest.len())..];
+        } else {
+            code.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    code
+}
+
+// GraphQL: the kind of each type (type, input, ...), and fields by
+// "Type.name" with their type, enum values with an empty type
+fn parse_graphql(content: &str) -> (BTreeMap<String, String>, BTreeMap<String, String>) {
+    let mut types = BTreeMap::new();
+    let mut fields = BTreeMap::new();
+    let mut current: Option<(String, bool)> = None;
+    for line in graphql_code(content).lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('}') {
+            current = None;
+            continue;
+        }
+        if let Some(header) = line.strip_suffix('{') {
+            let words: Vec<&str> = header.split_whitespace().collect();
+            let keyword_at = words.iter().position(|w| ["type", "input", "interface", "enum"].contains(w));
+            if let Some(name) = keyword_at.and_then(|i| words.get(i + 1)) {
+                types.insert(name.to_string(), words[keyword_at.unwrap_or_default()].to_string());
+                current = Some((name.to_string(), words[keyword_at.unwrap_or_default()] == "enum"));
+            }
+            continue;
+        }
+        let Some((type_name, is_enum)) = &current else {
+            continue;
+        };
+        let line = line.split(" @").next().unwrap_or(line);
+        if *is_enum {
+            fields.insert(format!("{}.{}", type_name, line), String::new());
+            continue;
+        }
+        let name_end = line.find(['(', ':']).unwrap_or(line.len());
+        let name = line[..name_end].trim();
+        // The type follows the arguments, if any
+        let rest = match line.rfind(')') {
+            Some(close) => &line[close + 1..],
+            None => &line[name_end..],
+        };
+        let field_type = rest.trim_start_matches([':', ' ']).split('=').next().unwrap_or_default().trim();
+        if !name.is_empty() {
+            fields.insert(format!("{}.{}", type_name, name), field_type.to_string());
+        }
+    }
+    (types, fields)
+}
+
+fn graphql_breaking(before: &str, after: &str) -> Vec<String> {
+    let ((before_types, before_fields), (after_types, after_fields)) = (parse_graphql(before), parse_graphql(after));
+    let mut breaking: Vec<String> = before_types
+        .keys()
+        .filter(|name| !after_types.contains_key(*name))
+        .map(|name| format!("Type {} removed", name))
+        .collect();
+    for (field, field_type) in &before_fields {
+        let Some(kind) = after_types.get(message_of(field)) else {
+            continue;
+        };
+        match after_fields.get(field) {
+            None if field_type.is_empty() => breaking.push(format!("Enum value {} removed", field)),
+            None => breaking.push(format!("Field {} removed", field)),
+            // Clients can always handle a result that is never null
+            Some(new_type) if kind != "input" && new_type.replace('!', "") == field_type.replace('!', "") => {
+                if new_type.len() < field_type.len() {
+                    breaking.push(format!("Field {} became nullable ({} to {})", field, field_type, new_type));
+                }
+            }
+            Some(new_type) if new_type != field_type => {
+                breaking.push(format!("Field {} changed type from {} to {}", field, field_type, new_type))
+            }
+            Some(_) => {}
+        }
+    }
+    breaking
+}
+
+const METHODS: [&str; 8] = ["get", "put", "post", "delete", "patch", "options", "head", "trace"];
+
+fn schema_type(schema: &Value) -> String {
+    schema["$ref"]
+        .as_str()
+        .or(schema["type"].as_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn names(value: &Value) -> BTreeSet<String> {
+    value.as_array().into_iter().flatten().filter_map(|v| v.as_str().map(str::to_string)).collect()
+}
+
+fn openapi_breaking(before: &str, after: &str, json: bool) -> Result<Vec<String>, Box<dyn Error>> {
+    let parse = |content: &str| -> Result<Value, Box<dyn Error>> {
+        Ok(if json { serde_json::from_str(content)? } else { serde_yaml::from_str(content)? })
+    };
+    let (before, after) = (parse(before)?, parse(after)?);
+    let mut breaking = Vec::new();
+
+    for (path, operations) in before["paths"].as_object().into_iter().flatten() {
+        let new_operations = &after["paths"][path];
+        if new_operations.is_null() {
+            breaking.push(format!("Path {} removed", path));
+            continue;
+        }
+        for method in METHODS.iter().filter(|m| !operations[**m].is_null()) {
+            let operation = &new_operations[*method];
+            if operation.is_null() {
+                breaking.push(format!("Operation {} {} removed", method.to_uppercase(), path));
+                continue;
+            }
+            let existing: BTreeSet<&str> = operations[*method]["parameters"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|p| p["name"].as_str())
+                .collect();
+            for parameter in operation["parameters"].as_array().into_iter().flatten() {
+                let name = parameter["name"].as_str().unwrap_or_default();
+                if parameter["required"] == true && !existing.contains(name) {
+                    breaking.push(format!(
+                        "Operation {} {} has a new required parameter {}",
+                        method.to_uppercase(),
+                        path,
+                        name
+                    ));
+                }
+            }
+        }
+    }
+
+    // OpenAPI 3 keeps schemas under components, Swagger 2 under definitions
+    let schemas = |spec: &Value| {
+        let components = &spec["components"]["schemas"];
+        if components.is_null() { spec["definitions"].clone() } else { components.clone() }
+    };
+    let (old_schemas, new_schemas) = (schemas(&before), schemas(&after));
+    for (name, schema) in old_schemas.as_object().into_iter().flatten() {
+        let new_schema = &new_schemas[name];
+        if new_schema.is_null() {
+            breaking.push(format!("Schema {} removed", name));
+            continue;
+        }
+        for (property, definition) in schema["properties"].as_object().into_iter().flatten() {
+            let new_definition = &new_schema["properties"][property];
+            if new_definition.is_null() {
+                breaking.push(format!("Property {}.{} removed", name, property));
+            } else if schema_type(definition) != schema_type(new_definition) {
+                breaking.push(format!(
+                    "Property {}.{} changed type from {} to {}",
+                    name,
+                    property,
+                    schema_type(definition),
+                    schema_type(new_definition)
+                ));
+            }
+        }
+        for required in names(&new_schema["required"]).difference(&names(&schema["required"])) {
+            breaking.push(format!("Property {}.{} is now required", name, required));
+        }
+    }
+    Ok(breaking)
+}
+
+async fn file_breaking(
+    owner: &str,
+    repo: &str,
+    details: &PullRequestDetail,
+    file: &FileChange,
+    kind: Kind,
+    github_token: Option<&str>,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let before_path = file.previous_filename.as_deref().unwrap_or(&file.filename);
+    let before = github::file_at(owner, repo, before_path, &details.base.sha, github_token)
+        .await?
+        .unwrap_or_default();
+    let after = match file.status.as_str() {
+        "removed" => None,
+        _ => details.head_file(owner, repo, &file.filename, github_token).await?,
+    };
+    let Some(after) = after else {
+        return Ok(vec![format!("Schema file {} removed", before_path)]);
+    };
+    Ok(match kind {
+        Kind::Protobuf => proto_breaking(&before, &after),
+        Kind::GraphQl => graphql_breaking(&before, &after),
+        Kind::OpenApi => openapi_breaking(&before, &after, file.filename.ends_with(".json"))?,
+    })
+}
+
+// Adds the Schema Compatibility section for the protobuf, OpenAPI and GraphQL
+// schemas a PR modifies. New schemas can't break anyone
+pub async fn review(
+    owner: &str,
+    repo: &str,
+    details: &PullRequestDetail,
+    github_token: Option<&str>,
+    output: &mut OutputBuffer,
+) {
+    let schemas: Vec<(&FileChange, Kind)> = details
+        .files
+        .iter()
+        .filter(|f| f.status != "added")
+        .filter_map(|f| kind(&f.filename).map(|kind| (f, kind)))
+        .collect();
+    if schemas.is_empty() {
+        return;
+    }
+
+    output.add_header("Schema Compatibility");
+    for (file, kind) in schemas {
+        output.add_section(&file.filename);
+        info!("Checking {} for breaking changes...", file.filename);
+        match file_breaking(owner, repo, details, file, kind, github_token).await {
+            Ok(breaking) if breaking.is_empty() => {
+                output.add_box_content("No backward-incompatible changes found");
+            }
+            Ok(breaking) => {
+                let mut lines = Vec::new();
+                for change in breaking {
+                    let finding = Finding::new(Severity::High, format!("Breaking change: {}", change));
+                    output.findings.push(history::ReviewFinding::new(
+                        "schema",
+                        &file.filename,
+                        &change,
+                        &finding.message,
+                    ));
+                    lines.push(output.format_finding(&finding));
+                }
+                output.add_box_content(&lines.join("\n"));
+            }
+            Err(e) => {
+                warn!("Could not check {}: {}", file.filename, e);
+                output.add_box_content("Could not be checked, see the log");
+            }
+        }
+    }
+    output.add_diff_separator();
+}
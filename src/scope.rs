@@ -0,0 +1,92 @@
+use crate::findings::{Finding, Severity};
+use crate::{OutputBuffer, PullRequestDetail, ReviewOptions, history, list_items, markdown_sections, send_prompt};
+use log::{info, warn};
+
+// Patches beyond this are left out of the prompt, the file list still names
+// every changed file so forgotten ones can be spotted
+const MAX_DIFF_CHARS: usize = 60_000;
+
+fn prompt(details: &PullRequestDetail, description: &str) -> String {
+    let mut diff = String::new();
+    let mut left_out = Vec::new();
+    for file in &details.files {
+        let Some(patch) = file.ai_patch() else {
+            continue;
+        };
+        if diff.len() + patch.len() > MAX_DIFF_CHARS {
+            left_out.push(file.filename.as_str());
+            continue;
+        }
+        diff.push_str(&format!("### {}\n```\n{}\n```\n\n", file.filename, patch));
+    }
+    let files: Vec<String> = details
+        .files
+        .iter()
+        .map(|f| format!("- {} ({}, +{} -{})", f.filename, f.status, f.additions, f.deletions))
+        .collect();
+    let mut prompt = format!(
+        "Compare what this pull request's description claims with what its diff actually does. \
+        Format the response with a '## Verdict' section (one line: Matches, Partially matches or \
+        Does not match, and why), a '## Missing' section (what the description promises that the diff \
+        doesn't do, including files it should have touched) and an '## Undescribed' section (changes in \
+        the diff the description doesn't mention, such as unrelated refactors or scope creep). Use short \
+        markdown lists and write 'None' in a section with nothing to report. Ignore formatting, tests \
+        and small refactors that naturally come with the change.\n\n\
+        Title: {}\n\nDescription:\n{}\n\nChanged files:\n{}\n\n{}",
+        details.title,
+        description,
+        files.join("\n"),
+        diff
+    );
+    if !left_out.is_empty() {
+        prompt.push_str(&format!(
+            "The patches of {} were left out for size, judge them by their name only.\n",
+            left_out.join(", ")
+        ));
+    }
+    prompt
+}
+
+// Asks the model whether the diff does what the description says, catching
+// forgotten pieces and scope creep
+pub async fn review(details: &PullRequestDetail, options: &ReviewOptions, output: &mut OutputBuffer) {
+    output.add_section("Description vs. Implementation");
+    let description = details.body.as_deref().unwrap_or_default().trim();
+    if description.is_empty() {
+        output.add_box_content("No description to compare the changes with");
+        return;
+    }
+
+    info!("Comparing the description with the diff...");
+    let reply = match send_prompt(prompt(details, description), options).await {
+        Ok(reply) => reply,
+        Err(e) => {
+            warn!("Could not compare the description with the diff: {}", e);
+            output.add_box_content("Could not be checked, see the log");
+            return;
+        }
+    };
+
+    let mut lines = Vec::new();
+    for section in markdown_sections(&reply) {
+        let (title, body) = section.split_once('\n').unwrap_or((section, ""));
+        let (severity, lead) = match title.trim() {
+            "Verdict" => {
+                lines.push(body.trim().to_string());
+                continue;
+            }
+            "Missing" => (Severity::Medium, "Described but not in the diff"),
+            "Undescribed" => (Severity::Info, "Not in the description"),
+            _ => continue,
+        };
+        for item in list_items(body) {
+            let finding = Finding::new(severity, format!("{}: {}", lead, item));
+            output.findings.push(history::ReviewFinding::new("description", "", &item, &finding.message));
+            lines.push(output.format_finding(&finding));
+        }
+    }
+    if lines.is_empty() {
+        lines.push(reply.trim().to_string());
+    }
+    output.add_box_content(&lines.join("\n"));
+}
@@ -0,0 +1,88 @@
+use crate::http;
+use serde_json::json;
+use std::error::Error;
+
+// Drops the colors and inline images only a terminal would render
+fn strip_escapes(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            plain.push(c);
+            continue;
+        }
+        match chars.next() {
+            // Colors: ESC [ parameters, ended by a letter
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if c.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+            // Images: ESC _ or ESC ], ended by BEL or ESC \
+            Some('_' | ']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == '\x1b' {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    plain
+}
+
+// The report as markdown: a heading linking the PR, then the report as it
+// looked in the terminal, in a code block so its boxes and diffs line up
+fn markdown(reference: &str, title: &str, url: &str, report: &str) -> String {
+    let report = strip_escapes(report);
+    // Longer than any fence in the AI's answers, which would end the block early
+    let longest = report.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+    format!(
+        "# [{}]({}): {}\n\n{}text\n{}\n{}\n",
+        reference,
+        url,
+        title,
+        fence,
+        report.trim_end(),
+        fence
+    )
+}
+
+// Uploads the report as a secret gist, readable by anyone given the link, and
+// returns its URL. None in read-only mode, where nothing is uploaded
+pub async fn gist(
+    owner: &str,
+    repo: &str,
+    pr_number: u32,
+    title: &str,
+    url: &str,
+    report: &str,
+    github_token: Option<&str>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    if github_token.is_none() {
+        return Err("Gists can only be created with a token, set GITHUB_TOKEN (with the gist scope)".into());
+    }
+    let reference = format!("{}/{}#{}", owner, repo, pr_number);
+    let body = json!({
+        "description": format!("rubber review of {}: {}", reference, title),
+        "public": false,
+        "files": {
+            format!("{}-{}-{}.md", owner, repo, pr_number): { "content": markdown(&reference, title, url, report) }
+        }
+    });
+    let response = http::GithubClient::new(github_token).post(&format!("{}/gists", http::github_api()), &body).await?;
+    if http::read_only() {
+        return Ok(None);
+    }
+    let created: serde_json::Value = serde_json::from_str(&response)?;
+    let url = created["html_url"].as_str().ok_or("GitHub did not return the URL of the gist")?;
+    Ok(Some(url.to_string()))
+}
@@ -0,0 +1,225 @@
+use crate::artifacts;
+use crate::cache;
+use crate::config::Sizes;
+use crate::findings::{Finding, Severity};
+use crate::terminal::Color;
+use crate::{OutputBuffer, PullRequestDetail, history};
+use globset::Glob;
+use log::{info, warn};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Bytes by output
+type Measured = BTreeMap<String, u64>;
+
+// Lines of a failed build's output kept in the error
+const BUILD_ERROR_LINES: usize = 20;
+
+// Sizes of the base and of the head, from the size reports CI uploaded
+async fn from_artifact(
+    owner: &str,
+    repo: &str,
+    name: &str,
+    sizes: &Sizes,
+    details: &PullRequestDetail,
+    github_token: Option<&str>,
+) -> Result<(Option<Measured>, Option<Measured>), Box<dyn Error>> {
+    let (head, base) = artifacts::find(owner, repo, name, details, github_token).await?;
+    let read = async |id: Option<u64>| -> Result<Option<Measured>, Box<dyn Error>> {
+        let Some(id) = id else {
+            return Ok(None);
+        };
+        let content = artifacts::download(owner, repo, id, sizes.file.as_deref(), github_token).await?;
+        Ok(Some(serde_json::from_str(&content)?))
+    };
+    Ok((read(base).await?, read(head).await?))
+}
+
+fn git<'a>(root: &Path, args: impl IntoIterator<Item = &'a str>) -> Result<(), Box<dyn Error>> {
+    let output = Command::new("git").arg("-C").arg(root).args(args).output()?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string().into());
+    }
+    Ok(())
+}
+
+// The build's output would end up in the report, so it is only shown when
+// the build fails
+fn build(dir: &Path, command: &str) -> Result<(), Box<dyn Error>> {
+    info!("Running {} in {}...", command, dir.display());
+    let (shell, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+    let output = Command::new(shell).args([flag, command]).current_dir(dir).output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let lines: Vec<&str> = stderr.lines().collect();
+        let tail = lines[lines.len().saturating_sub(BUILD_ERROR_LINES)..].join("\n");
+        return Err(format!("{} failed in {}:\n{}", command, dir.display(), tail).into());
+    }
+    Ok(())
+}
+
+// Adds up the files matching each output glob. Only the directory before the
+// first wildcard is walked, build directories are too big to scan whole
+fn measure(root: &Path, outputs: &[String]) -> Result<Measured, Box<dyn Error>> {
+    let mut sizes = Measured::new();
+    for pattern in outputs {
+        let matcher = Glob::new(pattern)?.compile_matcher();
+        let prefix: PathBuf = Path::new(pattern)
+            .components()
+            .take_while(|c| !c.as_os_str().to_string_lossy().contains(['*', '?', '[', '{']))
+            .collect();
+
+        let mut total = None;
+        let mut pending = vec![root.join(prefix)];
+        while let Some(path) = pending.pop() {
+            let Ok(metadata) = fs::metadata(&path) else {
+                continue;
+            };
+            if metadata.is_dir() {
+                for entry in fs::read_dir(&path)? {
+                    pending.push(entry?.path());
+                }
+            } else if path.strip_prefix(root).is_ok_and(|relative| matcher.is_match(relative)) {
+                *total.get_or_insert(0) += metadata.len();
+            }
+        }
+        if let Some(total) = total {
+            sizes.insert(pattern.clone(), total);
+        }
+    }
+    Ok(sizes)
+}
+
+// Builds the checkout, taken to be the PR head, and the base commit in a
+// throwaway worktree so the checkout is left alone
+fn from_build(
+    root: &Path,
+    base_sha: &str,
+    command: &str,
+    outputs: &[String],
+) -> Result<(Option<Measured>, Option<Measured>), Box<dyn Error>> {
+    build(root, command)?;
+    let head = measure(root, outputs)?;
+
+    let worktree = std::env::temp_dir().join(format!("rubber-base-{}", &base_sha[..base_sha.len().min(12)]));
+    let path = worktree.to_string_lossy();
+    // Left over by an interrupted run
+    let _ = git(root, ["worktree", "remove", "--force", &path]);
+    git(root, ["worktree", "add", "--detach", &path, base_sha])
+        .map_err(|e| format!("Could not check out the base commit {}: {}", base_sha, e))?;
+    let base = build(&worktree, command).and_then(|_| measure(&worktree, outputs));
+    if let Err(e) = git(root, ["worktree", "remove", "--force", &path]) {
+        warn!("Could not remove the worktree {}: {}", worktree.display(), e);
+    }
+    Ok((Some(base?), Some(head)))
+}
+
+// Adds the Output Sizes section, comparing the PR's build outputs with its
+// base's. The head sizes are kept in the review history
+pub async fn review(
+    owner: &str,
+    repo: &str,
+    details: &PullRequestDetail,
+    sizes: &Sizes,
+    repo_path: Option<&Path>,
+    github_token: Option<&str>,
+    output: &mut OutputBuffer,
+) {
+    let measured = match (&sizes.artifact, &sizes.build, repo_path) {
+        (Some(name), _, _) => {
+            info!("Fetching the {} size report...", name);
+            from_artifact(owner, repo, name, sizes, details, github_token).await
+        }
+        (None, Some(command), Some(root)) => from_build(root, &details.base.sha, command, &sizes.outputs),
+        _ => return,
+    };
+
+    output.add_header("Output Sizes");
+    let (base, head) = match measured {
+        Ok(measured) => measured,
+        Err(e) => {
+            warn!("Could not measure the output sizes: {}", e);
+            output.add_box_content("Could not be measured, see the log");
+            output.add_diff_separator();
+            return;
+        }
+    };
+    let Some(head) = head else {
+        output.add_box_content("No size report for the PR head yet");
+        output.add_diff_separator();
+        return;
+    };
+    if head.is_empty() {
+        output.add_box_content("None of the outputs were found");
+        output.add_diff_separator();
+        return;
+    }
+
+    let theme = output.theme;
+    let mut lines = Vec::new();
+    let names: BTreeSet<&String> = head.keys().chain(base.iter().flat_map(|b| b.keys())).collect();
+    for name in names {
+        let before = base.as_ref().and_then(|b| b.get(name).copied());
+        let after = head.get(name).copied();
+        let (before, after) = match (before, after) {
+            (Some(before), Some(after)) => (before, after),
+            (None, Some(after)) if base.is_none() => {
+                lines.push(format!("{}: {} (nothing to compare with)", name, cache::human_size(after)));
+                continue;
+            }
+            (None, Some(after)) => {
+                lines.push(format!("{}: {} (new)", name, cache::human_size(after)));
+                continue;
+            }
+            (Some(before), None) => {
+                lines.push(format!("{}: gone (was {})", name, cache::human_size(before)));
+                continue;
+            }
+            (None, None) => continue,
+        };
+        if before == after {
+            lines.push(format!("{}: {} (unchanged)", name, cache::human_size(after)));
+            continue;
+        }
+        if before == 0 {
+            lines.push(format!("{}: {} (was empty)", name, cache::human_size(after)));
+            continue;
+        }
+
+        let change = (after as f64 - before as f64) / before as f64 * 100.0;
+        let over = change > sizes.max_increase;
+        let color = match change {
+            _ if over => Color::Red,
+            c if c < 0.0 => Color::Green,
+            _ => Color::Yellow,
+        };
+        lines.push(format!(
+            "{}: {} -> {} {}",
+            name,
+            cache::human_size(before),
+            cache::human_size(after),
+            theme.paint(&format!("({:+.1}%)", change), color)
+        ));
+        if over {
+            let finding = Finding::new(
+                Severity::Medium,
+                format!(
+                    "{} grew {:.1}% ({} -> {}), more than the {}% allowed",
+                    name,
+                    change,
+                    cache::human_size(before),
+                    cache::human_size(after),
+                    sizes.max_increase
+                ),
+            );
+            output.findings.push(history::ReviewFinding::new("size", "", name, &finding.message));
+            lines.push(format!("  {}", output.format_finding(&finding)));
+        }
+    }
+    output.add_box_content(&lines.join("\n"));
+    output.add_diff_separator();
+    output.sizes = head;
+}
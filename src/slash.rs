@@ -0,0 +1,198 @@
+use crate::explain;
+use crate::github;
+use crate::memory::{self, Stance};
+use crate::terminal::Theme;
+use crate::{ReviewOptions, SKIP_LABEL, review_pr};
+use log::info;
+use serde_json::Value;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+const HELP: &str = "Commands:\n\
+    - `/rubber review`: review the whole PR\n\
+    - `/rubber security`: review the PR with a focus on security\n\
+    - `/rubber explain <file>`: explain what the change to a file does\n\
+    - `/rubber reject <feedback>`: stop raising this kind of feedback in this repository\n\
+    - `/rubber accept <feedback>`: keep raising this kind of feedback in this repository\n\
+    - `/rubber help`: show this message";
+
+// Commenters who may run commands, each one can cost an AI review. Also the
+// reviewers whose verdicts auto-merge counts
+pub const TRUSTED_ASSOCIATIONS: [&str; 3] = ["OWNER", "MEMBER", "COLLABORATOR"];
+
+// GitHub refuses comments over 65536 characters, this leaves room for the
+// quoted command
+const MAX_REPLY_CHARS: usize = 65000;
+
+const SECURITY_INSTRUCTION: &str = "Review this change for security only: injection, authentication and \
+    authorization, secrets in code or logs, unsafe code, input validation, path traversal and denial of \
+    service. Skip style and maintainability remarks.";
+
+#[derive(Debug, PartialEq)]
+pub enum SlashCommand {
+    Review,
+    Security,
+    Explain(String),
+    // How the team feels about a kind of feedback, remembered for the repository
+    Remember(Stance, String),
+    Help,
+}
+
+// The first line starting with /rubber is the command, the rest of the
+// comment is ignored
+pub fn parse(body: &str) -> Option<Result<SlashCommand, String>> {
+    let line = body.lines().map(str::trim).find(|l| l.starts_with("/rubber"))?;
+    let mut words = line.split_whitespace().skip(1);
+    Some(match words.next() {
+        None | Some("review") => Ok(SlashCommand::Review),
+        Some("security") => Ok(SlashCommand::Security),
+        Some("explain") => match words.next() {
+            Some(file) => Ok(SlashCommand::Explain(file.to_string())),
+            None => Err("`/rubber explain` needs a file, e.g. `/rubber explain src/lib.rs`".to_string()),
+        },
+        Some(verb @ ("reject" | "accept")) => {
+            let theme = words.collect::<Vec<_>>().join(" ");
+            let stance = if verb == "reject" { Stance::Rejected } else { Stance::Accepted };
+            if theme.is_empty() {
+                Err(format!("`/rubber {}` needs the feedback, e.g. `/rubber {} unwrap in tests`", verb, verb))
+            } else {
+                Ok(SlashCommand::Remember(stance, theme))
+            }
+        }
+        Some("help") => Ok(SlashCommand::Help),
+        Some(other) => Err(format!("Unknown command `{}`.\n\n{}", other, HELP)),
+    })
+}
+
+// Cuts a reply down to what GitHub accepts in a comment, closing a code
+// block left open by the cut
+fn fit_reply(reply: String) -> String {
+    let Some((end, _)) = reply.char_indices().nth(MAX_REPLY_CHARS) else {
+        return reply;
+    };
+    let mut cut = reply[..end].to_string();
+    if cut.matches("```").count() % 2 == 1 {
+        cut.push_str("\n```");
+    }
+    cut.push_str("\n\n_Cut short, the full reply is too long for a comment._");
+    cut
+}
+
+// owner/repo of the event's repository, to pick the config profile before
+// the event is handled
+pub fn event_repository(event_path: &Path) -> Option<String> {
+    let event: Value = serde_json::from_str(&fs::read_to_string(event_path).ok()?).ok()?;
+    event["repository"]["full_name"].as_str().map(str::to_string)
+}
+
+// Handles an issue_comment or pull_request_review_comment webhook payload,
+// as delivered to GitHub Actions in $GITHUB_EVENT_PATH
+pub async fn handle_event(
+    event_path: &Path,
+    github_token: Option<&str>,
+    mut options: ReviewOptions,
+) -> Result<String, Box<dyn Error>> {
+    let event: Value = serde_json::from_str(&fs::read_to_string(event_path)?)?;
+    let comment = &event["comment"];
+    let body = comment["body"].as_str().unwrap_or_default();
+
+    // Never answer bots, rubber included
+    if comment["user"]["type"] == "Bot" || body.contains(github::MARKER) {
+        return Ok("Ignoring a comment posted by a bot.\n".to_string());
+    }
+    if event["action"].as_str().is_some_and(|a| a != "created") {
+        return Ok("Ignoring an edited or deleted comment.\n".to_string());
+    }
+    let Some(command) = parse(body) else {
+        return Ok("No /rubber command in the comment.\n".to_string());
+    };
+    // Anyone can comment on a public PR, only the repository's people may
+    // spend on AI reviews or make rubber post
+    let association = comment["author_association"].as_str().unwrap_or("NONE");
+    if !TRUSTED_ASSOCIATIONS.contains(&association) {
+        return Ok(format!(
+            "Ignoring a command from {} ({}), only owners, members and collaborators can run /rubber commands.\n",
+            comment["user"]["login"].as_str().unwrap_or("someone"),
+            association
+        ));
+    }
+
+    let labels = event["issue"]["labels"]
+        .as_array()
+        .or(event["pull_request"]["labels"].as_array())
+        .cloned()
+        .unwrap_or_default();
+    if labels.iter().any(|l| l["name"] == SKIP_LABEL) {
+        return Ok(format!("Ignoring a PR labeled {}.\n", SKIP_LABEL));
+    }
+
+    let owner = event["repository"]["owner"]["login"].as_str().ok_or("Event has no repository owner")?;
+    let repo = event["repository"]["name"].as_str().ok_or("Event has no repository name")?;
+    // Issue comments only concern rubber when the issue is a PR
+    let pr_number = if event["issue"]["pull_request"].is_object() {
+        event["issue"]["number"].as_u64()
+    } else {
+        event["pull_request"]["number"].as_u64()
+    }
+    .ok_or("The comment is not on a pull request")? as u32;
+
+    info!("Running {:?} for {}/{}#{}", command, owner, repo, pr_number);
+    // The reply is markdown, so no box drawing or colors, and the report is
+    // needed whole to post it
+    options.theme = Theme::ascii();
+    options.stream = false;
+    let reply = match command {
+        Ok(SlashCommand::Help) => HELP.to_string(),
+        Ok(SlashCommand::Explain(file)) => {
+            match explain::explain(owner, repo, pr_number, &file, None, github_token, &options).await {
+                Ok(explanation) => explanation.text,
+                // Usually a typo in the file name, which the commenter should hear about
+                Err(e) => e.to_string(),
+            }
+        }
+        Ok(SlashCommand::Remember(stance, theme)) => {
+            // Goes into every future prompt for the repository, so it is only
+            // taken from the trusted commenters checked above, and who said it is kept
+            let login = comment["user"]["login"].as_str().unwrap_or("unknown");
+            let source = format!("{}/{}#{} by {}", owner, repo, pr_number, login);
+            match (memory::record(owner, repo, stance, &theme, &source), stance) {
+                // A theme that's too long, which the commenter should hear about
+                (Err(e), _) => e.to_string(),
+                (Ok(()), Stance::Rejected) => {
+                    format!("Noted, future reviews of {}/{} won't raise: {}", owner, repo, theme)
+                }
+                (Ok(()), Stance::Accepted) => {
+                    format!("Noted, future reviews of {}/{} will keep raising: {}", owner, repo, theme)
+                }
+            }
+        }
+        Ok(SlashCommand::Review) => {
+            let report = review_pr(pr_number, owner, repo, github_token, &options).await?;
+            format!("```text\n{}\n```", report.trim_end())
+        }
+        Ok(SlashCommand::Security) => {
+            options.instructions.push(SECURITY_INSTRUCTION.to_string());
+            let report = review_pr(pr_number, owner, repo, github_token, &options).await?;
+            format!("```text\n{}\n```", report.trim_end())
+        }
+        Err(message) => message,
+    };
+
+    let quoted = body.lines().find(|l| l.trim().starts_with("/rubber")).unwrap_or_default();
+    let reply = fit_reply(format!("> {}\n\n{}", quoted.trim(), reply));
+    let posted = match comment["id"].as_u64() {
+        Some(id) if event["pull_request"].is_object() => {
+            github::reply_to_review_comment(owner, repo, pr_number, id, &reply, github_token).await?
+        }
+        _ => github::post_issue_comment(owner, repo, pr_number, &reply, github_token).await?,
+    };
+
+    Ok(format!(
+        "Replied to {}/{}#{}: {}\n",
+        owner,
+        repo,
+        pr_number,
+        posted["html_url"].as_str().unwrap_or_default()
+    ))
+}
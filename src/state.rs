@@ -0,0 +1,92 @@
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::hash::BuildHasher;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Where rubber keeps data between runs (usage records, history, ...)
+pub fn data_dir() -> Option<PathBuf> {
+    if let Ok(dir) = env::var("RUBBER_DATA_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    if let Ok(dir) = env::var("XDG_DATA_HOME") {
+        return Some(PathBuf::from(dir).join("rubber"));
+    }
+    if cfg!(windows) {
+        return env::var("LOCALAPPDATA").ok().map(|dir| PathBuf::from(dir).join("rubber"));
+    }
+    env::var("HOME")
+        .ok()
+        .map(|home| PathBuf::from(home).join(".local").join("share").join("rubber"))
+}
+
+pub fn ensure_data_dir() -> io::Result<PathBuf> {
+    let dir = data_dir().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "Could not determine the data directory")
+    })?;
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+// How a repository is named in stored file names, owner__repo. The '/' of a
+// GitLab nested group becomes '~', which names can't contain
+pub fn file_key(owner: &str, repo: &str) -> String {
+    format!("{}__{}", owner.replace('/', "~"), repo)
+}
+
+// Tries at names already taken before giving up on a temp file
+const TEMP_ATTEMPTS: u32 = 16;
+
+// A new file in dir that only this user can read, for reports and drafts
+// written outside the encrypted stores. Its name can't be guessed and it's
+// never an existing file, so a symlink planted there isn't followed
+pub fn create_private(dir: &Path, prefix: &str, extension: &str) -> io::Result<(PathBuf, File)> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut attempt = 0;
+    loop {
+        let nonce = RandomState::new().hash_one((std::process::id(), attempt));
+        let path = dir.join(format!("rubber-{}-{:016x}.{}", prefix, nonce, extension));
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && attempt < TEMP_ATTEMPTS => attempt += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+const LOCK_FILE: &str = "rubber.lock";
+
+// Held while reading or writing files in the data directory. Several rubber
+// processes (hooks, manual runs, ...) can run at once, so appends and
+// rewrites are serialized through an OS file lock released on drop
+pub struct StateLock {
+    _file: File,
+}
+
+fn open_lock() -> io::Result<File> {
+    let path = ensure_data_dir()?.join(LOCK_FILE);
+    OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+}
+
+pub fn lock() -> io::Result<StateLock> {
+    let file = open_lock()?;
+    file.lock()?;
+    Ok(StateLock { _file: file })
+}
+
+pub fn lock_shared() -> io::Result<StateLock> {
+    let file = open_lock()?;
+    file.lock_shared()?;
+    Ok(StateLock { _file: file })
+}
@@ -0,0 +1,271 @@
+use crate::findings::{Finding, Severity};
+use crate::{OutputBuffer, PullRequestDetail, github, history, http};
+use log::{info, warn};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+const GITMODULES: &str = ".gitmodules";
+
+// Commits listed per submodule before the rest is only counted
+const MAX_LISTED: usize = 20;
+
+#[derive(Deserialize)]
+struct Comparison {
+    // ahead, behind, diverged or identical
+    status: String,
+    ahead_by: usize,
+    behind_by: usize,
+    #[serde(default)]
+    commits: Vec<Commit>,
+}
+
+#[derive(Deserialize)]
+struct Commit {
+    sha: String,
+    commit: CommitDetail,
+}
+
+#[derive(Deserialize)]
+struct CommitDetail {
+    message: String,
+}
+
+fn short(sha: &str) -> &str {
+    &sha[..sha.len().min(7)]
+}
+
+// The commits a submodule points at before and after, from a patch made only
+// of "Subproject commit <sha>" lines. None for the patch of anything else
+fn pointer_change(patch: &str) -> Option<(Option<&str>, Option<&str>)> {
+    let (mut before, mut after) = (None, None);
+    for line in patch.lines().filter(|line| !line.starts_with("@@") && !line.trim().is_empty()) {
+        let sha = line.get(1..)?.strip_prefix("Subproject commit ")?.trim();
+        // A dirty submodule in the commit is marked as such
+        let sha = sha.trim_end_matches("-dirty");
+        match &line[..1] {
+            "-" => before = Some(sha),
+            "+" => after = Some(sha),
+            _ => return None,
+        }
+    }
+    (before.is_some() || after.is_some()).then_some((before, after))
+}
+
+pub fn is_pointer(patch: &str) -> bool {
+    pointer_change(patch).is_some()
+}
+
+// The URL of every submodule in a .gitmodules file, by path
+fn parse_gitmodules(content: &str) -> BTreeMap<String, String> {
+    let mut urls = BTreeMap::new();
+    let (mut path, mut url): (Option<String>, Option<String>) = (None, None);
+    for line in content.lines().map(str::trim) {
+        if line.starts_with('[') {
+            if let (Some(path), Some(url)) = (path.take(), url.take()) {
+                urls.insert(path, url);
+            }
+        } else if let Some((key, value)) = line.split_once('=')
+            && !line.starts_with('#')
+            && !line.starts_with(';')
+        {
+            match key.trim() {
+                "path" => path = Some(value.trim().to_string()),
+                "url" => url = Some(value.trim().to_string()),
+                _ => {}
+            }
+        }
+    }
+    if let (Some(path), Some(url)) = (path, url) {
+        urls.insert(path, url);
+    }
+    urls
+}
+
+// The host and path of a clone URL, https://host/path, ssh://user@host/path
+// or scp-like user@host:path
+fn host_and_path(url: &str) -> Option<(String, String)> {
+    if url.contains("://") {
+        let url = reqwest::Url::parse(url).ok()?;
+        return Some((url.host_str()?.to_string(), url.path().trim_start_matches('/').to_string()));
+    }
+    let (user_host, path) = url.split_once(':')?;
+    let host = user_host.rsplit_once('@').map_or(user_host, |(_, host)| host);
+    Some((host.to_string(), path.trim_start_matches('/').to_string()))
+}
+
+// owner/repo of a submodule hosted where the API is, github.com or the GitHub
+// Enterprise server. Relative URLs are relative to the superproject's,
+// "../other" being a sibling repository of the same owner
+fn github_repo(url: &str, owner: &str) -> Option<(String, String)> {
+    let url = url.trim().trim_end_matches('/').trim_end_matches(".git");
+    let path = match url.strip_prefix("../") {
+        Some(relative) => match relative.strip_prefix("../") {
+            Some(elsewhere) => elsewhere.to_string(),
+            None => format!("{}/{}", owner, relative),
+        },
+        None => {
+            let (host, path) = host_and_path(url)?;
+            let github = http::github_host()?;
+            if !host.eq_ignore_ascii_case(&github) {
+                return None;
+            }
+            path
+        }
+    };
+    match path.split_once('/') {
+        Some((owner, repo)) if !owner.is_empty() && !repo.is_empty() && !repo.contains('/') => {
+            Some((owner.to_string(), repo.to_string()))
+        }
+        _ => None,
+    }
+}
+
+async fn compare(
+    owner: &str,
+    repo: &str,
+    before: &str,
+    after: &str,
+    github_token: Option<&str>,
+) -> Result<Comparison, Box<dyn Error>> {
+    let url = format!("{}/repos/{}/{}/compare/{}...{}", http::github_api(), owner, repo, before, after);
+    Ok(serde_json::from_str(&http::GithubClient::new(github_token).get(&url).await?)?)
+}
+
+// What moving the pointer from before to after pulls in
+async fn describe_bump(
+    url: &str,
+    owner: &str,
+    before: &str,
+    after: &str,
+    github_token: Option<&str>,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let Some((sub_owner, sub_repo)) = github_repo(url, owner) else {
+        return Ok(vec![format!(
+            "{}..{} of {}, not on GitHub so its commits can't be listed",
+            short(before),
+            short(after),
+            url
+        )]);
+    };
+    let comparison = compare(&sub_owner, &sub_repo, before, after, github_token).await?;
+    let mut lines = vec![format!("{}/{} {}..{}", sub_owner, sub_repo, short(before), short(after))];
+    match comparison.status.as_str() {
+        "identical" => lines.push("Same commit, nothing changes".to_string()),
+        "behind" => lines.push(format!(
+            "Moves back {} commits, dropping what they brought in",
+            comparison.behind_by
+        )),
+        status => {
+            if status == "diverged" {
+                lines.push(format!(
+                    "Not a descendant of the old commit: {} commits in, {} of the old ones left out",
+                    comparison.ahead_by, comparison.behind_by
+                ));
+            } else {
+                lines.push(format!("Pulls in {} commits:", comparison.ahead_by));
+            }
+            // Listed oldest first, the newest are the ones worth reading
+            for commit in comparison.commits.iter().rev().take(MAX_LISTED) {
+                let subject = commit.commit.message.lines().next().unwrap_or_default();
+                lines.push(format!("  {} {}", short(&commit.sha), subject));
+            }
+            if comparison.ahead_by > MAX_LISTED {
+                lines.push(format!("  ... and {} more", comparison.ahead_by - MAX_LISTED));
+            }
+        }
+    }
+    Ok(lines)
+}
+
+// Adds the Submodule Changes section: what each submodule pointer bump pulls
+// in, from the submodule's commits between the two pointers, and the
+// submodules added, removed or pointed elsewhere in .gitmodules
+pub async fn review(
+    owner: &str,
+    repo: &str,
+    details: &PullRequestDetail,
+    github_token: Option<&str>,
+    output: &mut OutputBuffer,
+) {
+    let pointers: Vec<(&str, Option<&str>, Option<&str>)> = details
+        .files
+        .iter()
+        .filter_map(|file| {
+            let (before, after) = pointer_change(file.patch.as_deref()?)?;
+            Some((file.filename.as_str(), before, after))
+        })
+        .collect();
+    let gitmodules_changed = details.files.iter().any(|file| file.filename == GITMODULES);
+    if pointers.is_empty() && !gitmodules_changed {
+        return;
+    }
+
+    info!("Reading {}...", GITMODULES);
+    let read = |content: Result<Option<String>, Box<dyn Error>>, sha: &str| match content {
+        Ok(content) => parse_gitmodules(&content.unwrap_or_default()),
+        Err(e) => {
+            warn!("Could not read {} at {}: {}", GITMODULES, short(sha), e);
+            BTreeMap::new()
+        }
+    };
+    let base = &details.base.sha;
+    let before_urls = read(github::file_at(owner, repo, GITMODULES, base, github_token).await, base);
+    let after_urls = read(details.head_file(owner, repo, GITMODULES, github_token).await, &details.head.sha);
+
+    // Pointing a submodule at another repository swaps out everything it
+    // builds, easy to miss next to a pointer bump
+    let moved: Vec<(&String, &String, &String)> = before_urls
+        .iter()
+        .filter_map(|(path, before)| {
+            let after = after_urls.get(path).filter(|after| *after != before)?;
+            Some((path, before, after))
+        })
+        .collect();
+    if pointers.is_empty() && moved.is_empty() {
+        return;
+    }
+
+    output.add_header("Submodule Changes");
+    for (path, before, after) in pointers {
+        output.add_section(path);
+        let url = after_urls.get(path).or_else(|| before_urls.get(path));
+        let lines = match (before, after, url) {
+            (None, Some(after), _) => vec![format!(
+                "Added at {} from {}",
+                short(after),
+                url.map(String::as_str).unwrap_or("an unknown URL")
+            )],
+            (Some(before), None, _) => vec![format!("Removed, it was at {}", short(before))],
+            (Some(before), Some(after), Some(url)) => {
+                info!("Comparing {} {}..{}...", path, short(before), short(after));
+                match describe_bump(url, owner, before, after, github_token).await {
+                    Ok(lines) => lines,
+                    Err(e) => {
+                        warn!("Could not compare {}: {}", path, e);
+                        vec![format!("{}..{}, the commits could not be read, see the log", short(before), short(after))]
+                    }
+                }
+            }
+            (Some(before), Some(after), None) => {
+                vec![format!("{}..{}, not in {} so its repository is unknown", short(before), short(after), GITMODULES)]
+            }
+            (None, None, _) => continue,
+        };
+        output.add_box_content(&lines.join("\n"));
+    }
+
+    if !moved.is_empty() {
+        output.add_section(GITMODULES);
+        let mut lines = Vec::new();
+        for (path, before, after) in moved {
+            let finding = Finding::new(Severity::Medium, format!("{} now points at {} instead of {}", path, after, before));
+            output
+                .findings
+                .push(history::ReviewFinding::new("submodule", GITMODULES, path, &finding.message));
+            lines.push(output.format_finding(&finding));
+        }
+        output.add_box_content(&lines.join("\n"));
+    }
+    output.add_diff_separator();
+}
@@ -0,0 +1,51 @@
+use crate::config::{Config, Team};
+use crate::http;
+use crate::{FileChange, PullRequestDetail};
+use log::info;
+use serde_json::json;
+use std::error::Error;
+
+// Teams whose paths the PR touches, with their files, in config order
+pub fn touched<'a>(config: &'a Config, files: &'a [FileChange]) -> Vec<(&'a Team, Vec<&'a str>)> {
+    let mut touched: Vec<(&Team, Vec<&str>)> = Vec::new();
+    for team in &config.teams {
+        let owned: Vec<&str> = files
+            .iter()
+            .map(|f| f.filename.as_str())
+            .filter(|path| config.team_for(path).is_some_and(|t| std::ptr::eq(t, team)))
+            .collect();
+        if !owned.is_empty() {
+            touched.push((team, owned));
+        }
+    }
+    touched
+}
+
+// Tells every touched team with a webhook about the PR
+pub async fn notify(
+    owner: &str,
+    repo: &str,
+    pr_number: u32,
+    details: &PullRequestDetail,
+    config: &Config,
+) -> Result<(), Box<dyn Error>> {
+    for (team, files) in touched(config, &details.files) {
+        let Some(webhook) = &team.slack_webhook else {
+            continue;
+        };
+        info!("Notifying team {}...", team.name);
+        let text = format!(
+            "<{}|{}/{}#{}> \"{}\" touches {} file(s) owned by {}:\n{}",
+            details.html_url,
+            owner,
+            repo,
+            pr_number,
+            details.title,
+            files.len(),
+            team.name,
+            files.iter().map(|f| format!("• `{}`", f)).collect::<Vec<_>>().join("\n")
+        );
+        http::post_webhook(webhook, &json!({ "text": text })).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use crate::config;
+use log::info;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// A reviewed patch shown to the AI before the real one, so reviews keep the
+// same shape and level of detail from run to run
+#[derive(Debug)]
+pub struct Example {
+    pub patch: String,
+    pub review: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Templates {
+    // Sent as the system prompt of every AI request
+    pub system: Option<String>,
+    pub examples: Vec<Example>,
+}
+
+pub fn default_dir() -> Option<PathBuf> {
+    config::config_dir().map(|dir| dir.join("templates"))
+}
+
+// Reads system.md and examples/<name>.patch with its examples/<name>.md review.
+// An explicitly configured directory must exist, the default one is optional
+pub fn load(configured: Option<&Path>) -> Result<Templates, Box<dyn Error>> {
+    let dir = match configured {
+        Some(dir) if !dir.is_dir() => {
+            return Err(format!("Templates directory {} does not exist", dir.display()).into());
+        }
+        Some(dir) => dir.to_path_buf(),
+        None => match default_dir() {
+            Some(dir) if dir.is_dir() => dir,
+            _ => return Ok(Templates::default()),
+        },
+    };
+
+    let system_path = dir.join("system.md");
+    let system = if system_path.exists() {
+        Some(fs::read_to_string(&system_path)?).filter(|s| !s.trim().is_empty())
+    } else {
+        None
+    };
+
+    let mut examples = Vec::new();
+    let examples_dir = dir.join("examples");
+    if examples_dir.is_dir() {
+        let mut patches: Vec<PathBuf> = fs::read_dir(&examples_dir)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<_, _>>()?;
+        patches.retain(|path| path.extension().is_some_and(|ext| ext == "patch"));
+        patches.sort();
+        for patch_path in patches {
+            let review_path = patch_path.with_extension("md");
+            if !review_path.exists() {
+                return Err(format!(
+                    "Example {} has no review, expected {}",
+                    patch_path.display(),
+                    review_path.display()
+                )
+                .into());
+            }
+            examples.push(Example {
+                patch: fs::read_to_string(&patch_path)?,
+                review: fs::read_to_string(&review_path)?.trim().to_string(),
+            });
+        }
+    }
+
+    if system.is_some() || !examples.is_empty() {
+        info!(
+            "Using templates from {} ({} example{})",
+            dir.display(),
+            examples.len(),
+            if examples.len() == 1 { "" } else { "s" }
+        );
+    }
+    Ok(Templates { system, examples })
+}
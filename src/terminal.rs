@@ -0,0 +1,184 @@
+#[derive(Debug)]
+pub struct Glyphs {
+    pub header: &'static str,
+    pub section: &'static str,
+    pub footer: &'static str,
+    pub vertical: &'static str,
+    pub horizontal: &'static str,
+    pub rule: &'static str,
+    pub collapsed: &'static str,
+    pub moved: &'static str,
+    pub uncovered: &'static str,
+    pub comment: &'static str,
+}
+
+const UNICODE: Glyphs = Glyphs {
+    header: "┏━━",
+    section: "┣━━",
+    footer: "┗",
+    vertical: "┃",
+    horizontal: "━",
+    rule: "─",
+    collapsed: "⋯",
+    moved: "↳",
+    uncovered: "◌",
+    comment: "»",
+};
+
+// Safe on code pages that can't render box drawing characters
+const ASCII: Glyphs = Glyphs {
+    header: "+--",
+    section: "|--",
+    footer: "+",
+    vertical: "|",
+    horizontal: "-",
+    rule: "-",
+    collapsed: "...",
+    moved: "->",
+    uncovered: "o",
+    comment: ">>",
+};
+
+// Screen readers and dumb terminals: no drawing at all, words instead of
+// symbols. Headings and file markers are written out by OutputBuffer.
+const PLAIN: Glyphs = Glyphs {
+    header: "",
+    section: "",
+    footer: "",
+    vertical: "",
+    horizontal: "",
+    rule: "",
+    collapsed: "NOTE:",
+    moved: "NOTE:",
+    uncovered: "NOTE:",
+    comment: "COMMENT:",
+};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Color {
+    Red,
+    Green,
+    Yellow,
+    Cyan,
+    Dim,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Theme {
+    pub unicode: bool,
+    pub color: bool,
+    // Linear output for screen readers, see PLAIN
+    pub plain: bool,
+    // Columns taken by headers and separators
+    pub width: usize,
+    // Absolute times in the local timezone next to the relative ones
+    pub local_time: bool,
+}
+
+pub const DEFAULT_WIDTH: usize = 80;
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            unicode: true,
+            color: true,
+            plain: false,
+            width: DEFAULT_WIDTH,
+            local_time: false,
+        }
+    }
+}
+
+impl Theme {
+    pub fn ascii() -> Self {
+        Self {
+            unicode: false,
+            color: false,
+            plain: false,
+            width: DEFAULT_WIDTH,
+            local_time: false,
+        }
+    }
+
+    pub fn plain() -> Self {
+        Self {
+            plain: true,
+            ..Self::ascii()
+        }
+    }
+
+    pub fn detect(force_ascii: bool, plain: bool) -> Self {
+        if plain {
+            return Self::plain();
+        }
+        if force_ascii {
+            return Self::ascii();
+        }
+        platform_theme()
+    }
+
+    pub fn glyphs(&self) -> &'static Glyphs {
+        if self.plain {
+            &PLAIN
+        } else if self.unicode {
+            &UNICODE
+        } else {
+            &ASCII
+        }
+    }
+
+    pub fn paint(&self, text: &str, color: Color) -> String {
+        if !self.color {
+            return text.to_string();
+        }
+        let code = match color {
+            Color::Red => "31",
+            Color::Green => "32",
+            Color::Yellow => "33",
+            Color::Cyan => "36",
+            Color::Dim => "2",
+        };
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    }
+}
+
+#[cfg(not(windows))]
+fn platform_theme() -> Theme {
+    Theme::default()
+}
+
+// Legacy Windows consoles (cmd.exe, older PowerShell hosts) need virtual
+// terminal processing switched on before they interpret ANSI escapes, and a
+// UTF-8 code page before box drawing characters render.
+#[cfg(windows)]
+fn platform_theme() -> Theme {
+    use windows_sys::Win32::System::Console::{
+        ENABLE_VIRTUAL_TERMINAL_PROCESSING, GetConsoleMode, GetStdHandle, STD_OUTPUT_HANDLE,
+        SetConsoleMode, SetConsoleOutputCP,
+    };
+    const CP_UTF8: u32 = 65001;
+
+    // SAFETY: plain Win32 console calls on this process' own stdout handle
+    unsafe {
+        let handle = GetStdHandle(STD_OUTPUT_HANDLE);
+        let mut mode = 0;
+        if GetConsoleMode(handle, &mut mode) == 0 {
+            // Redirected to a file or pipe: keep UTF-8 but skip the escapes
+            return Theme {
+                unicode: true,
+                color: false,
+                ..Theme::default()
+            };
+        }
+
+        let utf8 = SetConsoleOutputCP(CP_UTF8) != 0;
+        let virtual_terminal = mode & ENABLE_VIRTUAL_TERMINAL_PROCESSING != 0
+            || SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0;
+
+        Theme {
+            unicode: utf8 && virtual_terminal,
+            color: virtual_terminal,
+            ..Theme::default()
+        }
+    }
+}
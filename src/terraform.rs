@@ -0,0 +1,145 @@
+use crate::findings::{Finding, Severity};
+use serde_json::Value;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+// Resources listed in the report before the rest is only counted
+const MAX_LISTED: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Action {
+    Destroy,
+    Replace,
+    Update,
+    Create,
+}
+
+impl Action {
+    fn verb(&self) -> &'static str {
+        match self {
+            Action::Destroy => "destroy",
+            Action::Replace => "replace",
+            Action::Update => "update",
+            Action::Create => "create",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ResourceChange {
+    pub address: String,
+    pub action: Action,
+}
+
+impl ResourceChange {
+    // Destroying or replacing a resource can lose data or cause downtime
+    pub fn to_finding(&self) -> Option<Finding> {
+        let message = match self.action {
+            Action::Destroy => format!("{} will be destroyed", self.address),
+            Action::Replace => format!("{} will be replaced (destroyed and re-created)", self.address),
+            _ => return None,
+        };
+        Some(Finding::new(Severity::High, message))
+    }
+}
+
+// `terraform show -json` lists actions such as ["delete", "create"]
+fn from_actions(actions: &[Value]) -> Option<Action> {
+    let actions: Vec<&str> = actions.iter().filter_map(Value::as_str).collect();
+    match actions.as_slice() {
+        ["create"] => Some(Action::Create),
+        ["update"] => Some(Action::Update),
+        ["delete"] => Some(Action::Destroy),
+        ["delete", "create"] | ["create", "delete"] => Some(Action::Replace),
+        // Reads and no-ops don't change anything
+        _ => None,
+    }
+}
+
+// `terraform plan -json` streams one planned_change message per resource
+fn from_action(action: &str) -> Option<Action> {
+    match action {
+        "create" => Some(Action::Create),
+        "update" => Some(Action::Update),
+        "delete" => Some(Action::Destroy),
+        "replace" => Some(Action::Replace),
+        _ => None,
+    }
+}
+
+// Accepts the JSON lines of `terraform plan -json` as well as the plan
+// document of `terraform show -json <planfile>`
+pub fn load(path: &Path) -> Result<Vec<ResourceChange>, Box<dyn Error>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let mut changes = Vec::new();
+
+    let document = serde_json::from_str::<Value>(&content)
+        .ok()
+        .filter(|d| d["resource_changes"].is_array());
+    match document {
+        Some(document) => {
+            for resource in document["resource_changes"].as_array().into_iter().flatten() {
+                let actions = resource["change"]["actions"].as_array().map(Vec::as_slice).unwrap_or_default();
+                if let (Some(address), Some(action)) = (resource["address"].as_str(), from_actions(actions)) {
+                    changes.push(ResourceChange {
+                        address: address.to_string(),
+                        action,
+                    });
+                }
+            }
+        }
+        None => {
+            for line in content.lines().filter(|l| !l.trim().is_empty()) {
+                let message: Value = serde_json::from_str(line)
+                    .map_err(|e| format!("{} is not a Terraform JSON plan: {}", path.display(), e))?;
+                if message["type"] != "planned_change" {
+                    continue;
+                }
+                let change = &message["change"];
+                let address = change["resource"]["addr"].as_str();
+                let action = change["action"].as_str().and_then(from_action);
+                if let (Some(address), Some(action)) = (address, action) {
+                    changes.push(ResourceChange {
+                        address: address.to_string(),
+                        action,
+                    });
+                }
+            }
+        }
+    }
+
+    // Destructive changes first
+    changes.sort_by(|a, b| a.action.cmp(&b.action).then(a.address.cmp(&b.address)));
+    Ok(changes)
+}
+
+// "2 to create, 1 to destroy" in Terraform's own order
+pub fn summary(changes: &[ResourceChange]) -> String {
+    let counts: Vec<String> = [Action::Create, Action::Update, Action::Replace, Action::Destroy]
+        .iter()
+        .map(|action| (action, changes.iter().filter(|c| c.action == *action).count()))
+        .filter(|(_, count)| *count > 0)
+        .map(|(action, count)| format!("{} to {}", count, action.verb()))
+        .collect();
+    if counts.is_empty() {
+        "No infrastructure changes".to_string()
+    } else {
+        counts.join(", ")
+    }
+}
+
+// The resources that are created or updated, one per line
+pub fn listing(changes: &[ResourceChange]) -> Vec<String> {
+    let safe: Vec<&ResourceChange> = changes.iter().filter(|c| c.to_finding().is_none()).collect();
+    let mut lines: Vec<String> = safe
+        .iter()
+        .take(MAX_LISTED)
+        .map(|c| format!("{} {}", c.action.verb(), c.address))
+        .collect();
+    if safe.len() > MAX_LISTED {
+        lines.push(format!("... and {} more", safe.len() - MAX_LISTED));
+    }
+    lines
+}
@@ -0,0 +1,50 @@
+use crate::terminal::Theme;
+use chrono::{DateTime, Local, Utc};
+
+fn plural(count: i64, unit: &str) -> String {
+    if count == 1 {
+        format!("1 {} ago", unit)
+    } else {
+        format!("{} {}s ago", count, unit)
+    }
+}
+
+// "3 days ago", in the largest whole unit
+pub fn relative(time: DateTime<Utc>) -> String {
+    let elapsed = Utc::now() - time;
+    let minutes = elapsed.num_minutes();
+    let days = elapsed.num_days();
+    if minutes < 1 {
+        "just now".to_string()
+    } else if minutes < 60 {
+        plural(minutes, "minute")
+    } else if days < 1 {
+        plural(elapsed.num_hours(), "hour")
+    } else if days < 30 {
+        plural(days, "day")
+    } else if days < 365 {
+        plural(days / 30, "month")
+    } else {
+        plural(days / 365, "year")
+    }
+}
+
+// The same for narrow columns: 45m, 5h, 3d, 2y
+pub fn compact(time: DateTime<Utc>) -> String {
+    let minutes = (Utc::now() - time).num_minutes().max(0);
+    match minutes {
+        0..60 => format!("{}m", minutes),
+        60..1440 => format!("{}h", minutes / 60),
+        1440..525_600 => format!("{}d", minutes / 1440),
+        _ => format!("{}y", minutes / 525_600),
+    }
+}
+
+// Relative, followed by the time in the local timezone with --local-time
+pub fn show(time: DateTime<Utc>, theme: &Theme) -> String {
+    if theme.local_time {
+        format!("{} ({})", relative(time), time.with_timezone(&Local).format("%Y-%m-%d %H:%M %Z"))
+    } else {
+        relative(time)
+    }
+}
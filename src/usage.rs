@@ -0,0 +1,96 @@
+use crate::state;
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const USAGE_FILE: &str = "usage.jsonl";
+
+// One Anthropic API call, appended to the usage log after every response
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UsageRecord {
+    pub timestamp: u64,
+    pub model: String,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    // Rate limit headers as last reported by the API
+    #[serde(default)]
+    pub rate_limits: Vec<(String, String)>,
+}
+
+impl UsageRecord {
+    // USD per million input/output tokens
+    fn prices(&self) -> Option<(f64, f64)> {
+        let model = self.model.as_str();
+        if model.contains("opus") {
+            Some((15.0, 75.0))
+        } else if model.contains("sonnet") {
+            Some((3.0, 15.0))
+        } else if model.contains("3-5-haiku") || model.contains("haiku-4") {
+            Some((0.8, 4.0))
+        } else if model.contains("haiku") {
+            Some((0.25, 1.25))
+        } else if model.starts_with("gpt-4o-mini") {
+            Some((0.15, 0.6))
+        } else if model.starts_with("gpt-4o") {
+            Some((2.5, 10.0))
+        } else {
+            None
+        }
+    }
+
+    pub fn cost(&self) -> Option<f64> {
+        self.prices().map(|(input, output)| {
+            (self.input_tokens as f64 * input + self.output_tokens as f64 * output) / 1_000_000.0
+        })
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn usage_path() -> Option<PathBuf> {
+    state::data_dir().map(|dir| dir.join(USAGE_FILE))
+}
+
+pub fn record(model: &str, input_tokens: u64, output_tokens: u64, headers: &HeaderMap) -> Result<(), Box<dyn Error>> {
+    let rate_limits = headers
+        .iter()
+        .filter(|(name, _)| name.as_str().starts_with("anthropic-ratelimit-") || name.as_str().starts_with("x-ratelimit-"))
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+
+    let record = UsageRecord {
+        timestamp: now(),
+        model: model.to_string(),
+        input_tokens,
+        output_tokens,
+        rate_limits,
+    };
+
+    let _lock = state::lock()?;
+    let path = state::ensure_data_dir()?.join(USAGE_FILE);
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(&record)?)?;
+    Ok(())
+}
+
+pub fn load() -> Result<Vec<UsageRecord>, Box<dyn Error>> {
+    let Some(path) = usage_path().filter(|p| p.exists()) else {
+        return Ok(Vec::new());
+    };
+
+    let _lock = state::lock_shared()?;
+    // Skip lines that can't be parsed rather than failing on a corrupt log
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}